@@ -17,7 +17,9 @@ use crate::repl::{repl_main, ReplArgs};
 use crate::server::{server_main, ServerArgs};
 
 mod client;
+mod raft;
 mod repl;
+mod replication;
 mod server;
 
 #[derive(Parser)]
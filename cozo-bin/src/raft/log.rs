@@ -0,0 +1,455 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Persistent state of a Raft node: the current term, the vote, the latest snapshot, and the
+//! log entries after the snapshot.
+//!
+//! On disk, the state lives in a directory holding three files, all of them starting with
+//! a header of big-endian `u64`s:
+//!
+//! * `meta`, containing the term and the vote and replaced atomically on every change,
+//! * `snapshot`, with the index and the term of the last entry it covers as the header,
+//!   followed by chunks of the content of the database, each one being its length
+//!   (`u32`, big-endian) followed by the encoded chunk. It is replaced atomically.
+//! * `log`, with the index of the entry before its first entry as the header, followed by an
+//!   append-only sequence of entries, encoded like the chunks of the snapshot. It is rewritten
+//!   without the entries covered by the snapshot whenever a snapshot is installed.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use miette::{bail, IntoDiagnostic, Result};
+
+use cozo::WriteOp;
+
+use crate::raft::NodeId;
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum EntryPayload {
+    /// Appended by a new leader to commit the entries of previous terms
+    Noop,
+    /// The write set of a transaction
+    WriteSet(Vec<WriteOp>),
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct LogEntry {
+    pub(crate) term: u64,
+    pub(crate) payload: EntryPayload,
+}
+
+#[derive(Debug, Default, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+struct HardState {
+    term: u64,
+    voted_for: Option<NodeId>,
+}
+
+/// Where the chunks of a snapshot are kept.
+enum SnapshotChunks {
+    /// In memory, for stores not backed by files
+    Memory(Vec<Vec<WriteOp>>),
+    /// Byte offsets of the chunks in the snapshot file
+    File(Vec<u64>),
+}
+
+impl Default for SnapshotChunks {
+    fn default() -> Self {
+        SnapshotChunks::Memory(vec![])
+    }
+}
+
+/// A snapshot being built, which becomes the snapshot of the store with
+/// [RaftStore::install_snapshot].
+pub(crate) struct SnapshotBuilder {
+    pub(crate) index: u64,
+    pub(crate) term: u64,
+    /// The temporary file the snapshot is written to, with the number of bytes written
+    file: Option<(PathBuf, BufWriter<File>, u64)>,
+    chunks: SnapshotChunks,
+}
+
+impl SnapshotBuilder {
+    pub(crate) fn add_chunk(&mut self, chunk: Vec<WriteOp>) -> Result<()> {
+        match (&mut self.file, &mut self.chunks) {
+            (Some((_, file, len)), SnapshotChunks::File(offsets)) => {
+                offsets.push(*len);
+                *len += write_frame(file, &chunk)?;
+            }
+            (_, SnapshotChunks::Memory(chunks)) => chunks.push(chunk),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SnapshotBuilder {
+    fn drop(&mut self) {
+        // only a snapshot that is not installed still has its file
+        if let Some((path, _, _)) = self.file.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The state a Raft node must keep across restarts. Indices of log entries start from 1.
+pub(crate) struct RaftStore {
+    dir: Option<PathBuf>,
+    hard_state: HardState,
+    /// Index of the last entry covered by the snapshot, 0 if there is no snapshot
+    snapshot_index: u64,
+    snapshot_term: u64,
+    snapshot_chunks: SnapshotChunks,
+    /// The entries after the snapshot
+    entries: Vec<LogEntry>,
+    /// Byte offsets of the entries in the log file
+    offsets: Vec<u64>,
+    log_file: Option<File>,
+}
+
+/// Write `msg` prefixed by its length, returning the number of bytes written.
+fn write_frame<T: serde::Serialize>(out: &mut impl Write, msg: &T) -> Result<u64> {
+    let bytes = rmp_serde::to_vec(msg).into_diagnostic()?;
+    out.write_all(&(bytes.len() as u32).to_be_bytes())
+        .into_diagnostic()?;
+    out.write_all(&bytes).into_diagnostic()?;
+    Ok(4 + bytes.len() as u64)
+}
+
+/// Read a message written by [write_frame], or `None` at the end of the input or of the
+/// last complete message.
+fn read_frame<T: serde::de::DeserializeOwned>(rdr: &mut impl Read) -> Result<Option<(T, u64)>> {
+    let mut len_bytes = [0u8; 4];
+    if rdr.read_exact(&mut len_bytes).is_err() {
+        return Ok(None);
+    }
+    let mut bytes = vec![0u8; u32::from_be_bytes(len_bytes) as usize];
+    if rdr.read_exact(&mut bytes).is_err() {
+        return Ok(None);
+    }
+    let msg = rmp_serde::from_slice(&bytes).into_diagnostic()?;
+    Ok(Some((msg, 4 + bytes.len() as u64)))
+}
+
+fn read_u64(rdr: &mut impl Read) -> Result<Option<u64>> {
+    let mut bytes = [0u8; 8];
+    match rdr.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(u64::from_be_bytes(bytes))),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+impl RaftStore {
+    /// A store that keeps everything in memory, for tests.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            dir: None,
+            hard_state: Default::default(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            snapshot_chunks: Default::default(),
+            entries: vec![],
+            offsets: vec![],
+            log_file: None,
+        }
+    }
+
+    /// Open the store in `dir`, creating it if necessary.
+    pub(crate) fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).into_diagnostic()?;
+        let hard_state = match fs::read(dir.join("meta")) {
+            Ok(bytes) => rmp_serde::from_slice(&bytes).into_diagnostic()?,
+            Err(err) if err.kind() == ErrorKind::NotFound => HardState::default(),
+            Err(err) => return Err(err).into_diagnostic(),
+        };
+
+        let mut snapshot_index = 0;
+        let mut snapshot_term = 0;
+        let mut chunk_offsets = vec![];
+        if let Ok(file) = File::open(dir.join("snapshot")) {
+            let mut rdr = BufReader::new(file);
+            match (read_u64(&mut rdr)?, read_u64(&mut rdr)?) {
+                (Some(index), Some(term)) => {
+                    snapshot_index = index;
+                    snapshot_term = term;
+                }
+                _ => bail!("The Raft snapshot is corrupted"),
+            }
+            let mut offset = 16;
+            let mut len_bytes = [0u8; 4];
+            while rdr.read_exact(&mut len_bytes).is_ok() {
+                let len = u32::from_be_bytes(len_bytes);
+                rdr.seek_relative(len as i64).into_diagnostic()?;
+                chunk_offsets.push(offset);
+                offset += 4 + len as u64;
+            }
+        }
+
+        let mut entries = vec![];
+        let mut offsets = vec![];
+        let mut valid_len = 8u64;
+        let mut base = snapshot_index;
+        if let Ok(file) = File::open(dir.join("log")) {
+            let mut rdr = BufReader::new(file);
+            if let Some(log_base) = read_u64(&mut rdr)? {
+                base = log_base;
+                // a torn write of the last entry was never acknowledged, and is dropped
+                while let Some((entry, len)) = read_frame(&mut rdr)? {
+                    offsets.push(valid_len);
+                    entries.push(entry);
+                    valid_len += len;
+                }
+            }
+        }
+        if base > snapshot_index {
+            bail!("The Raft log does not continue from the snapshot");
+        }
+        let mut store = Self {
+            dir: Some(dir.to_path_buf()),
+            hard_state,
+            snapshot_index,
+            snapshot_term,
+            snapshot_chunks: SnapshotChunks::File(chunk_offsets),
+            entries,
+            offsets,
+            log_file: None,
+        };
+        if base < snapshot_index {
+            // the snapshot was installed, but the log not rewritten yet
+            let covered = ((snapshot_index - base) as usize).min(store.entries.len());
+            let covered_term = match covered {
+                0 => None,
+                n => Some(store.entries[n - 1].term),
+            };
+            store.entries.drain(..covered);
+            if covered_term != Some(snapshot_term) {
+                store.entries.clear();
+            }
+            store.rewrite_log()?;
+        } else {
+            let log_file = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join("log"))
+                .into_diagnostic()?;
+            log_file.set_len(valid_len).into_diagnostic()?;
+            if valid_len == 8 {
+                (&log_file)
+                    .write_all(&base.to_be_bytes())
+                    .into_diagnostic()?;
+                log_file.sync_data().into_diagnostic()?;
+            }
+            store.log_file = Some(log_file);
+        }
+        Ok(store)
+    }
+
+    pub(crate) fn term(&self) -> u64 {
+        self.hard_state.term
+    }
+
+    pub(crate) fn voted_for(&self) -> Option<NodeId> {
+        self.hard_state.voted_for
+    }
+
+    pub(crate) fn set_term_and_vote(&mut self, term: u64, voted_for: Option<NodeId>) -> Result<()> {
+        self.hard_state = HardState { term, voted_for };
+        if let Some(dir) = &self.dir {
+            let tmp_path = dir.join("meta.tmp");
+            let mut file = File::create(&tmp_path).into_diagnostic()?;
+            file.write_all(&rmp_serde::to_vec(&self.hard_state).into_diagnostic()?)
+                .into_diagnostic()?;
+            file.sync_all().into_diagnostic()?;
+            fs::rename(tmp_path, dir.join("meta")).into_diagnostic()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn snapshot_index(&self) -> u64 {
+        self.snapshot_index
+    }
+
+    pub(crate) fn snapshot_term(&self) -> u64 {
+        self.snapshot_term
+    }
+
+    pub(crate) fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub(crate) fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map(|e| e.term)
+            .unwrap_or(self.snapshot_term)
+    }
+
+    /// The term of the entry at `idx`, with index 0 having term 0.
+    /// `None` for entries that do not exist, or are covered by the snapshot except the last one.
+    pub(crate) fn term_at(&self, idx: u64) -> Option<u64> {
+        if idx == self.snapshot_index {
+            Some(self.snapshot_term)
+        } else {
+            self.get(idx).map(|e| e.term)
+        }
+    }
+
+    /// The entry at `idx`, unless it does not exist or is covered by the snapshot.
+    pub(crate) fn get(&self, idx: u64) -> Option<&LogEntry> {
+        if idx <= self.snapshot_index {
+            None
+        } else {
+            self.entries.get((idx - self.snapshot_index - 1) as usize)
+        }
+    }
+
+    /// Entries from `from` onwards, at most `limit` of them.
+    /// `from` must come after the snapshot.
+    pub(crate) fn entries_from(&self, from: u64, limit: usize) -> Vec<LogEntry> {
+        let start = (from.max(self.snapshot_index + 1) - self.snapshot_index - 1) as usize;
+        self.entries
+            .iter()
+            .skip(start)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Append entries durably, returning the index of the last one.
+    pub(crate) fn append(&mut self, new_entries: Vec<LogEntry>) -> Result<u64> {
+        if let Some(file) = &mut self.log_file {
+            let mut offset = file.seek(SeekFrom::End(0)).into_diagnostic()?;
+            let mut buf = vec![];
+            for entry in &new_entries {
+                self.offsets.push(offset);
+                offset += write_frame(&mut buf, entry)?;
+            }
+            file.write_all(&buf).into_diagnostic()?;
+            file.sync_data().into_diagnostic()?;
+        }
+        self.entries.extend(new_entries);
+        Ok(self.last_index())
+    }
+
+    /// Remove the entries from `idx` onwards. `idx` must come after the snapshot.
+    pub(crate) fn truncate_from(&mut self, idx: u64) -> Result<()> {
+        let keep = (idx.max(self.snapshot_index + 1) - self.snapshot_index - 1) as usize;
+        if keep >= self.entries.len() {
+            return Ok(());
+        }
+        if let Some(file) = &mut self.log_file {
+            file.set_len(self.offsets[keep]).into_diagnostic()?;
+            file.sync_data().into_diagnostic()?;
+            self.offsets.truncate(keep);
+        }
+        self.entries.truncate(keep);
+        Ok(())
+    }
+
+    /// Start building a snapshot covering the entries up to `index`, which has `term`.
+    /// Nothing changes in the store until the snapshot is installed.
+    pub(crate) fn new_snapshot(&self, index: u64, term: u64) -> Result<SnapshotBuilder> {
+        let (file, chunks) = match &self.dir {
+            None => (None, SnapshotChunks::Memory(vec![])),
+            Some(dir) => {
+                let path = dir.join(format!("snapshot.{}.tmp", rand::random::<u64>()));
+                let mut file = BufWriter::new(File::create(&path).into_diagnostic()?);
+                file.write_all(&index.to_be_bytes()).into_diagnostic()?;
+                file.write_all(&term.to_be_bytes()).into_diagnostic()?;
+                (Some((path, file, 16)), SnapshotChunks::File(vec![]))
+            }
+        };
+        Ok(SnapshotBuilder {
+            index,
+            term,
+            file,
+            chunks,
+        })
+    }
+
+    /// Replace the snapshot by a newer one, and drop the log entries it covers. If the log
+    /// does not contain the last entry covered by the snapshot, the whole log is dropped.
+    pub(crate) fn install_snapshot(&mut self, mut snapshot: SnapshotBuilder) -> Result<()> {
+        if snapshot.index <= self.snapshot_index {
+            return Ok(());
+        }
+        if let (Some((tmp_path, file, _)), Some(dir)) = (snapshot.file.take(), &self.dir) {
+            let file = file.into_inner().into_diagnostic()?;
+            file.sync_all().into_diagnostic()?;
+            fs::rename(tmp_path, dir.join("snapshot")).into_diagnostic()?;
+        }
+        if self.term_at(snapshot.index) == Some(snapshot.term) {
+            let covered = (snapshot.index - self.snapshot_index) as usize;
+            self.entries.drain(..covered);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        self.snapshot_chunks = std::mem::take(&mut snapshot.chunks);
+        self.rewrite_log()
+    }
+
+    /// Number of chunks in the snapshot.
+    pub(crate) fn snapshot_len(&self) -> usize {
+        match &self.snapshot_chunks {
+            SnapshotChunks::Memory(chunks) => chunks.len(),
+            SnapshotChunks::File(offsets) => offsets.len(),
+        }
+    }
+
+    /// The chunk numbered `n` of the snapshot.
+    pub(crate) fn snapshot_chunk(&self, n: usize) -> Result<Vec<WriteOp>> {
+        match &self.snapshot_chunks {
+            SnapshotChunks::Memory(chunks) => Ok(chunks.get(n).cloned().unwrap_or_default()),
+            SnapshotChunks::File(offsets) => {
+                let (offset, dir) = match (offsets.get(n), &self.dir) {
+                    (Some(offset), Some(dir)) => (*offset, dir),
+                    _ => return Ok(vec![]),
+                };
+                let mut file = File::open(dir.join("snapshot")).into_diagnostic()?;
+                file.seek(SeekFrom::Start(offset)).into_diagnostic()?;
+                match read_frame(&mut BufReader::new(file))? {
+                    Some((chunk, _)) => Ok(chunk),
+                    None => bail!("The Raft snapshot is corrupted"),
+                }
+            }
+        }
+    }
+
+    /// Write the log anew with the entries in memory, which must follow the snapshot.
+    fn rewrite_log(&mut self) -> Result<()> {
+        let dir = match &self.dir {
+            None => return Ok(()),
+            Some(dir) => dir,
+        };
+        let tmp_path = dir.join("log.tmp");
+        let mut buf = self.snapshot_index.to_be_bytes().to_vec();
+        let mut offsets = vec![];
+        for entry in &self.entries {
+            offsets.push(buf.len() as u64);
+            write_frame(&mut buf, entry)?;
+        }
+        let mut file = File::create(&tmp_path).into_diagnostic()?;
+        file.write_all(&buf).into_diagnostic()?;
+        file.sync_all().into_diagnostic()?;
+        fs::rename(&tmp_path, dir.join("log")).into_diagnostic()?;
+        self.log_file = Some(
+            File::options()
+                .write(true)
+                .open(dir.join("log"))
+                .into_diagnostic()?,
+        );
+        self.offsets = offsets;
+        Ok(())
+    }
+}
@@ -0,0 +1,1184 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Embedded clustering: a Raft group replicating the write sets of the local database.
+//!
+//! Only the leader accepts scripts. It runs them as usual, except that the commit of every
+//! write transaction is held back until its write set is committed in the Raft log, and
+//! write transactions are serialized. Followers apply committed write sets in log order.
+//! Scripts sent to followers are forwarded to the leader, and the leader confirms its
+//! leadership with a majority before running any script, so reads are linearizable.
+//!
+//! Every node snapshots its local database once enough entries are applied since its last
+//! snapshot, and drops the log entries the snapshot covers. Followers needing compacted entries
+//! are sent the snapshot of the leader instead. A node restarting restores its snapshot and
+//! replays the log after it. A snapshot may contain the effects of some entries after the last
+//! entry it covers, which is harmless: replaying puts and deletions in order converges to the
+//! right state even if some of them are already applied.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ::log::{error, info};
+use miette::{bail, miette, Report, Result};
+use rand::Rng;
+use serde_json::json;
+
+use cozo::{
+    format_error_as_json, CommitCoordinator, DataValue, DbInstance, ScriptMutability, WriteOp,
+};
+
+use crate::raft::log::{EntryPayload, LogEntry, RaftStore, SnapshotBuilder};
+use crate::raft::transport::{NoResponse, RaftTransport};
+
+pub(crate) mod log;
+pub(crate) mod transport;
+#[cfg(test)]
+mod tests;
+
+pub(crate) type NodeId = u64;
+
+/// Maximum number of entries in a single append request
+const MAX_APPEND_BATCH: usize = 256;
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum RaftRequest {
+    RequestVote {
+        term: u64,
+        candidate: NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    AppendEntries {
+        term: u64,
+        leader: NodeId,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    },
+    /// A chunk of the snapshot of the leader, sent to followers needing compacted entries
+    InstallSnapshot {
+        term: u64,
+        leader: NodeId,
+        last_included_index: u64,
+        last_included_term: u64,
+        /// The number of the chunk, starting from 0
+        chunk: u64,
+        data: Vec<WriteOp>,
+        /// Whether this is the last chunk
+        done: bool,
+    },
+    /// A script forwarded to the leader, with parameters in JSON
+    Script {
+        script: String,
+        params: String,
+        immutable: bool,
+    },
+}
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum RaftResponse {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    Append {
+        term: u64,
+        success: bool,
+        /// On success, the index of the last entry matching the leader's log.
+        /// Otherwise a hint of where the logs may match.
+        last_index: u64,
+    },
+    Snapshot {
+        term: u64,
+        success: bool,
+    },
+    /// Result of a forwarded script in JSON
+    Script(String),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RaftConfig {
+    /// Interval of heartbeats sent by the leader
+    pub(crate) heartbeat_interval: Duration,
+    /// Followers start an election after hearing nothing from the leader for a random time
+    /// between this and twice of this
+    pub(crate) election_timeout: Duration,
+    /// How long to wait for the cluster when committing or reading
+    pub(crate) request_timeout: Duration,
+    /// Snapshot the database and compact the log once this many entries are applied
+    /// after the last snapshot
+    pub(crate) snapshot_threshold: u64,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(50),
+            election_timeout: Duration::from_millis(500),
+            request_timeout: Duration::from_secs(10),
+            snapshot_threshold: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+fn not_leader(leader: Option<NodeId>) -> Report {
+    match leader {
+        Some(leader) => miette!(
+            code = "raft::not_leader",
+            help = format!("The current leader is node {leader}"),
+            "This node is not the leader of the cluster"
+        ),
+        None => miette!(
+            code = "raft::not_leader",
+            "This node is not the leader of the cluster, and no leader is known"
+        ),
+    }
+}
+
+fn indeterminate_write(reason: &str) -> Report {
+    miette!(
+        code = "raft::indeterminate",
+        help = "Check whether the write has taken effect before retrying it",
+        "The result of the write is unknown, as {}: it may or may not have been committed",
+        reason
+    )
+}
+
+fn cluster_timeout(reason: &str) -> Report {
+    miette!(
+        code = "raft::timeout",
+        "Timed out waiting for the cluster: {}",
+        reason
+    )
+}
+
+struct RaftState {
+    role: Role,
+    store: RaftStore,
+    leader: Option<NodeId>,
+    commit_index: u64,
+    last_applied: u64,
+    /// An entry that its proposer is committing to the local database
+    claimed: Option<u64>,
+    election_deadline: Instant,
+    next_index: BTreeMap<NodeId, u64>,
+    match_index: BTreeMap<NodeId, u64>,
+    /// For followers being sent the snapshot, its index and the next chunk to send
+    snapshot_progress: BTreeMap<NodeId, (u64, u64)>,
+    /// A snapshot being received from the leader, with the number of the next chunk
+    receiving: Option<(SnapshotBuilder, u64)>,
+}
+
+pub(crate) struct RaftNode {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    config: RaftConfig,
+    db: DbInstance,
+    transport: Arc<dyn RaftTransport>,
+    state: Mutex<RaftState>,
+    changed: Condvar,
+    stopped: AtomicBool,
+    /// Held while changing the local database, which must follow the order of the log
+    applying: Mutex<()>,
+}
+
+enum ReplicateOutcome {
+    /// The peer acknowledged us as the leader
+    Acked,
+    /// The peer rejected the request or we are not the leader
+    Rejected,
+    Unreachable,
+}
+
+/// What was sent to a follower by [RaftNode::replicate_to].
+enum Replicated {
+    Entries { prev_log_index: u64, n_entries: u64 },
+    Snapshot { index: u64, chunk: u64, done: bool },
+}
+
+/// The write set replacing the content of a database by the snapshot in `store`.
+fn restoring_snapshot(store: &RaftStore) -> Result<Vec<WriteOp>> {
+    let mut ops = vec![WriteOp::delete_all()];
+    for n in 0..store.snapshot_len() {
+        ops.extend(store.snapshot_chunk(n)?);
+    }
+    Ok(ops)
+}
+
+impl RaftNode {
+    /// Start a node with the given `id`, taking over the commits of `db`.
+    /// `peers` are the IDs of the other nodes in the cluster.
+    ///
+    /// The content of `db` is replaced by the snapshot in `store`, if there is one.
+    pub(crate) fn start(
+        id: NodeId,
+        peers: Vec<NodeId>,
+        config: RaftConfig,
+        db: DbInstance,
+        store: RaftStore,
+        transport: Arc<dyn RaftTransport>,
+    ) -> Result<Arc<Self>> {
+        // the database may not be durable, or not have been synced with the snapshot
+        let applied = store.snapshot_index();
+        if applied > 0 {
+            db.apply_write_set(&restoring_snapshot(&store)?)?;
+        }
+        let election_deadline = Instant::now() + random_election_timeout(&config);
+        let node = Arc::new(Self {
+            id,
+            peers,
+            config,
+            db,
+            transport,
+            state: Mutex::new(RaftState {
+                role: Role::Follower,
+                store,
+                leader: None,
+                commit_index: applied,
+                last_applied: applied,
+                claimed: None,
+                election_deadline,
+                next_index: Default::default(),
+                match_index: Default::default(),
+                snapshot_progress: Default::default(),
+                receiving: None,
+            }),
+            changed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            applying: Mutex::new(()),
+        });
+        node.db
+            .set_commit_coordinator(Some(Arc::new(RaftCoordinator(Arc::downgrade(&node)))));
+        {
+            let node = node.clone();
+            thread::spawn(move || node.run_ticker());
+        }
+        {
+            let node = node.clone();
+            thread::spawn(move || node.run_applier());
+        }
+        for peer in node.peers.clone() {
+            let node = node.clone();
+            thread::spawn(move || node.run_replicator(peer));
+        }
+        Ok(node)
+    }
+
+    #[cfg(test)]
+    /// Stop all background activities of the node.
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.db.set_commit_coordinator(None);
+        self.changed.notify_all();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn id(&self) -> NodeId {
+        self.id
+    }
+
+    #[cfg(test)]
+    pub(crate) fn role(&self) -> Role {
+        self.state.lock().unwrap().role
+    }
+
+    pub(crate) fn status(&self) -> serde_json::Value {
+        let st = self.state.lock().unwrap();
+        let role = match st.role {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+        };
+        json!({
+            "ok": true,
+            "id": self.id,
+            "role": role,
+            "term": st.store.term(),
+            "leader": st.leader,
+            "last_index": st.store.last_index(),
+            "snapshot_index": st.store.snapshot_index(),
+            "commit_index": st.commit_index,
+            "last_applied": st.last_applied,
+        })
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    fn majority(&self) -> usize {
+        let n_nodes = self.peers.len() + 1;
+        n_nodes / 2 + 1
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RaftState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Wait on the state for at most until `deadline`, returning `None` if the deadline passed.
+    fn wait_until<'a>(
+        &self,
+        st: MutexGuard<'a, RaftState>,
+        deadline: Instant,
+    ) -> Option<MutexGuard<'a, RaftState>> {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        Some(self.changed.wait_timeout(st, deadline - now).unwrap().0)
+    }
+
+    fn step_down(&self, st: &mut RaftState, term: u64) {
+        if term > st.store.term() {
+            if let Err(err) = st.store.set_term_and_vote(term, None) {
+                error!("Cannot persist Raft state: {}", err);
+            }
+            st.leader = None;
+        }
+        if st.role != Role::Follower {
+            info!("Node {} becomes a follower in term {}", self.id, term);
+        }
+        st.role = Role::Follower;
+        st.claimed = None;
+        st.election_deadline = Instant::now() + random_election_timeout(&self.config);
+        self.changed.notify_all();
+    }
+
+    fn advance_commit(&self, st: &mut RaftState) {
+        let term = st.store.term();
+        let mut n = st.store.last_index();
+        while n > st.commit_index {
+            if st.store.term_at(n) != Some(term) {
+                break;
+            }
+            let replicated = 1 + st.match_index.values().filter(|m| **m >= n).count();
+            if replicated >= self.majority() {
+                st.commit_index = n;
+                self.changed.notify_all();
+                break;
+            }
+            n -= 1;
+        }
+    }
+
+    fn run_ticker(self: Arc<Self>) {
+        while !self.is_stopped() {
+            thread::sleep(Duration::from_millis(10));
+            let should_elect = {
+                let st = self.lock();
+                st.role != Role::Leader && Instant::now() >= st.election_deadline
+            };
+            if should_elect {
+                self.run_election();
+            }
+        }
+    }
+
+    fn run_election(&self) {
+        let (term, req) = {
+            let mut st = self.lock();
+            let term = st.store.term() + 1;
+            if let Err(err) = st.store.set_term_and_vote(term, Some(self.id)) {
+                error!("Cannot persist Raft state: {}", err);
+                return;
+            }
+            st.role = Role::Candidate;
+            st.leader = None;
+            st.election_deadline = Instant::now() + random_election_timeout(&self.config);
+            let req = RaftRequest::RequestVote {
+                term,
+                candidate: self.id,
+                last_log_index: st.store.last_index(),
+                last_log_term: st.store.last_term(),
+            };
+            (term, req)
+        };
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        for peer in self.peers.iter().copied() {
+            let transport = self.transport.clone();
+            let sender = sender.clone();
+            let req = req.clone();
+            thread::spawn(move || {
+                let _ = sender.send(transport.call(peer, req));
+            });
+        }
+        drop(sender);
+
+        let mut votes = 1;
+        let deadline = Instant::now() + self.config.election_timeout;
+        while votes < self.majority() {
+            match receiver.recv_deadline(deadline) {
+                Ok(Ok(RaftResponse::Vote {
+                    term: resp_term,
+                    granted,
+                })) => {
+                    if resp_term > term {
+                        let mut st = self.lock();
+                        self.step_down(&mut st, resp_term);
+                        return;
+                    }
+                    if granted {
+                        votes += 1;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        if votes >= self.majority() {
+            self.become_leader(term);
+        }
+    }
+
+    fn become_leader(&self, term: u64) {
+        let mut st = self.lock();
+        if st.role != Role::Candidate || st.store.term() != term {
+            return;
+        }
+        info!("Node {} becomes the leader in term {}", self.id, term);
+        st.role = Role::Leader;
+        st.leader = Some(self.id);
+        let next = st.store.last_index() + 1;
+        for peer in &self.peers {
+            st.next_index.insert(*peer, next);
+            st.match_index.insert(*peer, 0);
+        }
+        // entries of previous terms only become committed with an entry of the current term
+        if let Err(err) = st.store.append(vec![LogEntry {
+            term,
+            payload: EntryPayload::Noop,
+        }]) {
+            error!("Cannot append to the Raft log: {}", err);
+            self.step_down(&mut st, term);
+            return;
+        }
+        self.advance_commit(&mut st);
+        self.changed.notify_all();
+    }
+
+    fn run_replicator(self: Arc<Self>, peer: NodeId) {
+        while !self.is_stopped() {
+            {
+                let mut st = self.lock();
+                let deadline = Instant::now() + self.config.heartbeat_interval;
+                loop {
+                    let behind = st.role == Role::Leader
+                        && st.next_index.get(&peer).copied().unwrap_or(1) <= st.store.last_index();
+                    if behind || self.is_stopped() {
+                        break;
+                    }
+                    match self.wait_until(st, deadline) {
+                        Some(guard) => st = guard,
+                        None => break,
+                    }
+                }
+            }
+            if let ReplicateOutcome::Unreachable = self.replicate_to(peer) {
+                thread::sleep(self.config.heartbeat_interval);
+            }
+        }
+    }
+
+    /// Send the entries the peer is missing, or a heartbeat if there is none.
+    /// If the entries are compacted, send the next chunk of the snapshot instead.
+    fn replicate_to(&self, peer: NodeId) -> ReplicateOutcome {
+        let (term, sent, req) = {
+            let st = self.lock();
+            if st.role != Role::Leader {
+                return ReplicateOutcome::Rejected;
+            }
+            let term = st.store.term();
+            let next = st.next_index.get(&peer).copied().unwrap_or(1);
+            let snapshot_index = st.store.snapshot_index();
+            if next <= snapshot_index {
+                let chunk = match st.snapshot_progress.get(&peer) {
+                    Some((index, chunk)) if *index == snapshot_index => *chunk,
+                    _ => 0,
+                };
+                let data = match st.store.snapshot_chunk(chunk as usize) {
+                    Ok(data) => data,
+                    Err(err) => {
+                        error!("Cannot read the Raft snapshot: {}", err);
+                        return ReplicateOutcome::Unreachable;
+                    }
+                };
+                let done = chunk + 1 >= st.store.snapshot_len() as u64;
+                let req = RaftRequest::InstallSnapshot {
+                    term,
+                    leader: self.id,
+                    last_included_index: snapshot_index,
+                    last_included_term: st.store.snapshot_term(),
+                    chunk,
+                    data,
+                    done,
+                };
+                let sent = Replicated::Snapshot {
+                    index: snapshot_index,
+                    chunk,
+                    done,
+                };
+                (term, sent, req)
+            } else {
+                let prev_log_index = next - 1;
+                let entries = st.store.entries_from(next, MAX_APPEND_BATCH);
+                let n_entries = entries.len() as u64;
+                let req = RaftRequest::AppendEntries {
+                    term,
+                    leader: self.id,
+                    prev_log_index,
+                    prev_log_term: st.store.term_at(prev_log_index).unwrap_or(0),
+                    entries,
+                    leader_commit: st.commit_index,
+                };
+                let sent = Replicated::Entries {
+                    prev_log_index,
+                    n_entries,
+                };
+                (term, sent, req)
+            }
+        };
+        let resp = match self.transport.call(peer, req) {
+            Ok(resp) => resp,
+            Err(_) => return ReplicateOutcome::Unreachable,
+        };
+        let mut st = self.lock();
+        let resp_term = match resp {
+            RaftResponse::Append { term, .. } | RaftResponse::Snapshot { term, .. } => term,
+            _ => return ReplicateOutcome::Rejected,
+        };
+        if resp_term > st.store.term() {
+            self.step_down(&mut st, resp_term);
+            return ReplicateOutcome::Rejected;
+        }
+        if st.role != Role::Leader || st.store.term() != term {
+            return ReplicateOutcome::Rejected;
+        }
+        match (resp, sent) {
+            (
+                RaftResponse::Append {
+                    success,
+                    last_index,
+                    ..
+                },
+                Replicated::Entries {
+                    prev_log_index,
+                    n_entries,
+                },
+            ) => {
+                if success {
+                    let matched = prev_log_index + n_entries;
+                    let match_index = st.match_index.entry(peer).or_default();
+                    *match_index = (*match_index).max(matched);
+                    let next = *match_index + 1;
+                    st.next_index.insert(peer, next);
+                    self.advance_commit(&mut st);
+                } else {
+                    let next = (last_index + 1).min(prev_log_index).max(1);
+                    st.next_index.insert(peer, next);
+                }
+                self.changed.notify_all();
+                ReplicateOutcome::Acked
+            }
+            (RaftResponse::Snapshot { success, .. }, Replicated::Snapshot { index, chunk, done }) => {
+                if !success {
+                    // start over from the first chunk
+                    st.snapshot_progress.remove(&peer);
+                } else if done {
+                    st.snapshot_progress.remove(&peer);
+                    let match_index = st.match_index.entry(peer).or_default();
+                    *match_index = (*match_index).max(index);
+                    let next = *match_index + 1;
+                    st.next_index.insert(peer, next);
+                    self.advance_commit(&mut st);
+                } else {
+                    st.snapshot_progress.insert(peer, (index, chunk + 1));
+                }
+                self.changed.notify_all();
+                ReplicateOutcome::Acked
+            }
+            _ => ReplicateOutcome::Rejected,
+        }
+    }
+
+    fn run_applier(self: Arc<Self>) {
+        loop {
+            let next = {
+                let mut st = self.lock();
+                loop {
+                    if self.is_stopped() {
+                        return;
+                    }
+                    let next = st.last_applied + 1;
+                    // compacted entries wait for the snapshot to be restored
+                    if next <= st.commit_index
+                        && st.claimed != Some(next)
+                        && next > st.store.snapshot_index()
+                    {
+                        break Some(next);
+                    }
+                    if st.last_applied >= st.store.snapshot_index() + self.config.snapshot_threshold
+                    {
+                        break None;
+                    }
+                    st = self
+                        .changed
+                        .wait_timeout(st, self.config.heartbeat_interval)
+                        .unwrap()
+                        .0;
+                }
+            };
+            let idx = match next {
+                Some(idx) => idx,
+                None => {
+                    if let Err(err) = self.take_snapshot() {
+                        error!("Cannot take a Raft snapshot: {}", err);
+                        thread::sleep(self.config.heartbeat_interval);
+                    }
+                    continue;
+                }
+            };
+            let applying = self.applying.lock().unwrap();
+            let entry = {
+                let st = self.lock();
+                // a snapshot may have been installed in the meantime
+                if st.last_applied + 1 != idx || st.claimed == Some(idx) {
+                    continue;
+                }
+                st.store.get(idx).cloned()
+            };
+            if let Some(LogEntry {
+                payload: EntryPayload::WriteSet(ops),
+                ..
+            }) = &entry
+            {
+                if let Err(err) = self.db.apply_write_set(ops) {
+                    error!("Cannot apply Raft log entry {}: {}", idx, err);
+                    drop(applying);
+                    thread::sleep(self.config.heartbeat_interval);
+                    continue;
+                }
+            }
+            let mut st = self.lock();
+            st.last_applied = st.last_applied.max(idx);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Snapshot the local database and drop the log entries covered by the snapshot.
+    fn take_snapshot(&self) -> Result<()> {
+        let mut snapshot = {
+            let st = self.lock();
+            let index = st.last_applied;
+            st.store
+                .new_snapshot(index, st.store.term_at(index).unwrap_or(0))?
+        };
+        // The database has every entry up to the index applied, and maybe some more,
+        // which are kept in the log.
+        self.db.export_store(&mut |chunk| snapshot.add_chunk(chunk))?;
+        let mut st = self.lock();
+        st.store.install_snapshot(snapshot)?;
+        info!(
+            "Node {} compacted its log up to entry {}",
+            self.id,
+            st.store.snapshot_index()
+        );
+        Ok(())
+    }
+
+    pub(crate) fn handle_request(&self, req: RaftRequest) -> RaftResponse {
+        match req {
+            RaftRequest::RequestVote {
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => self.handle_vote(term, candidate, last_log_index, last_log_term),
+            RaftRequest::AppendEntries {
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => self.handle_append(
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            ),
+            RaftRequest::InstallSnapshot {
+                term,
+                leader,
+                last_included_index,
+                last_included_term,
+                chunk,
+                data,
+                done,
+            } => self.handle_snapshot(
+                term,
+                leader,
+                last_included_index,
+                last_included_term,
+                chunk,
+                data,
+                done,
+            ),
+            RaftRequest::Script {
+                script,
+                params,
+                immutable,
+            } => {
+                let res = match serde_json::from_str(&params) {
+                    Ok(params) => self.run_script_locally(&script, params, immutable),
+                    Err(err) => json!({"ok": false, "message": err.to_string()}),
+                };
+                RaftResponse::Script(res.to_string())
+            }
+        }
+    }
+
+    fn handle_vote(
+        &self,
+        term: u64,
+        candidate: NodeId,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> RaftResponse {
+        let mut st = self.lock();
+        if term > st.store.term() {
+            self.step_down(&mut st, term);
+        }
+        let up_to_date = last_log_term > st.store.last_term()
+            || (last_log_term == st.store.last_term() && last_log_index >= st.store.last_index());
+        let can_vote = match st.store.voted_for() {
+            None => true,
+            Some(voted) => voted == candidate,
+        };
+        let mut granted = term == st.store.term() && can_vote && up_to_date;
+        if granted {
+            match st.store.set_term_and_vote(term, Some(candidate)) {
+                Ok(()) => {
+                    st.election_deadline = Instant::now() + random_election_timeout(&self.config)
+                }
+                Err(err) => {
+                    error!("Cannot persist Raft state: {}", err);
+                    granted = false;
+                }
+            }
+        }
+        RaftResponse::Vote {
+            term: st.store.term(),
+            granted,
+        }
+    }
+
+    fn handle_append(
+        &self,
+        term: u64,
+        leader: NodeId,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    ) -> RaftResponse {
+        let mut st = self.lock();
+        if term < st.store.term() {
+            return RaftResponse::Append {
+                term: st.store.term(),
+                success: false,
+                last_index: st.store.last_index(),
+            };
+        }
+        if term > st.store.term() || st.role != Role::Follower {
+            self.step_down(&mut st, term);
+        }
+        st.leader = Some(leader);
+        st.election_deadline = Instant::now() + random_election_timeout(&self.config);
+
+        // entries covered by the snapshot are committed, so they match those of the leader
+        let mut prev_log_index = prev_log_index;
+        let mut prev_log_term = prev_log_term;
+        let mut entries = entries;
+        let snapshot_index = st.store.snapshot_index();
+        if prev_log_index < snapshot_index {
+            let covered = (snapshot_index - prev_log_index) as usize;
+            if entries.len() < covered {
+                return RaftResponse::Append {
+                    term,
+                    success: true,
+                    last_index: prev_log_index + entries.len() as u64,
+                };
+            }
+            entries.drain(..covered);
+            prev_log_index = snapshot_index;
+            prev_log_term = st.store.snapshot_term();
+        }
+        if st.store.term_at(prev_log_index) != Some(prev_log_term) {
+            return RaftResponse::Append {
+                term,
+                success: false,
+                last_index: st.store.last_index().min(prev_log_index.saturating_sub(1)),
+            };
+        }
+        let n_entries = entries.len() as u64;
+        let mut new_entries = vec![];
+        for (i, entry) in entries.into_iter().enumerate() {
+            let idx = prev_log_index + 1 + i as u64;
+            if new_entries.is_empty() {
+                match st.store.term_at(idx) {
+                    Some(t) if t == entry.term => continue,
+                    Some(_) => {
+                        if let Err(err) = st.store.truncate_from(idx) {
+                            error!("Cannot truncate the Raft log: {}", err);
+                            return RaftResponse::Append {
+                                term,
+                                success: false,
+                                last_index: 0,
+                            };
+                        }
+                    }
+                    None => {}
+                }
+            }
+            new_entries.push(entry);
+        }
+        if !new_entries.is_empty() {
+            if let Err(err) = st.store.append(new_entries) {
+                error!("Cannot append to the Raft log: {}", err);
+                return RaftResponse::Append {
+                    term,
+                    success: false,
+                    last_index: 0,
+                };
+            }
+        }
+        let last_new = prev_log_index + n_entries;
+        if leader_commit > st.commit_index {
+            st.commit_index = leader_commit.min(last_new).max(st.commit_index);
+            self.changed.notify_all();
+        }
+        RaftResponse::Append {
+            term,
+            success: true,
+            last_index: last_new,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_snapshot(
+        &self,
+        term: u64,
+        leader: NodeId,
+        last_included_index: u64,
+        last_included_term: u64,
+        chunk: u64,
+        data: Vec<WriteOp>,
+        done: bool,
+    ) -> RaftResponse {
+        let _applying = self.applying.lock().unwrap();
+        let mut st = self.lock();
+        if term < st.store.term() {
+            return RaftResponse::Snapshot {
+                term: st.store.term(),
+                success: false,
+            };
+        }
+        if term > st.store.term() || st.role != Role::Follower {
+            self.step_down(&mut st, term);
+        }
+        st.leader = Some(leader);
+        st.election_deadline = Instant::now() + random_election_timeout(&self.config);
+
+        let failed = RaftResponse::Snapshot {
+            term,
+            success: false,
+        };
+        if chunk == 0 {
+            match st.store.new_snapshot(last_included_index, last_included_term) {
+                Ok(snapshot) => st.receiving = Some((snapshot, 0)),
+                Err(err) => {
+                    error!("Cannot receive the Raft snapshot: {}", err);
+                    return failed;
+                }
+            }
+        }
+        match &mut st.receiving {
+            Some((snapshot, next))
+                if snapshot.index == last_included_index
+                    && snapshot.term == last_included_term
+                    && *next == chunk =>
+            {
+                if let Err(err) = snapshot.add_chunk(data) {
+                    error!("Cannot receive the Raft snapshot: {}", err);
+                    st.receiving = None;
+                    return failed;
+                }
+                *next += 1;
+            }
+            _ => return failed,
+        }
+        let succeeded = RaftResponse::Snapshot {
+            term,
+            success: true,
+        };
+        let snapshot = match st.receiving.take() {
+            Some((snapshot, _)) if done => snapshot,
+            receiving => {
+                st.receiving = receiving;
+                return succeeded;
+            }
+        };
+        // a retried snapshot may be installed already
+        if last_included_index <= st.last_applied {
+            return succeeded;
+        }
+        let restoring = st
+            .store
+            .install_snapshot(snapshot)
+            .and_then(|_| restoring_snapshot(&st.store));
+        let ops = match restoring {
+            Ok(ops) => ops,
+            Err(err) => {
+                error!("Cannot install the Raft snapshot: {}", err);
+                return failed;
+            }
+        };
+        // A deposed leader may need the lock to abort its write transaction,
+        // which holds up restoring the database.
+        drop(st);
+        if let Err(err) = self.db.apply_write_set(&ops) {
+            error!("Cannot restore the Raft snapshot: {}", err);
+            return failed;
+        }
+        let mut st = self.lock();
+        st.commit_index = st.commit_index.max(last_included_index);
+        st.last_applied = st.last_applied.max(last_included_index);
+        self.changed.notify_all();
+        info!(
+            "Node {} installed the snapshot up to entry {} from the leader",
+            self.id, last_included_index
+        );
+        succeeded
+    }
+
+    /// Wait until this node is the leader with everything committed applied.
+    fn wait_caught_up(&self) -> Result<MutexGuard<'_, RaftState>> {
+        let mut st = self.lock();
+        let deadline = Instant::now() + self.config.request_timeout;
+        loop {
+            if st.role != Role::Leader {
+                bail!(not_leader(st.leader));
+            }
+            let term = st.store.term();
+            if st.store.term_at(st.commit_index) == Some(term) && st.last_applied == st.commit_index
+            {
+                return Ok(st);
+            }
+            st = match self.wait_until(st, deadline) {
+                Some(st) => st,
+                None => bail!(cluster_timeout("the leader cannot catch up")),
+            }
+        }
+    }
+
+    /// Confirm the leadership with a majority, then wait until every entry committed
+    /// before the call is applied locally.
+    pub(crate) fn read_barrier(&self) -> Result<()> {
+        let read_index = self.wait_caught_up()?.commit_index;
+        let acks = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .peers
+                .iter()
+                .map(|peer| s.spawn(move || self.replicate_to(*peer)))
+                .collect();
+            1 + handles
+                .into_iter()
+                .filter_map(|h| h.join().ok())
+                .filter(|o| matches!(o, ReplicateOutcome::Acked))
+                .count()
+        });
+        if acks < self.majority() {
+            bail!(cluster_timeout("cannot reach a majority of the cluster"));
+        }
+        let mut st = self.lock();
+        let deadline = Instant::now() + self.config.request_timeout;
+        while st.last_applied < read_index {
+            st = match self.wait_until(st, deadline) {
+                Some(st) => st,
+                None => bail!(cluster_timeout("reads cannot catch up")),
+            }
+        }
+        Ok(())
+    }
+
+    fn commit_write_set(
+        &self,
+        ops: &[WriteOp],
+        local_commit: &mut dyn FnMut() -> Result<()>,
+    ) -> Result<()> {
+        let mut st = self.lock();
+        if st.role != Role::Leader {
+            bail!(not_leader(st.leader));
+        }
+        let term = st.store.term();
+        let idx = st.store.append(vec![LogEntry {
+            term,
+            payload: EntryPayload::WriteSet(ops.to_vec()),
+        }])?;
+        st.claimed = Some(idx);
+        self.advance_commit(&mut st);
+        self.changed.notify_all();
+
+        // From now on, the entry may be committed by this node or by a later leader even if
+        // we give up waiting, so failures must not look like the write was not made.
+        let deadline = Instant::now() + self.config.request_timeout;
+        loop {
+            if st.store.term() != term || st.store.term_at(idx) != Some(term) {
+                st.claimed = None;
+                self.changed.notify_all();
+                bail!(indeterminate_write(
+                    "this node lost the leadership before the write was committed"
+                ));
+            }
+            if st.commit_index >= idx && st.last_applied + 1 == idx {
+                break;
+            }
+            st = match self.wait_until(st, deadline) {
+                Some(st) => st,
+                None => {
+                    // the entry may still be committed later, when the applier will take over
+                    let mut st = self.lock();
+                    st.claimed = None;
+                    self.changed.notify_all();
+                    bail!(indeterminate_write("the write was not committed in time"))
+                }
+            }
+        }
+        drop(st);
+
+        let _applying = self.applying.lock().unwrap();
+        let st = self.lock();
+        if st.claimed != Some(idx) {
+            // we lost the leadership while waiting, and the entry may have been applied
+            // by the applier or replaced by a snapshot
+            bail!(indeterminate_write(
+                "this node lost the leadership while applying the write"
+            ));
+        }
+        drop(st);
+        let res = local_commit();
+        let mut st = self.lock();
+        st.claimed = None;
+        if res.is_ok() {
+            st.last_applied = idx;
+        }
+        self.changed.notify_all();
+        res
+    }
+
+    /// Run a script on the leader, forwarding it if this node is not the leader.
+    pub(crate) fn run_script(
+        &self,
+        script: &str,
+        params: BTreeMap<String, serde_json::Value>,
+        immutable: bool,
+    ) -> serde_json::Value {
+        let (role, leader) = {
+            let st = self.lock();
+            (st.role, st.leader)
+        };
+        if role == Role::Leader {
+            return self.run_script_locally(script, params, immutable);
+        }
+        let leader = match leader {
+            Some(leader) => leader,
+            None => return format_error_as_json(not_leader(None), None),
+        };
+        let req = RaftRequest::Script {
+            script: script.to_string(),
+            params: serde_json::to_string(&params).unwrap(),
+            immutable,
+        };
+        match self.transport.call(leader, req) {
+            Ok(RaftResponse::Script(res)) => serde_json::from_str(&res)
+                .unwrap_or_else(|err| json!({"ok": false, "message": err.to_string()})),
+            Ok(_) => json!({"ok": false, "message": "unexpected response from the leader"}),
+            Err(err) if !immutable && err.downcast_ref::<NoResponse>().is_some() => {
+                format_error_as_json(
+                    indeterminate_write(&format!(
+                        "it was forwarded to the leader (node {leader}) but no response arrived"
+                    )),
+                    None,
+                )
+            }
+            Err(err) => format_error_as_json(err, None),
+        }
+    }
+
+    fn run_script_locally(
+        &self,
+        script: &str,
+        params: BTreeMap<String, serde_json::Value>,
+        immutable: bool,
+    ) -> serde_json::Value {
+        if let Err(err) = self.read_barrier() {
+            return format_error_as_json(err, None);
+        }
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k, DataValue::from(v)))
+            .collect();
+        let mutability = if immutable {
+            ScriptMutability::Immutable
+        } else {
+            ScriptMutability::Mutable
+        };
+        self.db.run_script_fold_err(script, params, mutability)
+    }
+}
+
+fn random_election_timeout(config: &RaftConfig) -> Duration {
+    let base = config.election_timeout.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(base..2 * base))
+}
+
+struct RaftCoordinator(Weak<RaftNode>);
+
+impl RaftCoordinator {
+    fn node(&self) -> Result<Arc<RaftNode>> {
+        match self.0.upgrade() {
+            Some(node) => Ok(node),
+            None => bail!("The Raft node has stopped"),
+        }
+    }
+}
+
+impl CommitCoordinator for RaftCoordinator {
+    fn before_write(&self) -> Result<()> {
+        self.node()?.wait_caught_up().map(|_| ())
+    }
+
+    fn commit(&self, ops: &[WriteOp], local_commit: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        self.node()?.commit_write_set(ops, local_commit)
+    }
+}
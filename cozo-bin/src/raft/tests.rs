@@ -0,0 +1,377 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use miette::{bail, Report, Result};
+use serde_json::json;
+
+use cozo::{DbInstance, ScriptMutability, WriteOp};
+
+use crate::raft::log::{EntryPayload, LogEntry, RaftStore};
+use crate::raft::transport::{NoResponse, RaftTransport};
+use crate::raft::{NodeId, RaftConfig, RaftNode, RaftRequest, RaftResponse, Role};
+
+/// Nodes living in the same process, with simulated network partitions.
+#[derive(Default)]
+struct InProcessNetwork {
+    nodes: RwLock<BTreeMap<NodeId, Weak<RaftNode>>>,
+    isolated: RwLock<BTreeSet<NodeId>>,
+    /// Nodes handling forwarded scripts whose responses are lost
+    unresponsive: RwLock<BTreeSet<NodeId>>,
+    /// Whether acknowledgements of log entries are lost, while heartbeats still get through
+    acks_lost: RwLock<bool>,
+}
+
+struct InProcessTransport {
+    network: Arc<InProcessNetwork>,
+    from: NodeId,
+}
+
+impl RaftTransport for InProcessTransport {
+    fn call(&self, target: NodeId, req: RaftRequest) -> Result<RaftResponse> {
+        {
+            let isolated = self.network.isolated.read().unwrap();
+            if isolated.contains(&self.from) || isolated.contains(&target) {
+                bail!("node {} is unreachable", target);
+            }
+        }
+        let node = self.network.nodes.read().unwrap().get(&target).cloned();
+        let node = match node.and_then(|n| n.upgrade()) {
+            Some(node) => node,
+            None => bail!("node {} is unreachable", target),
+        };
+        let forwarded = matches!(req, RaftRequest::Script { .. });
+        let has_entries =
+            matches!(&req, RaftRequest::AppendEntries { entries, .. } if !entries.is_empty());
+        let resp = node.handle_request(req);
+        if forwarded && self.network.unresponsive.read().unwrap().contains(&target) {
+            return Err(Report::new(NoResponse::new(target, "response lost")));
+        }
+        if has_entries && *self.network.acks_lost.read().unwrap() {
+            bail!("the acknowledgement of node {} is lost", target);
+        }
+        Ok(resp)
+    }
+}
+
+struct Cluster {
+    network: Arc<InProcessNetwork>,
+    nodes: Vec<Arc<RaftNode>>,
+}
+
+impl Cluster {
+    fn start(n: u64) -> Self {
+        Self::start_compacting(n, 10000)
+    }
+
+    /// Start a cluster taking snapshots every `snapshot_threshold` entries.
+    fn start_compacting(n: u64, snapshot_threshold: u64) -> Self {
+        let network = Arc::new(InProcessNetwork::default());
+        let config = RaftConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            election_timeout: Duration::from_millis(200),
+            request_timeout: Duration::from_secs(5),
+            snapshot_threshold,
+        };
+        let nodes: Vec<_> = (1..=n)
+            .map(|id| {
+                let node = RaftNode::start(
+                    id,
+                    (1..=n).filter(|p| *p != id).collect(),
+                    config.clone(),
+                    DbInstance::new("mem", "", "").unwrap(),
+                    RaftStore::in_memory(),
+                    Arc::new(InProcessTransport {
+                        network: network.clone(),
+                        from: id,
+                    }),
+                )
+                .unwrap();
+                network
+                    .nodes
+                    .write()
+                    .unwrap()
+                    .insert(id, Arc::downgrade(&node));
+                node
+            })
+            .collect();
+        Self { network, nodes }
+    }
+
+    fn set_isolated(&self, id: NodeId, isolated: bool) {
+        let mut nodes = self.network.isolated.write().unwrap();
+        if isolated {
+            nodes.insert(id);
+        } else {
+            nodes.remove(&id);
+        }
+    }
+
+    fn wait_for_leader(&self, excluding: Option<NodeId>) -> Arc<RaftNode> {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while Instant::now() < deadline {
+            let leaders: Vec<_> = self
+                .nodes
+                .iter()
+                .filter(|n| Some(n.id()) != excluding && n.role() == Role::Leader)
+                .collect();
+            if leaders.len() == 1 {
+                return leaders[0].clone();
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("no leader elected")
+    }
+
+    fn follower(&self, leader: &RaftNode) -> Arc<RaftNode> {
+        self.nodes
+            .iter()
+            .find(|n| n.id() != leader.id())
+            .unwrap()
+            .clone()
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            node.stop();
+        }
+    }
+}
+
+/// Wait until the local database of `node` returns `expected` for `script`.
+fn wait_for_rows(node: &RaftNode, script: &str, expected: serde_json::Value) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let res = node
+            .db
+            .run_script_fold_err(script, Default::default(), ScriptMutability::Immutable);
+        if res["rows"] == expected {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "node {} has {}, expecting {}",
+            node.id(),
+            res,
+            expected
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn replicate_and_forward() {
+    let cluster = Cluster::start(3);
+    let leader = cluster.wait_for_leader(None);
+    let res = leader.run_script(":create a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+    let res = leader.run_script("?[x] <- [[1]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x]", json!([[1]]));
+    }
+
+    // writes and reads sent to followers are run on the leader
+    let follower = cluster.follower(&leader);
+    let res = follower.run_script(
+        "?[x] <- [[$x]] :put a {x}",
+        BTreeMap::from([("x".to_string(), json!(2))]),
+        false,
+    );
+    assert_eq!(res["ok"], json!(true), "{}", res);
+    let res = follower.run_script("?[x] := *a[x]", Default::default(), true);
+    assert_eq!(res["rows"], json!([[1], [2]]), "{}", res);
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x]", json!([[1], [2]]));
+    }
+
+    // immutable scripts cannot write, even when forwarded
+    let res = follower.run_script("?[x] <- [[3]] :put a {x}", Default::default(), true);
+    assert_eq!(res["ok"], json!(false), "{}", res);
+}
+
+#[test]
+fn leader_failover() {
+    let cluster = Cluster::start(3);
+    let old_leader = cluster.wait_for_leader(None);
+    let res = old_leader.run_script(":create a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+    let res = old_leader.run_script("?[x] <- [[1]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+
+    cluster.set_isolated(old_leader.id(), true);
+    // a leader cut off from the majority cannot serve reads or writes
+    let res = old_leader.run_script("?[x] := *a[x]", Default::default(), true);
+    assert_eq!(res["ok"], json!(false), "{}", res);
+    let res = old_leader.run_script("?[x] <- [[10]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(false), "{}", res);
+
+    let new_leader = cluster.wait_for_leader(Some(old_leader.id()));
+    let res = new_leader.run_script("?[x] <- [[2]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+
+    // the old leader steps down and catches up when it rejoins
+    cluster.set_isolated(old_leader.id(), false);
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x]", json!([[1], [2]]));
+    }
+}
+
+#[test]
+fn persistent_log() {
+    let dir = std::env::temp_dir().join(format!("cozo-raft-test-{}", rand::random::<u64>()));
+    let entries = vec![
+        LogEntry {
+            term: 1,
+            payload: EntryPayload::Noop,
+        },
+        LogEntry {
+            term: 1,
+            payload: EntryPayload::WriteSet(vec![WriteOp::Put(vec![1, 2], vec![3])]),
+        },
+        LogEntry {
+            term: 2,
+            payload: EntryPayload::Noop,
+        },
+    ];
+    {
+        let mut store = RaftStore::open(&dir).unwrap();
+        store.set_term_and_vote(2, Some(3)).unwrap();
+        assert_eq!(store.append(entries.clone()).unwrap(), 3);
+        store.truncate_from(3).unwrap();
+        assert_eq!(
+            store
+                .append(vec![LogEntry {
+                    term: 2,
+                    payload: EntryPayload::Noop
+                }])
+                .unwrap(),
+            3
+        );
+    }
+    {
+        let mut store = RaftStore::open(&dir).unwrap();
+        assert_eq!(store.term(), 2);
+        assert_eq!(store.voted_for(), Some(3));
+        assert_eq!(store.last_index(), 3);
+        assert_eq!(store.term_at(0), Some(0));
+        assert_eq!(store.entries_from(1, 10), entries);
+
+        let mut snapshot = store.new_snapshot(2, 1).unwrap();
+        snapshot
+            .add_chunk(vec![WriteOp::Put(vec![1], vec![1])])
+            .unwrap();
+        snapshot
+            .add_chunk(vec![WriteOp::Put(vec![2], vec![2])])
+            .unwrap();
+        store.install_snapshot(snapshot).unwrap();
+    }
+    // the log only keeps the entries after the snapshot
+    let store = RaftStore::open(&dir).unwrap();
+    assert_eq!(store.snapshot_index(), 2);
+    assert_eq!(store.snapshot_term(), 1);
+    assert_eq!(store.last_index(), 3);
+    assert_eq!(store.term_at(2), Some(1));
+    assert_eq!(store.get(2), None);
+    assert_eq!(store.entries_from(3, 10), entries[2..]);
+    assert_eq!(store.snapshot_len(), 2);
+    assert_eq!(
+        store.snapshot_chunk(1).unwrap(),
+        vec![WriteOp::Put(vec![2], vec![2])]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn snapshot_catch_up() {
+    let cluster = Cluster::start_compacting(3, 5);
+    let leader = cluster.wait_for_leader(None);
+    let res = leader.run_script(":create a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+
+    // the leader compacts its log while a follower is away
+    let follower = cluster.follower(&leader);
+    cluster.set_isolated(follower.id(), true);
+    for x in 0..10 {
+        let res = leader.run_script(
+            "?[x] <- [[$x]] :put a {x}",
+            BTreeMap::from([("x".to_string(), json!(x))]),
+            false,
+        );
+        assert_eq!(res["ok"], json!(true), "{}", res);
+    }
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while leader.lock().store.snapshot_index() == 0 {
+        assert!(
+            Instant::now() < deadline,
+            "the leader did not compact its log"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    // the returning follower receives the snapshot instead of the log
+    cluster.set_isolated(follower.id(), false);
+    let expected = json!((0..10).map(|x| [x]).collect::<Vec<_>>());
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x] :order x", expected.clone());
+    }
+    assert!(follower.lock().store.snapshot_index() > 0);
+}
+
+#[test]
+fn forwarded_write_without_response() {
+    let cluster = Cluster::start(3);
+    let leader = cluster.wait_for_leader(None);
+    let res = leader.run_script(":create a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+
+    cluster
+        .network
+        .unresponsive
+        .write()
+        .unwrap()
+        .insert(leader.id());
+    let follower = cluster.follower(&leader);
+    let res = follower.run_script("?[x] <- [[1]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(false), "{}", res);
+    assert_eq!(res["code"], json!("raft::indeterminate"), "{}", res);
+    // a failed read is not indeterminate
+    let res = follower.run_script("?[x] := *a[x]", Default::default(), true);
+    assert_ne!(res["code"], json!("raft::indeterminate"), "{}", res);
+
+    // the write did go through
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x]", json!([[1]]));
+    }
+}
+
+#[test]
+fn uncommitted_write_is_indeterminate() {
+    let cluster = Cluster::start(3);
+    let leader = cluster.wait_for_leader(None);
+    let res = leader.run_script(":create a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(true), "{}", res);
+
+    // the followers receive the write, but the leader cannot tell in time
+    *cluster.network.acks_lost.write().unwrap() = true;
+    let res = leader.run_script("?[x] <- [[1]] :put a {x}", Default::default(), false);
+    assert_eq!(res["ok"], json!(false), "{}", res);
+    assert_eq!(res["code"], json!("raft::indeterminate"), "{}", res);
+
+    // the write is committed once the network recovers
+    *cluster.network.acks_lost.write().unwrap() = false;
+    for node in &cluster.nodes {
+        wait_for_rows(node, "?[x] := *a[x]", json!([[1]]));
+    }
+}
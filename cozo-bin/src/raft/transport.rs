@@ -0,0 +1,171 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Transports delivering requests between Raft nodes.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+
+use crate::raft::{NodeId, RaftNode, RaftRequest, RaftResponse};
+
+/// Delivers requests to other nodes of the cluster and waits for their responses.
+pub(crate) trait RaftTransport: Send + Sync {
+    fn call(&self, target: NodeId, req: RaftRequest) -> Result<RaftResponse>;
+}
+
+/// A request was sent, but no response arrived: the target may or may not have handled it.
+#[derive(Debug)]
+pub(crate) struct NoResponse {
+    pub(crate) target: NodeId,
+    reason: String,
+}
+
+impl NoResponse {
+    pub(crate) fn new(target: NodeId, reason: impl Display) -> Self {
+        Self {
+            target,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for NoResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no response from node {}: {}", self.target, self.reason)
+    }
+}
+
+impl std::error::Error for NoResponse {}
+
+impl Diagnostic for NoResponse {}
+
+/// Timeout of sending requests, and of waiting for the responses to the requests
+/// of the Raft protocol itself
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How much longer than the request timeout of the cluster to wait for the result
+/// of a forwarded script, which the leader may spend running the script
+const FORWARD_TIMEOUT_MARGIN: Duration = Duration::from_secs(5);
+
+/// A transport over TCP: each request and response is sent as its length (`u32`, big-endian)
+/// followed by its encoded bytes. Connections are kept open and reused.
+pub(crate) struct TcpTransport {
+    addrs: BTreeMap<NodeId, String>,
+    connections: Mutex<BTreeMap<NodeId, TcpStream>>,
+    forward_timeout: Duration,
+}
+
+impl TcpTransport {
+    /// `request_timeout` is that of the cluster, see [RaftConfig](crate::raft::RaftConfig).
+    pub(crate) fn new(addrs: BTreeMap<NodeId, String>, request_timeout: Duration) -> Self {
+        Self {
+            addrs,
+            connections: Default::default(),
+            forward_timeout: request_timeout + FORWARD_TIMEOUT_MARGIN,
+        }
+    }
+
+    fn connect(&self, target: NodeId) -> Result<TcpStream> {
+        if let Some(stream) = self.connections.lock().unwrap().remove(&target) {
+            return Ok(stream);
+        }
+        let addr = match self.addrs.get(&target) {
+            Some(addr) => addr,
+            None => bail!("unknown node {}", target),
+        };
+        let stream = TcpStream::connect(addr).into_diagnostic()?;
+        stream.set_write_timeout(Some(TCP_TIMEOUT)).into_diagnostic()?;
+        stream.set_nodelay(true).into_diagnostic()?;
+        Ok(stream)
+    }
+}
+
+impl RaftTransport for TcpTransport {
+    fn call(&self, target: NodeId, req: RaftRequest) -> Result<RaftResponse> {
+        let mut stream = self.connect(target)?;
+        let timeout = match &req {
+            RaftRequest::Script { .. } => self.forward_timeout,
+            _ => TCP_TIMEOUT,
+        };
+        stream.set_read_timeout(Some(timeout)).into_diagnostic()?;
+        write_frame(&mut stream, &req)?;
+        let resp = read_frame(&mut stream).map_err(|err| NoResponse::new(target, err))?;
+        match resp {
+            Some(resp) => {
+                // only return healthy connections to the pool
+                self.connections.lock().unwrap().insert(target, stream);
+                Ok(resp)
+            }
+            None => Err(NoResponse::new(target, "the connection is closed").into()),
+        }
+    }
+}
+
+fn write_frame<T: serde::Serialize>(out: &mut impl Write, msg: &T) -> Result<()> {
+    let bytes = rmp_serde::to_vec(msg).into_diagnostic()?;
+    let mut out = BufWriter::new(out);
+    out.write_all(&(bytes.len() as u32).to_be_bytes())
+        .into_diagnostic()?;
+    out.write_all(&bytes).into_diagnostic()?;
+    out.flush().into_diagnostic()
+}
+
+fn read_frame<T: serde::de::DeserializeOwned>(rdr: &mut impl Read) -> Result<Option<T>> {
+    let mut len_bytes = [0u8; 4];
+    match rdr.read_exact(&mut len_bytes) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).into_diagnostic(),
+    }
+    let mut bytes = vec![0u8; u32::from_be_bytes(len_bytes) as usize];
+    rdr.read_exact(&mut bytes).into_diagnostic()?;
+    rmp_serde::from_slice(&bytes).into_diagnostic().map(Some)
+}
+
+/// Serve requests from other nodes arriving at `addr` in the background.
+pub(crate) fn serve_tcp(node: Arc<RaftNode>, addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr).into_diagnostic()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Failed to accept Raft connection: {}", err);
+                    continue;
+                }
+            };
+            let node = node.clone();
+            thread::spawn(move || {
+                let mut rdr = BufReader::new(&stream);
+                loop {
+                    let req = match read_frame(&mut rdr) {
+                        Ok(Some(req)) => req,
+                        Ok(None) => break,
+                        Err(err) => {
+                            warn!("Bad Raft request: {}", err);
+                            break;
+                        }
+                    };
+                    let resp = node.handle_request(req);
+                    if let Err(err) = write_frame(&mut &stream, &resp) {
+                        warn!("Failed to send Raft response: {}", err);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
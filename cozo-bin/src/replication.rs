@@ -0,0 +1,103 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! TCP transport for replication: each message is sent as its length (`u32`, big-endian)
+//! followed by its encoded bytes.
+
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use miette::{IntoDiagnostic, Result};
+
+use cozo::{DbInstance, ReplicationMessage, ReplicationReceiver, ReplicationSender};
+
+/// A replica that does not accept data for this long is detached
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct TcpReplicationSender(BufWriter<TcpStream>);
+
+impl ReplicationSender for TcpReplicationSender {
+    fn send(&mut self, msg: &ReplicationMessage) -> Result<()> {
+        let bytes = msg.to_bytes();
+        self.0
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .into_diagnostic()?;
+        self.0.write_all(&bytes).into_diagnostic()?;
+        self.0.flush().into_diagnostic()
+    }
+}
+
+struct TcpReplicationReceiver(BufReader<TcpStream>);
+
+impl ReplicationReceiver for TcpReplicationReceiver {
+    fn recv(&mut self) -> Result<Option<ReplicationMessage>> {
+        let mut len_bytes = [0u8; 4];
+        match self.0.read_exact(&mut len_bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err).into_diagnostic(),
+        }
+        let mut bytes = vec![0u8; u32::from_be_bytes(len_bytes) as usize];
+        self.0.read_exact(&mut bytes).into_diagnostic()?;
+        ReplicationMessage::from_bytes(&bytes).map(Some)
+    }
+}
+
+/// Make `db` a primary and accept replicas connecting to `addr` in the background.
+pub(crate) fn serve_replicas(db: DbInstance, addr: &str) -> Result<()> {
+    db.enable_replication()?;
+    let listener = TcpListener::bind(addr).into_diagnostic()?;
+    info!("Accepting replicas at {}", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("Failed to accept replica: {}", err);
+                    continue;
+                }
+            };
+            let peer = stream
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            if let Err(err) = stream.set_write_timeout(Some(SEND_TIMEOUT)) {
+                warn!("Failed to set up replica {}: {}", peer, err);
+                continue;
+            }
+            // the snapshot is sent in the attaching thread, do not hold up the next replica
+            let db = db.clone();
+            thread::spawn(move || {
+                let sender = TcpReplicationSender(BufWriter::new(stream));
+                match db.attach_replica(Box::new(sender)) {
+                    Ok(id) => info!("Replica {} at {} attached", id, peer),
+                    Err(err) => warn!("Failed to attach replica at {}: {}", peer, err),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Follow the primary at `addr` as a read replica in the background.
+pub(crate) fn follow_primary(db: DbInstance, addr: &str) -> Result<()> {
+    let stream = TcpStream::connect(addr).into_diagnostic()?;
+    info!("Replicating from the primary at {}", addr);
+    let addr = addr.to_string();
+    thread::spawn(move || {
+        let mut receiver = TcpReplicationReceiver(BufReader::new(stream));
+        match db.run_replica(&mut receiver) {
+            Ok(()) => warn!("The primary at {} closed the replication stream", addr),
+            Err(err) => error!("Replication from {} failed: {}", addr, err),
+        }
+    });
+    Ok(())
+}
@@ -37,6 +37,11 @@ use tower_http::cors::{Any, CorsLayer};
 
 use cozo::{DataValue, DbInstance, format_error_as_json, MultiTransaction, NamedRows, ScriptMutability, SimpleFixedRule};
 
+use crate::raft::log::RaftStore;
+use crate::raft::transport::{serve_tcp, TcpTransport};
+use crate::raft::{RaftConfig, RaftNode};
+use crate::replication::{follow_primary, serve_replicas};
+
 #[derive(Args, Debug)]
 pub(crate) struct ServerArgs {
     /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
@@ -66,6 +71,25 @@ pub(crate) struct ServerArgs {
     /// When set, the content of the named table will be used as a token table
     #[clap(long)]
     token_table: Option<String>,
+
+    /// Make the database a replication primary accepting replicas at this address.
+    /// The replication stream is not authenticated, only bind it to trusted networks.
+    #[clap(long, conflicts_with = "replica_of")]
+    replication_bind: Option<String>,
+
+    /// Follow the primary at this address as a read replica
+    #[clap(long)]
+    replica_of: Option<String>,
+
+    /// Members of a Raft cluster this server is part of, as comma-separated `id=address` pairs,
+    /// where the addresses are for the traffic between nodes. Requires `--node-id`.
+    /// The traffic is not authenticated, only bind it to trusted networks.
+    #[clap(long, requires = "node_id", conflicts_with_all = ["replication_bind", "replica_of"])]
+    cluster: Option<String>,
+
+    /// ID of this server in the Raft cluster
+    #[clap(long, requires = "cluster")]
+    node_id: Option<u64>,
 }
 
 #[derive(Clone)]
@@ -75,6 +99,18 @@ struct DbState {
     rule_counter: Arc<AtomicU32>,
     tx_counter: Arc<AtomicU32>,
     txs: Arc<Mutex<BTreeMap<u32, Arc<MultiTransaction>>>>,
+    raft: Option<Arc<RaftNode>>,
+}
+
+impl DbState {
+    /// In a cluster, only the leader serves reads outside scripts, and only after confirming
+    /// its leadership, as followers and deposed leaders may hold stale data.
+    fn read_barrier(&self) -> miette::Result<()> {
+        match &self.raft {
+            Some(node) => node.read_barrier(),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -188,6 +224,44 @@ impl AsyncAuthorizeRequest<Body> for MyAuth
 #[test]
 fn x() {}
 
+fn start_raft_node(
+    path: &str,
+    cluster: &str,
+    node_id: u64,
+    db: &DbInstance,
+) -> miette::Result<Arc<RaftNode>> {
+    let mut addrs = BTreeMap::new();
+    for member in cluster.split(',') {
+        let (id, addr) = match member.trim().split_once('=') {
+            Some(pair) => pair,
+            None => miette::bail!("bad cluster member '{}', expect `id=address`", member),
+        };
+        let id: u64 = id
+            .parse()
+            .map_err(|_| miette!("bad node id '{}'", id))?;
+        addrs.insert(id, addr.to_string());
+    }
+    let own_addr = match addrs.remove(&node_id) {
+        Some(addr) => addr,
+        None => miette::bail!("node {} is not a member of the cluster", node_id),
+    };
+    let store = RaftStore::open(format!("{path}.raft"))?;
+    let peers = addrs.keys().copied().collect();
+    let config = RaftConfig::default();
+    let transport = Arc::new(TcpTransport::new(addrs, config.request_timeout));
+    let node = RaftNode::start(
+        node_id,
+        peers,
+        config,
+        db.clone(),
+        store,
+        transport,
+    )?;
+    serve_tcp(node.clone(), &own_addr)?;
+    info!("Node {} of the cluster serving at {}", node_id, own_addr);
+    Ok(node)
+}
+
 pub(crate) async fn server_main(args: ServerArgs) {
     let db = DbInstance::new(&args.engine, &args.path, &args.config).unwrap();
     if let Some(p) = &args.restore {
@@ -197,6 +271,31 @@ pub(crate) async fn server_main(args: ServerArgs) {
             panic!()
         }
     }
+    if let Some(addr) = &args.replication_bind {
+        if let Err(err) = serve_replicas(db.clone(), addr) {
+            error!("{}", err);
+            error!("Cannot start replication, terminate");
+            panic!()
+        }
+    }
+    if let Some(addr) = &args.replica_of {
+        if let Err(err) = follow_primary(db.clone(), addr) {
+            error!("{}", err);
+            error!("Cannot connect to the primary, terminate");
+            panic!()
+        }
+    }
+    let raft = match (&args.cluster, args.node_id) {
+        (Some(cluster), Some(node_id)) => match start_raft_node(&args.path, cluster, node_id, &db) {
+            Ok(node) => Some(node),
+            Err(err) => {
+                error!("{}", err);
+                error!("Cannot join the cluster, terminate");
+                panic!()
+            }
+        },
+        _ => None,
+    };
 
     let skip_auth = args.bind == "127.0.0.1";
 
@@ -234,6 +333,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
         rule_counter: Default::default(),
         tx_counter: Default::default(),
         txs: Default::default(),
+        raft,
     };
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
@@ -242,6 +342,7 @@ pub(crate) async fn server_main(args: ServerArgs) {
 
     let app = Router::new()
         .route("/text-query", post(text_query))
+        .route("/cluster", get(cluster_status))
         .route("/export/:relations", get(export_relations))
         .route("/import", put(import_relations))
         .route("/backup", post(backup))
@@ -291,6 +392,19 @@ async fn start_transact(
     State(st): State<DbState>,
     Query(payload): Query<StartTransactPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    if st.raft.is_some() {
+        let barrier_st = st.clone();
+        match spawn_blocking(move || barrier_st.read_barrier()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format_error_as_json(err, None).into(),
+                )
+            }
+            Err(err) => return internal_error(err),
+        }
+    }
     let tx = st.db.multi_transaction(payload.write);
     let id = st.tx_counter.fetch_add(1, Ordering::SeqCst);
     st.txs.lock().unwrap().insert(id, Arc::new(tx));
@@ -367,15 +481,24 @@ async fn text_query(
     State(st): State<DbState>,
     Json(payload): Json<QueryPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
+    let immutable = match mutability {
+        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
+        ScriptMutability::Immutable => true,
+    };
+    if let Some(node) = st.raft.clone() {
+        let result =
+            spawn_blocking(move || node.run_script(&payload.script, payload.params, immutable))
+                .await;
+        return match result {
+            Ok(res) => wrap_json(res),
+            Err(err) => internal_error(err),
+        };
+    }
     let params = payload
         .params
         .into_iter()
         .map(|(k, v)| (k, DataValue::from(v)))
         .collect();
-    let immutable = match mutability {
-        ScriptMutability::Mutable => payload.immutable.unwrap_or(false),
-        ScriptMutability::Immutable => true,
-    };
     let result = spawn_blocking(move || {
         st.db.run_script_fold_err(
             &payload.script,
@@ -394,6 +517,13 @@ async fn text_query(
     }
 }
 
+async fn cluster_status(State(st): State<DbState>) -> (StatusCode, Json<serde_json::Value>) {
+    match &st.raft {
+        Some(node) => wrap_json(node.status()),
+        None => wrap_json(json!({"ok": false, "message": "not running in a cluster"})),
+    }
+}
+
 async fn export_relations(
     State(st): State<DbState>,
     Path(relations): Path<String>,
@@ -408,7 +538,11 @@ async fn export_relations(
             }
         })
         .collect_vec();
-    let result = spawn_blocking(move || st.db.export_relations(relations.iter())).await;
+    let result = spawn_blocking(move || {
+        st.read_barrier()?;
+        st.db.export_relations(relations.iter())
+    })
+    .await;
     match result {
         Ok(Ok(s)) => {
             let s: serde_json::Map<_, _> = s.into_iter().map(|(k, v)| (k, v.into_json())).collect();
@@ -472,7 +606,11 @@ async fn backup(
     State(st): State<DbState>,
     Json(payload): Json<BackupPayload>,
 ) -> (StatusCode, Json<serde_json::Value>) {
-    let result = spawn_blocking(move || st.db.backup_db(payload.path)).await;
+    let result = spawn_blocking(move || {
+        st.read_barrier()?;
+        st.db.backup_db(payload.path)
+    })
+    .await;
 
     match result {
         Ok(Ok(())) => {
@@ -909,6 +909,9 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "l2_normalize" => &OP_L2_NORMALIZE,
         "ip_dist" => &OP_IP_DIST,
         "cos_dist" => &OP_COS_DIST,
+        "l1_dist" => &OP_L1_DIST,
+        "hamming_dist" => &OP_HAMMING_DIST,
+        "jaccard_dist" => &OP_JACCARD_DIST,
         "int_range" => &OP_INT_RANGE,
         "rand_float" => &OP_RAND_FLOAT,
         "rand_bernoulli" => &OP_RAND_BERNOULLI,
@@ -2254,6 +2254,81 @@ pub(crate) fn op_cos_dist(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_L1_DIST, 2, false);
+pub(crate) fn op_l1_dist(args: &[DataValue]) -> Result<DataValue> {
+    let a = &args[0];
+    let b = &args[1];
+    match (a, b) {
+        (DataValue::Vec(Vector::F32(a)), DataValue::Vec(Vector::F32(b))) => {
+            if a.len() != b.len() {
+                bail!("'l1_dist' requires two vectors of the same length");
+            }
+            let diff = a - b;
+            Ok(DataValue::from(diff.mapv(f32::abs).sum() as f64))
+        }
+        (DataValue::Vec(Vector::F64(a)), DataValue::Vec(Vector::F64(b))) => {
+            if a.len() != b.len() {
+                bail!("'l1_dist' requires two vectors of the same length");
+            }
+            let diff = a - b;
+            Ok(DataValue::from(diff.mapv(f64::abs).sum()))
+        }
+        _ => bail!("'l1_dist' requires two vectors of the same type"),
+    }
+}
+
+define_op!(OP_HAMMING_DIST, 2, false);
+pub(crate) fn op_hamming_dist(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Bytes(a), DataValue::Bytes(b)) => {
+            ensure!(
+                a.len() == b.len(),
+                "'hamming_dist' requires two bit vectors of the same length"
+            );
+            let dist: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+            Ok(DataValue::from(dist as i64))
+        }
+        _ => bail!("'hamming_dist' requires two bit vectors"),
+    }
+}
+
+define_op!(OP_JACCARD_DIST, 2, false);
+pub(crate) fn op_jaccard_dist(args: &[DataValue]) -> Result<DataValue> {
+    fn to_set(arg: &DataValue) -> Result<BTreeSet<&DataValue>> {
+        Ok(match arg {
+            DataValue::List(l) => l.iter().collect(),
+            DataValue::Set(s) => s.iter().collect(),
+            _ => bail!("'jaccard_dist' requires two bit vectors, or two lists"),
+        })
+    }
+
+    let (intersection, union) = match (&args[0], &args[1]) {
+        (DataValue::Bytes(a), DataValue::Bytes(b)) => {
+            ensure!(
+                a.len() == b.len(),
+                "'jaccard_dist' requires two bit vectors of the same length"
+            );
+            a.iter().zip(b).fold((0, 0), |(i, u), (x, y)| {
+                (i + (x & y).count_ones(), u + (x | y).count_ones())
+            })
+        }
+        (a, b) => {
+            let a = to_set(a)?;
+            let b = to_set(b)?;
+            let intersection = a.intersection(&b).count();
+            (
+                intersection as u32,
+                (a.len() + b.len() - intersection) as u32,
+            )
+        }
+    };
+    // two empty sets are identical
+    if union == 0 {
+        return Ok(DataValue::from(0.));
+    }
+    Ok(DataValue::from(1. - intersection as f64 / union as f64))
+}
+
 define_op!(OP_INT_RANGE, 1, true);
 pub(crate) fn op_int_range(args: &[DataValue]) -> Result<DataValue> {
     let [start, end] = match args.len() {
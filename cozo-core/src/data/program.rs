@@ -987,6 +987,7 @@ pub(crate) struct HnswSearch {
 pub(crate) enum FtsScoreKind {
     TfIdf,
     Tf,
+    Bm25,
 }
 
 #[derive(Clone, Debug)]
@@ -996,11 +997,19 @@ pub(crate) struct FtsSearch {
     pub(crate) manifest: FtsIndexManifest,
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) k: usize,
-    // pub(crate) k1: f64,
-    // pub(crate) b: f64,
+    pub(crate) k1: f64,
+    pub(crate) b: f64,
     pub(crate) query: Symbol,
     pub(crate) score_kind: FtsScoreKind,
     pub(crate) bind_score: Option<Symbol>,
+    /// Binds the byte offsets of the matched tokens
+    pub(crate) bind_highlights: Option<Symbol>,
+    /// Binds an excerpt of the text with the matched tokens marked
+    pub(crate) bind_snippet: Option<Symbol>,
+    pub(crate) highlight_start: SmartString<LazyCompact>,
+    pub(crate) highlight_end: SmartString<LazyCompact>,
+    /// Length of the snippet in characters, zero for the whole text
+    pub(crate) snippet_size: usize,
     // pub(crate) lax_mode: bool,
     pub(crate) filter: Option<Expr>,
     pub(crate) span: SourceSpan,
@@ -1019,7 +1028,11 @@ impl HnswSearch {
 
 impl FtsSearch {
     pub(crate) fn all_bindings(&self) -> impl Iterator<Item=&Symbol> {
-        self.bindings.iter().chain(self.bind_score.iter())
+        self.bindings
+            .iter()
+            .chain(self.bind_score.iter())
+            .chain(self.bind_highlights.iter())
+            .chain(self.bind_snippet.iter())
     }
 }
 
@@ -1028,6 +1041,7 @@ impl SearchInput {
         mut self,
         base_handle: RelationHandle,
         idx_handle: RelationHandle,
+        inv_idx_handle: RelationHandle,
         manifest: MinHashLshIndexManifest,
         gen: &mut TempSymbGen,
     ) -> Result<Disjunction> {
@@ -1141,6 +1155,48 @@ impl SearchInput {
             }
         };
 
+        let threshold = match self.parameters.remove("threshold") {
+            None => None,
+            Some(expr) => {
+                let threshold = expr
+                    .eval_to_const()?
+                    .get_float()
+                    .ok_or_else(|| miette!("`threshold` for LSH search must be a float"))?;
+                ensure!(
+                    threshold > 0. && threshold < 1.,
+                    "`threshold` for LSH search must be between 0 and 1"
+                );
+                Some(threshold)
+            }
+        };
+        let params = manifest.query_params(threshold)?;
+        let threshold = threshold.unwrap_or(manifest.threshold);
+
+        let rerank = match self.parameters.remove("rerank") {
+            None => false,
+            Some(expr) => expr
+                .eval_to_const()?
+                .get_bool()
+                .ok_or_else(|| miette!("`rerank` for LSH search must be a boolean"))?,
+        };
+
+        let bind_similarity = match self.parameters.remove("bind_similarity") {
+            None => None,
+            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(expr) => {
+                let span = expr.span();
+                let kw = gen.next(span);
+                let unif = NormalFormAtom::Unification(Unification {
+                    binding: kw.clone(),
+                    expr,
+                    one_many_unif: false,
+                    span,
+                });
+                conj.push(unif);
+                Some(kw)
+            }
+        };
+
         let filter = self.parameters.remove("filter");
 
         #[derive(Debug, Error, Diagnostic)]
@@ -1158,10 +1214,15 @@ impl SearchInput {
         conj.push(NormalFormAtom::LshSearch(LshSearch {
             base_handle,
             idx_handle,
+            inv_idx_handle,
             manifest,
             bindings,
             k,
             query,
+            params,
+            threshold,
+            rerank,
+            bind_similarity,
             span: self.span,
             filter,
         }));
@@ -1284,28 +1345,83 @@ impl SearchInput {
                 match r {
                     "tf_idf" => FtsScoreKind::TfIdf,
                     "tf" => FtsScoreKind::Tf,
+                    "bm25" => FtsScoreKind::Bm25,
                     s => bail!("Unknown score kind for FTS: {}", s),
                 }
             }
             None => FtsScoreKind::TfIdf,
         };
 
+        let k1 = match self.parameters.remove("k1") {
+            Some(expr) => {
+                let k1 = expr
+                    .eval_to_const()?
+                    .get_float()
+                    .ok_or_else(|| miette!("`k1` for FTS must be a number"))?;
+                ensure!(k1 >= 0., "`k1` for FTS must be non-negative");
+                k1
+            }
+            None => 1.2,
+        };
+
+        let b = match self.parameters.remove("b") {
+            Some(expr) => {
+                let b = expr
+                    .eval_to_const()?
+                    .get_float()
+                    .ok_or_else(|| miette!("`b` for FTS must be a number"))?;
+                ensure!(
+                    (0. ..=1.).contains(&b),
+                    "`b` for FTS must be between 0 and 1"
+                );
+                b
+            }
+            None => 0.75,
+        };
+
         let filter = self.parameters.remove("filter");
 
-        let bind_score = match self.parameters.remove("bind_score") {
-            None => None,
-            Some(Expr::Binding { var, .. }) => Some(var),
+        let [bind_score, bind_highlights, bind_snippet] =
+            ["bind_score", "bind_highlights", "bind_snippet"].map(|name| {
+                match self.parameters.remove(name) {
+                    None => None,
+                    Some(Expr::Binding { var, .. }) => Some(var),
+                    Some(expr) => {
+                        let span = expr.span();
+                        let kw = gen.next(span);
+                        let unif = NormalFormAtom::Unification(Unification {
+                            binding: kw.clone(),
+                            expr,
+                            one_many_unif: false,
+                            span,
+                        });
+                        conj.push(unif);
+                        Some(kw)
+                    }
+                }
+            });
+
+        let mut get_marker = |name: &str, default: &str| -> Result<SmartString<LazyCompact>> {
+            match self.parameters.remove(name) {
+                None => Ok(SmartString::from(default)),
+                Some(expr) => match expr.eval_to_const()? {
+                    DataValue::Str(s) => Ok(s),
+                    v => bail!("`{}` for FTS must be a string, got {}", name, v),
+                },
+            }
+        };
+        let highlight_start = get_marker("highlight_start", "<b>")?;
+        let highlight_end = get_marker("highlight_end", "</b>")?;
+
+        let snippet_size = match self.parameters.remove("snippet_size") {
+            None => 100,
             Some(expr) => {
-                let span = expr.span();
-                let kw = gen.next(span);
-                let unif = NormalFormAtom::Unification(Unification {
-                    binding: kw.clone(),
-                    expr,
-                    one_many_unif: false,
-                    span,
-                });
-                conj.push(unif);
-                Some(kw)
+                let size = expr
+                    .eval_to_const()?
+                    .get_int()
+                    .ok_or_else(|| miette!("`snippet_size` for FTS must be an integer"))?;
+                ensure!(size >= 0, "`snippet_size` for FTS must be non-negative");
+                size as usize
             }
         };
 
@@ -1322,9 +1438,14 @@ impl SearchInput {
             query,
             score_kind,
             bind_score,
+            bind_highlights,
+            bind_snippet,
+            highlight_start,
+            highlight_end,
+            snippet_size,
             // lax_mode,
-            // k1,
-            // b,
+            k1,
+            b,
             filter,
             span: self.span,
         }));
@@ -1582,10 +1703,10 @@ impl SearchInput {
         {
             return self.normalize_fts(base_handle, idx_handle, manifest, gen);
         }
-        if let Some((idx_handle, _, manifest)) =
+        if let Some((idx_handle, inv_idx_handle, manifest)) =
             base_handle.lsh_indices.get(&self.index.name).cloned()
         {
-            return self.normalize_lsh(base_handle, idx_handle, manifest, gen);
+            return self.normalize_lsh(base_handle, idx_handle, inv_idx_handle, manifest, gen);
         }
         #[derive(Debug, Error, Diagnostic)]
         #[error("Index {name} not found on relation {relation}")]
@@ -69,6 +69,9 @@ impl Display for NullableColType {
                 write!(f, ";{len}")?;
                 f.write_str(">")?;
             }
+            ColType::BitVec { len } => {
+                write!(f, "<Bit;{len}>")?;
+            }
             ColType::Json => {
                 f.write_str("Json")?;
             }
@@ -97,6 +100,12 @@ pub enum ColType {
         eltype: VecElementType,
         len: usize,
     },
+    /// A vector of `len` bits, stored as bytes in the layout of `pack_bits`:
+    /// the first bit is the most significant bit of the first byte,
+    /// and the padding bits of the last byte are zero.
+    BitVec {
+        len: usize,
+    },
     Tuple(Vec<NullableColType>),
     Validity,
     Json,
@@ -170,6 +179,35 @@ impl StoredRelationMetadata {
     }
 }
 
+/// Packs a list of booleans, or of the integers 0 and 1, into the bytes of a bit vector,
+/// in the same layout as the `pack_bits` function.
+pub(crate) fn pack_bits(l: &[DataValue]) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; l.len().div_ceil(8)];
+    for (i, el) in l.iter().enumerate() {
+        let bit = match el {
+            DataValue::Bool(b) => *b,
+            DataValue::Num(Num::Int(0)) => false,
+            DataValue::Num(Num::Int(1)) => true,
+            _ => return None,
+        };
+        if bit {
+            bytes[i / 8] |= 0b10000000 >> (i % 8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Whether `bytes` is a valid bit vector of `len` bits.
+pub(crate) fn is_bit_vec(bytes: &[u8], len: usize) -> bool {
+    if bytes.len() != len.div_ceil(8) {
+        return false;
+    }
+    match (bytes.last(), len % 8) {
+        (Some(last), rem) if rem != 0 => last & (0xff >> rem) == 0,
+        _ => true,
+    }
+}
+
 impl NullableColType {
     pub(crate) fn coerce(&self, data: DataValue, cur_vld: ValidityTs) -> Result<DataValue> {
         if matches!(data, DataValue::Null) {
@@ -317,6 +355,30 @@ impl NullableColType {
                 }
                 _ => bail!(make_err()),
             },
+            ColType::BitVec { len } => match &data {
+                DataValue::List(l) => {
+                    if l.len() != *len {
+                        bail!(BadListLength(self.clone(), l.len()))
+                    }
+                    DataValue::Bytes(pack_bits(l).ok_or_else(make_err)?)
+                }
+                DataValue::Bytes(b) => {
+                    if is_bit_vec(b, *len) {
+                        data.clone()
+                    } else {
+                        bail!(make_err())
+                    }
+                }
+                DataValue::Str(s) => {
+                    let b = STANDARD.decode(s).map_err(|_| make_err())?;
+                    if is_bit_vec(&b, *len) {
+                        DataValue::Bytes(b)
+                    } else {
+                        bail!(make_err())
+                    }
+                }
+                _ => bail!(make_err()),
+            },
             ColType::Tuple(typ) => {
                 if let DataValue::List(l) = data {
                     ensure!(typ.len() == l.len(), BadListLength(self.clone(), l.len()));
@@ -1487,3 +1487,40 @@ fn test_range() {
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
 }
+
+#[test]
+fn test_distances() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default("?[a] := a = l1_dist(vec([1, 2, 3]), vec([2, 0, 3]))")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!(3.0));
+    let res = db
+        .run_default("?[a] := a = hamming_dist(pack_bits([true, false, true]), pack_bits([false, false, true]))")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!(1));
+    let res = db
+        .run_default("?[a] := a = jaccard_dist(pack_bits([true, true, false]), pack_bits([false, true, true]))")
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"][0][0], json!(1. - 1. / 3.));
+    assert_eq!(
+        op_jaccard_dist(&[
+            DataValue::List(vec![DataValue::from(1), DataValue::from(2)]),
+            DataValue::List(vec![
+                DataValue::from(2),
+                DataValue::from(3),
+                DataValue::from(4)
+            ])
+        ])
+        .unwrap(),
+        DataValue::from(0.75)
+    );
+    assert_eq!(
+        op_jaccard_dist(&[DataValue::List(vec![]), DataValue::List(vec![])]).unwrap(),
+        DataValue::from(0.)
+    );
+    assert!(op_hamming_dist(&[DataValue::Bytes(vec![0]), DataValue::Bytes(vec![0, 0])]).is_err());
+}
@@ -14,7 +14,11 @@ use itertools::Itertools;
 use miette::Result;
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
 use rayon::prelude::*;
+use smallvec::{smallvec, SmallVec};
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
@@ -38,7 +42,8 @@ impl FixedRule for BetweennessCentrality {
         let edges = payload.get_input(0)?;
         let undirected = payload.bool_option("undirected", Some(false))?;
 
-        let (graph, indices, _inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
 
         let n = graph.node_count();
         if n == 0 {
@@ -50,7 +55,7 @@ impl FixedRule for BetweennessCentrality {
         let centrality_segs: Vec<_> = it
             .map(|start| -> Result<BTreeMap<u32, f32>> {
                 let res_for_start =
-                    dijkstra_keep_ties(&graph, start, &(), &(), &(), poison.clone())?;
+                    dijkstra_keep_ties(graph, start, &(), &(), &(), poison.clone())?;
                 let mut ret: BTreeMap<u32, f32> = Default::default();
                 let grouped = res_for_start.into_iter().group_by(|(n, _, _)| *n);
                 for (_, grp) in grouped.into_iter() {
@@ -94,6 +99,131 @@ impl FixedRule for BetweennessCentrality {
     }
 }
 
+/// Betweenness centrality estimated from the shortest paths starting at `k` random pivots,
+/// with Brandes' dependency accumulation, scaled by `n / k` to be comparable to the exact
+/// values.
+///
+/// Unless `samples` is given, `k` is chosen so that with probability at least `1 - delta`,
+/// every estimate is within `epsilon * n * (n - 2)` of the exact value.
+pub(crate) struct ApproxBetweennessCentrality;
+
+impl FixedRule for ApproxBetweennessCentrality {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        // the options are only absent when not given, invalid values are errors
+        let samples = match payload.expr_option("samples", None) {
+            Ok(_) => Some(payload.pos_integer_option("samples", None)?),
+            Err(_) => None,
+        };
+        let epsilon = payload.pos_float_option("epsilon", Some(0.05))?;
+        let delta = payload.unit_interval_option("delta", Some(0.1))?;
+        let mut rng = match payload.expr_option("seed", None) {
+            Ok(_) => StdRng::seed_from_u64(payload.non_neg_integer_option("seed", None)? as u64),
+            Err(_) => StdRng::from_entropy(),
+        };
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+
+        let n = graph.node_count() as usize;
+        if n == 0 {
+            return Ok(());
+        }
+        // Hoeffding's inequality with a union bound over all nodes
+        let k = samples
+            .unwrap_or_else(|| {
+                ((2. * n as f64 / delta).ln() / (2. * epsilon * epsilon)).ceil() as usize
+            })
+            .min(n);
+        let pivots = sample(&mut rng, n, k).into_vec();
+
+        let dependencies: Vec<_> = pivots
+            .into_par_iter()
+            .map(|start| brandes_dependencies(graph, start as u32, &poison))
+            .collect::<Result<_>>()?;
+        let scale = n as f64 / k as f64;
+        let mut centrality = vec![0.; n];
+        for dep in dependencies {
+            for (c, d) in centrality.iter_mut().zip(dep) {
+                *c += d * scale;
+            }
+        }
+
+        for (i, s) in centrality.into_iter().enumerate() {
+            out.put(vec![indices[i].clone(), DataValue::from(s)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// The dependency of `start` on every node, i.e. the sum over all targets of the fraction
+/// of shortest paths from `start` to the target that pass through the node.
+fn brandes_dependencies(
+    edges: &DirectedCsrGraph<u32, (), f32>,
+    start: u32,
+    poison: &Poison,
+) -> Result<Vec<f64>> {
+    let n = edges.node_count() as usize;
+    let mut distance = vec![f32::INFINITY; n];
+    let mut num_paths = vec![0f64; n];
+    let mut settled = vec![false; n];
+    let mut preds: Vec<SmallVec<[u32; 1]>> = vec![smallvec![]; n];
+    let mut order = vec![];
+    let mut pq = PriorityQueue::new();
+    distance[start as usize] = 0.;
+    num_paths[start as usize] = 1.;
+    pq.push(start, Reverse(OrderedFloat(0.)));
+
+    while let Some((node, Reverse(OrderedFloat(cost)))) = pq.pop() {
+        settled[node as usize] = true;
+        order.push(node);
+        for target in edges.out_neighbors_with_values(node) {
+            let nxt_node = target.target as usize;
+            if settled[nxt_node] {
+                continue;
+            }
+            let nxt_cost = cost + target.value;
+            if nxt_cost < distance[nxt_node] {
+                pq.push_increase(target.target, Reverse(OrderedFloat(nxt_cost)));
+                distance[nxt_node] = nxt_cost;
+                num_paths[nxt_node] = num_paths[node as usize];
+                preds[nxt_node].clear();
+                preds[nxt_node].push(node);
+            } else if nxt_cost == distance[nxt_node] {
+                num_paths[nxt_node] += num_paths[node as usize];
+                preds[nxt_node].push(node);
+            }
+        }
+        poison.check()?;
+    }
+
+    let mut dependency = vec![0f64; n];
+    for &node in order.iter().rev() {
+        let node = node as usize;
+        for &pred in &preds[node] {
+            let pred = pred as usize;
+            dependency[pred] += num_paths[pred] / num_paths[node] * (1. + dependency[node]);
+        }
+    }
+    dependency[start as usize] = 0.;
+    Ok(dependency)
+}
+
 pub(crate) struct ClosenessCentrality;
 
 impl FixedRule for ClosenessCentrality {
@@ -106,7 +236,8 @@ impl FixedRule for ClosenessCentrality {
         let edges = payload.get_input(0)?;
         let undirected = payload.bool_option("undirected", Some(false))?;
 
-        let (graph, indices, _inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
 
         let n = graph.node_count();
         if n == 0 {
@@ -116,7 +247,7 @@ impl FixedRule for ClosenessCentrality {
 
         let res: Vec<_> = it
             .map(|start| -> Result<f32> {
-                let distances = dijkstra_cost_only(&graph, start, poison.clone())?;
+                let distances = dijkstra_cost_only(graph, start, poison.clone())?;
                 let total_dist: f32 = distances.iter().filter(|d| d.is_finite()).cloned().sum();
                 let nc: f32 = distances.iter().filter(|d| d.is_finite()).count() as f32;
                 Ok(nc * nc / total_dist / (n - 1) as f32)
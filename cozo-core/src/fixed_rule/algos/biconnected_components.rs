@@ -0,0 +1,260 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Nodes whose removal disconnects the graph. Edge directions are ignored.
+pub(crate) struct ArticulationPoints;
+
+impl FixedRule for ArticulationPoints {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let projection = edges.as_cached_directed_graph(true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let res = Biconnectivity::new(&undirected_adjacency(graph), poison)?;
+        for (idx, node) in indices.iter().cloned().enumerate() {
+            if res.articulation_points[idx] {
+                out.put(vec![node]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(1)
+    }
+}
+
+/// Edges whose removal disconnects the graph. Edge directions are ignored.
+pub(crate) struct Bridges;
+
+impl FixedRule for Bridges {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let projection = edges.as_cached_directed_graph(true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let res = Biconnectivity::new(&undirected_adjacency(graph), poison)?;
+        for (from, to) in res.bridges {
+            out.put(vec![
+                indices[from as usize].clone(),
+                indices[to as usize].clone(),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Maximal subgraphs that stay connected after removing any single node.
+/// Articulation points belong to several components, and are returned once for each.
+pub(crate) struct BiconnectedComponents;
+
+impl FixedRule for BiconnectedComponents {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let projection = edges.as_cached_directed_graph(true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let res = Biconnectivity::new(&undirected_adjacency(graph), poison)?;
+        for (grp_id, component) in res.components.into_iter().enumerate() {
+            for idx in component {
+                out.put(vec![
+                    indices[idx as usize].clone(),
+                    DataValue::from(grp_id as i64),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Sorted neighbours of each node of a graph in which every edge appears in both directions,
+/// without self-loops and parallel edges.
+pub(crate) fn undirected_adjacency(graph: &DirectedCsrGraph<u32>) -> Vec<Vec<u32>> {
+    (0..graph.node_count())
+        .map(|u| {
+            graph
+                .out_neighbors(u)
+                .filter(|v| **v != u)
+                .cloned()
+                .dedup()
+                .collect_vec()
+        })
+        .collect_vec()
+}
+
+/// Articulation points, bridges and biconnected components found with Tarjan's algorithm.
+/// The depth-first search is iterative, so that deep graphs do not overflow the stack.
+struct Biconnectivity {
+    articulation_points: Vec<bool>,
+    bridges: Vec<(u32, u32)>,
+    components: Vec<BTreeSet<u32>>,
+}
+
+impl Biconnectivity {
+    fn new(adj: &[Vec<u32>], poison: Poison) -> Result<Self> {
+        let n = adj.len();
+        let mut ret = Self {
+            articulation_points: vec![false; n],
+            bridges: vec![],
+            components: vec![],
+        };
+        let mut discovered = vec![u32::MAX; n];
+        let mut low = vec![0; n];
+        let mut parent = vec![u32::MAX; n];
+        let mut time = 0;
+        let mut edge_stack: Vec<(u32, u32)> = vec![];
+        for root in 0..n as u32 {
+            if discovered[root as usize] != u32::MAX {
+                continue;
+            }
+            poison.check()?;
+            discovered[root as usize] = time;
+            low[root as usize] = time;
+            time += 1;
+            let mut root_children = 0;
+            let mut stack = vec![(root, 0)];
+            while let Some((u, next)) = stack.last_mut() {
+                let u = *u;
+                if let Some(&v) = adj[u as usize].get(*next) {
+                    *next += 1;
+                    if discovered[v as usize] == u32::MAX {
+                        parent[v as usize] = u;
+                        discovered[v as usize] = time;
+                        low[v as usize] = time;
+                        time += 1;
+                        edge_stack.push((u, v));
+                        stack.push((v, 0));
+                        if u == root {
+                            root_children += 1;
+                        }
+                    } else if v != parent[u as usize]
+                        && discovered[v as usize] < discovered[u as usize]
+                    {
+                        low[u as usize] = min(low[u as usize], discovered[v as usize]);
+                        edge_stack.push((u, v));
+                    }
+                    continue;
+                }
+                stack.pop();
+                if let Some(&(p, _)) = stack.last() {
+                    low[p as usize] = min(low[p as usize], low[u as usize]);
+                    if low[u as usize] > discovered[p as usize] {
+                        ret.bridges.push((p, u));
+                    }
+                    if low[u as usize] >= discovered[p as usize] {
+                        if p != root {
+                            ret.articulation_points[p as usize] = true;
+                        }
+                        let mut component = BTreeSet::new();
+                        while let Some((a, b)) = edge_stack.pop() {
+                            component.insert(a);
+                            component.insert(b);
+                            if (a, b) == (p, u) {
+                                break;
+                            }
+                        }
+                        ret.components.push(component);
+                    }
+                }
+            }
+            if root_children > 1 {
+                ret.articulation_points[root as usize] = true;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::fixed_rule::algos::biconnected_components::Biconnectivity;
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn two_triangles_and_a_tail() {
+        // triangles 0-1-2 and 2-3-4 share node 2, and 4-5 hangs off the second one
+        let edges = [(0, 1), (1, 2), (2, 0), (2, 3), (3, 4), (4, 2), (4, 5)];
+        let mut adj = vec![vec![]; 6];
+        for (a, b) in edges {
+            adj[a as usize].push(b);
+            adj[b as usize].push(a);
+        }
+        for l in adj.iter_mut() {
+            l.sort();
+        }
+        let res = Biconnectivity::new(&adj, Poison::default()).unwrap();
+        assert_eq!(
+            res.articulation_points,
+            vec![false, false, true, false, true, false]
+        );
+        assert_eq!(res.bridges, vec![(4, 5)]);
+        let mut components = res.components;
+        components.sort();
+        assert_eq!(
+            components,
+            vec![
+                BTreeSet::from([0, 1, 2]),
+                BTreeSet::from([2, 3, 4]),
+                BTreeSet::from([4, 5])
+            ]
+        );
+    }
+}
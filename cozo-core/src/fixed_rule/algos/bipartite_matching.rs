@@ -0,0 +1,306 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use miette::{ensure, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload, NotAnEdgeError};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Maximum matching in a bipartite graph whose edges go from the left nodes to the right nodes.
+///
+/// Without weights, Hopcroft–Karp finds a maximum cardinality matching. With `weighted: true`,
+/// the Hungarian algorithm finds, among the maximum cardinality matchings, the one with the
+/// largest (or with `minimize: true`, the smallest) total weight.
+pub(crate) struct BipartiteMatching;
+
+impl FixedRule for BipartiteMatching {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?.ensure_min_len(2)?;
+        let weighted = payload.bool_option("weighted", Some(false))?;
+        let minimize = payload.bool_option("minimize", Some(false))?;
+
+        // the two sides are indexed separately, so the same value may appear on both sides
+        let mut left: Vec<DataValue> = vec![];
+        let mut inv_left: BTreeMap<DataValue, usize> = BTreeMap::new();
+        let mut right: Vec<DataValue> = vec![];
+        let mut inv_right: BTreeMap<DataValue, usize> = BTreeMap::new();
+        let mut weights: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+        for tuple in edges.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let l = tuple.next().ok_or_else(|| NotAnEdgeError(edges.span()))?;
+            let r = tuple.next().ok_or_else(|| NotAnEdgeError(edges.span()))?;
+            let w = match tuple.next() {
+                Some(v) if weighted => {
+                    let w = v.get_float().unwrap_or(f64::NAN);
+                    ensure!(
+                        w.is_finite(),
+                        BadExprValueError(
+                            v,
+                            edges.span(),
+                            "edge weights must be finite numbers".to_string()
+                        )
+                    );
+                    w
+                }
+                _ => 1.,
+            };
+            let l_idx = *inv_left.entry(l.clone()).or_insert_with(|| {
+                left.push(l);
+                left.len() - 1
+            });
+            let r_idx = *inv_right.entry(r.clone()).or_insert_with(|| {
+                right.push(r);
+                right.len() - 1
+            });
+            let entry = weights.entry((l_idx, r_idx)).or_insert(w);
+            // of parallel edges, keep the one that is best for the objective
+            if (minimize && w < *entry) || (!minimize && w > *entry) {
+                *entry = w;
+            }
+        }
+
+        let matching = if weighted {
+            hungarian(left.len(), right.len(), &weights, minimize, poison)?
+        } else {
+            let mut adj = vec![vec![]; left.len()];
+            for (l, r) in weights.keys() {
+                adj[*l].push(*r);
+            }
+            hopcroft_karp(&adj, right.len(), poison)?
+        };
+        for (l, r) in matching {
+            out.put(vec![left[l].clone(), right[r].clone()]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+const UNMATCHED: usize = usize::MAX;
+
+pub(crate) fn hopcroft_karp(
+    adj: &[Vec<usize>],
+    n_right: usize,
+    poison: Poison,
+) -> Result<Vec<(usize, usize)>> {
+    let n_left = adj.len();
+    let mut match_left = vec![UNMATCHED; n_left];
+    let mut match_right = vec![UNMATCHED; n_right];
+    let mut dist = vec![usize::MAX; n_left];
+    let mut next_edge = vec![0; n_left];
+    loop {
+        // layer the free left nodes and the left nodes reachable through alternating paths
+        let mut queue = VecDeque::new();
+        for u in 0..n_left {
+            if match_left[u] == UNMATCHED {
+                dist[u] = 0;
+                queue.push_back(u);
+            } else {
+                dist[u] = usize::MAX;
+            }
+        }
+        let mut found = false;
+        while let Some(u) = queue.pop_front() {
+            for &v in &adj[u] {
+                let w = match_right[v];
+                if w == UNMATCHED {
+                    found = true;
+                } else if dist[w] == usize::MAX {
+                    dist[w] = dist[u] + 1;
+                    queue.push_back(w);
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+
+        // augment along vertex-disjoint shortest alternating paths
+        next_edge.iter_mut().for_each(|i| *i = 0);
+        for start in 0..n_left {
+            if match_left[start] != UNMATCHED {
+                continue;
+            }
+            let mut stack = vec![start];
+            while let Some(&u) = stack.last() {
+                if next_edge[u] == adj[u].len() {
+                    dist[u] = usize::MAX;
+                    stack.pop();
+                    continue;
+                }
+                let v = adj[u][next_edge[u]];
+                next_edge[u] += 1;
+                let w = match_right[v];
+                if w == UNMATCHED {
+                    for &x in &stack {
+                        let y = adj[x][next_edge[x] - 1];
+                        match_left[x] = y;
+                        match_right[y] = x;
+                    }
+                    break;
+                } else if dist[w] == dist[u] + 1 {
+                    stack.push(w);
+                }
+            }
+        }
+        poison.check()?;
+    }
+    Ok(match_left
+        .into_iter()
+        .enumerate()
+        .filter(|(_, r)| *r != UNMATCHED)
+        .collect())
+}
+
+/// Solve the assignment problem with the Hungarian algorithm in its shortest augmenting
+/// path form, using a dense cost matrix. Missing edges are given a cost large enough that
+/// they are only used when no larger matching exists, and are dropped from the result.
+pub(crate) fn hungarian(
+    n_left: usize,
+    n_right: usize,
+    weights: &BTreeMap<(usize, usize), f64>,
+    minimize: bool,
+    poison: Poison,
+) -> Result<Vec<(usize, usize)>> {
+    // the algorithm requires at least as many columns as rows
+    let transposed = n_left > n_right;
+    let (n, m) = if transposed {
+        (n_right, n_left)
+    } else {
+        (n_left, n_right)
+    };
+    let missing = 2. * weights.values().map(|w| w.abs()).sum::<f64>() + 1.;
+    let mut costs = vec![vec![missing; m]; n];
+    for ((l, r), w) in weights {
+        let (i, j) = if transposed { (*r, *l) } else { (*l, *r) };
+        costs[i][j] = if minimize { *w } else { -*w };
+    }
+
+    // potentials and assignments, with index 0 reserved as a sentinel
+    let mut row_potentials = vec![0.; n + 1];
+    let mut col_potentials = vec![0.; m + 1];
+    let mut assigned_row = vec![0; m + 1];
+    let mut way = vec![0; m + 1];
+    for i in 1..=n {
+        poison.check()?;
+        assigned_row[0] = i;
+        let mut j0 = 0;
+        let mut min_slack = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = assigned_row[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let slack = costs[i0 - 1][j - 1] - row_potentials[i0] - col_potentials[j];
+                if slack < min_slack[j] {
+                    min_slack[j] = slack;
+                    way[j] = j0;
+                }
+                if min_slack[j] < delta {
+                    delta = min_slack[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    row_potentials[assigned_row[j]] += delta;
+                    col_potentials[j] -= delta;
+                } else {
+                    min_slack[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if assigned_row[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            assigned_row[j0] = assigned_row[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut ret = vec![];
+    for (j, &i) in assigned_row.iter().enumerate().skip(1) {
+        if i == 0 {
+            continue;
+        }
+        let (l, r) = if transposed {
+            (j - 1, i - 1)
+        } else {
+            (i - 1, j - 1)
+        };
+        if weights.contains_key(&(l, r)) {
+            ret.push((l, r));
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::fixed_rule::algos::bipartite_matching::{hopcroft_karp, hungarian};
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn unweighted() {
+        // a greedy matching of 0 -> 0 would block 1
+        let adj = vec![vec![0, 1], vec![0], vec![1, 2], vec![2]];
+        let matching = hopcroft_karp(&adj, 3, Poison::default()).unwrap();
+        assert_eq!(matching.len(), 3);
+    }
+
+    #[test]
+    fn weighted() {
+        let weights = BTreeMap::from([
+            ((0, 0), 4.),
+            ((0, 1), 1.),
+            ((1, 0), 2.),
+            ((1, 1), 5.),
+            ((2, 0), 10.),
+        ]);
+        let mut max = hungarian(3, 2, &weights, false, Poison::default()).unwrap();
+        max.sort();
+        assert_eq!(max, vec![(1, 1), (2, 0)]);
+        let mut min = hungarian(3, 2, &weights, true, Poison::default()).unwrap();
+        min.sort();
+        assert_eq!(min, vec![(0, 1), (1, 0)]);
+    }
+}
@@ -0,0 +1,342 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::algos::leiden::renumber;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Community detection with Infomap (Rosvall & Bergstrom, 2008), which groups nodes
+/// into modules that minimise the two-level map equation of a random walk.
+///
+/// For directed graphs the flow is given by PageRank with the `teleportation` probability,
+/// and teleportation steps are not recorded when computing module exits.
+pub(crate) struct CommunityDetectionInfomap;
+
+impl FixedRule for CommunityDetectionInfomap {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let teleportation = payload.unit_interval_option("teleportation", Some(0.15))?;
+        let max_iter = payload.pos_integer_option("max_iter", Some(10))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let modules = infomap(graph, undirected, teleportation, max_iter, poison)?;
+        for (module, node) in modules.into_iter().zip(indices.iter().cloned()) {
+            out.put(vec![DataValue::from(module as i64), node]);
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+fn plogp(p: f64) -> f64 {
+    if p > 0. {
+        p * p.log2()
+    } else {
+        0.
+    }
+}
+
+/// Stationary flow of the random walk: the visit rate of each node and the flow along
+/// each link, excluding self-links.
+struct FlowNetwork {
+    flows: Vec<f64>,
+    out_links: Vec<Vec<(usize, f64)>>,
+    in_links: Vec<Vec<(usize, f64)>>,
+}
+
+impl FlowNetwork {
+    fn from_graph(
+        graph: &DirectedCsrGraph<u32, (), f32>,
+        undirected: bool,
+        teleportation: f64,
+    ) -> Self {
+        let n = graph.node_count() as usize;
+        let out_weights = (0..n)
+            .map(|u| {
+                graph
+                    .out_neighbors_with_values(u as u32)
+                    .map(|t| t.value as f64)
+                    .sum::<f64>()
+            })
+            .collect_vec();
+        let total: f64 = out_weights.iter().sum();
+
+        let mut links: Vec<BTreeMap<usize, f64>> = vec![Default::default(); n];
+        let flows = if undirected {
+            // `as_directed_weighted_graph` has added the reverse of every edge
+            for (u, targets) in links.iter_mut().enumerate() {
+                for t in graph.out_neighbors_with_values(u as u32) {
+                    *targets.entry(t.target as usize).or_default() += t.value as f64 / total;
+                }
+            }
+            out_weights.iter().map(|w| w / total).collect_vec()
+        } else {
+            let flows = page_rank(graph, &out_weights, teleportation);
+            for u in 0..n {
+                for t in graph.out_neighbors_with_values(u as u32) {
+                    *links[u].entry(t.target as usize).or_default() +=
+                        (1. - teleportation) * flows[u] * t.value as f64 / out_weights[u];
+                }
+            }
+            flows
+        };
+        Self::from_links(flows, links)
+    }
+
+    fn from_links(flows: Vec<f64>, links: Vec<BTreeMap<usize, f64>>) -> Self {
+        let n = flows.len();
+        let mut out_links = vec![vec![]; n];
+        let mut in_links = vec![vec![]; n];
+        for (u, targets) in links.into_iter().enumerate() {
+            for (v, f) in targets {
+                if u != v && f > 0. {
+                    out_links[u].push((v, f));
+                    in_links[v].push((u, f));
+                }
+            }
+        }
+        Self {
+            flows,
+            out_links,
+            in_links,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Collapse every module into a single node, dropping the flow inside modules.
+    fn aggregate(&self, modules: &[usize], n_modules: usize) -> Self {
+        let mut flows = vec![0.; n_modules];
+        let mut links: Vec<BTreeMap<usize, f64>> = vec![Default::default(); n_modules];
+        for (u, targets) in self.out_links.iter().enumerate() {
+            flows[modules[u]] += self.flows[u];
+            for &(v, f) in targets {
+                *links[modules[u]].entry(modules[v]).or_default() += f;
+            }
+        }
+        Self::from_links(flows, links)
+    }
+}
+
+fn page_rank(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    out_weights: &[f64],
+    teleportation: f64,
+) -> Vec<f64> {
+    let n = out_weights.len();
+    let mut flows = vec![1. / n as f64; n];
+    for _ in 0..200 {
+        let dangling: f64 = (0..n)
+            .filter(|u| out_weights[*u] <= 0.)
+            .map(|u| flows[u])
+            .sum();
+        let base = (teleportation + (1. - teleportation) * dangling) / n as f64;
+        let mut next = vec![base; n];
+        for u in 0..n {
+            if out_weights[u] <= 0. {
+                continue;
+            }
+            for t in graph.out_neighbors_with_values(u as u32) {
+                next[t.target as usize] +=
+                    (1. - teleportation) * flows[u] * t.value as f64 / out_weights[u];
+            }
+        }
+        let diff: f64 = next.iter().zip(&flows).map(|(a, b)| (a - b).abs()).sum();
+        flows = next;
+        if diff < 1e-15 {
+            break;
+        }
+    }
+    flows
+}
+
+pub(crate) fn infomap(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    undirected: bool,
+    teleportation: f64,
+    max_iter: usize,
+    poison: Poison,
+) -> Result<Vec<usize>> {
+    let mut network = FlowNetwork::from_graph(graph, undirected, teleportation);
+    let n = network.len();
+    // membership[i] is the node of the current network containing the original node i
+    let mut membership: Vec<usize> = (0..n).collect();
+    for _ in 0..max_iter {
+        let mut modules = move_nodes(&network, &poison)?;
+        let n_modules = renumber(&mut modules);
+        if n_modules == network.len() {
+            break;
+        }
+        for m in membership.iter_mut() {
+            *m = modules[*m];
+        }
+        network = network.aggregate(&modules, n_modules);
+    }
+    Ok(membership)
+}
+
+/// Greedily move nodes into neighbouring modules as long as the map equation decreases,
+/// starting from every node in its own module.
+fn move_nodes(network: &FlowNetwork, poison: &Poison) -> Result<Vec<usize>> {
+    let n = network.len();
+    let out_flows = network
+        .out_links
+        .iter()
+        .map(|l| l.iter().map(|(_, f)| f).sum::<f64>())
+        .collect_vec();
+
+    let mut modules: Vec<usize> = (0..n).collect();
+    let mut module_flows = network.flows.clone();
+    let mut module_exits = out_flows.clone();
+    // the terms of the map equation that depend on the partition
+    let mut sum_exit: f64 = module_exits.iter().sum();
+    let mut sum_exit_log_exit: f64 = module_exits.iter().map(|e| plogp(*e)).sum();
+    let mut sum_size_log_size: f64 = module_exits
+        .iter()
+        .zip(&module_flows)
+        .map(|(e, f)| plogp(e + f))
+        .sum();
+    let codelength = |sum_exit: f64, sum_exit_log_exit: f64, sum_size_log_size: f64| {
+        plogp(sum_exit) - 2. * sum_exit_log_exit + sum_size_log_size
+    };
+
+    let mut out_to: BTreeMap<usize, f64> = Default::default();
+    let mut in_from: BTreeMap<usize, f64> = Default::default();
+    loop {
+        let mut moved = false;
+        for v in 0..n {
+            poison.check()?;
+            out_to.clear();
+            in_from.clear();
+            for &(u, f) in &network.out_links[v] {
+                *out_to.entry(modules[u]).or_default() += f;
+            }
+            for &(u, f) in &network.in_links[v] {
+                *in_from.entry(modules[u]).or_default() += f;
+            }
+
+            let old = modules[v];
+            let old_out = out_to.get(&old).cloned().unwrap_or(0.);
+            let old_in = in_from.get(&old).cloned().unwrap_or(0.);
+            let old_exit = module_exits[old] - out_flows[v] + old_out + old_in;
+            let old_flow = module_flows[old] - network.flows[v];
+            let current = codelength(sum_exit, sum_exit_log_exit, sum_size_log_size);
+
+            let mut best = None;
+            let mut best_codelength = current - 1e-10;
+            for &c in out_to.keys().chain(in_from.keys()) {
+                if c == old {
+                    continue;
+                }
+                let new_exit = module_exits[c] + out_flows[v]
+                    - out_to.get(&c).cloned().unwrap_or(0.)
+                    - in_from.get(&c).cloned().unwrap_or(0.);
+                let new_flow = module_flows[c] + network.flows[v];
+                let delta_exit = old_exit - module_exits[old] + new_exit - module_exits[c];
+                let delta_exit_log_exit = plogp(old_exit) - plogp(module_exits[old])
+                    + plogp(new_exit)
+                    - plogp(module_exits[c]);
+                let delta_size_log_size = plogp(old_exit + old_flow)
+                    - plogp(module_exits[old] + module_flows[old])
+                    + plogp(new_exit + new_flow)
+                    - plogp(module_exits[c] + module_flows[c]);
+                let l = codelength(
+                    sum_exit + delta_exit,
+                    sum_exit_log_exit + delta_exit_log_exit,
+                    sum_size_log_size + delta_size_log_size,
+                );
+                if l < best_codelength {
+                    best = Some((c, new_exit, new_flow));
+                    best_codelength = l;
+                }
+            }
+
+            if let Some((c, new_exit, new_flow)) = best {
+                sum_exit += old_exit - module_exits[old] + new_exit - module_exits[c];
+                sum_exit_log_exit += plogp(old_exit) - plogp(module_exits[old]) + plogp(new_exit)
+                    - plogp(module_exits[c]);
+                sum_size_log_size += plogp(old_exit + old_flow)
+                    - plogp(module_exits[old] + module_flows[old])
+                    + plogp(new_exit + new_flow)
+                    - plogp(module_exits[c] + module_flows[c]);
+                module_exits[old] = old_exit;
+                module_flows[old] = old_flow;
+                module_exits[c] = new_exit;
+                module_flows[c] = new_flow;
+                modules[v] = c;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::{CsrLayout, GraphBuilder};
+
+    use crate::fixed_rule::algos::infomap::infomap;
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn two_cliques() {
+        let mut edges = vec![];
+        for group in [0u32, 5] {
+            for i in group..group + 5 {
+                for j in group..group + 5 {
+                    if i != j {
+                        edges.push((i, j, 1.));
+                    }
+                }
+            }
+        }
+        edges.push((4, 5, 1.));
+        edges.push((5, 4, 1.));
+        let graph = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(edges)
+            .build();
+        for undirected in [true, false] {
+            let modules = infomap(&graph, undirected, 0.15, 10, Poison::default()).unwrap();
+            assert_eq!(modules, vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1]);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::algos::biconnected_components::undirected_adjacency;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// The core number of each node: the largest `k` such that the node belongs to a subgraph
+/// in which every node has degree at least `k`. Edge directions are ignored.
+pub(crate) struct KCore;
+
+impl FixedRule for KCore {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let projection = edges.as_cached_directed_graph(true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let cores = core_numbers(&undirected_adjacency(graph), poison)?;
+        for (node, core) in indices.iter().cloned().zip(cores) {
+            out.put(vec![node, DataValue::from(core as i64)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// The linear time algorithm of Batagelj and Zaversnik: nodes are kept sorted by their
+/// current degree in buckets, and removing a node of the smallest degree decrements
+/// the degrees of its neighbours by moving them one bucket down.
+fn core_numbers(adj: &[Vec<u32>], poison: Poison) -> Result<Vec<usize>> {
+    let n = adj.len();
+    let mut degrees = adj.iter().map(|l| l.len()).collect::<Vec<_>>();
+    let max_degree = degrees.iter().cloned().max().unwrap_or(0);
+
+    // bucket_start[d] is the position in `sorted` of the first node of degree d
+    let mut bucket_start = vec![0; max_degree + 1];
+    for d in &degrees {
+        bucket_start[*d] += 1;
+    }
+    let mut start = 0;
+    for b in bucket_start.iter_mut() {
+        let count = *b;
+        *b = start;
+        start += count;
+    }
+    let mut sorted = vec![0; n];
+    let mut position = vec![0; n];
+    let mut next_free = bucket_start.clone();
+    for (v, d) in degrees.iter().enumerate() {
+        position[v] = next_free[*d];
+        sorted[position[v]] = v;
+        next_free[*d] += 1;
+    }
+
+    for i in 0..n {
+        if i % 1024 == 0 {
+            poison.check()?;
+        }
+        let v = sorted[i];
+        for &u in &adj[v] {
+            let u = u as usize;
+            if degrees[u] > degrees[v] {
+                // swap u with the first node of its bucket, then shrink the bucket
+                let du = degrees[u];
+                let first = sorted[bucket_start[du]];
+                if first != u {
+                    let (pu, pf) = (position[u], bucket_start[du]);
+                    sorted.swap(pu, pf);
+                    position[u] = pf;
+                    position[first] = pu;
+                }
+                bucket_start[du] += 1;
+                degrees[u] -= 1;
+            }
+        }
+    }
+    Ok(degrees)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixed_rule::algos::k_core::core_numbers;
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn clique_with_tail() {
+        // a 4-clique 0-1-2-3, with 4 attached to 3 and 5 attached to 4
+        let edges = [
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (1, 2),
+            (1, 3),
+            (2, 3),
+            (3, 4),
+            (4, 5),
+        ];
+        let mut adj = vec![vec![]; 6];
+        for (a, b) in edges {
+            adj[a as usize].push(b);
+            adj[b as usize].push(a);
+        }
+        let cores = core_numbers(&adj, Poison::default()).unwrap();
+        assert_eq!(cores, vec![3, 3, 3, 3, 1, 1]);
+    }
+}
@@ -34,11 +34,12 @@ impl FixedRule for MinimumSpanningForestKruskal {
         poison: Poison,
     ) -> Result<()> {
         let edges = payload.get_input(0)?;
-        let (graph, indices, _) = edges.as_directed_weighted_graph(true, true)?;
+        let projection = edges.as_cached_directed_weighted_graph(true, true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
         if graph.node_count() == 0 {
             return Ok(());
         }
-        let msp = kruskal(&graph, poison)?;
+        let msp = kruskal(graph, poison)?;
         for (src, dst, cost) in msp {
             out.put(vec![
                 indices[src as usize].clone(),
@@ -0,0 +1,358 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Community detection with the Leiden algorithm (Traag, Waltman & van Eck, 2019).
+///
+/// Unlike Louvain, every pass refines the communities found by local moving before
+/// aggregating the graph, so the communities returned are guaranteed to be connected.
+/// Edge directions are ignored.
+pub(crate) struct CommunityDetectionLeiden;
+
+impl FixedRule for CommunityDetectionLeiden {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let resolution = payload.pos_float_option("resolution", Some(1.0))?;
+        let max_iter = payload.pos_integer_option("max_iter", Some(10))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let communities = leiden(graph, resolution, max_iter, poison)?;
+        for (community, node) in communities.into_iter().zip(indices.iter().cloned()) {
+            out.put(vec![DataValue::from(community as i64), node]);
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// An undirected weighted network. Self-loops are stored in the adjacency lists
+/// with their full contribution to the degree of the node.
+struct Network {
+    adj: Vec<Vec<(usize, f64)>>,
+    degrees: Vec<f64>,
+}
+
+impl Network {
+    fn from_graph(graph: &DirectedCsrGraph<u32, (), f32>) -> Self {
+        let n = graph.node_count() as usize;
+        let mut adj: Vec<BTreeMap<usize, f64>> = vec![Default::default(); n];
+        for u in 0..n {
+            for target in graph.out_neighbors_with_values(u as u32) {
+                let v = target.target as usize;
+                let w = target.value as f64;
+                *adj[u].entry(v).or_default() += w;
+                *adj[v].entry(u).or_default() += w;
+            }
+        }
+        Self::from_adj(adj)
+    }
+
+    fn from_adj(adj: Vec<BTreeMap<usize, f64>>) -> Self {
+        let degrees = adj.iter().map(|nbrs| nbrs.values().sum()).collect();
+        let adj = adj
+            .into_iter()
+            .map(|nbrs| nbrs.into_iter().collect())
+            .collect();
+        Self { adj, degrees }
+    }
+
+    fn len(&self) -> usize {
+        self.adj.len()
+    }
+
+    /// Collapse every group of nodes sharing a label into a single node.
+    fn aggregate(&self, labels: &[usize], n_labels: usize) -> Self {
+        let mut adj: Vec<BTreeMap<usize, f64>> = vec![Default::default(); n_labels];
+        for (v, nbrs) in self.adj.iter().enumerate() {
+            let lv = labels[v];
+            for &(u, w) in nbrs {
+                *adj[lv].entry(labels[u]).or_default() += w;
+            }
+        }
+        Self::from_adj(adj)
+    }
+}
+
+pub(crate) fn leiden(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    resolution: f64,
+    max_iter: usize,
+    poison: Poison,
+) -> Result<Vec<usize>> {
+    let mut network = Network::from_graph(graph);
+    let n = network.len();
+    let two_m: f64 = network.degrees.iter().sum();
+    if two_m <= 0. {
+        return Ok((0..n).collect());
+    }
+
+    // membership[i] is the node of the current network containing the original node i
+    let mut membership: Vec<usize> = (0..n).collect();
+    let mut partition: Vec<usize> = (0..n).collect();
+    for _ in 0..max_iter {
+        move_nodes_fast(&network, &mut partition, resolution, two_m, &poison)?;
+        let n_communities = renumber(&mut partition);
+        if n_communities == network.len() {
+            break;
+        }
+        let mut refined = refine_partition(&network, &partition, resolution, two_m, &poison)?;
+        let n_refined = renumber(&mut refined);
+        if n_refined == network.len() {
+            break;
+        }
+        for m in membership.iter_mut() {
+            *m = refined[*m];
+        }
+        // the aggregate network starts from the unrefined partition
+        let mut next_partition = vec![0; n_refined];
+        for (v, r) in refined.iter().enumerate() {
+            next_partition[*r] = partition[v];
+        }
+        network = network.aggregate(&refined, n_refined);
+        partition = next_partition;
+    }
+    let mut communities = membership.into_iter().map(|m| partition[m]).collect_vec();
+    renumber(&mut communities);
+    Ok(communities)
+}
+
+/// Relabel in order of first appearance so that labels are `0..n`, returning `n`.
+pub(crate) fn renumber(labels: &mut [usize]) -> usize {
+    let mut mapping = vec![usize::MAX; labels.len()];
+    let mut n = 0;
+    for label in labels.iter_mut() {
+        if mapping[*label] == usize::MAX {
+            mapping[*label] = n;
+            n += 1;
+        }
+        *label = mapping[*label];
+    }
+    n
+}
+
+/// The local moving phase: visit nodes from a queue and move each to the community
+/// with the largest modularity gain, re-queueing neighbours that may now want to follow.
+fn move_nodes_fast(
+    network: &Network,
+    partition: &mut [usize],
+    resolution: f64,
+    two_m: f64,
+    poison: &Poison,
+) -> Result<()> {
+    let n = network.len();
+    let mut totals = vec![0.; n];
+    let mut sizes = vec![0usize; n];
+    for (v, c) in partition.iter().enumerate() {
+        totals[*c] += network.degrees[v];
+        sizes[*c] += 1;
+    }
+    let mut empty = (0..n).filter(|c| sizes[*c] == 0).collect_vec();
+    let mut queue: VecDeque<usize> = (0..n).collect();
+    let mut queued = vec![true; n];
+    let mut weights_to = vec![0.; n];
+    let mut touched = vec![];
+
+    while let Some(v) = queue.pop_front() {
+        poison.check()?;
+        queued[v] = false;
+        let k_v = network.degrees[v];
+        let old = partition[v];
+        totals[old] -= k_v;
+        sizes[old] -= 1;
+
+        for &(u, w) in &network.adj[v] {
+            if u == v {
+                continue;
+            }
+            let c = partition[u];
+            if weights_to[c] == 0. {
+                touched.push(c);
+            }
+            weights_to[c] += w;
+        }
+
+        let mut best = old;
+        let mut best_gain = weights_to[old] - resolution * k_v * totals[old] / two_m;
+        for &c in &touched {
+            let gain = weights_to[c] - resolution * k_v * totals[c] / two_m;
+            if gain > best_gain {
+                best = c;
+                best_gain = gain;
+            }
+        }
+        if best_gain < 0. && sizes[old] > 0 {
+            if let Some(c) = empty.pop() {
+                best = c;
+            }
+        }
+        for c in touched.drain(..) {
+            weights_to[c] = 0.;
+        }
+
+        totals[best] += k_v;
+        sizes[best] += 1;
+        partition[v] = best;
+        if best != old {
+            if sizes[old] == 0 {
+                empty.push(old);
+            }
+            for &(u, _) in &network.adj[v] {
+                if !queued[u] && partition[u] != best {
+                    queued[u] = true;
+                    queue.push_back(u);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The refinement phase: starting from singletons, merge nodes inside each community
+/// of `partition` into well-connected subcommunities. Only singletons are moved,
+/// so every refined community is connected.
+fn refine_partition(
+    network: &Network,
+    partition: &[usize],
+    resolution: f64,
+    two_m: f64,
+    poison: &Poison,
+) -> Result<Vec<usize>> {
+    let n = network.len();
+    let mut members: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut community_totals = vec![0.; n];
+    for (v, c) in partition.iter().enumerate() {
+        members[*c].push(v);
+        community_totals[*c] += network.degrees[v];
+    }
+
+    let mut refined: Vec<usize> = (0..n).collect();
+    let mut totals = network.degrees.clone();
+    let mut sizes = vec![1usize; n];
+    // weight of the edges between each node and the rest of its community
+    let mut internal = vec![0.; n];
+    for v in 0..n {
+        internal[v] = network.adj[v]
+            .iter()
+            .filter(|(u, _)| *u != v && partition[*u] == partition[v])
+            .map(|(_, w)| *w)
+            .sum();
+    }
+    // weight of the edges between each refined community and the rest of its community
+    let mut external = internal.clone();
+    let mut weights_to = vec![0.; n];
+    let mut touched = vec![];
+
+    for (c, nodes) in members.iter().enumerate() {
+        let total = community_totals[c];
+        for &v in nodes {
+            poison.check()?;
+            if sizes[refined[v]] != 1 {
+                continue;
+            }
+            let k_v = network.degrees[v];
+            if internal[v] < resolution * k_v * (total - k_v) / two_m {
+                continue;
+            }
+
+            for &(u, w) in &network.adj[v] {
+                if u == v || partition[u] != c {
+                    continue;
+                }
+                let r = refined[u];
+                if weights_to[r] == 0. {
+                    touched.push(r);
+                }
+                weights_to[r] += w;
+            }
+
+            let own = refined[v];
+            let mut best = own;
+            let mut best_gain = 0.;
+            for &r in &touched {
+                if r == own || external[r] < resolution * totals[r] * (total - totals[r]) / two_m {
+                    continue;
+                }
+                let gain = weights_to[r] - resolution * k_v * totals[r] / two_m;
+                if gain > best_gain {
+                    best = r;
+                    best_gain = gain;
+                }
+            }
+            if best != own {
+                let w = weights_to[best];
+                totals[best] += k_v;
+                sizes[best] += 1;
+                external[best] += internal[v] - 2. * w;
+                totals[own] = 0.;
+                sizes[own] = 0;
+                refined[v] = best;
+            }
+            for r in touched.drain(..) {
+                weights_to[r] = 0.;
+            }
+        }
+    }
+    Ok(refined)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::{CsrLayout, GraphBuilder};
+
+    use crate::fixed_rule::algos::leiden::leiden;
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn two_cliques() {
+        let mut edges = vec![];
+        for group in [0u32, 5] {
+            for i in group..group + 5 {
+                for j in i + 1..group + 5 {
+                    edges.push((i, j, 1.));
+                }
+            }
+        }
+        edges.push((4, 5, 1.));
+        let graph = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(edges)
+            .build();
+        let communities = leiden(&graph, 1., 10, Poison::default()).unwrap();
+        assert_eq!(communities, vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,384 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph};
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRuleInputRelation, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::graph_cache::GraphProjection;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Maximum flow from the source nodes to the sink nodes, computed with Dinic's algorithm.
+/// Returns the net flow along every edge carrying flow.
+pub(crate) struct MaxFlow;
+
+impl FixedRule for MaxFlow {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let (projection, network) = match solve(&payload, poison)? {
+            None => return Ok(()),
+            Some(res) => res,
+        };
+        let indices = &projection.indices;
+        for (from, to, flow) in network.edge_flows() {
+            out.put(vec![
+                indices[from].clone(),
+                indices[to].clone(),
+                DataValue::from(flow),
+            ]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// The minimum cut separating the source nodes from the sink nodes.
+/// Returns every node together with whether it is on the source side of the cut.
+pub(crate) struct MinCut;
+
+impl FixedRule for MinCut {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let (projection, network) = match solve(&payload, poison)? {
+            None => return Ok(()),
+            Some(res) => res,
+        };
+        let indices = &projection.indices;
+        let source_side = network.source_side();
+        for (idx, node) in indices.iter().enumerate() {
+            out.put(vec![node.clone(), DataValue::from(source_side[idx])]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The flow from the sources to the sinks is unbounded")]
+#[diagnostic(code(algo::unbounded_flow))]
+#[diagnostic(help("a path from a source to a sink has edges of infinite capacity only"))]
+struct UnboundedFlowError(#[label] SourceSpan);
+
+type WeightedProjection = Arc<GraphProjection<DirectedCsrGraph<u32, (), f32>>>;
+
+fn solve(
+    payload: &FixedRulePayload<'_, '_>,
+    poison: Poison,
+) -> Result<Option<(WeightedProjection, FlowNetwork)>> {
+    let edges = payload.get_input(0)?;
+    let sources = payload.get_input(1)?;
+    let sinks = payload.get_input(2)?;
+    let undirected = payload.bool_option("undirected", Some(false))?;
+
+    let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+    let (graph, indices, inv_indices) = (
+        &projection.graph,
+        &projection.indices,
+        &projection.inv_indices,
+    );
+    let collect_nodes = |rel: FixedRuleInputRelation<'_, '_>| -> Result<BTreeSet<usize>> {
+        let mut nodes = BTreeSet::new();
+        for tuple in rel.iter()? {
+            let tuple = tuple?;
+            if let Some(idx) = inv_indices.get(&tuple[0]) {
+                nodes.insert(*idx as usize);
+            }
+        }
+        Ok(nodes)
+    };
+    let source_nodes = collect_nodes(sources)?;
+    let sink_nodes = collect_nodes(sinks)?;
+    if let Some(node) = source_nodes.intersection(&sink_nodes).next() {
+        bail!(BadExprValueError(
+            indices[*node].clone(),
+            sinks.span(),
+            "a node cannot be both a source and a sink".to_string()
+        ));
+    }
+    if source_nodes.is_empty() || sink_nodes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut network = FlowNetwork::new(graph, &source_nodes, &sink_nodes);
+    network.max_flow(poison, payload.span())?;
+    Ok(Some((projection.clone(), network)))
+}
+
+/// A residual network. Arcs are stored in pairs, arc `i ^ 1` being the reverse of arc `i`,
+/// so that the flow along an arc is always the negation of the flow along its reverse.
+struct FlowNetwork {
+    adj: Vec<Vec<usize>>,
+    heads: Vec<usize>,
+    capacities: Vec<f64>,
+    flows: Vec<f64>,
+    n_nodes: usize,
+    source: usize,
+    sink: usize,
+    epsilon: f64,
+}
+
+impl FlowNetwork {
+    /// Build the network with an extra super source linked to all sources,
+    /// and an extra super sink linked from all sinks.
+    fn new(
+        graph: &DirectedCsrGraph<u32, (), f32>,
+        sources: &BTreeSet<usize>,
+        sinks: &BTreeSet<usize>,
+    ) -> Self {
+        let n_nodes = graph.node_count() as usize;
+        let mut capacities: BTreeMap<(usize, usize), (f64, f64)> = BTreeMap::new();
+        for u in 0..n_nodes {
+            for t in graph.out_neighbors_with_values(u as u32) {
+                let v = t.target as usize;
+                let c = t.value as f64;
+                if u < v {
+                    capacities.entry((u, v)).or_default().0 += c;
+                } else if u > v {
+                    capacities.entry((v, u)).or_default().1 += c;
+                }
+            }
+        }
+        // infinite capacities are left out of the tolerance, as they are detected as unbounded flows
+        let max_capacity = capacities
+            .values()
+            .flat_map(|(a, b)| [*a, *b])
+            .filter(|c| c.is_finite())
+            .fold(0., f64::max);
+
+        let mut network = Self {
+            adj: vec![vec![]; n_nodes + 2],
+            heads: vec![],
+            capacities: vec![],
+            flows: vec![],
+            n_nodes,
+            source: n_nodes,
+            sink: n_nodes + 1,
+            epsilon: max_capacity * 1e-12,
+        };
+        for ((u, v), (forward, backward)) in capacities {
+            network.add_arc_pair(u, v, forward, backward);
+        }
+        for s in sources {
+            network.add_arc_pair(network.source, *s, f64::INFINITY, 0.);
+        }
+        for t in sinks {
+            network.add_arc_pair(*t, network.sink, f64::INFINITY, 0.);
+        }
+        network
+    }
+
+    fn add_arc_pair(&mut self, u: usize, v: usize, forward: f64, backward: f64) {
+        self.adj[u].push(self.heads.len());
+        self.heads.push(v);
+        self.capacities.push(forward);
+        self.flows.push(0.);
+        self.adj[v].push(self.heads.len());
+        self.heads.push(u);
+        self.capacities.push(backward);
+        self.flows.push(0.);
+    }
+
+    fn residual(&self, arc: usize) -> f64 {
+        self.capacities[arc] - self.flows[arc]
+    }
+
+    /// Distances from the super source in the residual network.
+    fn levels(&self) -> Vec<usize> {
+        let mut levels = vec![usize::MAX; self.adj.len()];
+        let mut queue = VecDeque::from([self.source]);
+        levels[self.source] = 0;
+        while let Some(u) = queue.pop_front() {
+            for &arc in &self.adj[u] {
+                let v = self.heads[arc];
+                if levels[v] == usize::MAX && self.residual(arc) > self.epsilon {
+                    levels[v] = levels[u] + 1;
+                    queue.push_back(v);
+                }
+            }
+        }
+        levels
+    }
+
+    fn max_flow(&mut self, poison: Poison, span: SourceSpan) -> Result<()> {
+        loop {
+            let levels = self.levels();
+            if levels[self.sink] == usize::MAX {
+                return Ok(());
+            }
+            self.blocking_flow(&levels, span)?;
+            poison.check()?;
+        }
+    }
+
+    /// Saturate all shortest augmenting paths in the level graph, searching iteratively
+    /// so that long paths do not overflow the stack.
+    fn blocking_flow(&mut self, levels: &[usize], span: SourceSpan) -> Result<()> {
+        let mut next_arc = vec![0; self.adj.len()];
+        let mut path: Vec<usize> = vec![];
+        let mut u = self.source;
+        loop {
+            if u == self.sink {
+                let bottleneck = path
+                    .iter()
+                    .map(|arc| self.residual(*arc))
+                    .fold(f64::INFINITY, f64::min);
+                if bottleneck.is_infinite() {
+                    bail!(UnboundedFlowError(span));
+                }
+                for &arc in &path {
+                    self.flows[arc] += bottleneck;
+                    self.flows[arc ^ 1] -= bottleneck;
+                }
+                path.clear();
+                u = self.source;
+                continue;
+            }
+            let mut advanced = false;
+            while next_arc[u] < self.adj[u].len() {
+                let arc = self.adj[u][next_arc[u]];
+                let v = self.heads[arc];
+                if levels[v] == levels[u] + 1 && self.residual(arc) > self.epsilon {
+                    path.push(arc);
+                    u = v;
+                    advanced = true;
+                    break;
+                }
+                next_arc[u] += 1;
+            }
+            if !advanced {
+                match path.pop() {
+                    None => return Ok(()),
+                    Some(arc) => {
+                        u = self.heads[arc ^ 1];
+                        next_arc[u] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Net flow along each pair of nodes of the original graph, oriented in the direction of flow.
+    fn edge_flows(&self) -> Vec<(usize, usize, f64)> {
+        let mut ret = vec![];
+        for arc in (0..self.heads.len()).step_by(2) {
+            let from = self.heads[arc ^ 1];
+            let to = self.heads[arc];
+            if from >= self.n_nodes || to >= self.n_nodes {
+                continue;
+            }
+            let flow = self.flows[arc];
+            if flow > self.epsilon {
+                ret.push((from, to, flow));
+            } else if flow < -self.epsilon {
+                ret.push((to, from, -flow));
+            }
+        }
+        ret
+    }
+
+    /// Nodes reachable from the sources in the residual network after the maximum flow
+    /// has been found form the source side of a minimum cut.
+    fn source_side(&self) -> Vec<bool> {
+        let levels = self.levels();
+        levels[..self.n_nodes]
+            .iter()
+            .map(|l| *l != usize::MAX)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use graph::prelude::{CsrLayout, GraphBuilder};
+
+    use crate::fixed_rule::algos::max_flow::FlowNetwork;
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn classic_network() {
+        // the example network from CLRS
+        let edges: Vec<(u32, u32, f32)> = vec![
+            (0, 1, 16.),
+            (0, 2, 13.),
+            (2, 1, 4.),
+            (1, 3, 12.),
+            (3, 2, 9.),
+            (2, 4, 14.),
+            (4, 3, 7.),
+            (3, 5, 20.),
+            (4, 5, 4.),
+        ];
+        let graph = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(edges)
+            .build();
+        let mut network = FlowNetwork::new(&graph, &BTreeSet::from([0]), &BTreeSet::from([5]));
+        network
+            .max_flow(Poison::default(), Default::default())
+            .unwrap();
+        let total: f64 = network
+            .edge_flows()
+            .into_iter()
+            .filter(|(from, _, _)| *from == 0)
+            .map(|(_, _, f)| f)
+            .sum();
+        assert_eq!(total, 23.);
+        assert_eq!(
+            network.source_side(),
+            vec![true, true, true, false, true, false]
+        );
+    }
+    #[test]
+    fn unbounded_flow() {
+        let edges: Vec<(u32, u32, f32)> = vec![(0, 1, f32::INFINITY), (1, 2, f32::INFINITY)];
+        let graph = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(edges)
+            .build();
+        let mut network = FlowNetwork::new(&graph, &BTreeSet::from([0]), &BTreeSet::from([2]));
+        assert!(network
+            .max_flow(Poison::default(), Default::default())
+            .is_err());
+    }
+}
@@ -0,0 +1,311 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues, Graph, Target};
+use miette::Result;
+use ndarray::Array1;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::{DataValue, Vector};
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Node embeddings learnt by training a skip-gram model with negative sampling
+/// on random walks over the graph.
+///
+/// With `biased` set, the walks are the second-order walks of node2vec controlled by the
+/// return parameter `p` and the in-out parameter `q`, otherwise they are the first-order
+/// walks of DeepWalk. Edge weights are respected in both cases.
+pub(crate) struct Node2Vec {
+    biased: bool,
+}
+
+impl Node2Vec {
+    pub(crate) fn new(biased: bool) -> Self {
+        Self { biased }
+    }
+}
+
+impl FixedRule for Node2Vec {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let (p, q) = if self.biased {
+            (
+                payload.pos_float_option("p", Some(1.))?,
+                payload.pos_float_option("q", Some(1.))?,
+            )
+        } else {
+            (1., 1.)
+        };
+        let walk_config = WalkConfig {
+            walks_per_node: payload.pos_integer_option("walks_per_node", Some(10))?,
+            walk_length: payload.pos_integer_option("walk_length", Some(20))?,
+            p,
+            q,
+        };
+        let train_config = SkipGramConfig {
+            dimensions: payload.pos_integer_option("dimensions", Some(64))?,
+            window: payload.pos_integer_option("window", Some(5))?,
+            negative: payload.pos_integer_option("negative", Some(5))?,
+            epochs: payload.pos_integer_option("epochs", Some(1))?,
+            learning_rate: payload.pos_float_option("learning_rate", Some(0.025))?,
+        };
+        let mut rng = match payload.expr_option("seed", None) {
+            Ok(_) => StdRng::seed_from_u64(payload.non_neg_integer_option("seed", None)? as u64),
+            Err(_) => StdRng::from_entropy(),
+        };
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let walks = generate_walks(graph, &walk_config, &mut rng, &poison)?;
+        let embeddings = train_skip_gram(indices.len(), &walks, &train_config, &mut rng, &poison)?;
+        let dim = train_config.dimensions;
+        for (idx, node) in indices.iter().cloned().enumerate() {
+            let v = Array1::from(embeddings[idx * dim..(idx + 1) * dim].to_vec());
+            out.put(vec![node, DataValue::Vec(Vector::F32(v))]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+struct WalkConfig {
+    walks_per_node: usize,
+    walk_length: usize,
+    p: f64,
+    q: f64,
+}
+
+struct SkipGramConfig {
+    dimensions: usize,
+    window: usize,
+    negative: usize,
+    epochs: usize,
+    learning_rate: f64,
+}
+
+fn generate_walks(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    config: &WalkConfig,
+    rng: &mut impl Rng,
+    poison: &Poison,
+) -> Result<Vec<Vec<u32>>> {
+    let unbiased = config.p == 1. && config.q == 1.;
+    let mut starts = (0..graph.node_count()).collect::<Vec<_>>();
+    let mut walks = Vec::with_capacity(starts.len() * config.walks_per_node);
+    let mut weights = vec![];
+    for _ in 0..config.walks_per_node {
+        starts.shuffle(rng);
+        for &start in &starts {
+            poison.check()?;
+            let mut walk = vec![start];
+            while walk.len() < config.walk_length {
+                let cur = walk[walk.len() - 1];
+                let neighbours = graph.out_neighbors_with_values(cur).as_slice();
+                if neighbours.is_empty() {
+                    break;
+                }
+                weights.clear();
+                match walk.len().checked_sub(2).map(|i| walk[i]) {
+                    Some(prev) if !unbiased => {
+                        let prev_neighbours = graph.out_neighbors_with_values(prev).as_slice();
+                        weights.extend(neighbours.iter().map(|t| {
+                            let bias = if t.target == prev {
+                                1. / config.p
+                            } else if is_neighbour(prev_neighbours, t.target) {
+                                1.
+                            } else {
+                                1. / config.q
+                            };
+                            t.value as f64 * bias
+                        }));
+                    }
+                    _ => weights.extend(neighbours.iter().map(|t| t.value as f64)),
+                }
+                match WeightedIndex::new(&weights) {
+                    Ok(dist) => walk.push(neighbours[dist.sample(rng)].target),
+                    // all outgoing edges have zero weight
+                    Err(_) => break,
+                }
+            }
+            walks.push(walk);
+        }
+    }
+    Ok(walks)
+}
+
+fn is_neighbour(sorted_neighbours: &[Target<u32, f32>], node: u32) -> bool {
+    sorted_neighbours
+        .binary_search_by_key(&node, |t| t.target)
+        .is_ok()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1. / (1. + (-x.clamp(-6., 6.)).exp())
+}
+
+/// Train skip-gram with negative sampling as in word2vec, treating walks as sentences.
+/// Returns the input vectors of all nodes, laid out contiguously.
+fn train_skip_gram(
+    n_nodes: usize,
+    walks: &[Vec<u32>],
+    config: &SkipGramConfig,
+    rng: &mut impl Rng,
+    poison: &Poison,
+) -> Result<Vec<f32>> {
+    let dim = config.dimensions;
+    let mut inputs: Vec<f32> = (0..n_nodes * dim)
+        .map(|_| (rng.gen::<f32>() - 0.5) / dim as f32)
+        .collect();
+    let mut outputs = vec![0f32; n_nodes * dim];
+    if n_nodes == 0 {
+        return Ok(inputs);
+    }
+
+    // negative samples are drawn from the unigram distribution raised to the power 3/4
+    let mut counts = vec![0usize; n_nodes];
+    for node in walks.iter().flatten() {
+        counts[*node as usize] += 1;
+    }
+    let noise = WeightedIndex::new(counts.iter().map(|c| (*c as f64).powf(0.75))).unwrap();
+
+    let total_steps = (config.epochs * walks.len()) as f64;
+    let mut step = 0;
+    let mut gradient = vec![0f32; dim];
+    for _ in 0..config.epochs {
+        for walk in walks {
+            poison.check()?;
+            let progress = step as f64 / total_steps;
+            let lr =
+                (config.learning_rate * (1. - progress)).max(config.learning_rate * 1e-4) as f32;
+            step += 1;
+            for (i, center) in walk.iter().enumerate() {
+                // shrink the window randomly, which weighs nearer contexts more
+                let window = rng.gen_range(1..=config.window);
+                let lo = i.saturating_sub(window);
+                let hi = (i + window + 1).min(walk.len());
+                for (j, context) in walk.iter().enumerate().take(hi).skip(lo) {
+                    if i == j {
+                        continue;
+                    }
+                    let input = *center as usize * dim;
+                    gradient.iter_mut().for_each(|g| *g = 0.);
+                    for k in 0..=config.negative {
+                        let (target, label) = if k == 0 {
+                            (*context as usize, 1.)
+                        } else {
+                            let neg = noise.sample(rng);
+                            if neg == *context as usize {
+                                continue;
+                            }
+                            (neg, 0.)
+                        };
+                        let output = target * dim;
+                        let dot: f32 = (0..dim)
+                            .map(|d| inputs[input + d] * outputs[output + d])
+                            .sum();
+                        let g = (label - sigmoid(dot)) * lr;
+                        for d in 0..dim {
+                            gradient[d] += g * outputs[output + d];
+                            outputs[output + d] += g * inputs[input + d];
+                        }
+                    }
+                    for d in 0..dim {
+                        inputs[input + d] += gradient[d];
+                    }
+                }
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::{CsrLayout, GraphBuilder};
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+
+    use crate::fixed_rule::algos::node2vec::{
+        generate_walks, train_skip_gram, SkipGramConfig, WalkConfig,
+    };
+    use crate::runtime::db::Poison;
+
+    #[test]
+    fn separates_communities() {
+        // two dense clusters joined by a single edge
+        let mut edges = vec![];
+        for group in [0u32, 6] {
+            for i in group..group + 6 {
+                for j in group..group + 6 {
+                    if i != j {
+                        edges.push((i, j, 1f32));
+                    }
+                }
+            }
+        }
+        edges.push((5, 6, 1.));
+        edges.push((6, 5, 1.));
+        let graph = GraphBuilder::new()
+            .csr_layout(CsrLayout::Sorted)
+            .edges_with_values(edges)
+            .build();
+        let mut rng = StdRng::seed_from_u64(42);
+        let walk_config = WalkConfig {
+            walks_per_node: 20,
+            walk_length: 20,
+            p: 1.,
+            q: 0.5,
+        };
+        let walks = generate_walks(&graph, &walk_config, &mut rng, &Poison::default()).unwrap();
+        assert_eq!(walks.len(), 12 * 20);
+        assert!(walks.iter().all(|w| w.len() == 20));
+
+        let config = SkipGramConfig {
+            dimensions: 16,
+            window: 3,
+            negative: 5,
+            epochs: 5,
+            learning_rate: 0.025,
+        };
+        let emb = train_skip_gram(12, &walks, &config, &mut rng, &Poison::default()).unwrap();
+        let cosine = |a: usize, b: usize| {
+            let va = &emb[a * 16..(a + 1) * 16];
+            let vb = &emb[b * 16..(b + 1) * 16];
+            let dot: f32 = va.iter().zip(vb).map(|(x, y)| x * y).sum();
+            let na: f32 = va.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let nb: f32 = vb.iter().map(|x| x * x).sum::<f32>().sqrt();
+            dot / (na * nb)
+        };
+        assert!(cosine(0, 1) > cosine(0, 11));
+        assert!(cosine(10, 11) > cosine(1, 11));
+    }
+}
@@ -10,14 +10,16 @@ use std::collections::BTreeMap;
 
 #[cfg(not(feature = "rayon"))]
 use approx::AbsDiffEq;
-use graph::prelude::{page_rank, PageRankConfig};
-use miette::Result;
+use graph::prelude::{
+    page_rank, DirectedCsrGraph, DirectedNeighborsWithValues, Graph, PageRankConfig,
+};
+use miette::{ensure, Result};
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::fixed_rule::{BadExprValueError, FixedRule, FixedRulePayload};
 use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
 use crate::runtime::temp_store::RegularTempStore;
@@ -38,14 +40,15 @@ impl FixedRule for PageRank {
         let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))? as f32;
         let iterations = payload.pos_integer_option("iterations", Some(10))?;
 
-        let (graph, indices, _) = edges.as_directed_graph(undirected)?;
+        let projection = edges.as_cached_directed_graph(undirected)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
 
         if indices.is_empty() {
             return Ok(());
         }
 
         let (ranks, _n_run, _) = page_rank(
-            &graph,
+            graph,
             PageRankConfig::new(iterations, epsilon as f64, theta),
         );
 
@@ -65,6 +68,127 @@ impl FixedRule for PageRank {
     }
 }
 
+/// PageRank where the random surfer teleports back to a weighted set of seed nodes
+/// instead of to a uniformly random node.
+pub(crate) struct PersonalizedPageRank;
+
+impl FixedRule for PersonalizedPageRank {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let seeds = payload.get_input(1)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let theta = payload.unit_interval_option("theta", Some(0.85))?;
+        let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = payload.pos_integer_option("iterations", Some(100))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices, inv_indices) = (
+            &projection.graph,
+            &projection.indices,
+            &projection.inv_indices,
+        );
+
+        let mut personalization = vec![0.; indices.len()];
+        for tuple in seeds.iter()? {
+            let tuple = tuple?;
+            let weight = match tuple.get(1) {
+                None => 1.,
+                Some(v) => {
+                    let w = v.get_float().unwrap_or(-1.);
+                    ensure!(
+                        w.is_finite() && w >= 0.,
+                        BadExprValueError(
+                            v.clone(),
+                            seeds.span(),
+                            "the weight of a seed node must be a non-negative number".to_string()
+                        )
+                    );
+                    w
+                }
+            };
+            if let Some(idx) = inv_indices.get(&tuple[0]) {
+                personalization[*idx as usize] += weight;
+            }
+        }
+        let total: f64 = personalization.iter().sum();
+        if total <= 0. {
+            return Ok(());
+        }
+        for p in personalization.iter_mut() {
+            *p /= total;
+        }
+
+        let ranks =
+            personalized_page_rank(graph, &personalization, theta, epsilon, iterations, poison)?;
+        for (node, score) in indices.iter().cloned().zip(ranks) {
+            if score > 0. {
+                out.put(vec![node, DataValue::from(score)]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Power iteration for personalized PageRank. The mass of dangling nodes
+/// is returned to the seeds, so the scores always sum to one.
+fn personalized_page_rank(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    personalization: &[f64],
+    theta: f64,
+    epsilon: f64,
+    iterations: usize,
+    poison: Poison,
+) -> Result<Vec<f64>> {
+    let n = graph.node_count() as usize;
+    let out_weights: Vec<f64> = (0..n)
+        .map(|u| {
+            graph
+                .out_neighbors_with_values(u as u32)
+                .map(|t| t.value as f64)
+                .sum()
+        })
+        .collect();
+    let mut ranks = personalization.to_vec();
+    for _ in 0..iterations {
+        let mut next = vec![0.; n];
+        let mut dangling = 0.;
+        for (u, rank) in ranks.iter().enumerate() {
+            if out_weights[u] > 0. {
+                for t in graph.out_neighbors_with_values(u as u32) {
+                    next[t.target as usize] += theta * rank * t.value as f64 / out_weights[u];
+                }
+            } else {
+                dangling += rank;
+            }
+        }
+        let restart = 1. - theta + theta * dangling;
+        for (r, p) in next.iter_mut().zip(personalization) {
+            *r += restart * p;
+        }
+        let diff: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+        ranks = next;
+        if diff < epsilon {
+            break;
+        }
+        poison.check()?;
+    }
+    Ok(ranks)
+}
+
 #[cfg(not(feature = "rayon"))]
 fn pagerank(
     edges: &[Vec<usize>],
@@ -35,7 +35,12 @@ impl FixedRule for MinimumSpanningTreePrim {
         poison: Poison,
     ) -> Result<()> {
         let edges = payload.get_input(0)?;
-        let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(true, true)?;
+        let projection = edges.as_cached_directed_weighted_graph(true, true)?;
+        let (graph, indices, inv_indices) = (
+            &projection.graph,
+            &projection.indices,
+            &projection.inv_indices,
+        );
         if graph.node_count() == 0 {
             return Ok(());
         }
@@ -61,7 +66,7 @@ impl FixedRule for MinimumSpanningTreePrim {
                 })?
             }
         };
-        let msp = prim(&graph, starting, poison)?;
+        let msp = prim(graph, starting, poison)?;
         for (src, dst, cost) in msp {
             out.put(vec![
                 indices[src as usize].clone(),
@@ -42,7 +42,12 @@ impl FixedRule for ShortestPathDijkstra {
         let undirected = payload.bool_option("undirected", Some(false))?;
         let keep_ties = payload.bool_option("keep_ties", Some(false))?;
 
-        let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices, inv_indices) = (
+            &projection.graph,
+            &projection.indices,
+            &projection.inv_indices,
+        );
 
         let mut starting_nodes = BTreeSet::new();
         for tuple in starting.iter()? {
@@ -67,85 +72,53 @@ impl FixedRule for ShortestPathDijkstra {
             }
         };
 
-        if starting_nodes.len() <= 1 {
-            for start in starting_nodes {
-                let res = if let Some(tn) = &termination_nodes {
-                    if tn.len() == 1 {
-                        let single = Some(*tn.iter().next().unwrap());
-                        if keep_ties {
-                            dijkstra_keep_ties(&graph, start, &single, &(), &(), poison.clone())?
-                        } else {
-                            dijkstra(&graph, start, &single, &(), &())
-                        }
-                    } else if keep_ties {
-                        dijkstra_keep_ties(&graph, start, tn, &(), &(), poison.clone())?
+        let shortest_paths_from = |start: u32| -> Result<Vec<(u32, f32, Vec<u32>)>> {
+            match &termination_nodes {
+                Some(tn) if tn.len() == 1 => {
+                    let single = Some(*tn.iter().next().unwrap());
+                    if keep_ties {
+                        dijkstra_keep_ties(graph, start, &single, &(), &(), poison.clone())
+                    } else {
+                        dijkstra(graph, start, &single, &(), &(), &poison)
+                    }
+                }
+                Some(tn) => {
+                    if keep_ties {
+                        dijkstra_keep_ties(graph, start, tn, &(), &(), poison.clone())
                     } else {
-                        dijkstra(&graph, start, tn, &(), &())
+                        dijkstra(graph, start, tn, &(), &(), &poison)
                     }
-                } else {
-                    dijkstra(&graph, start, &(), &(), &())
-                };
-                for (target, cost, path) in res {
-                    let t = vec![
-                        indices[start as usize].clone(),
-                        indices[target as usize].clone(),
-                        DataValue::from(cost as f64),
-                        DataValue::List(
-                            path.into_iter()
-                                .map(|u| indices[u as usize].clone())
-                                .collect_vec(),
-                        ),
-                    ];
-                    out.put(t)
                 }
+                None => dijkstra(graph, start, &(), &(), &(), &poison),
             }
+        };
+
+        // each starting node is searched independently, so several of them run in parallel,
+        // all stopping as soon as the query is killed
+        let all_res: Vec<_> = if starting_nodes.len() <= 1 {
+            starting_nodes
+                .into_iter()
+                .map(|start| Ok((start, shortest_paths_from(start)?)))
+                .collect::<Result<_>>()?
         } else {
-            let it = starting_nodes.into_par_iter();
-
-            let all_res: Vec<_> = it
-                .map(|start| -> Result<(u32, Vec<(u32, f32, Vec<u32>)>)> {
-                    Ok((
-                        start,
-                        if let Some(tn) = &termination_nodes {
-                            if tn.len() == 1 {
-                                let single = Some(*tn.iter().next().unwrap());
-                                if keep_ties {
-                                    dijkstra_keep_ties(
-                                        &graph,
-                                        start,
-                                        &single,
-                                        &(),
-                                        &(),
-                                        poison.clone(),
-                                    )?
-                                } else {
-                                    dijkstra(&graph, start, &single, &(), &())
-                                }
-                            } else if keep_ties {
-                                dijkstra_keep_ties(&graph, start, tn, &(), &(), poison.clone())?
-                            } else {
-                                dijkstra(&graph, start, tn, &(), &())
-                            }
-                        } else {
-                            dijkstra(&graph, start, &(), &(), &())
-                        },
-                    ))
-                })
-                .collect::<Result<_>>()?;
-            for (start, res) in all_res {
-                for (target, cost, path) in res {
-                    let t = vec![
-                        indices[start as usize].clone(),
-                        indices[target as usize].clone(),
-                        DataValue::from(cost as f64),
-                        DataValue::List(
-                            path.into_iter()
-                                .map(|u| indices[u as usize].clone())
-                                .collect_vec(),
-                        ),
-                    ];
-                    out.put(t)
-                }
+            starting_nodes
+                .into_par_iter()
+                .map(|start| Ok((start, shortest_paths_from(start)?)))
+                .collect::<Result<_>>()?
+        };
+        for (start, res) in all_res {
+            for (target, cost, path) in res {
+                let t = vec![
+                    indices[start as usize].clone(),
+                    indices[target as usize].clone(),
+                    DataValue::from(cost as f64),
+                    DataValue::List(
+                        path.into_iter()
+                            .map(|u| indices[u as usize].clone())
+                            .collect_vec(),
+                    ),
+                ];
+                out.put(t)
             }
         }
 
@@ -277,7 +250,8 @@ pub(crate) fn dijkstra<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
     goals: &G,
     forbidden_edges: &FE,
     forbidden_nodes: &FN,
-) -> Vec<(u32, f32, Vec<u32>)> {
+    poison: &Poison,
+) -> Result<Vec<(u32, f32, Vec<u32>)>> {
     let graph_size = edges.node_count();
     let mut distance = vec![f32::INFINITY; graph_size as usize];
     let mut pq = PriorityQueue::new();
@@ -290,6 +264,7 @@ pub(crate) fn dijkstra<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
         if cost > distance[node as usize] {
             continue;
         }
+        poison.check()?;
 
         for target in edges.out_neighbors_with_values(node) {
             let nxt_node = target.target;
@@ -335,7 +310,7 @@ pub(crate) fn dijkstra<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
         })
         .collect_vec();
 
-    ret
+    Ok(ret)
 }
 
 pub(crate) fn dijkstra_keep_ties<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
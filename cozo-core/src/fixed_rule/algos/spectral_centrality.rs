@@ -0,0 +1,226 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use graph::prelude::{DirectedCsrGraph, DirectedNeighborsWithValues};
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Hub and authority scores of Kleinberg's HITS algorithm.
+pub(crate) struct Hits;
+
+impl FixedRule for Hits {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = payload.pos_integer_option("iterations", Some(100))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let n = indices.len();
+        let mut hubs = vec![1. / (n as f64).sqrt(); n];
+        let mut authorities = vec![0.; n];
+        for _ in 0..iterations {
+            let mut next_authorities = vec![0.; n];
+            for (u, hub) in hubs.iter().enumerate() {
+                for t in graph.out_neighbors_with_values(u as u32) {
+                    next_authorities[t.target as usize] += t.value as f64 * hub;
+                }
+            }
+            l2_normalize(&mut next_authorities);
+            let mut next_hubs = vec![0.; n];
+            for (u, hub) in next_hubs.iter_mut().enumerate() {
+                for t in graph.out_neighbors_with_values(u as u32) {
+                    *hub += t.value as f64 * next_authorities[t.target as usize];
+                }
+            }
+            l2_normalize(&mut next_hubs);
+            let diff =
+                l1_distance(&hubs, &next_hubs) + l1_distance(&authorities, &next_authorities);
+            hubs = next_hubs;
+            authorities = next_authorities;
+            if diff < n as f64 * epsilon {
+                break;
+            }
+            poison.check()?;
+        }
+
+        for ((node, hub), authority) in indices.iter().cloned().zip(hubs).zip(authorities) {
+            out.put(vec![node, DataValue::from(hub), DataValue::from(authority)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// Eigenvector centrality: the score of a node is proportional to the sum of the scores
+/// of the nodes linking to it.
+pub(crate) struct EigenvectorCentrality;
+
+impl FixedRule for EigenvectorCentrality {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = payload.pos_integer_option("iterations", Some(100))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let n = indices.len();
+        let mut scores = vec![1. / (n as f64).sqrt(); n];
+        for _ in 0..iterations {
+            // iterating with `A + I` instead of `A` has the same eigenvectors,
+            // but does not oscillate on bipartite graphs
+            let mut next = scores.clone();
+            propagate(graph, &scores, 1., &mut next);
+            l2_normalize(&mut next);
+            let diff = l1_distance(&scores, &next);
+            scores = next;
+            if diff < n as f64 * epsilon {
+                break;
+            }
+            poison.check()?;
+        }
+
+        for (node, score) in indices.iter().cloned().zip(scores) {
+            out.put(vec![node, DataValue::from(score)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Katz centrality: every node receives the base score `beta`, plus the scores of the nodes
+/// linking to it attenuated by `alpha`.
+pub(crate) struct KatzCentrality;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Katz centrality did not converge after {0} iterations")]
+#[diagnostic(code(algo::katz_not_converged))]
+#[diagnostic(help(
+    "'alpha' must be smaller than the reciprocal of the largest eigenvalue of the adjacency matrix"
+))]
+struct KatzNotConvergedError(usize, #[label] SourceSpan);
+
+impl FixedRule for KatzCentrality {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = payload.get_input(0)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let alpha = payload.pos_float_option("alpha", Some(0.1))?;
+        let beta = payload.float_option("beta", Some(1.0))?;
+        let normalized = payload.bool_option("normalized", Some(true))?;
+        let epsilon = payload.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = payload.pos_integer_option("iterations", Some(100))?;
+
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let n = indices.len();
+        let mut scores = vec![0.; n];
+        let mut converged = false;
+        for _ in 0..iterations {
+            let mut next = vec![beta; n];
+            propagate(graph, &scores, alpha, &mut next);
+            let diff = l1_distance(&scores, &next);
+            scores = next;
+            if diff < n as f64 * epsilon {
+                converged = true;
+                break;
+            }
+            poison.check()?;
+        }
+        if !converged {
+            bail!(KatzNotConvergedError(iterations, payload.span()))
+        }
+        if normalized {
+            l2_normalize(&mut scores);
+        }
+
+        for (node, score) in indices.iter().cloned().zip(scores) {
+            out.put(vec![node, DataValue::from(score)]);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// Add `factor` times the weighted scores of the in-neighbours of each node to `target`.
+fn propagate(
+    graph: &DirectedCsrGraph<u32, (), f32>,
+    scores: &[f64],
+    factor: f64,
+    target: &mut [f64],
+) {
+    for (u, score) in scores.iter().enumerate() {
+        for t in graph.out_neighbors_with_values(u as u32) {
+            target[t.target as usize] += factor * t.value as f64 * score;
+        }
+    }
+}
+
+fn l2_normalize(v: &mut [f64]) {
+    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm > 0. {
+        for x in v.iter_mut() {
+            *x /= norm;
+        }
+    }
+}
+
+fn l1_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum()
+}
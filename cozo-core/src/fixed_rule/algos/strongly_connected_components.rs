@@ -9,7 +9,7 @@
 
 use graph::prelude::{DirectedCsrGraph, DirectedNeighbors, Graph};
 use std::cmp::min;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::Result;
@@ -47,7 +47,12 @@ impl FixedRule for StronglyConnectedComponent {
     ) -> Result<()> {
         let edges = payload.get_input(0)?;
 
-        let (graph, indices, mut inv_indices) = edges.as_directed_graph(!self.strong)?;
+        let projection = edges.as_cached_directed_graph(!self.strong)?;
+        let (graph, indices, inv_indices) = (
+            &projection.graph,
+            &projection.indices,
+            &projection.inv_indices,
+        );
 
         let tarjan = TarjanSccG::new(graph).run(poison)?;
         for (grp_id, cc) in tarjan.iter().enumerate() {
@@ -59,13 +64,13 @@ impl FixedRule for StronglyConnectedComponent {
         }
 
         let mut counter = tarjan.len() as i64;
+        let mut extra_nodes = BTreeSet::new();
 
         if let Ok(nodes) = payload.get_input(1) {
             for tuple in nodes.iter()? {
                 let tuple = tuple?;
                 let node = tuple.into_iter().next().unwrap();
-                if !inv_indices.contains_key(&node) {
-                    inv_indices.insert(node.clone(), u32::MAX);
+                if !inv_indices.contains_key(&node) && extra_nodes.insert(node.clone()) {
                     let tuple = vec![node, DataValue::from(counter)];
                     out.put(tuple);
                     counter += 1;
@@ -86,8 +91,8 @@ impl FixedRule for StronglyConnectedComponent {
     }
 }
 
-pub(crate) struct TarjanSccG {
-    graph: DirectedCsrGraph<u32>,
+pub(crate) struct TarjanSccG<'a> {
+    graph: &'a DirectedCsrGraph<u32>,
     id: u32,
     ids: Vec<Option<u32>>,
     low: Vec<u32>,
@@ -95,8 +100,8 @@ pub(crate) struct TarjanSccG {
     stack: Vec<u32>,
 }
 
-impl TarjanSccG {
-    pub(crate) fn new(graph: DirectedCsrGraph<u32>) -> Self {
+impl<'a> TarjanSccG<'a> {
+    pub(crate) fn new(graph: &'a DirectedCsrGraph<u32>) -> Self {
         let graph_size = graph.node_count();
         Self {
             graph,
@@ -0,0 +1,293 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload, NotAnEdgeError};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Find all embeddings of a small pattern graph into a data graph.
+///
+/// Pattern nodes are matched one at a time, each connected to the nodes already matched
+/// whenever possible, and the candidates for a node are obtained by intersecting the
+/// adjacency lists of the images of its matched neighbours. Each embedding is returned
+/// as rows of `[embedding_id, pattern_node, data_node]`.
+pub(crate) struct SubgraphMatch;
+
+impl FixedRule for SubgraphMatch {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let labeled = payload.bool_option("labeled", Some(false))?;
+        let min_len = if labeled { 3 } else { 2 };
+        let data = payload.get_input(0)?.ensure_min_len(min_len)?;
+        let pattern = payload.get_input(1)?.ensure_min_len(min_len)?;
+        let undirected = payload.bool_option("undirected", Some(false))?;
+        let induced = payload.bool_option("induced", Some(false))?;
+        let distinct = payload.bool_option("distinct", Some(false))?;
+        let limit = match payload.expr_option("limit", None) {
+            Ok(_) => Some(payload.pos_integer_option("limit", None)?),
+            Err(_) => None,
+        };
+
+        let data = LabeledGraph::new(data, labeled, undirected)?;
+        let pattern = LabeledGraph::new(pattern, labeled, undirected)?;
+        let mut matcher = Matcher {
+            data: &data,
+            pattern: &pattern,
+            order: pattern.matching_order(),
+            mapping: vec![usize::MAX; pattern.len()],
+            used: vec![false; data.len()],
+            induced,
+            seen: distinct.then(BTreeSet::new),
+            limit,
+            found: vec![],
+            poison,
+        };
+        matcher.extend(0)?;
+
+        for (idx, mapping) in matcher.found.into_iter().enumerate() {
+            for (p, d) in mapping.into_iter().enumerate() {
+                out.put(vec![
+                    DataValue::from(idx as i64),
+                    pattern.nodes[p].clone(),
+                    data.nodes[d].clone(),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+}
+
+/// A directed multigraph whose edges carry labels. Unlabeled edges are all labeled with null.
+struct LabeledGraph {
+    nodes: Vec<DataValue>,
+    out_edges: Vec<BTreeMap<usize, BTreeSet<DataValue>>>,
+    in_edges: Vec<BTreeMap<usize, BTreeSet<DataValue>>>,
+}
+
+impl LabeledGraph {
+    fn new(rel: FixedRuleInputRelation<'_, '_>, labeled: bool, undirected: bool) -> Result<Self> {
+        let mut graph = Self {
+            nodes: vec![],
+            out_edges: vec![],
+            in_edges: vec![],
+        };
+        let mut inv_nodes: BTreeMap<DataValue, usize> = BTreeMap::new();
+        let mut index = |graph: &mut Self, node: DataValue| -> usize {
+            *inv_nodes.entry(node.clone()).or_insert_with(|| {
+                graph.nodes.push(node);
+                graph.out_edges.push(Default::default());
+                graph.in_edges.push(Default::default());
+                graph.nodes.len() - 1
+            })
+        };
+        for tuple in rel.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let from = tuple.next().ok_or_else(|| NotAnEdgeError(rel.span()))?;
+            let to = tuple.next().ok_or_else(|| NotAnEdgeError(rel.span()))?;
+            let label = if labeled {
+                tuple.next().unwrap_or(DataValue::Null)
+            } else {
+                DataValue::Null
+            };
+            let from = index(&mut graph, from);
+            let to = index(&mut graph, to);
+            graph.add_edge(from, to, label.clone());
+            if undirected {
+                graph.add_edge(to, from, label);
+            }
+        }
+        Ok(graph)
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, label: DataValue) {
+        self.out_edges[from]
+            .entry(to)
+            .or_default()
+            .insert(label.clone());
+        self.in_edges[to].entry(from).or_default().insert(label);
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn degree(&self, node: usize) -> (usize, usize) {
+        (self.out_edges[node].len(), self.in_edges[node].len())
+    }
+
+    fn is_adjacent(&self, a: usize, b: usize) -> bool {
+        self.out_edges[a].contains_key(&b) || self.in_edges[a].contains_key(&b)
+    }
+
+    /// Order the pattern nodes so that each node is connected to as many earlier nodes as
+    /// possible, starting from the node of the highest degree.
+    fn matching_order(&self) -> Vec<usize> {
+        let total_degree = |n: usize| {
+            let (o, i) = self.degree(n);
+            o + i
+        };
+        let mut order = vec![];
+        let mut placed = vec![false; self.len()];
+        while order.len() < self.len() {
+            let next = (0..self.len())
+                .filter(|n| !placed[*n])
+                .max_by_key(|n| {
+                    let connections = order.iter().filter(|m| self.is_adjacent(*n, **m)).count();
+                    (connections, total_degree(*n), std::cmp::Reverse(*n))
+                })
+                .unwrap();
+            placed[next] = true;
+            order.push(next);
+        }
+        order
+    }
+}
+
+struct Matcher<'a> {
+    data: &'a LabeledGraph,
+    pattern: &'a LabeledGraph,
+    order: Vec<usize>,
+    mapping: Vec<usize>,
+    used: Vec<bool>,
+    induced: bool,
+    seen: Option<BTreeSet<Vec<(usize, usize)>>>,
+    limit: Option<usize>,
+    found: Vec<Vec<usize>>,
+    poison: Poison,
+}
+
+impl Matcher<'_> {
+    fn done(&self) -> bool {
+        matches!(self.limit, Some(l) if self.found.len() >= l)
+    }
+
+    fn extend(&mut self, depth: usize) -> Result<()> {
+        if depth == self.order.len() {
+            self.record();
+            return Ok(());
+        }
+        self.poison.check()?;
+        let p = self.order[depth];
+        for candidate in self.candidates(p) {
+            if self.done() {
+                break;
+            }
+            if self.used[candidate] || !self.is_feasible(p, candidate) {
+                continue;
+            }
+            self.mapping[p] = candidate;
+            self.used[candidate] = true;
+            self.extend(depth + 1)?;
+            self.used[candidate] = false;
+            self.mapping[p] = usize::MAX;
+        }
+        Ok(())
+    }
+
+    /// Data nodes adjacent to the images of all matched neighbours of the pattern node `p`,
+    /// found by intersecting adjacency lists starting from the smallest one.
+    fn candidates(&self, p: usize) -> Vec<usize> {
+        let mut lists = vec![];
+        for q in self.pattern.out_edges[p].keys() {
+            if *q != p && self.mapping[*q] != usize::MAX {
+                lists.push(&self.data.in_edges[self.mapping[*q]]);
+            }
+        }
+        for q in self.pattern.in_edges[p].keys() {
+            if *q != p && self.mapping[*q] != usize::MAX {
+                lists.push(&self.data.out_edges[self.mapping[*q]]);
+            }
+        }
+        match lists.iter().position_min_by_key(|l| l.len()) {
+            None => (0..self.data.len()).collect(),
+            Some(smallest) => lists[smallest]
+                .keys()
+                .filter(|c| lists.iter().all(|l| l.contains_key(c)))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn is_feasible(&self, p: usize, candidate: usize) -> bool {
+        let (p_out, p_in) = self.pattern.degree(p);
+        let (c_out, c_in) = self.data.degree(candidate);
+        if c_out < p_out || c_in < p_in {
+            return false;
+        }
+        let edges_match =
+            |pattern_edges: &BTreeMap<usize, BTreeSet<DataValue>>,
+             data_edges: &BTreeMap<usize, BTreeSet<DataValue>>| {
+                pattern_edges.iter().all(|(q, labels)| {
+                    let image = if *q == p { candidate } else { self.mapping[*q] };
+                    image == usize::MAX
+                        || matches!(data_edges.get(&image), Some(l) if labels.is_subset(l))
+                })
+            };
+        if !edges_match(&self.pattern.out_edges[p], &self.data.out_edges[candidate])
+            || !edges_match(&self.pattern.in_edges[p], &self.data.in_edges[candidate])
+        {
+            return false;
+        }
+        if self.induced {
+            let is_mapped = |q: usize| q == p || self.mapping[q] != usize::MAX;
+            let pattern_has = |a: usize, b: usize| self.pattern.out_edges[a].contains_key(&b);
+            if self.data.out_edges[candidate].contains_key(&candidate) && !pattern_has(p, p) {
+                return false;
+            }
+            for q in (0..self.pattern.len()).filter(|q| *q != p && is_mapped(*q)) {
+                let image = self.mapping[q];
+                if (self.data.out_edges[candidate].contains_key(&image) && !pattern_has(p, q))
+                    || (self.data.in_edges[candidate].contains_key(&image) && !pattern_has(q, p))
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn record(&mut self) {
+        if let Some(seen) = &mut self.seen {
+            let mut edges = vec![];
+            for (p, targets) in self.pattern.out_edges.iter().enumerate() {
+                for q in targets.keys() {
+                    let (a, b) = (self.mapping[p], self.mapping[*q]);
+                    edges.push((a, b));
+                }
+            }
+            edges.sort();
+            if !seen.insert(edges) {
+                return;
+            }
+        }
+        self.found.push(self.mapping.clone());
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+use itertools::Itertools;
+use miette::{ensure, Result};
+use ordered_float::OrderedFloat;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::expr::Expr;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{
+    BadExprValueError, FixedRule, FixedRuleInputRelation, FixedRulePayload, NotAnEdgeError,
+};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// For each starting node, the earliest time at which every other node can be reached
+/// by a time-respecting path, i.e. one in which each edge departs no earlier than the
+/// previous edge arrives.
+///
+/// Edges are given as `[from, to, departure, arrival]`, where the times are numbers or
+/// validity timestamps. Output rows are `[source, node, arrival, path]`.
+pub(crate) struct TemporalEarliestArrival;
+
+impl FixedRule for TemporalEarliestArrival {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let window = TimeWindow::from_payload(&payload)?;
+        let graph = TemporalGraph::new(payload.get_input(0)?, &window, false)?;
+        for start in graph.node_indices(payload.get_input(1)?)? {
+            let res = earliest_arrival(&graph, start, window.start, &poison)?;
+            for (node, pred) in res.iter().enumerate() {
+                if let Some(edge) = pred {
+                    let mut path = res.path_to(node as u32);
+                    path.reverse();
+                    out.put(vec![
+                        graph.nodes[start as usize].clone(),
+                        graph.nodes[node].clone(),
+                        graph.edges[*edge].arr_val.clone(),
+                        graph.path_value(path),
+                    ]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+/// For each target node, the latest time at which every other node can be left so that
+/// the target is still reached by a time-respecting path before the end of the window.
+///
+/// Output rows are `[target, node, departure, path]`, with the path leading from the node
+/// to the target.
+pub(crate) struct TemporalLatestDeparture;
+
+impl FixedRule for TemporalLatestDeparture {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let window = TimeWindow::from_payload(&payload)?;
+        // running backwards in time is the same as running forwards on the reversed graph
+        // with all times negated
+        let graph = TemporalGraph::new(payload.get_input(0)?, &window, true)?;
+        for target in graph.node_indices(payload.get_input(1)?)? {
+            let res = earliest_arrival(&graph, target, -window.end, &poison)?;
+            for (node, pred) in res.iter().enumerate() {
+                if let Some(edge) = pred {
+                    out.put(vec![
+                        graph.nodes[target as usize].clone(),
+                        graph.nodes[node].clone(),
+                        graph.edges[*edge].arr_val.clone(),
+                        graph.path_value(res.path_to(node as u32)),
+                    ]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+}
+
+/// For each starting node, the time-respecting path to every other node that takes the
+/// least time between leaving the source and arriving at the node.
+///
+/// Output rows are `[source, node, departure, arrival, path]`.
+pub(crate) struct TemporalFastestPath;
+
+impl FixedRule for TemporalFastestPath {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let window = TimeWindow::from_payload(&payload)?;
+        let graph = TemporalGraph::new(payload.get_input(0)?, &window, false)?;
+        for start in graph.node_indices(payload.get_input(1)?)? {
+            for (node, (first, last, path)) in fastest_paths(&graph, start, &poison)? {
+                out.put(vec![
+                    graph.nodes[start as usize].clone(),
+                    graph.nodes[node as usize].clone(),
+                    graph.edges[first].dep_val.clone(),
+                    graph.edges[last].arr_val.clone(),
+                    graph.path_value(path),
+                ]);
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(5)
+    }
+}
+
+struct TimeWindow {
+    start: f64,
+    end: f64,
+}
+
+impl TimeWindow {
+    fn from_payload(payload: &FixedRulePayload<'_, '_>) -> Result<Self> {
+        Ok(Self {
+            start: payload.float_option("start_time", Some(f64::NEG_INFINITY))?,
+            end: payload.float_option("end_time", Some(f64::INFINITY))?,
+        })
+    }
+}
+
+struct TemporalEdge {
+    from: u32,
+    to: u32,
+    dep: f64,
+    arr: f64,
+    dep_val: DataValue,
+    arr_val: DataValue,
+}
+
+/// Edges with departure and arrival times. The outgoing edges of each node are sorted
+/// by departure time.
+struct TemporalGraph {
+    nodes: Vec<DataValue>,
+    inv_nodes: BTreeMap<DataValue, u32>,
+    edges: Vec<TemporalEdge>,
+    out_edges: Vec<Vec<usize>>,
+}
+
+impl TemporalGraph {
+    /// Only edges that depart and arrive within the window are kept. With `reversed`, every
+    /// edge is turned around and its times are negated, so that arrivals become departures.
+    fn new(
+        rel: FixedRuleInputRelation<'_, '_>,
+        window: &TimeWindow,
+        reversed: bool,
+    ) -> Result<Self> {
+        let rel = rel.ensure_min_len(4)?;
+        let mut graph = Self {
+            nodes: vec![],
+            inv_nodes: BTreeMap::new(),
+            edges: vec![],
+            out_edges: vec![],
+        };
+        for tuple in rel.iter()? {
+            let mut tuple = tuple?.into_iter();
+            let mut next = || tuple.next().ok_or_else(|| NotAnEdgeError(rel.span()));
+            let from = next()?;
+            let to = next()?;
+            let dep_val = next()?;
+            let arr_val = next()?;
+            let dep = time_of(&dep_val, rel.span())?;
+            let arr = time_of(&arr_val, rel.span())?;
+            ensure!(
+                arr >= dep,
+                BadExprValueError(
+                    arr_val,
+                    rel.span(),
+                    "the arrival time of an edge cannot precede its departure time".to_string()
+                )
+            );
+            if dep < window.start || arr > window.end {
+                continue;
+            }
+            let from = graph.index(from);
+            let to = graph.index(to);
+            graph.edges.push(if reversed {
+                TemporalEdge {
+                    from: to,
+                    to: from,
+                    dep: -arr,
+                    arr: -dep,
+                    dep_val: arr_val,
+                    arr_val: dep_val,
+                }
+            } else {
+                TemporalEdge {
+                    from,
+                    to,
+                    dep,
+                    arr,
+                    dep_val,
+                    arr_val,
+                }
+            });
+        }
+        for (idx, edge) in graph.edges.iter().enumerate() {
+            graph.out_edges[edge.from as usize].push(idx);
+        }
+        for list in graph.out_edges.iter_mut() {
+            list.sort_by(|a, b| graph.edges[*a].dep.total_cmp(&graph.edges[*b].dep));
+        }
+        Ok(graph)
+    }
+
+    fn index(&mut self, node: DataValue) -> u32 {
+        *self.inv_nodes.entry(node.clone()).or_insert_with(|| {
+            self.nodes.push(node);
+            self.out_edges.push(vec![]);
+            (self.nodes.len() - 1) as u32
+        })
+    }
+
+    /// Indices of the nodes in the first column of `rel`. Nodes without edges are skipped.
+    fn node_indices(&self, rel: FixedRuleInputRelation<'_, '_>) -> Result<BTreeSet<u32>> {
+        let mut ret = BTreeSet::new();
+        for tuple in rel.iter()? {
+            let tuple = tuple?;
+            if let Some(idx) = self.inv_nodes.get(&tuple[0]) {
+                ret.insert(*idx);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn path_value(&self, path: Vec<u32>) -> DataValue {
+        DataValue::List(
+            path.into_iter()
+                .map(|n| self.nodes[n as usize].clone())
+                .collect_vec(),
+        )
+    }
+}
+
+fn time_of(v: &DataValue, span: SourceSpan) -> Result<f64> {
+    let t = match v {
+        DataValue::Num(n) => n.get_float(),
+        DataValue::Validity(vld) => vld.timestamp.0 .0 as f64,
+        _ => f64::NAN,
+    };
+    ensure!(
+        !t.is_nan(),
+        BadExprValueError(
+            v.clone(),
+            span,
+            "times of edges must be numbers or validity timestamps".to_string()
+        )
+    );
+    Ok(t)
+}
+
+/// The edge through which each node is first reached, or `None` for unreachable nodes
+/// and the source itself.
+struct Arrivals<'a> {
+    graph: &'a TemporalGraph,
+    pred: Vec<Option<usize>>,
+}
+
+impl Arrivals<'_> {
+    fn iter(&self) -> impl Iterator<Item = &Option<usize>> {
+        self.pred.iter()
+    }
+
+    /// Nodes from `node` back to the source.
+    fn path_to(&self, node: u32) -> Vec<u32> {
+        let mut path = vec![node];
+        let mut cur = node;
+        while let Some(edge) = self.pred[cur as usize] {
+            cur = self.graph.edges[edge].from;
+            path.push(cur);
+        }
+        path
+    }
+}
+
+/// Dijkstra's algorithm with arrival times as distances. Since an edge never arrives before
+/// it departs, a node is settled once it is popped, and from a node reached at time `t`
+/// only the edges departing at or after `t` need to be considered.
+fn earliest_arrival<'a>(
+    graph: &'a TemporalGraph,
+    source: u32,
+    start_time: f64,
+    poison: &Poison,
+) -> Result<Arrivals<'a>> {
+    let n = graph.nodes.len();
+    let mut arrival = vec![f64::INFINITY; n];
+    let mut pred = vec![None; n];
+    let mut settled = vec![false; n];
+    arrival[source as usize] = start_time;
+    let mut heap = BinaryHeap::new();
+    heap.push((Reverse(OrderedFloat(start_time)), source));
+    while let Some((Reverse(OrderedFloat(t)), u)) = heap.pop() {
+        if settled[u as usize] {
+            continue;
+        }
+        settled[u as usize] = true;
+        poison.check()?;
+        let out_edges = &graph.out_edges[u as usize];
+        let first = out_edges.partition_point(|e| graph.edges[*e].dep < t);
+        for &e in &out_edges[first..] {
+            let edge = &graph.edges[e];
+            let v = edge.to as usize;
+            if !settled[v] && edge.arr < arrival[v] {
+                arrival[v] = edge.arr;
+                pred[v] = Some(e);
+                heap.push((Reverse(OrderedFloat(edge.arr)), edge.to));
+            }
+        }
+    }
+    Ok(Arrivals { graph, pred })
+}
+
+/// A fastest path that leaves the source at time `t` is also an earliest arrival path when
+/// starting at `t`, so it suffices to compute earliest arrivals for every departure time
+/// of the source, and keep for each node the result taking the least time.
+///
+/// Returns, for each reachable node, the first and last edges of the path and the path itself.
+fn fastest_paths(
+    graph: &TemporalGraph,
+    source: u32,
+    poison: &Poison,
+) -> Result<BTreeMap<u32, (usize, usize, Vec<u32>)>> {
+    let departures = graph.out_edges[source as usize]
+        .iter()
+        .map(|e| graph.edges[*e].dep)
+        .dedup()
+        .collect_vec();
+    let mut best: BTreeMap<u32, (f64, usize, usize, Vec<u32>)> = BTreeMap::new();
+    for t in departures {
+        let res = earliest_arrival(graph, source, t, poison)?;
+        for (node, pred) in res.iter().enumerate() {
+            if let Some(last) = pred {
+                let mut path = res.path_to(node as u32);
+                path.reverse();
+                let first = res.pred[path[1] as usize].unwrap();
+                let duration = graph.edges[*last].arr - graph.edges[first].dep;
+                let node = node as u32;
+                if !matches!(best.get(&node), Some((d, ..)) if duration >= *d) {
+                    best.insert(node, (duration, first, *last, path));
+                }
+            }
+        }
+    }
+    Ok(best
+        .into_iter()
+        .map(|(node, (_, first, last, path))| (node, (first, last, path)))
+        .collect())
+}
@@ -31,9 +31,10 @@ impl FixedRule for TopSort {
     ) -> Result<()> {
         let edges = payload.get_input(0)?;
 
-        let (graph, indices, _) = edges.as_directed_graph(false)?;
+        let projection = edges.as_cached_directed_graph(false)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
 
-        let sorted = kahn_g(&graph, poison)?;
+        let sorted = kahn_g(graph, poison)?;
 
         for (idx, val_id) in sorted.iter().enumerate() {
             let val = indices.get(*val_id as usize).unwrap();
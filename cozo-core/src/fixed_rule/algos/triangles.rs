@@ -33,8 +33,9 @@ impl FixedRule for ClusteringCoefficients {
         poison: Poison,
     ) -> Result<()> {
         let edges = payload.get_input(0)?;
-        let (graph, indices, _) = edges.as_directed_graph(true)?;
-        let coefficients = clustering_coefficients(&graph, poison)?;
+        let projection = edges.as_cached_directed_graph(true)?;
+        let (graph, indices) = (&projection.graph, &projection.indices);
+        let coefficients = clustering_coefficients(graph, poison)?;
         for (idx, (cc, n_triangles, degree)) in coefficients.into_iter().enumerate() {
             out.put(vec![
                 indices[idx].clone(),
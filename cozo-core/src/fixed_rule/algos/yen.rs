@@ -39,7 +39,12 @@ impl FixedRule for KShortestPathYen {
         let undirected = payload.bool_option("undirected", Some(false))?;
         let k = payload.pos_integer_option("k", None)?;
 
-        let (graph, indices, inv_indices) = edges.as_directed_weighted_graph(undirected, false)?;
+        let projection = edges.as_cached_directed_weighted_graph(undirected, false)?;
+        let (graph, indices, inv_indices) = (
+            &projection.graph,
+            &projection.indices,
+            &projection.inv_indices,
+        );
 
         let mut starting_nodes = BTreeSet::new();
         for tuple in starting.iter()? {
@@ -60,8 +65,7 @@ impl FixedRule for KShortestPathYen {
         if starting_nodes.len() <= 1 && termination_nodes.len() <= 1 {
             for start in starting_nodes {
                 for goal in &termination_nodes {
-                    for (cost, path) in
-                        k_shortest_path_yen(k, &graph, start, *goal, poison.clone())?
+                    for (cost, path) in k_shortest_path_yen(k, graph, start, *goal, poison.clone())?
                     {
                         let t = vec![
                             indices[start as usize].clone(),
@@ -90,7 +94,7 @@ impl FixedRule for KShortestPathYen {
                         Ok((
                             start,
                             goal,
-                            k_shortest_path_yen(k, &graph, start, goal, poison.clone())?,
+                            k_shortest_path_yen(k, graph, start, goal, poison.clone())?,
                         ))
                     },
                 )
@@ -135,7 +139,7 @@ fn k_shortest_path_yen(
     let mut k_shortest: Vec<(f32, Vec<u32>)> = Vec::with_capacity(k);
     let mut candidates: Vec<(f32, Vec<u32>)> = vec![];
 
-    match dijkstra(edges, start, &Some(goal), &(), &())
+    match dijkstra(edges, start, &Some(goal), &(), &(), &poison)?
         .into_iter()
         .next()
     {
@@ -171,7 +175,8 @@ fn k_shortest_path_yen(
                 &Some(goal),
                 &forbidden_edges,
                 &forbidden_nodes,
-            )
+                &poison,
+            )?
             .into_iter()
             .next()
             {
@@ -35,6 +35,8 @@ use crate::fixed_rule::algos::*;
 use crate::fixed_rule::utilities::*;
 use crate::parse::SourceSpan;
 use crate::runtime::db::Poison;
+#[cfg(feature = "graph-algo")]
+use crate::runtime::graph_cache::{CachedGraph, GraphKey, GraphProjection};
 use crate::runtime::temp_store::{EpochStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 use crate::NamedRows;
@@ -326,6 +328,86 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
 
         Ok((graph, indices, inv_indices))
     }
+    /// Like [as_directed_graph](Self::as_directed_graph), but the graph is shared with other
+    /// fixed rules and later queries for as long as the stored relation is unchanged.
+    #[cfg(feature = "graph-algo")]
+    pub(crate) fn as_cached_directed_graph(
+        &self,
+        undirected: bool,
+    ) -> Result<Arc<GraphProjection<DirectedCsrGraph<u32>>>> {
+        let key = self.graph_key(undirected, None)?;
+        if let Some(key) = &key {
+            if let Some(CachedGraph::Unweighted(projection)) = self.tx.graph_cache.get_graph(key) {
+                return Ok(projection);
+            }
+        }
+        let (graph, indices, inv_indices) = self.as_directed_graph(undirected)?;
+        let projection = Arc::new(GraphProjection {
+            graph,
+            indices,
+            inv_indices,
+        });
+        if let Some(key) = key {
+            self.tx.graph_cache.put_graph(
+                key,
+                CachedGraph::Unweighted(projection.clone()),
+                self.tx.graph_generation,
+            );
+        }
+        Ok(projection)
+    }
+    /// Like [as_directed_weighted_graph](Self::as_directed_weighted_graph), but the graph is
+    /// shared with other fixed rules and later queries for as long as the stored relation
+    /// is unchanged.
+    #[cfg(feature = "graph-algo")]
+    pub(crate) fn as_cached_directed_weighted_graph(
+        &self,
+        undirected: bool,
+        allow_negative_weights: bool,
+    ) -> Result<Arc<GraphProjection<DirectedCsrGraph<u32, (), f32>>>> {
+        let key = self.graph_key(undirected, Some(allow_negative_weights))?;
+        if let Some(key) = &key {
+            if let Some(CachedGraph::Weighted(projection)) = self.tx.graph_cache.get_graph(key) {
+                return Ok(projection);
+            }
+        }
+        let (graph, indices, inv_indices) =
+            self.as_directed_weighted_graph(undirected, allow_negative_weights)?;
+        let projection = Arc::new(GraphProjection {
+            graph,
+            indices,
+            inv_indices,
+        });
+        if let Some(key) = key {
+            self.tx.graph_cache.put_graph(
+                key,
+                CachedGraph::Weighted(projection.clone()),
+                self.tx.graph_generation,
+            );
+        }
+        Ok(projection)
+    }
+    /// Graphs are only cached for stored relations whose version is known to the transaction.
+    #[cfg(feature = "graph-algo")]
+    fn graph_key(&self, undirected: bool, weighted: Option<bool>) -> Result<Option<GraphKey>> {
+        match &self.arg_manifest {
+            MagicFixedRuleRuleArg::Stored { name, valid_at, .. } if !name.is_temp_store_name() => {
+                let relation = self.tx.get_relation(name, false)?;
+                Ok(self
+                    .tx
+                    .graph_cache
+                    .relation_version(relation.id, self.tx.graph_generation)
+                    .map(|version| GraphKey {
+                        relation: relation.id,
+                        version,
+                        valid_at: *valid_at,
+                        undirected,
+                        weighted,
+                    }))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 impl<'a, 'b> FixedRulePayload<'a, 'b> {
@@ -494,6 +576,20 @@ impl<'a, 'b> FixedRulePayload<'a, 'b> {
             },
         }
     }
+    /// Extract a positive floating point option
+    pub fn pos_float_option(&self, name: &str, default: Option<f64>) -> Result<f64> {
+        let f = self.float_option(name, default)?;
+        ensure!(
+            f > 0.,
+            WrongFixedRuleOptionError {
+                name: name.to_string(),
+                span: self.option_span(name)?,
+                rule_name: self.manifest.fixed_handle.name.to_string(),
+                help: "a positive number is required".to_string(),
+            }
+        );
+        Ok(f)
+    }
     /// Extract a floating point option between 0. and 1.
     pub fn unit_interval_option(&self, name: &str, default: Option<f64>) -> Result<f64> {
         let f = self.float_option(name, default)?;
@@ -726,6 +822,11 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(BetweennessCentrality)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "ApproxBetweennessCentrality".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ApproxBetweennessCentrality)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "DepthFirstSearch".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(Dfs)),
@@ -796,16 +897,71 @@ lazy_static! {
                 Arc::<Box<dyn FixedRule>>::new(Box::new(StronglyConnectedComponent::new(true))),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "KCore".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(KCore)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "ArticulationPoints".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(ArticulationPoints)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "Bridges".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Bridges)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "BiconnectedComponents".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(BiconnectedComponents)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "PageRank".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(PageRank)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "PersonalizedPageRank".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(PersonalizedPageRank)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "HITS".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Hits)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "EigenvectorCentrality".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(EigenvectorCentrality)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "KatzCentrality".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(KatzCentrality)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "CommunityDetectionLouvain".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionLouvain)),
             ),
             #[cfg(feature = "graph-algo")]
+            (
+                "CommunityDetectionLeiden".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionLeiden)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "CommunityDetectionInfomap".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionInfomap)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "Infomap".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(CommunityDetectionInfomap)),
+            ),
+            #[cfg(feature = "graph-algo")]
             (
                 "LabelPropagation".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(LabelPropagation)),
@@ -815,6 +971,55 @@ lazy_static! {
                 "RandomWalk".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(RandomWalk)),
             ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "Node2Vec".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Node2Vec::new(true))),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "DeepWalk".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(Node2Vec::new(false))),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MaxFlow".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MaxFlow)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "MinCut".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(MinCut)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "BipartiteMatching".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(BipartiteMatching)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "SubgraphMatch".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(SubgraphMatch)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "TemporalEarliestArrival".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TemporalEarliestArrival)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "TemporalLatestDeparture".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TemporalLatestDeparture)),
+            ),
+            #[cfg(feature = "graph-algo")]
+            (
+                "TemporalFastestPath".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(TemporalFastestPath)),
+            ),
+            (
+                "RankFusion".to_string(),
+                Arc::<Box<dyn FixedRule>>::new(Box::new(RankFusion)),
+            ),
             (
                 "ReorderSort".to_string(),
                 Arc::<Box<dyn FixedRule>>::new(Box::new(ReorderSort)),
@@ -847,7 +1052,7 @@ impl FixedRuleHandle {
 #[error("The relation cannot be interpreted as an edge")]
 #[diagnostic(code(algo::not_an_edge))]
 #[diagnostic(help("Edge relation requires tuples of length at least two"))]
-struct NotAnEdgeError(#[label] SourceSpan);
+pub(crate) struct NotAnEdgeError(#[label] pub(crate) SourceSpan);
 
 #[derive(Error, Diagnostic, Debug)]
 #[error(
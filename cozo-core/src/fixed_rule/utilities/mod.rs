@@ -9,9 +9,11 @@
 pub(crate) mod constant;
 pub(crate) mod csv;
 pub(crate) mod jlines;
+pub(crate) mod rank_fusion;
 pub(crate) mod reorder_sort;
 
 pub(crate) use self::csv::CsvReader;
 pub(crate) use constant::Constant;
 pub(crate) use jlines::JsonReader;
+pub(crate) use rank_fusion::RankFusion;
 pub(crate) use reorder_sort::ReorderSort;
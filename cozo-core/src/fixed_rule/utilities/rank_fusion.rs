@@ -0,0 +1,173 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::WrongFixedRuleOptionError;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::fixed_rule::{FixedRule, FixedRulePayload};
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::temp_store::RegularTempStore;
+
+/// Merges several ranked lists, typically the results of vector and full-text searches,
+/// into a single one. Each input relation holds a key and a score in its first two columns.
+///
+/// With the `rrf` method, the fused score is the reciprocal rank fusion
+/// `sum(weight / (k + rank))`. With the `weighted` method, the scores of each input are
+/// normalized to `[0, 1]` and summed with the weights. Keys missing from an input
+/// get nothing from it.
+pub(crate) struct RankFusion;
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The value {0:?} at the second position in the relation cannot be interpreted as a score")]
+#[diagnostic(code(algo::invalid_fusion_score))]
+#[diagnostic(help("Scores must be numbers and not NaN"))]
+struct BadScoreError(DataValue, #[label] SourceSpan);
+
+impl FixedRule for RankFusion {
+    fn run(
+        &self,
+        payload: FixedRulePayload<'_, '_>,
+        out: &mut RegularTempStore,
+        poison: Poison,
+    ) -> Result<()> {
+        let n_inputs = payload.inputs_count();
+        let rrf = match payload.string_option("method", Some("rrf"))?.as_str() {
+            "rrf" => true,
+            "weighted" => false,
+            _ => bail!(WrongFixedRuleOptionError {
+                name: "method".to_string(),
+                span: payload.option_span("method")?,
+                rule_name: payload.name().to_string(),
+                help: "either 'rrf' or 'weighted' is required".to_string(),
+            }),
+        };
+        let k = payload.float_option("k", Some(60.))?;
+        ensure!(
+            k >= 0.,
+            WrongFixedRuleOptionError {
+                name: "k".to_string(),
+                span: payload.option_span("k")?,
+                rule_name: payload.name().to_string(),
+                help: "a non-negative number is required".to_string(),
+            }
+        );
+        let weights = list_option(&payload, "weights", n_inputs, 1., |v| {
+            v.get_float().filter(|f| f.is_finite())
+        })?;
+        let ascending = list_option(&payload, "ascending", n_inputs, false, |v| v.get_bool())?;
+        let take = payload.non_neg_integer_option("take", Some(0))?;
+
+        let mut fused: BTreeMap<DataValue, f64> = BTreeMap::new();
+        for i in 0..n_inputs {
+            let rel = payload.get_input(i)?.ensure_min_len(2)?;
+            // the best score of each key, with higher scores always better
+            let mut scores: BTreeMap<DataValue, f64> = BTreeMap::new();
+            for tuple in rel.iter()? {
+                let mut tuple = tuple?;
+                let score = tuple.swap_remove(1);
+                let key = tuple.swap_remove(0);
+                let mut score = match score.get_float() {
+                    Some(f) if !f.is_nan() => f,
+                    _ => bail!(BadScoreError(score, rel.span())),
+                };
+                if ascending[i] {
+                    score = -score;
+                }
+                scores
+                    .entry(key)
+                    .and_modify(|s| *s = s.max(score))
+                    .or_insert(score);
+                poison.check()?;
+            }
+            if rrf {
+                let mut ranked = scores.into_iter().collect::<Vec<_>>();
+                ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+                let mut rank = 0;
+                let mut last = f64::NAN;
+                for (idx, (key, score)) in ranked.into_iter().enumerate() {
+                    // tied scores share the same rank
+                    if score != last {
+                        rank = idx + 1;
+                        last = score;
+                    }
+                    *fused.entry(key).or_default() += weights[i] / (k + rank as f64);
+                }
+            } else {
+                let min = scores.values().copied().fold(f64::INFINITY, f64::min);
+                let max = scores.values().copied().fold(f64::NEG_INFINITY, f64::max);
+                for (key, score) in scores {
+                    let normalized = if max > min {
+                        (score - min) / (max - min)
+                    } else {
+                        1.
+                    };
+                    *fused.entry(key).or_default() += weights[i] * normalized;
+                }
+            }
+        }
+
+        let mut fused = fused.into_iter().collect::<Vec<_>>();
+        fused.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        if take > 0 {
+            fused.truncate(take);
+        }
+        for (key, score) in fused {
+            out.put(vec![key, DataValue::from(score)]);
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+/// An option that is either a single value applying to all inputs,
+/// or a list with one value for each input.
+fn list_option<T: Copy>(
+    payload: &FixedRulePayload<'_, '_>,
+    name: &str,
+    n_inputs: usize,
+    default: T,
+    convert: impl Fn(&DataValue) -> Option<T>,
+) -> Result<Vec<T>> {
+    let expr = match payload.expr_option(name, None) {
+        Ok(expr) => expr,
+        Err(_) => return Ok(vec![default; n_inputs]),
+    };
+    let span = expr.span();
+    let err = || WrongFixedRuleOptionError {
+        name: name.to_string(),
+        span,
+        rule_name: payload.name().to_string(),
+        help: format!("a value or a list of {n_inputs} values of the right type is required"),
+    };
+    match expr.eval_to_const()? {
+        DataValue::List(l) => {
+            ensure!(l.len() == n_inputs, err());
+            l.iter()
+                .map(|v| convert(v).ok_or_else(|| err().into()))
+                .collect()
+        }
+        v => Ok(vec![convert(&v).ok_or_else(err)?; n_inputs]),
+    }
+}
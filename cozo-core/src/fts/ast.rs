@@ -15,6 +15,10 @@ pub(crate) struct FtsLiteral {
     pub(crate) value: SmartString<LazyCompact>,
     pub(crate) is_prefix: bool,
     pub(crate) booster: OrderedFloat<f64>,
+    /// Only match in this field of a multi-field index
+    pub(crate) field: Option<SmartString<LazyCompact>>,
+    /// Also match terms within this Levenshtein distance
+    pub(crate) max_edits: u8,
 }
 
 impl FtsLiteral {
@@ -30,21 +34,111 @@ impl FtsLiteral {
                 value: SmartString::from(&t.text),
                 is_prefix: false,
                 booster: self.booster,
+                field: self.field.clone(),
+                max_edits: self.max_edits,
             })
         }
     }
+
+    /// Tokenize a quoted string into a phrase, keeping the relative positions of the tokens.
+    fn tokenize_phrase(self, tokenizer: &TextAnalyzer) -> FtsExpr {
+        if self.is_prefix {
+            return FtsExpr::Literal(self);
+        }
+
+        let mut literals = vec![];
+        let mut offsets = vec![];
+        let mut tokens = tokenizer.token_stream(&self.value);
+        while let Some(t) = tokens.next() {
+            literals.push(FtsLiteral {
+                value: SmartString::from(&t.text),
+                is_prefix: false,
+                booster: self.booster,
+                field: self.field.clone(),
+                max_edits: self.max_edits,
+            });
+            offsets.push(t.position as u32);
+        }
+        match literals.len() {
+            0 => FtsExpr::And(vec![]),
+            1 => FtsExpr::Literal(literals.pop().unwrap()),
+            _ => {
+                let first = offsets[0];
+                for o in offsets.iter_mut() {
+                    *o -= first;
+                }
+                FtsExpr::Phrase(FtsPhrase { literals, offsets })
+            }
+        }
+    }
+
+    fn set_field_if_absent(&mut self, field: &SmartString<LazyCompact>) {
+        if self.field.is_none() {
+            self.field = Some(field.clone());
+        }
+    }
+
+    /// Whether a term of the index is matched by the literal.
+    pub(crate) fn matches_term(&self, term: &str) -> bool {
+        if self.max_edits == 0 {
+            return if self.is_prefix {
+                term.starts_with(self.value.as_str())
+            } else {
+                term == self.value
+            };
+        }
+        let query = self.value.chars().collect::<Vec<_>>();
+        let n = query.len();
+        let max_edits = self.max_edits as u32;
+        let mut row = (0..=n as u32).collect::<Vec<_>>();
+        if self.is_prefix && row[n] <= max_edits {
+            return true;
+        }
+        for c in term.chars() {
+            row = levenshtein_step(&query, &row, c);
+            if self.is_prefix && row[n] <= max_edits {
+                return true;
+            }
+            if *row.iter().min().unwrap() > max_edits {
+                return false;
+            }
+        }
+        row[n] <= max_edits
+    }
+}
+
+/// Given the row of the Levenshtein matrix for some prefix of a term,
+/// compute the row for the prefix extended by `c`.
+pub(crate) fn levenshtein_step(query: &[char], row: &[u32], c: char) -> Vec<u32> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+    for j in 1..row.len() {
+        let cost = if query[j - 1] == c { 0 } else { 1 };
+        next.push((row[j - 1] + cost).min(row[j] + 1).min(next[j - 1] + 1));
+    }
+    next
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct FtsNear {
     pub(crate) literals: Vec<FtsLiteral>,
     pub(crate) distance: u32,
+    /// Whether the literals must appear in the given order
+    pub(crate) ordered: bool,
+}
+
+/// Literals that must appear in sequence, at the given positions relative to the first one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FtsPhrase {
+    pub(crate) literals: Vec<FtsLiteral>,
+    pub(crate) offsets: Vec<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum FtsExpr {
     Literal(FtsLiteral),
     Near(FtsNear),
+    Phrase(FtsPhrase),
     And(Vec<FtsExpr>),
     Or(Vec<FtsExpr>),
     Not(Box<FtsExpr>, Box<FtsExpr>),
@@ -71,6 +165,7 @@ impl FtsExpr {
                 l.booster == 0. || l.value.is_empty()
             },
             FtsExpr::Near(FtsNear{ literals, .. }) => {literals.is_empty()}
+            FtsExpr::Phrase(FtsPhrase { literals, .. }) => literals.is_empty(),
             FtsExpr::And(v) => {v.is_empty()}
             FtsExpr::Or(v) => {v.is_empty()}
             FtsExpr::Not(lhs, _) => {lhs.is_empty()}
@@ -125,6 +220,44 @@ impl FtsExpr {
             }
             FtsExpr::Literal(l) => FtsExpr::Literal(l),
             FtsExpr::Near(n) => FtsExpr::Near(n),
+            FtsExpr::Phrase(p) => FtsExpr::Phrase(p),
+        }
+    }
+
+    /// Literals whose occurrences contribute to a match, i.e. those not under the right side of NOT.
+    pub(crate) fn positive_literals<'a>(&'a self, coll: &mut Vec<&'a FtsLiteral>) {
+        match self {
+            FtsExpr::Literal(l) => coll.push(l),
+            FtsExpr::Near(FtsNear { literals, .. })
+            | FtsExpr::Phrase(FtsPhrase { literals, .. }) => coll.extend(literals.iter()),
+            FtsExpr::And(exprs) | FtsExpr::Or(exprs) => {
+                for e in exprs {
+                    e.positive_literals(coll)
+                }
+            }
+            FtsExpr::Not(lhs, _) => lhs.positive_literals(coll),
+        }
+    }
+
+    /// Restrict all literals without an explicit field to `field`.
+    pub(crate) fn set_field_if_absent(&mut self, field: &SmartString<LazyCompact>) {
+        match self {
+            FtsExpr::Literal(l) => l.set_field_if_absent(field),
+            FtsExpr::Near(FtsNear { literals, .. })
+            | FtsExpr::Phrase(FtsPhrase { literals, .. }) => {
+                for l in literals {
+                    l.set_field_if_absent(field)
+                }
+            }
+            FtsExpr::And(exprs) | FtsExpr::Or(exprs) => {
+                for e in exprs {
+                    e.set_field_if_absent(field)
+                }
+            }
+            FtsExpr::Not(lhs, rhs) => {
+                lhs.set_field_if_absent(field);
+                rhs.set_field_if_absent(field);
+            }
         }
     }
 
@@ -139,7 +272,11 @@ impl FtsExpr {
                     FtsExpr::And(tokens.into_iter().map(FtsExpr::Literal).collect())
                 }
             }
-            FtsExpr::Near(FtsNear { literals, distance }) => {
+            FtsExpr::Near(FtsNear {
+                literals,
+                distance,
+                ordered,
+            }) => {
                 let mut tokens = vec![];
                 for l in literals {
                     l.tokenize(tokenizer, &mut tokens);
@@ -147,8 +284,14 @@ impl FtsExpr {
                 FtsExpr::Near(FtsNear {
                     literals: tokens,
                     distance,
+                    ordered,
                 })
             }
+            // the parser gives phrases holding a single, untokenized quoted string
+            FtsExpr::Phrase(mut p) if p.literals.len() == 1 => {
+                p.literals.pop().unwrap().tokenize_phrase(tokenizer)
+            }
+            FtsExpr::Phrase(p) => FtsExpr::Phrase(p),
             FtsExpr::And(exprs) => FtsExpr::And(
                 exprs
                     .into_iter()
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::fts::ast::FtsLiteral;
+use crate::fts::tokenizer::TextAnalyzer;
+use crate::fts::FtsIndexManifest;
+use crate::DataValue;
+use smartstring::{LazyCompact, SmartString};
+
+const ELLIPSIS: &str = "…";
+
+/// Byte ranges of the tokens matching any of `literals`, for each field of the document.
+/// Overlapping ranges are merged.
+pub(crate) fn find_matches(
+    texts: &[SmartString<LazyCompact>],
+    manifest: &FtsIndexManifest,
+    literals: &[&FtsLiteral],
+    tokenizer: &TextAnalyzer,
+) -> Vec<Vec<(usize, usize)>> {
+    texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let field = manifest.fields.get(i).map(|f| &f.name);
+            let literals = literals
+                .iter()
+                .filter(|l| l.field.is_none() || l.field.as_ref() == field)
+                .collect::<Vec<_>>();
+            let mut ranges: Vec<(usize, usize)> = vec![];
+            let mut token_stream = tokenizer.token_stream(text);
+            while let Some(token) = token_stream.next() {
+                if literals.iter().any(|l| l.matches_term(&token.text)) {
+                    ranges.push((token.offset_from, token.offset_to));
+                }
+            }
+            ranges.sort_unstable();
+            let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+            for (from, to) in ranges {
+                match merged.last_mut() {
+                    Some((_, last_to)) if from <= *last_to => *last_to = (*last_to).max(to),
+                    _ => merged.push((from, to)),
+                }
+            }
+            merged
+        })
+        .collect()
+}
+
+/// The matches as a list of `[from, to]`, or of `[field, from, to]` for multi-field indices.
+pub(crate) fn highlights_value(
+    matches: &[Vec<(usize, usize)>],
+    manifest: &FtsIndexManifest,
+) -> DataValue {
+    let mut ret = vec![];
+    for (i, ranges) in matches.iter().enumerate() {
+        for (from, to) in ranges {
+            let mut item = Vec::with_capacity(3);
+            if let Some(field) = manifest.fields.get(i) {
+                item.push(DataValue::Str(field.name.clone()));
+            }
+            item.push(DataValue::from(*from as i64));
+            item.push(DataValue::from(*to as i64));
+            ret.push(DataValue::List(item));
+        }
+    }
+    DataValue::List(ret)
+}
+
+/// Snippet taken from the field with the most matches.
+pub(crate) fn snippet_value(
+    texts: &[SmartString<LazyCompact>],
+    matches: &[Vec<(usize, usize)>],
+    start_marker: &str,
+    end_marker: &str,
+    size: usize,
+) -> DataValue {
+    let best = matches
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(i, ranges)| (ranges.len(), !texts[*i].is_empty()))
+        .map(|(i, _)| i);
+    match best {
+        None => DataValue::Null,
+        Some(i) => DataValue::from(make_snippet(
+            &texts[i],
+            &matches[i],
+            start_marker,
+            end_marker,
+            size,
+        )),
+    }
+}
+
+/// Cut a window of at most `size` characters around the densest cluster of matches
+/// and surround the matches within it by the markers. A `size` of zero keeps the whole text.
+pub(crate) fn make_snippet(
+    text: &str,
+    ranges: &[(usize, usize)],
+    start_marker: &str,
+    end_marker: &str,
+    size: usize,
+) -> String {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let n_chars = chars.len();
+    let char_pos = |byte_offset: usize| chars.partition_point(|(b, _)| *b < byte_offset);
+    let byte_pos = |char_idx: usize| chars.get(char_idx).map_or(text.len(), |(b, _)| *b);
+
+    let (start, end) = if size == 0 || n_chars <= size {
+        (0, n_chars)
+    } else if ranges.is_empty() {
+        match (0..size).rev().find(|p| chars[*p].1.is_whitespace()) {
+            Some(p) => (0, p),
+            None => (0, size),
+        }
+    } else {
+        // the window starting at a match and containing the most matches
+        let mut best = (0, 0, 0);
+        for (i, (from, _)) in ranges.iter().enumerate() {
+            let first = char_pos(*from);
+            let mut last = char_pos(ranges[i].1);
+            let mut n = 0;
+            for (_, to) in &ranges[i..] {
+                let to = char_pos(*to);
+                if to > first + size {
+                    break;
+                }
+                last = to;
+                n += 1;
+            }
+            if n > best.0 {
+                best = (n, first, last);
+            }
+        }
+        let (_, first, last) = best;
+        let leftover = size.saturating_sub(last - first);
+        let end = (first.saturating_sub(leftover / 2) + size).min(n_chars);
+        let mut start = end - size;
+        let mut end = end;
+        // avoid cutting words at the edges of the window
+        if start > 0 {
+            if let Some(p) = (start..first).find(|p| chars[*p].1.is_whitespace()) {
+                start = p + 1;
+            }
+        }
+        if end < n_chars {
+            if let Some(p) = (last..end).rev().find(|p| chars[*p].1.is_whitespace()) {
+                end = p;
+            }
+        }
+        (start, end)
+    };
+
+    let (window_from, window_to) = (byte_pos(start), byte_pos(end));
+    let mut ret = String::new();
+    if start > 0 {
+        ret.push_str(ELLIPSIS);
+    }
+    let mut pos = window_from;
+    for (from, to) in ranges {
+        let from = (*from).max(window_from);
+        let to = (*to).min(window_to);
+        if from >= to {
+            continue;
+        }
+        ret.push_str(&text[pos..from]);
+        ret.push_str(start_marker);
+        ret.push_str(&text[from..to]);
+        ret.push_str(end_marker);
+        pos = to;
+    }
+    ret.push_str(&text[pos..window_to]);
+    if end < n_chars {
+        ret.push_str(ELLIPSIS);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fts::highlight::make_snippet;
+
+    #[test]
+    fn test_snippet() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        assert_eq!(
+            make_snippet(text, &[(16, 19)], "[", "]", 0),
+            "the quick brown [fox] jumps over the lazy dog"
+        );
+        assert_eq!(
+            make_snippet(text, &[(16, 19), (26, 30)], "[", "]", 20),
+            "…[fox] jumps [over]…"
+        );
+        assert_eq!(make_snippet(text, &[], "[", "]", 10), "the quick…");
+        assert_eq!(
+            make_snippet("été à la plage", &[(6, 8)], "<", ">", 100),
+            "été <à> la plage"
+        );
+    }
+}
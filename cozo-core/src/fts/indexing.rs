@@ -8,30 +8,109 @@
 
 use crate::data::expr::{eval_bytecode, eval_bytecode_pred, Bytecode};
 use crate::data::program::{FtsScoreKind, FtsSearch};
-use crate::data::tuple::{decode_tuple_from_key, Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::LARGEST_UTF_CHAR;
-use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear};
+use crate::fts::ast::{levenshtein_step, FtsExpr, FtsLiteral, FtsNear, FtsPhrase};
+use crate::fts::highlight::{find_matches, highlights_value, snippet_value};
 use crate::fts::tokenizer::TextAnalyzer;
+use crate::fts::FtsIndexManifest;
+use crate::parse::expr::build_expr;
 use crate::parse::fts::parse_fts_query;
-use crate::runtime::relation::RelationHandle;
+use crate::parse::{CozoScriptParser, Rule};
+use crate::runtime::relation::{RelationHandle, RelationId};
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Diagnostic, Result};
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result};
 use ordered_float::OrderedFloat;
+use pest::Parser;
 use rustc_hash::{FxHashMap, FxHashSet};
 use smartstring::{LazyCompact, SmartString};
 use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{btree_map, BTreeMap, HashMap};
 use thiserror::Error;
 
 #[derive(Default)]
 pub(crate) struct FtsCache {
     total_n_cache: FxHashMap<SmartString<LazyCompact>, usize>,
+    stats_cache: FxHashMap<SmartString<LazyCompact>, FtsIndexStats>,
+    extractor_cache: FxHashMap<SmartString<LazyCompact>, Vec<Bytecode>>,
+}
+
+/// Number of indexed documents and the sum of their lengths for each field,
+/// kept for indices with `doc_stats`.
+#[derive(Debug, Default, Clone)]
+struct FtsIndexStats {
+    n_docs: u64,
+    total_lengths: Vec<u64>,
+}
+
+/// Number of shards the statistics of an index are split into. Each write
+/// transaction updates a single shard, so that concurrent writers seldom
+/// conflict, and the shards are summed when searching.
+pub(crate) const FTS_STATS_SHARDS: u8 = 16;
+
+/// The changes to the statistics written through one shard, which may
+/// be negative when documents added through other shards are deleted.
+#[derive(Debug, Default, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+struct FtsStatsShard {
+    n_docs: i64,
+    total_lengths: Vec<i64>,
+}
+
+fn fts_stats_prefix(idx_handle: &RelationHandle) -> Tuple {
+    vec![
+        DataValue::Null,
+        DataValue::from("FTS_STATS"),
+        DataValue::from(idx_handle.id.0 as i64),
+    ]
+}
+
+fn fts_stats_key(idx_handle: &RelationHandle, shard: u8) -> Vec<u8> {
+    let mut key = fts_stats_prefix(idx_handle);
+    key.push(DataValue::from(shard as i64));
+    key.encode_as_key(RelationId::SYSTEM)
+}
+
+fn fts_stats_range(idx_handle: &RelationHandle) -> (Vec<u8>, Vec<u8>) {
+    let lower = fts_stats_prefix(idx_handle);
+    let mut upper = lower.clone();
+    upper.push(DataValue::Bot);
+    (
+        lower.encode_as_key(RelationId::SYSTEM),
+        upper.encode_as_key(RelationId::SYSTEM),
+    )
 }
 
 impl FtsCache {
+    fn get_extractor(&mut self, config: &FtsSearch) -> Result<&[Bytecode]> {
+        Ok(
+            match self.extractor_cache.entry(config.idx_handle.name.clone()) {
+                Entry::Vacant(v) => {
+                    let parsed = CozoScriptParser::parse(Rule::expr, &config.manifest.extractor)
+                        .into_diagnostic()?
+                        .next()
+                        .unwrap();
+                    let mut code_expr = build_expr(parsed, &Default::default())?;
+                    let binding_map = config.base_handle.raw_binding_map();
+                    code_expr.fill_binding_indices(&binding_map)?;
+                    v.insert(code_expr.compile()?)
+                }
+                Entry::Occupied(o) => o.into_mut(),
+            },
+        )
+    }
+    fn get_stats(
+        &mut self,
+        idx_handle: &RelationHandle,
+        tx: &SessionTx<'_>,
+    ) -> Result<&FtsIndexStats> {
+        Ok(match self.stats_cache.entry(idx_handle.name.clone()) {
+            Entry::Vacant(v) => v.insert(tx.get_fts_stats(idx_handle)?),
+            Entry::Occupied(o) => o.into_mut(),
+        })
+    }
     fn get_n_for_relation(&mut self, rel: &RelationHandle, tx: &SessionTx<'_>) -> Result<usize> {
         Ok(match self.total_n_cache.entry(rel.name.clone()) {
             Entry::Vacant(v) => {
@@ -50,23 +129,174 @@ struct PositionInfo {
     // from: u32,
     // to: u32,
     position: u32,
+    field: usize,
 }
 
 struct LiteralStats {
     key: Tuple,
     position_info: Vec<PositionInfo>,
-    // doc_len: u32,
+    field_lengths: Vec<u32>,
+    /// Less than one for documents found by fuzzy matching only
+    weight: f64,
+}
+
+/// Collection-wide quantities entering the score of a document.
+struct FtsScoreStats {
+    /// Number of documents: the size of the base relation for TF-IDF,
+    /// the number of indexed documents for BM25
+    n_total: usize,
+    avg_lengths: Vec<f64>,
+    boosts: Vec<f64>,
+}
+
+impl LiteralStats {
+    fn field_tfs(&self, n_fields: usize) -> Vec<usize> {
+        let mut tfs = vec![0; n_fields];
+        for pi in &self.position_info {
+            tfs[pi.field] += 1;
+        }
+        tfs
+    }
+}
+
+/// The smallest string greater than every string starting with `prefix`,
+/// or `None` if there is none.
+fn prefix_successor(prefix: &str) -> Option<SmartString<LazyCompact>> {
+    let mut chars = prefix.chars().collect_vec();
+    while let Some(last) = chars.pop() {
+        // skips the surrogates
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
 }
 
 impl<'a> SessionTx<'a> {
     fn fts_search_literal(
         &self,
         literal: &FtsLiteral,
+        config: &FtsSearch,
+    ) -> Result<Vec<LiteralStats>> {
+        let idx_handle = &config.idx_handle;
+        let field = match &literal.field {
+            None => None,
+            Some(name) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("FTS index {0} has no field named '{1}'")]
+                #[diagnostic(code(eval::fts::field_not_found))]
+                struct FtsFieldNotFound(String, String, #[label] SourceSpan);
+
+                let pos = config.manifest.fields.iter().position(|f| f.name == *name);
+                Some(pos.ok_or_else(|| {
+                    FtsFieldNotFound(idx_handle.name.to_string(), name.to_string(), config.span)
+                })?)
+            }
+        };
+        if literal.max_edits == 0 {
+            return self.fts_search_term(&literal.value, literal.is_prefix, field, idx_handle);
+        }
+
+        let query_len = literal.value.chars().count() as f64;
+        let mut found: BTreeMap<Tuple, LiteralStats> = BTreeMap::new();
+        for (term, distance) in self.fts_fuzzy_terms(literal, idx_handle)? {
+            let weight = 1. - distance as f64 / (query_len + 1.);
+            for mut el in self.fts_search_term(&term, false, field, idx_handle)? {
+                el.weight = weight;
+                match found.entry(el.key.clone()) {
+                    btree_map::Entry::Vacant(v) => {
+                        v.insert(el);
+                    }
+                    btree_map::Entry::Occupied(mut o) => {
+                        let prev = o.get_mut();
+                        prev.position_info.extend(el.position_info);
+                        prev.weight = prev.weight.max(weight);
+                    }
+                }
+            }
+        }
+        for el in found.values_mut() {
+            el.position_info.sort_by_key(|pi| (pi.field, pi.position));
+            el.position_info.dedup_by_key(|pi| (pi.field, pi.position));
+        }
+        Ok(found.into_values().collect())
+    }
+    /// Terms in the index within `literal.max_edits` of the literal, with their distances.
+    /// The sorted term dictionary is walked like a trie: the rows of the Levenshtein matrix
+    /// are computed character by character, and all terms sharing a prefix that can no longer
+    /// match are skipped with a single seek.
+    fn fts_fuzzy_terms(
+        &self,
+        literal: &FtsLiteral,
+        idx_handle: &RelationHandle,
+    ) -> Result<Vec<(SmartString<LazyCompact>, u32)>> {
+        let query = literal.value.chars().collect_vec();
+        let n = query.len();
+        let max_edits = literal.max_edits as u32;
+        let upper = idx_handle.encode_partial_key_for_store(&[DataValue::Bot]);
+        let mut lower_term = SmartString::<LazyCompact>::new();
+        let mut ret = vec![];
+        loop {
+            let lower = idx_handle.encode_partial_key_for_store(&[DataValue::Str(lower_term)]);
+            let term = match self.store_tx.range_scan(&lower, &upper).next() {
+                None => break,
+                Some(item) => {
+                    let (kvec, _) = item?;
+                    match decode_tuple_from_key(&kvec, 1).into_iter().next() {
+                        Some(DataValue::Str(s)) => s,
+                        _ => break,
+                    }
+                }
+            };
+
+            let mut row = (0..=n as u32).collect_vec();
+            // for prefix literals, the distance is that of the closest prefix of the term
+            let mut distance = (literal.is_prefix && row[n] <= max_edits).then_some(row[n]);
+            let mut dead_end = None;
+            for (pos, c) in term.char_indices() {
+                row = levenshtein_step(&query, &row, c);
+                if literal.is_prefix && row[n] <= max_edits {
+                    distance = Some(distance.map_or(row[n], |d| d.min(row[n])));
+                }
+                if *row.iter().min().unwrap() > max_edits {
+                    dead_end = Some(pos + c.len_utf8());
+                    break;
+                }
+            }
+            if !literal.is_prefix && dead_end.is_none() && row[n] <= max_edits {
+                distance = Some(row[n]);
+            }
+            let next_term = match dead_end {
+                Some(end) => prefix_successor(&term[..end]),
+                None => {
+                    // the smallest term greater than the current one
+                    let mut skip_to = term.clone();
+                    skip_to.push('\0');
+                    Some(skip_to)
+                }
+            };
+            if let Some(d) = distance {
+                ret.push((term, d));
+            }
+            match next_term {
+                Some(next) => lower_term = next,
+                None => break,
+            }
+        }
+        Ok(ret)
+    }
+    fn fts_search_term(
+        &self,
+        term: &str,
+        is_prefix: bool,
+        field: Option<usize>,
         idx_handle: &RelationHandle,
     ) -> Result<Vec<LiteralStats>> {
-        let start_key_str = &literal.value as &str;
+        let start_key_str = term;
         let start_key = vec![DataValue::Str(SmartString::from(start_key_str))];
-        let mut end_key_str = literal.value.clone();
+        let mut end_key_str = SmartString::<LazyCompact>::from(term);
         end_key_str.push(LARGEST_UTF_CHAR);
         let end_key = vec![DataValue::Str(end_key_str)];
         let start_key_bytes = idx_handle.encode_partial_key_for_store(&start_key);
@@ -76,7 +306,7 @@ impl<'a> SessionTx<'a> {
             let (kvec, vvec) = item?;
             let key_tuple = decode_tuple_from_key(&kvec, idx_handle.metadata.keys.len());
             let found_str_key = key_tuple[0].get_str().unwrap();
-            if literal.is_prefix {
+            if is_prefix {
                 if !found_str_key.starts_with(start_key_str) {
                     break;
                 }
@@ -88,21 +318,34 @@ impl<'a> SessionTx<'a> {
             let froms = vals[0].get_slice().unwrap();
             let tos = vals[1].get_slice().unwrap();
             let positions = vals[2].get_slice().unwrap();
-            // let total_length = vals[3].get_int().unwrap();
+            let total_length = vals[3].get_int().unwrap();
+            // indices created without `doc_stats` have neither fields nor field lengths
+            let fields = vals.get(4).and_then(|v| v.get_slice()).unwrap_or(&[]);
+            let field_lengths = match vals.get(5).and_then(|v| v.get_slice()) {
+                Some(ls) => ls.iter().map(|l| l.get_int().unwrap() as u32).collect(),
+                None => vec![total_length as u32],
+            };
             let position_info = froms
                 .iter()
                 .zip(tos.iter())
                 .zip(positions.iter())
-                .map(|(_, p)| PositionInfo {
+                .enumerate()
+                .map(|(i, (_, p))| PositionInfo {
                     // from: f.get_int().unwrap() as u32,
                     // to: t.get_int().unwrap() as u32,
                     position: p.get_int().unwrap() as u32,
+                    field: fields.get(i).map_or(0, |f| f.get_int().unwrap() as usize),
                 })
+                .filter(|pi| !matches!(field, Some(f) if pi.field != f))
                 .collect_vec();
+            if position_info.is_empty() {
+                continue;
+            }
             results.push(LiteralStats {
                 key: key_tuple[1..].to_vec(),
                 position_info,
-                // doc_len: total_length as u32,
+                field_lengths,
+                weight: 1.,
             });
         }
         Ok(results)
@@ -111,20 +354,22 @@ impl<'a> SessionTx<'a> {
         &self,
         ast: &FtsExpr,
         config: &FtsSearch,
-        n: usize,
+        stats: &FtsScoreStats,
     ) -> Result<FxHashMap<Tuple, f64>> {
+        let n_fields = stats.boosts.len();
         Ok(match ast {
             FtsExpr::Literal(l) => {
                 let mut res = FxHashMap::default();
-                let found_docs = self.fts_search_literal(l, &config.idx_handle)?;
+                let found_docs = self.fts_search_literal(l, config)?;
                 let found_docs_len = found_docs.len();
                 for el in found_docs {
                     let score = Self::fts_compute_score(
-                        el.position_info.len(),
+                        &el.field_tfs(n_fields),
+                        &el.field_lengths,
                         found_docs_len,
-                        n,
-                        l.booster.0,
+                        l.booster.0 * el.weight,
                         config,
+                        stats,
                     );
                     res.insert(el.key, score);
                 }
@@ -132,13 +377,9 @@ impl<'a> SessionTx<'a> {
             }
             FtsExpr::And(ls) => {
                 let mut l_iter = ls.iter();
-                let mut res = self.fts_search_impl(
-                    l_iter.next().unwrap(),
-                    config,
-                    n,
-                )?;
+                let mut res = self.fts_search_impl(l_iter.next().unwrap(), config, stats)?;
                 for nxt in l_iter {
-                    let nxt_res = self.fts_search_impl(nxt, config, n)?;
+                    let nxt_res = self.fts_search_impl(nxt, config, stats)?;
                     res = res
                         .into_iter()
                         .filter_map(|(k, v)| nxt_res.get(&k).map(|nxt_v| (k, v + nxt_v)))
@@ -149,7 +390,7 @@ impl<'a> SessionTx<'a> {
             FtsExpr::Or(ls) => {
                 let mut res: FxHashMap<Tuple, f64> = FxHashMap::default();
                 for nxt in ls {
-                    let nxt_res = self.fts_search_impl(nxt, config, n)?;
+                    let nxt_res = self.fts_search_impl(nxt, config, stats)?;
                     for (k, v) in nxt_res {
                         if let Some(old_v) = res.get_mut(&k) {
                             *old_v = (*old_v).max(v);
@@ -160,88 +401,150 @@ impl<'a> SessionTx<'a> {
                 }
                 res
             }
-            FtsExpr::Near(FtsNear { literals, distance }) => {
-                let mut l_it = literals.iter();
-                let mut coll: FxHashMap<_, _> = FxHashMap::default();
-                for first_el in self.fts_search_literal(l_it.next().unwrap(), &config.idx_handle)? {
-                    coll.insert(
-                        first_el.key,
-                        first_el
-                            .position_info
-                            .into_iter()
-                            .map(|el| el.position)
-                            .collect_vec(),
-                    );
-                }
-                for lit_nxt in literals {
-                    let el_res = self.fts_search_literal(lit_nxt, &config.idx_handle)?;
-                    coll = el_res
-                        .into_iter()
-                        .filter_map(|x| match coll.remove(&x.key) {
-                            None => None,
-                            Some(prev_pos) => {
-                                let mut inner_coll = FxHashSet::default();
-                                for p in prev_pos {
-                                    for pi in x.position_info.iter() {
-                                        let cur = pi.position;
-                                        if cur > p {
-                                            if cur - p <= *distance {
-                                                inner_coll.insert(p);
-                                            }
-                                        } else if p - cur <= *distance {
-                                            inner_coll.insert(cur);
-                                        }
-                                    }
-                                }
-                                if inner_coll.is_empty() {
-                                    None
-                                } else {
-                                    Some((x.key, inner_coll.into_iter().collect_vec()))
-                                }
-                            }
-                        })
-                        .collect();
-                }
-                let mut booster = 0.0;
-                for lit in literals {
-                    booster += lit.booster.0;
+            FtsExpr::Near(FtsNear {
+                literals,
+                distance,
+                ordered,
+            }) => {
+                let distance = *distance;
+                if *ordered {
+                    self.fts_search_positional(literals, config, stats, |_, p, cur| {
+                        (cur > p && cur - p <= distance).then_some(cur)
+                    })?
+                } else {
+                    self.fts_search_positional(literals, config, stats, |_, p, cur| {
+                        if cur > p {
+                            (cur - p <= distance).then_some(p)
+                        } else {
+                            (p - cur <= distance).then_some(cur)
+                        }
+                    })?
                 }
-                let coll_len = coll.len();
-                coll.into_iter()
-                    .map(|(k, cands)| {
-                        (
-                            k,
-                            Self::fts_compute_score(cands.len(), coll_len, n, booster, config),
-                        )
-                    })
-                    .collect()
+            }
+            FtsExpr::Phrase(FtsPhrase { literals, offsets }) => {
+                self.fts_search_positional(literals, config, stats, |i, p, cur| {
+                    (cur == p + offsets[i] - offsets[i - 1]).then_some(cur)
+                })?
             }
             FtsExpr::Not(fst, snd) => {
-                let mut res = self.fts_search_impl(fst, config, n)?;
-                for el in self
-                    .fts_search_impl(snd, config, n)?
-                    .keys()
-                {
+                let mut res = self.fts_search_impl(fst, config, stats)?;
+                for el in self.fts_search_impl(snd, config, stats)?.keys() {
                     res.remove(el);
                 }
                 res
             }
         })
     }
+    /// Search for documents in which the literals occur at related positions within the same field.
+    /// `matches(i, p, cur)` decides if the `i`-th literal at position `cur` continues a match
+    /// anchored at `p`, giving the new anchor if it does.
+    fn fts_search_positional(
+        &self,
+        literals: &[FtsLiteral],
+        config: &FtsSearch,
+        stats: &FtsScoreStats,
+        matches: impl Fn(usize, u32, u32) -> Option<u32>,
+    ) -> Result<FxHashMap<Tuple, f64>> {
+        let n_fields = stats.boosts.len();
+        let mut l_it = literals.iter();
+        let mut coll: FxHashMap<_, _> = FxHashMap::default();
+        for first_el in self.fts_search_literal(l_it.next().unwrap(), config)? {
+            coll.insert(
+                first_el.key,
+                (
+                    first_el
+                        .position_info
+                        .into_iter()
+                        .map(|el| (el.field, el.position))
+                        .collect_vec(),
+                    first_el.field_lengths,
+                ),
+            );
+        }
+        for (i, lit_nxt) in l_it.enumerate() {
+            let el_res = self.fts_search_literal(lit_nxt, config)?;
+            coll = el_res
+                .into_iter()
+                .filter_map(|x| match coll.remove(&x.key) {
+                    None => None,
+                    Some((prev_pos, field_lengths)) => {
+                        let mut inner_coll = FxHashSet::default();
+                        for (f, p) in prev_pos {
+                            // positions in different fields are not comparable
+                            for pi in x.position_info.iter().filter(|pi| pi.field == f) {
+                                if let Some(anchor) = matches(i + 1, p, pi.position) {
+                                    inner_coll.insert((f, anchor));
+                                }
+                            }
+                        }
+                        if inner_coll.is_empty() {
+                            None
+                        } else {
+                            Some((x.key, (inner_coll.into_iter().collect_vec(), field_lengths)))
+                        }
+                    }
+                })
+                .collect();
+        }
+        let mut booster = 0.0;
+        for lit in literals {
+            booster += lit.booster.0;
+        }
+        let coll_len = coll.len();
+        Ok(coll
+            .into_iter()
+            .map(|(k, (cands, field_lengths))| {
+                let mut tfs = vec![0; n_fields];
+                for (f, _) in cands {
+                    tfs[f] += 1;
+                }
+                (
+                    k,
+                    Self::fts_compute_score(&tfs, &field_lengths, coll_len, booster, config, stats),
+                )
+            })
+            .collect())
+    }
     fn fts_compute_score(
-        tf: usize,
+        field_tfs: &[usize],
+        field_lengths: &[u32],
         n_found_docs: usize,
-        n_total: usize,
         booster: f64,
         config: &FtsSearch,
+        stats: &FtsScoreStats,
     ) -> f64 {
-        let tf = tf as f64;
+        let idf = || {
+            let n_found_docs = n_found_docs as f64;
+            (1.0 + (stats.n_total as f64 - n_found_docs + 0.5) / (n_found_docs + 0.5)).ln()
+        };
+        let weighted_tf = || -> f64 {
+            field_tfs
+                .iter()
+                .zip(stats.boosts.iter())
+                .map(|(tf, boost)| *tf as f64 * boost)
+                .sum()
+        };
         match config.score_kind {
-            FtsScoreKind::Tf => tf * booster,
-            FtsScoreKind::TfIdf => {
-                let n_found_docs = n_found_docs as f64;
-                let idf = (1.0 + (n_total as f64 - n_found_docs + 0.5) / (n_found_docs + 0.5)).ln();
-                tf * idf * booster
+            FtsScoreKind::Tf => weighted_tf() * booster,
+            FtsScoreKind::TfIdf => weighted_tf() * idf() * booster,
+            FtsScoreKind::Bm25 => {
+                // BM25F: term frequencies are normalized by the length of their field
+                // before being weighted and combined
+                let tf: f64 = field_tfs
+                    .iter()
+                    .enumerate()
+                    .map(|(f, tf)| {
+                        let len = field_lengths.get(f).copied().unwrap_or(0) as f64;
+                        let avg = stats.avg_lengths[f];
+                        let norm = if avg > 0. {
+                            1. - config.b + config.b * len / avg
+                        } else {
+                            1.
+                        };
+                        stats.boosts[f] * *tf as f64 / norm
+                    })
+                    .sum();
+                idf() * tf * (config.k1 + 1.) / (config.k1 + tf) * booster
             }
         }
     }
@@ -258,13 +561,41 @@ impl<'a> SessionTx<'a> {
         if ast.is_empty() {
             return Ok(vec![]);
         }
-        let n = if config.score_kind == FtsScoreKind::TfIdf {
-            cache.get_n_for_relation(&config.base_handle, self)?
-        } else {
-            0
+        let n_fields = config.manifest.n_fields();
+        let mut stats = FtsScoreStats {
+            n_total: 0,
+            avg_lengths: vec![0.; n_fields],
+            boosts: config.manifest.field_boosts(),
         };
+        match config.score_kind {
+            FtsScoreKind::Tf => {}
+            FtsScoreKind::TfIdf => {
+                stats.n_total = cache.get_n_for_relation(&config.base_handle, self)?;
+            }
+            FtsScoreKind::Bm25 => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("FTS index {0} does not keep the document statistics required by BM25")]
+                #[diagnostic(code(eval::fts::no_doc_stats))]
+                #[diagnostic(help(
+                    "Indices created by older versions must be dropped and created again"
+                ))]
+                struct FtsNoDocStats(String, #[label] SourceSpan);
+
+                ensure!(
+                    config.manifest.doc_stats,
+                    FtsNoDocStats(config.idx_handle.name.to_string(), config.span)
+                );
+                let idx_stats = cache.get_stats(&config.idx_handle, self)?;
+                stats.n_total = idx_stats.n_docs as usize;
+                if idx_stats.n_docs > 0 {
+                    for (avg, total) in stats.avg_lengths.iter_mut().zip(&idx_stats.total_lengths) {
+                        *avg = *total as f64 / idx_stats.n_docs as f64;
+                    }
+                }
+            }
+        }
         let mut result: Vec<_> = self
-            .fts_search_impl(&ast, config, n)?
+            .fts_search_impl(&ast, config, &stats)?
             .into_iter()
             .collect();
         result.sort_by_key(|(_, score)| Reverse(OrderedFloat(*score)));
@@ -272,6 +603,14 @@ impl<'a> SessionTx<'a> {
             result.truncate(config.k);
         }
 
+        let extractor = if config.bind_highlights.is_some() || config.bind_snippet.is_some() {
+            Some(cache.get_extractor(config)?)
+        } else {
+            None
+        };
+        let mut literals = vec![];
+        ast.positive_literals(&mut literals);
+
         let mut ret = Vec::with_capacity(config.k);
         for (found_key, score) in result {
             let mut cand_tuple = config
@@ -283,6 +622,24 @@ impl<'a> SessionTx<'a> {
                 cand_tuple.push(DataValue::from(score));
             }
 
+            if let Some(extractor) = extractor {
+                let texts = extract_fts_fields(extractor, &cand_tuple, stack, &config.manifest)?
+                    .unwrap_or_default();
+                let matches = find_matches(&texts, &config.manifest, &literals, tokenizer);
+                if config.bind_highlights.is_some() {
+                    cand_tuple.push(highlights_value(&matches, &config.manifest));
+                }
+                if config.bind_snippet.is_some() {
+                    cand_tuple.push(snippet_value(
+                        &texts,
+                        &matches,
+                        &config.highlight_start,
+                        &config.highlight_end,
+                        config.snippet_size,
+                    ));
+                }
+            }
+
             if let Some((code, span)) = filter_code {
                 if !eval_bytecode_pred(code, &cand_tuple, stack, *span)? {
                     continue;
@@ -296,6 +653,60 @@ impl<'a> SessionTx<'a> {
         }
         Ok(ret)
     }
+    fn get_fts_stats(&self, idx_handle: &RelationHandle) -> Result<FtsIndexStats> {
+        let (lower, upper) = fts_stats_range(idx_handle);
+        let mut n_docs = 0i64;
+        let mut total_lengths: Vec<i64> = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            let (_, bytes) = kv?;
+            let shard: FtsStatsShard = rmp_serde::from_slice(&bytes).into_diagnostic()?;
+            n_docs += shard.n_docs;
+            if total_lengths.len() < shard.total_lengths.len() {
+                total_lengths.resize(shard.total_lengths.len(), 0);
+            }
+            for (total, len) in total_lengths.iter_mut().zip(&shard.total_lengths) {
+                *total += len;
+            }
+        }
+        Ok(FtsIndexStats {
+            n_docs: n_docs.max(0) as u64,
+            total_lengths: total_lengths.into_iter().map(|l| l.max(0) as u64).collect(),
+        })
+    }
+    fn update_fts_stats(
+        &mut self,
+        idx_handle: &RelationHandle,
+        field_lengths: &[u64],
+        is_add: bool,
+    ) -> Result<()> {
+        let key = fts_stats_key(idx_handle, self.fts_stats_shard);
+        let mut shard: FtsStatsShard = match self.store_tx.get(&key, true)? {
+            None => FtsStatsShard::default(),
+            Some(bytes) => rmp_serde::from_slice(&bytes).into_diagnostic()?,
+        };
+        if shard.total_lengths.len() < field_lengths.len() {
+            shard.total_lengths.resize(field_lengths.len(), 0);
+        }
+        let sign = if is_add { 1 } else { -1 };
+        shard.n_docs += sign;
+        for (total, len) in shard.total_lengths.iter_mut().zip(field_lengths) {
+            *total += sign * *len as i64;
+        }
+        let val = rmp_serde::to_vec(&shard).unwrap();
+        self.store_tx.put(&key, &val)
+    }
+    pub(crate) fn del_fts_stats(&mut self, idx_handle: &RelationHandle) -> Result<()> {
+        let (lower, upper) = fts_stats_range(idx_handle);
+        let keys = self
+            .store_tx
+            .range_scan(&lower, &upper)
+            .map_ok(|(k, _)| k)
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            self.store_tx.del(&key)?;
+        }
+        Ok(())
+    }
     pub(crate) fn put_fts_index_item(
         &mut self,
         tuple: &[DataValue],
@@ -304,30 +715,27 @@ impl<'a> SessionTx<'a> {
         tokenizer: &TextAnalyzer,
         rel_handle: &RelationHandle,
         idx_handle: &RelationHandle,
+        manifest: &FtsIndexManifest,
     ) -> Result<()> {
-        let to_index = match eval_bytecode(extractor, tuple, stack)? {
-            DataValue::Null => return Ok(()),
-            DataValue::Str(s) => s,
-            val => {
-                #[derive(Debug, Diagnostic, Error)]
-                #[error("FTS index extractor must return a string, got {0}")]
-                #[diagnostic(code(eval::fts::extractor::invalid_return_type))]
-                struct FtsExtractError(String);
-
-                bail!(FtsExtractError(format!("{}", val)))
-            }
+        let to_index = match extract_fts_fields(extractor, tuple, stack, manifest)? {
+            None => return Ok(()),
+            Some(fields) => fields,
         };
-        let mut token_stream = tokenizer.token_stream(&to_index);
-        let mut collector: HashMap<_, (Vec<_>, Vec<_>, Vec<_>), _> = FxHashMap::default();
-        let mut count = 0i64;
-        while let Some(token) = token_stream.next() {
-            let text = SmartString::<LazyCompact>::from(&token.text);
-            let (fr, to, position) = collector.entry(text).or_default();
-            fr.push(DataValue::from(token.offset_from as i64));
-            to.push(DataValue::from(token.offset_to as i64));
-            position.push(DataValue::from(token.position as i64));
-            count += 1;
+        let mut collector: HashMap<_, (Vec<_>, Vec<_>, Vec<_>, Vec<_>), _> = FxHashMap::default();
+        let mut field_lengths = vec![0u64; to_index.len()];
+        for (field_idx, text) in to_index.iter().enumerate() {
+            let mut token_stream = tokenizer.token_stream(text);
+            while let Some(token) = token_stream.next() {
+                let text = SmartString::<LazyCompact>::from(&token.text);
+                let (fr, to, position, field) = collector.entry(text).or_default();
+                fr.push(DataValue::from(token.offset_from as i64));
+                to.push(DataValue::from(token.offset_to as i64));
+                position.push(DataValue::from(token.position as i64));
+                field.push(DataValue::from(field_idx as i64));
+                field_lengths[field_idx] += 1;
+            }
         }
+        let count: u64 = field_lengths.iter().sum();
         let mut key = Vec::with_capacity(1 + rel_handle.metadata.keys.len());
         key.push(DataValue::Bot);
         for k in &tuple[..rel_handle.metadata.keys.len()] {
@@ -337,17 +745,32 @@ impl<'a> SessionTx<'a> {
             DataValue::Bot,
             DataValue::Bot,
             DataValue::Bot,
-            DataValue::from(count),
+            DataValue::from(count as i64),
         ];
-        for (text, (from, to, position)) in collector {
+        if manifest.doc_stats {
+            val.push(DataValue::Bot);
+            val.push(DataValue::List(
+                field_lengths
+                    .iter()
+                    .map(|l| DataValue::from(*l as i64))
+                    .collect(),
+            ));
+        }
+        for (text, (from, to, position, field)) in collector {
             key[0] = DataValue::Str(text);
             val[0] = DataValue::List(from);
             val[1] = DataValue::List(to);
             val[2] = DataValue::List(position);
+            if manifest.doc_stats {
+                val[4] = DataValue::List(field);
+            }
             let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
             let val_bytes = idx_handle.encode_val_only_for_store(&val, Default::default())?;
             self.store_tx.put(&key_bytes, &val_bytes)?;
         }
+        if manifest.doc_stats && count > 0 {
+            self.update_fts_stats(idx_handle, &field_lengths, true)?;
+        }
         Ok(())
     }
     pub(crate) fn del_fts_index_item(
@@ -358,24 +781,21 @@ impl<'a> SessionTx<'a> {
         tokenizer: &TextAnalyzer,
         rel_handle: &RelationHandle,
         idx_handle: &RelationHandle,
+        manifest: &FtsIndexManifest,
     ) -> Result<()> {
-        let to_index = match eval_bytecode(extractor, tuple, stack)? {
-            DataValue::Null => return Ok(()),
-            DataValue::Str(s) => s,
-            val => {
-                #[derive(Debug, Diagnostic, Error)]
-                #[error("FTS index extractor must return a string, got {0}")]
-                #[diagnostic(code(eval::fts::extractor::invalid_return_type))]
-                struct FtsExtractError(String);
-
-                bail!(FtsExtractError(format!("{}", val)))
-            }
+        let to_index = match extract_fts_fields(extractor, tuple, stack, manifest)? {
+            None => return Ok(()),
+            Some(fields) => fields,
         };
-        let mut token_stream = tokenizer.token_stream(&to_index);
         let mut collector = FxHashSet::default();
-        while let Some(token) = token_stream.next() {
-            let text = SmartString::<LazyCompact>::from(&token.text);
-            collector.insert(text);
+        let mut field_lengths = vec![0u64; to_index.len()];
+        for (field_idx, text) in to_index.iter().enumerate() {
+            let mut token_stream = tokenizer.token_stream(text);
+            while let Some(token) = token_stream.next() {
+                let text = SmartString::<LazyCompact>::from(&token.text);
+                collector.insert(text);
+                field_lengths[field_idx] += 1;
+            }
         }
         let mut key = Vec::with_capacity(1 + rel_handle.metadata.keys.len());
         key.push(DataValue::Bot);
@@ -387,6 +807,49 @@ impl<'a> SessionTx<'a> {
             let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
             self.store_tx.del(&key_bytes)?;
         }
+        if manifest.doc_stats && field_lengths.iter().any(|l| *l > 0) {
+            self.update_fts_stats(idx_handle, &field_lengths, false)?;
+        }
         Ok(())
     }
 }
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("FTS index extractor must return {0}, got {1}")]
+#[diagnostic(code(eval::fts::extractor::invalid_return_type))]
+struct FtsExtractError(String, String);
+
+/// Evaluates the extractor of the index, giving the text of each field,
+/// or `None` if the tuple is not to be indexed.
+fn extract_fts_fields(
+    extractor: &[Bytecode],
+    tuple: &[DataValue],
+    stack: &mut Vec<DataValue>,
+    manifest: &FtsIndexManifest,
+) -> Result<Option<Vec<SmartString<LazyCompact>>>> {
+    let val = eval_bytecode(extractor, tuple, stack)?;
+    if manifest.fields.is_empty() {
+        return Ok(match val {
+            DataValue::Null => None,
+            DataValue::Str(s) => Some(vec![s]),
+            val => bail!(FtsExtractError("a string".to_string(), val.to_string())),
+        });
+    }
+    let n_fields = manifest.fields.len();
+    match val {
+        DataValue::Null => Ok(None),
+        DataValue::List(l) if l.len() == n_fields => l
+            .into_iter()
+            .map(|v| match v {
+                DataValue::Null => Ok(SmartString::new()),
+                DataValue::Str(s) => Ok(s),
+                v => bail!(FtsExtractError("a string".to_string(), v.to_string())),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some),
+        val => bail!(FtsExtractError(
+            format!("a list of {n_fields} strings"),
+            val.to_string()
+        )),
+    }
+}
@@ -9,9 +9,10 @@
 use crate::data::memcmp::MemCmpEncoder;
 use crate::fts::cangjie::tokenizer::CangJieTokenizer;
 use crate::fts::tokenizer::{
-    AlphaNumOnlyFilter, AsciiFoldingFilter, BoxTokenFilter, Language, LowerCaser, NgramTokenizer,
-    RawTokenizer, RemoveLongFilter, SimpleTokenizer, SplitCompoundWords, Stemmer, StopWordFilter,
-    TextAnalyzer, Tokenizer, WhitespaceTokenizer,
+    AlphaNumOnlyFilter, AsciiFoldingFilter, BigramScripts, BoxTokenFilter, ElisionFilter, Language,
+    LowerCaser, NgramTokenizer, RawTokenizer, RemoveLongFilter, SimpleTokenizer,
+    SplitCompoundWords, Stemmer, StopWordFilter, SynonymFilter, TextAnalyzer, Tokenizer,
+    UnicodeTokenizer, WhitespaceTokenizer,
 };
 use crate::DataValue;
 use jieba_rs::Jieba;
@@ -24,6 +25,7 @@ use std::sync::{Arc, RwLock};
 
 pub(crate) mod ast;
 pub(crate) mod cangjie;
+pub(crate) mod highlight;
 pub(crate) mod indexing;
 pub(crate) mod tokenizer;
 
@@ -31,9 +33,36 @@ pub(crate) mod tokenizer;
 pub(crate) struct FtsIndexManifest {
     pub(crate) base_relation: SmartString<LazyCompact>,
     pub(crate) index_name: SmartString<LazyCompact>,
+    /// For multi-field indices, the extractor returns a list with one element for each field
     pub(crate) extractor: String,
     pub(crate) tokenizer: TokenizerConfig,
     pub(crate) filters: Vec<TokenizerConfig>,
+    /// Fields of a multi-field index, empty for single-field indices
+    #[serde(default)]
+    pub(crate) fields: Vec<FtsField>,
+    /// Whether the index keeps the document lengths and the statistics needed for BM25.
+    /// Indices created by older versions do not.
+    #[serde(default)]
+    pub(crate) doc_stats: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct FtsField {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) boost: f64,
+}
+
+impl FtsIndexManifest {
+    pub(crate) fn n_fields(&self) -> usize {
+        self.fields.len().max(1)
+    }
+    pub(crate) fn field_boosts(&self) -> Vec<f64> {
+        if self.fields.is_empty() {
+            vec![1.0]
+        } else {
+            self.fields.iter().map(|f| f.boost).collect()
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde_derive::Serialize, serde_derive::Deserialize)]
@@ -78,6 +107,10 @@ impl TokenizerConfig {
             "Raw" => Box::new(RawTokenizer),
             "Simple" => Box::new(SimpleTokenizer),
             "Whitespace" => Box::new(WhitespaceTokenizer),
+            "Unicode" => Box::new(UnicodeTokenizer::new(BigramScripts::None)),
+            "Japanese" => Box::new(UnicodeTokenizer::new(BigramScripts::Japanese)),
+            "Korean" => Box::new(UnicodeTokenizer::new(BigramScripts::Korean)),
+            "Thai" => Box::new(UnicodeTokenizer::new(BigramScripts::Thai)),
             "NGram" => {
                 let min_gram = self
                     .args
@@ -139,6 +172,23 @@ impl TokenizerConfig {
         Ok(match &self.name as &str {
             "AlphaNumOnly" => AlphaNumOnlyFilter.into(),
             "AsciiFolding" => AsciiFoldingFilter.into(),
+            "Elision" => match self.args.first() {
+                None => ElisionFilter::default().into(),
+                Some(DataValue::List(l)) => {
+                    let mut articles = Vec::new();
+                    for v in l {
+                        articles.push(
+                            v.get_str()
+                                .ok_or_else(|| {
+                                    miette!("First argument `articles` must be a list of strings")
+                                })?
+                                .to_string(),
+                        );
+                    }
+                    ElisionFilter::new(articles).into()
+                }
+                _ => bail!("First argument `articles` must be a list of strings"),
+            },
             "LowerCase" | "Lowercase" => LowerCaser.into(),
             "RemoveLong" => RemoveLongFilter::limit(
                 self.args
@@ -229,6 +279,33 @@ impl TokenizerConfig {
                     _ => bail!("Filter Stopwords requires language name or a list of stopwords"),
                 }
             }
+            "Synonyms" => {
+                let err =
+                    || miette!("First argument `synonyms` must be a list of lists of strings");
+                let mut groups = Vec::new();
+                match self
+                    .args
+                    .first()
+                    .ok_or_else(|| miette!("Missing first argument `synonyms`"))?
+                {
+                    DataValue::List(l) => {
+                        for group in l {
+                            let mut words = Vec::new();
+                            match group {
+                                DataValue::List(g) => {
+                                    for v in g {
+                                        words.push(v.get_str().ok_or_else(err)?.to_string());
+                                    }
+                                }
+                                _ => return Err(err()),
+                            }
+                            groups.push(words);
+                        }
+                    }
+                    _ => return Err(err()),
+                }
+                SynonymFilter::new(groups)?.into()
+            }
             _ => bail!("Unknown token filter: {:?}", self.name),
         })
     }
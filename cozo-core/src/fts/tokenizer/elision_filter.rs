@@ -0,0 +1,102 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Arc;
+
+use rustc_hash::FxHashSet;
+
+use super::{BoxTokenStream, Token, TokenFilter, TokenStream};
+
+const FRENCH_ARTICLES: &[&str] = &[
+    "l", "m", "t", "qu", "n", "s", "j", "d", "c", "jusqu", "quoiqu", "lorsqu", "puisqu",
+];
+
+/// `ElisionFilter` removes elided articles such as the `l'` of `l'avion` from the front of tokens.
+/// Articles are compared case-insensitively, both `'` and `’` are recognized as apostrophes.
+///
+/// Tokenizers splitting words on punctuation already separate the articles,
+/// so this is mostly useful after the `Whitespace` and `Unicode` tokenizers.
+#[derive(Clone)]
+pub(crate) struct ElisionFilter {
+    articles: Arc<FxHashSet<String>>,
+}
+
+impl ElisionFilter {
+    /// Creates an `ElisionFilter` removing the given articles.
+    pub(crate) fn new<W: IntoIterator<Item = String>>(articles: W) -> ElisionFilter {
+        ElisionFilter {
+            articles: Arc::new(articles.into_iter().map(|a| a.to_lowercase()).collect()),
+        }
+    }
+}
+
+impl Default for ElisionFilter {
+    /// Creates an `ElisionFilter` for the French articles.
+    fn default() -> Self {
+        ElisionFilter::new(FRENCH_ARTICLES.iter().map(|a| a.to_string()))
+    }
+}
+
+impl TokenFilter for ElisionFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(ElisionFilterStream {
+            articles: self.articles.clone(),
+            tail: token_stream,
+        })
+    }
+}
+
+pub(crate) struct ElisionFilterStream<'a> {
+    articles: Arc<FxHashSet<String>>,
+    tail: BoxTokenStream<'a>,
+}
+
+impl<'a> TokenStream for ElisionFilterStream<'a> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        let token = self.tail.token_mut();
+        if let Some((idx, apostrophe)) = token
+            .text
+            .char_indices()
+            .find(|(_, c)| *c == '\'' || *c == '’')
+        {
+            let rest = idx + apostrophe.len_utf8();
+            if rest < token.text.len() && self.articles.contains(&token.text[..idx].to_lowercase())
+            {
+                token.text.drain(..rest);
+            }
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fts::tokenizer::{ElisionFilter, TextAnalyzer, WhitespaceTokenizer};
+
+    #[test]
+    fn test_elision() {
+        let a = TextAnalyzer::from(WhitespaceTokenizer).filter(ElisionFilter::default());
+        let mut token_stream = a.token_stream("L'avion jusqu’ici l' aujourd'hui");
+        let mut tokens = vec![];
+        while let Some(token) = token_stream.next() {
+            tokens.push(token.text.clone());
+        }
+        assert_eq!(tokens, ["avion", "ici", "l'", "aujourd'hui"]);
+    }
+}
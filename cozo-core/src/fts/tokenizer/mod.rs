@@ -125,6 +125,7 @@
 //! ```
 mod alphanum_only;
 mod ascii_folding_filter;
+mod elision_filter;
 mod empty_tokenizer;
 mod lower_caser;
 mod ngram_tokenizer;
@@ -134,12 +135,15 @@ mod simple_tokenizer;
 mod split_compound_words;
 mod stemmer;
 mod stop_word_filter;
+mod synonym_filter;
 mod tokenized_string;
 mod tokenizer_impl;
+mod unicode_tokenizer;
 mod whitespace_tokenizer;
 
 pub(crate) use self::alphanum_only::AlphaNumOnlyFilter;
 pub(crate) use self::ascii_folding_filter::AsciiFoldingFilter;
+pub(crate) use self::elision_filter::ElisionFilter;
 pub(crate) use self::lower_caser::LowerCaser;
 pub(crate) use self::ngram_tokenizer::NgramTokenizer;
 pub(crate) use self::raw_tokenizer::RawTokenizer;
@@ -148,10 +152,12 @@ pub(crate) use self::simple_tokenizer::SimpleTokenizer;
 pub(crate) use self::split_compound_words::SplitCompoundWords;
 pub(crate) use self::stemmer::{Language, Stemmer};
 pub(crate) use self::stop_word_filter::StopWordFilter;
+pub(crate) use self::synonym_filter::SynonymFilter;
 // pub(crate) use self::tokenized_string::{PreTokenizedStream, PreTokenizedString};
 pub(crate) use self::tokenizer_impl::{
     BoxTokenFilter, BoxTokenStream, TextAnalyzer, Token, TokenFilter, TokenStream, Tokenizer,
 };
+pub(crate) use self::unicode_tokenizer::{BigramScripts, UnicodeTokenizer};
 pub(crate) use self::whitespace_tokenizer::WhitespaceTokenizer;
 
 #[cfg(test)]
@@ -0,0 +1,123 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Arc;
+
+use miette::{bail, Result};
+use rustc_hash::FxHashMap;
+
+use super::{BoxTokenStream, Token, TokenFilter, TokenStream};
+
+/// `SynonymFilter` replaces every token belonging to a group of synonyms by the first word
+/// of its group. As the same filter is applied to the documents and to the queries,
+/// searching for any word of a group finds all of them.
+///
+/// Only single-token synonyms are supported. Tokens are compared as they are,
+/// so the filter should come after `Lowercase` and before `Stemmer`.
+#[derive(Clone)]
+pub(crate) struct SynonymFilter {
+    canonical: Arc<FxHashMap<String, String>>,
+}
+
+impl SynonymFilter {
+    /// Creates a `SynonymFilter` from groups of equivalent words.
+    pub(crate) fn new(groups: Vec<Vec<String>>) -> Result<SynonymFilter> {
+        let mut canonical = FxHashMap::default();
+        for group in groups {
+            let mut words = group.into_iter();
+            let first = match words.next() {
+                None => continue,
+                Some(w) => w,
+            };
+            for word in words {
+                if word == first {
+                    continue;
+                }
+                if let Some(other) = canonical.insert(word.clone(), first.clone()) {
+                    if other != first {
+                        bail!("Synonym '{}' appears in more than one group", word);
+                    }
+                }
+            }
+        }
+        for word in canonical.values() {
+            if canonical.contains_key(word) {
+                bail!("Synonym '{}' appears in more than one group", word);
+            }
+        }
+        Ok(SynonymFilter {
+            canonical: Arc::new(canonical),
+        })
+    }
+}
+
+impl TokenFilter for SynonymFilter {
+    fn transform<'a>(&self, token_stream: BoxTokenStream<'a>) -> BoxTokenStream<'a> {
+        BoxTokenStream::from(SynonymFilterStream {
+            canonical: self.canonical.clone(),
+            tail: token_stream,
+        })
+    }
+}
+
+pub(crate) struct SynonymFilterStream<'a> {
+    canonical: Arc<FxHashMap<String, String>>,
+    tail: BoxTokenStream<'a>,
+}
+
+impl<'a> TokenStream for SynonymFilterStream<'a> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        let token = self.tail.token_mut();
+        if let Some(word) = self.canonical.get(&token.text) {
+            token.text.clear();
+            token.text.push_str(word);
+        }
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fts::tokenizer::{SimpleTokenizer, SynonymFilter, TextAnalyzer};
+
+    #[test]
+    fn test_synonyms() {
+        let groups = vec![
+            vec![
+                "car".to_string(),
+                "automobile".to_string(),
+                "auto".to_string(),
+            ],
+            vec!["big".to_string(), "large".to_string()],
+        ];
+        let a = TextAnalyzer::from(SimpleTokenizer).filter(SynonymFilter::new(groups).unwrap());
+        let mut token_stream = a.token_stream("a large auto, a big car");
+        let mut tokens = vec![];
+        while let Some(token) = token_stream.next() {
+            tokens.push(token.text.clone());
+        }
+        assert_eq!(tokens, ["a", "big", "car", "a", "big", "car"]);
+
+        let groups = vec![
+            vec!["car".to_string(), "auto".to_string()],
+            vec!["auto".to_string(), "automobile".to_string()],
+        ];
+        assert!(SynonymFilter::new(groups).is_err());
+    }
+}
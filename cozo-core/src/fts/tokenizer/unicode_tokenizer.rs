@@ -0,0 +1,202 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{BoxTokenStream, Token, TokenStream, Tokenizer};
+
+/// Scripts that are written without spaces between words, and are therefore
+/// indexed as overlapping bigrams of characters instead of as whole words.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum BigramScripts {
+    None,
+    /// Kanji, hiragana and katakana
+    Japanese,
+    /// Hangul and hanja
+    Korean,
+    Thai,
+}
+
+impl BigramScripts {
+    fn covers(self, c: char) -> bool {
+        match self {
+            BigramScripts::None => false,
+            BigramScripts::Japanese => is_han(c) || is_kana(c),
+            BigramScripts::Korean => is_hangul(c) || is_han(c),
+            BigramScripts::Thai => ('\u{0E00}'..='\u{0E7F}').contains(&c),
+        }
+    }
+}
+
+fn is_han(c: char) -> bool {
+    matches!(c,
+        '\u{3005}' | '\u{3007}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}')
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{FF66}'..='\u{FF9F}')
+}
+
+fn is_hangul(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'
+        | '\u{3130}'..='\u{318F}'
+        | '\u{A960}'..='\u{A97F}'
+        | '\u{AC00}'..='\u{D7FF}')
+}
+
+/// Tokenize the text into words following the Unicode word boundary rules (UAX #29),
+/// dropping segments without any alphanumeric character.
+///
+/// Runs of characters in the `bigram_scripts` are split into overlapping bigrams of
+/// grapheme clusters instead, so that text in these scripts can be searched without
+/// a dictionary. A run of a single character is kept as is.
+#[derive(Clone)]
+pub(crate) struct UnicodeTokenizer {
+    bigram_scripts: BigramScripts,
+}
+
+impl UnicodeTokenizer {
+    pub(crate) fn new(bigram_scripts: BigramScripts) -> Self {
+        Self { bigram_scripts }
+    }
+}
+
+pub(crate) struct UnicodeTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+    token: Token,
+}
+
+impl Tokenizer for UnicodeTokenizer {
+    fn token_stream<'a>(&self, text: &'a str) -> BoxTokenStream<'a> {
+        let mut tokens = vec![];
+        let mut push = |from: usize, to: usize| {
+            tokens.push(Token {
+                offset_from: from,
+                offset_to: to,
+                position: tokens.len(),
+                text: text[from..to].to_string(),
+                position_length: 1,
+            })
+        };
+        // byte range of the current run of characters in the bigram scripts
+        let mut run: Option<(usize, usize)> = None;
+        for (offset, word) in text.split_word_bound_indices() {
+            if word.chars().all(|c| self.bigram_scripts.covers(c)) {
+                run = Some(match run {
+                    None => (offset, offset + word.len()),
+                    Some((from, _)) => (from, offset + word.len()),
+                });
+                continue;
+            }
+            if let Some((from, to)) = run.take() {
+                push_bigrams(&text[from..to], from, &mut push);
+            }
+            if word.chars().any(|c| c.is_alphanumeric()) {
+                push(offset, offset + word.len());
+            }
+        }
+        if let Some((from, to)) = run {
+            push_bigrams(&text[from..to], from, &mut push);
+        }
+        BoxTokenStream::from(UnicodeTokenStream {
+            tokens,
+            index: 0,
+            token: Token::default(),
+        })
+    }
+}
+
+fn push_bigrams(run: &str, base: usize, push: &mut impl FnMut(usize, usize)) {
+    let mut bounds = run
+        .grapheme_indices(true)
+        .map(|(i, _)| base + i)
+        .collect::<Vec<_>>();
+    bounds.push(base + run.len());
+    if bounds.len() <= 3 {
+        push(base, base + run.len());
+    } else {
+        for w in bounds.windows(3) {
+            push(w[0], w[2]);
+        }
+    }
+}
+
+impl TokenStream for UnicodeTokenStream {
+    fn advance(&mut self) -> bool {
+        match self.tokens.get_mut(self.index) {
+            None => false,
+            Some(token) => {
+                self.token = std::mem::take(token);
+                self.index += 1;
+                true
+            }
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fts::tokenizer::tests::assert_token;
+    use crate::fts::tokenizer::unicode_tokenizer::BigramScripts;
+    use crate::fts::tokenizer::{TextAnalyzer, Token, UnicodeTokenizer};
+
+    #[test]
+    fn test_unicode_tokenizer() {
+        let tokens = token_stream_helper("Hello, l'été 3.14 — naïve!", BigramScripts::None);
+        assert_eq!(tokens.len(), 4);
+        assert_token(&tokens[0], 0, "Hello", 0, 5);
+        assert_token(&tokens[1], 1, "l'été", 7, 14);
+        assert_token(&tokens[2], 2, "3.14", 15, 19);
+        assert_token(&tokens[3], 3, "naïve", 24, 30);
+    }
+
+    #[test]
+    fn test_bigrams() {
+        let tokens = token_stream_helper("東京タワー is 高い", BigramScripts::Japanese);
+        let texts = tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["東京", "京タ", "タワ", "ワー", "is", "高い"]);
+        assert_token(&tokens[1], 1, "京タ", 3, 9);
+
+        let tokens = token_stream_helper("한국어 문장", BigramScripts::Korean);
+        let texts = tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["한국", "국어", "문장"]);
+
+        // combining vowels and tone marks stay with their consonants
+        let tokens = token_stream_helper("สวัสดี", BigramScripts::Thai);
+        let texts = tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["สวั", "วัส", "สดี"]);
+    }
+
+    fn token_stream_helper(text: &str, scripts: BigramScripts) -> Vec<Token> {
+        let a = TextAnalyzer::from(UnicodeTokenizer::new(scripts));
+        let mut token_stream = a.token_stream(text);
+        let mut tokens: Vec<Token> = vec![];
+        let mut add_token = |token: &Token| {
+            tokens.push(token.clone());
+        };
+        token_stream.process(&mut add_token);
+        tokens
+    }
+}
@@ -33,6 +33,7 @@
 
 use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
 #[allow(unused_imports)]
 use std::time::Instant;
 
@@ -55,14 +56,14 @@ pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
 #[cfg(feature = "storage-rocksdb")]
-pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+pub use storage::rocks::{new_cozo_rocksdb, restore_cozo_rocksdb_backup, RocksDbStorage};
 #[cfg(feature = "storage-sled")]
 pub use storage::sled::{new_cozo_sled, SledStorage};
 #[cfg(feature = "storage-sqlite")]
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+pub use storage::{BackupGeneration, Storage, StoreTx};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
@@ -76,6 +77,11 @@ pub use crate::runtime::db::get_variables;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::replication::{
+    local_replication_channel, CommitCoordinator, LocalReplicationReceiver, LocalReplicationSender,
+    ReplicationMessage, ReplicationReceiver, ReplicationRole, ReplicationSender,
+    ReplicationStatus, WriteOp,
+};
 
 pub(crate) mod data;
 pub(crate) mod fixed_rule;
@@ -134,6 +140,10 @@ impl DbInstance {
     ///
     /// `path` is ignored for `mem` and `tikv` engines.
     /// `options` is ignored for every engine except `tikv`.
+    ///
+    /// Graphs built by graph algorithms are cached until the relations are written to through
+    /// this instance. Writes by other processes sharing a `sqlite` file are not noticed, so
+    /// graph algorithms may see stale data in that case.
     #[allow(unused_variables)]
     pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
         let options = if options.is_empty() { "{}" } else { options };
@@ -346,7 +356,7 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.backup_db(out_file),
         }
     }
-    /// Backup the running database into an Sqlite file, with JSON string return value.
+    /// Backup the running database into a backup file, with JSON string return value.
     /// See [crate::Db::backup_db].
     pub fn backup_db_str(&self, out_file: impl AsRef<Path>) -> String {
         match self.backup_db(out_file) {
@@ -368,7 +378,7 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.restore_backup(in_file),
         }
     }
-    /// Restore from an Sqlite backup, with JSON string return value.
+    /// Restore from a backup file, with JSON string return value.
     /// See [crate::Db::restore_backup].
     pub fn restore_backup_str(&self, in_file: impl AsRef<Path>) -> String {
         match self.restore_backup(in_file) {
@@ -394,7 +404,7 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.import_from_backup(in_file, relations),
         }
     }
-    /// Import relations from an Sqlite backup, with JSON string return value.
+    /// Import relations from a backup file, with JSON string return value.
     /// See [crate::Db::import_from_backup].
     pub fn import_from_backup_str(&self, payload: &str) -> String {
         match self.import_from_backup_str_inner(payload) {
@@ -448,6 +458,132 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_callback(id),
         }
     }
+    /// Dispatcher method. See [crate::Db::enable_replication].
+    pub fn enable_replication(&self) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.enable_replication(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.enable_replication(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.enable_replication(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.enable_replication(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.enable_replication(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::attach_replica].
+    pub fn attach_replica(&self, sender: Box<dyn ReplicationSender>) -> Result<u32> {
+        match self {
+            DbInstance::Mem(db) => db.attach_replica(sender),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.attach_replica(sender),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.attach_replica(sender),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.attach_replica(sender),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.attach_replica(sender),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::detach_replica].
+    pub fn detach_replica(&self, id: u32) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.detach_replica(id),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.detach_replica(id),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.detach_replica(id),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.detach_replica(id),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.detach_replica(id),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_replica].
+    pub fn run_replica(&self, receiver: &mut dyn ReplicationReceiver) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.run_replica(receiver),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_replica(receiver),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_replica(receiver),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_replica(receiver),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_replica(receiver),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::promote_replica].
+    pub fn promote_replica(&self) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.promote_replica(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.promote_replica(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.promote_replica(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.promote_replica(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.promote_replica(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_store].
+    pub fn export_store(&self, chunk: &mut dyn FnMut(Vec<WriteOp>) -> Result<()>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_store(chunk),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_store(chunk),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_store(chunk),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_store(chunk),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_store(chunk),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::replication_status].
+    pub fn replication_status(&self) -> ReplicationStatus {
+        match self {
+            DbInstance::Mem(db) => db.replication_status(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.replication_status(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.replication_status(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.replication_status(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.replication_status(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_commit_coordinator].
+    pub fn set_commit_coordinator(&self, coordinator: Option<Arc<dyn CommitCoordinator>>) {
+        match self {
+            DbInstance::Mem(db) => db.set_commit_coordinator(coordinator),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_commit_coordinator(coordinator),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_commit_coordinator(coordinator),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_commit_coordinator(coordinator),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_commit_coordinator(coordinator),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::apply_write_set].
+    pub fn apply_write_set(&self, ops: &[WriteOp]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.apply_write_set(ops),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.apply_write_set(ops),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.apply_write_set(ops),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.apply_write_set(ops),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.apply_write_set(ops),
+        }
+    }
     /// Dispatcher method. See [crate::Db::register_fixed_rule].
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
         where
@@ -500,15 +636,15 @@ impl DbInstance {
         }
     }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
-    /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
+    /// Write transactions _may_ block other reads, but we guarantee that this does not happen
+    /// for the RocksDB, SQLite and in-memory backends.
     pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
         let (app2db_send, app2db_recv) = bounded(1);
         let (db2app_send, db2app_recv) = bounded(1);
         let db = self.clone();
-        #[cfg(target_arch = "wasm32")]
+        // not on the rayon pool: the transaction lives as long as the caller wants,
+        // and must not starve other transactions or queries of workers
         std::thread::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
-        #[cfg(not(target_arch = "wasm32"))]
-        rayon::spawn(move || db.run_multi_transaction(write, app2db_recv, db2app_send));
         MultiTransaction {
             sender: app2db_send,
             receiver: db2app_recv,
@@ -6,15 +6,15 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear};
+use crate::fts::ast::{FtsExpr, FtsLiteral, FtsNear, FtsPhrase};
 use crate::parse::expr::parse_string;
 use crate::parse::{CozoScriptParser, Pair, Rule};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use miette::{IntoDiagnostic, Result};
+use miette::{ensure, IntoDiagnostic, Result};
 use pest::pratt_parser::{Op, PrattParser};
 use pest::Parser;
-use smartstring::SmartString;
+use smartstring::{LazyCompact, SmartString};
 
 pub(crate) fn parse_fts_query(q: &str) -> Result<FtsExpr> {
     let mut pairs = CozoScriptParser::parse(Rule::fts_doc, q).into_diagnostic()?;
@@ -53,18 +53,33 @@ fn build_infix(lhs: Result<FtsExpr>, op: Pair<'_>, rhs: Result<FtsExpr>) -> Resu
 fn build_term(pair: Pair<'_>) -> Result<FtsExpr> {
     Ok(match pair.as_rule() {
         Rule::fts_grouped => {
-            let collected: Vec<_> = pair.into_inner().map(parse_fts_expr).try_collect()?;
-            if collected.len() == 1 {
+            let mut inner = pair.into_inner().peekable();
+            let field = match inner.peek() {
+                Some(p) if p.as_rule() == Rule::fts_field => {
+                    Some(build_field(inner.next().unwrap()))
+                }
+                _ => None,
+            };
+            let collected: Vec<_> = inner.map(parse_fts_expr).try_collect()?;
+            let mut expr = if collected.len() == 1 {
                 collected.into_iter().next().unwrap()
             } else {
                 FtsExpr::And(collected)
+            };
+            if let Some(field) = field {
+                expr.set_field_if_absent(&field);
             }
+            expr
         }
         Rule::fts_near => {
             let mut literals = vec![];
             let mut distance = 10;
+            let mut ordered = false;
+            let mut field = None;
             for pair in pair.into_inner() {
                 match pair.as_rule() {
+                    Rule::fts_field => field = Some(build_field(pair)),
+                    Rule::fts_near_op => ordered = pair.as_str() == "ONEAR",
                     Rule::pos_int => {
                         let i = pair
                             .as_str()
@@ -73,29 +88,76 @@ fn build_term(pair: Pair<'_>) -> Result<FtsExpr> {
                             .into_diagnostic()?;
                         distance = i as u32;
                     }
-                    _ => literals.push(build_phrase(pair)?),
+                    _ => literals.push(build_phrase(pair)?.0),
                 }
             }
-            FtsExpr::Near(FtsNear { literals, distance })
+            let mut expr = FtsExpr::Near(FtsNear {
+                literals,
+                distance,
+                ordered,
+            });
+            if let Some(field) = field {
+                expr.set_field_if_absent(&field);
+            }
+            expr
+        }
+        Rule::fts_phrase => {
+            let (literal, is_quoted) = build_phrase(pair)?;
+            if is_quoted {
+                FtsExpr::Phrase(FtsPhrase {
+                    literals: vec![literal],
+                    offsets: vec![0],
+                })
+            } else {
+                FtsExpr::Literal(literal)
+            }
         }
-        Rule::fts_phrase => FtsExpr::Literal(build_phrase(pair)?),
         r => panic!("unexpected rule: {:?}", r),
     })
 }
 
-fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
-    let mut inner = pair.into_inner();
+fn build_field(pair: Pair<'_>) -> SmartString<LazyCompact> {
+    SmartString::from(pair.as_str().trim_end_matches(':'))
+}
+
+/// Returns the literal, and whether it was given as a quoted string
+fn build_phrase(pair: Pair<'_>) -> Result<(FtsLiteral, bool)> {
+    let mut inner = pair.into_inner().peekable();
+    let field = match inner.peek() {
+        Some(p) if p.as_rule() == Rule::fts_field => Some(build_field(inner.next().unwrap())),
+        _ => None,
+    };
     let kernel = inner.next().unwrap();
+    let is_quoted = kernel.as_rule() != Rule::fts_phrase_group;
     let core_text = match kernel.as_rule() {
         Rule::fts_phrase_group => SmartString::from(kernel.as_str().trim()),
         Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string => parse_string(kernel)?,
         _ => unreachable!("unexpected rule: {:?}", kernel.as_rule()),
     };
-    let mut is_quoted = false;
+    let mut is_prefix = false;
     let mut booster = 1.0;
+    let mut max_edits = 0;
     for pair in inner {
         match pair.as_rule() {
-            Rule::fts_prefix_marker => is_quoted = true,
+            Rule::fts_prefix_marker => is_prefix = true,
+            Rule::fts_fuzzy => {
+                max_edits = match pair.into_inner().next() {
+                    None => 2,
+                    Some(p) => {
+                        let i = p
+                            .as_str()
+                            .replace('_', "")
+                            .parse::<u64>()
+                            .into_diagnostic()?;
+                        ensure!(
+                            (1..=2).contains(&i),
+                            "Fuzzy matching supports edit distances of 1 or 2, got {}",
+                            i
+                        );
+                        i as u8
+                    }
+                }
+            }
             Rule::fts_booster => {
                 let boosted = pair.into_inner().next().unwrap();
                 match boosted.as_rule() {
@@ -107,7 +169,7 @@ fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
                             .into_diagnostic()?;
                         booster = f;
                     }
-                    Rule::int => {
+                    Rule::pos_int => {
                         let i = boosted
                             .as_str()
                             .replace('_', "")
@@ -121,11 +183,16 @@ fn build_phrase(pair: Pair<'_>) -> Result<FtsLiteral> {
             _ => unreachable!("unexpected rule: {:?}", pair.as_rule()),
         }
     }
-    Ok(FtsLiteral {
-        value: core_text,
-        is_prefix: is_quoted,
-        booster: booster.into(),
-    })
+    Ok((
+        FtsLiteral {
+            value: core_text,
+            is_prefix,
+            booster: booster.into(),
+            field,
+            max_edits,
+        },
+        is_quoted,
+    ))
 }
 
 lazy_static! {
@@ -159,5 +226,53 @@ mod tests {
         let res = parse_fts_query(src).unwrap().flatten();
         assert!(matches!(res, FtsExpr::Near(FtsNear { distance: 10, .. })));
         println!("{:#?}", res);
+        let src = " ONEAR/3(abc def) ";
+        let res = parse_fts_query(src).unwrap().flatten();
+        assert!(matches!(
+            res,
+            FtsExpr::Near(FtsNear {
+                distance: 3,
+                ordered: true,
+                ..
+            })
+        ));
+        let src = "hello \"quick brown fox\"";
+        let res = parse_fts_query(src).unwrap().flatten();
+        match res {
+            FtsExpr::And(exprs) => {
+                assert!(matches!(exprs[0], FtsExpr::Literal(_)));
+                assert!(matches!(exprs[1], FtsExpr::Phrase(_)));
+            }
+            _ => panic!("{:?}", res),
+        }
+        let src = "jonh~1 smiht~ walker*~1^2";
+        let res = parse_fts_query(src).unwrap().flatten();
+        match res {
+            FtsExpr::And(exprs) => {
+                assert!(matches!(&exprs[0], FtsExpr::Literal(l) if l.max_edits == 1));
+                assert!(matches!(&exprs[1], FtsExpr::Literal(l) if l.max_edits == 2));
+                assert!(
+                    matches!(&exprs[2], FtsExpr::Literal(l) if l.max_edits == 1 && l.is_prefix)
+                );
+            }
+            _ => panic!("{:?}", res),
+        }
+        assert!(parse_fts_query("jonh~3").is_err());
+        let src = "title:fox body:(a OR b) title:NEAR(c d)";
+        let res = parse_fts_query(src).unwrap().flatten();
+        match res {
+            FtsExpr::And(exprs) => {
+                assert!(
+                    matches!(&exprs[0], FtsExpr::Literal(l) if l.field.as_deref() == Some("title"))
+                );
+                assert!(
+                    matches!(&exprs[1], FtsExpr::Or(es) if matches!(&es[1], FtsExpr::Literal(l) if l.field.as_deref() == Some("body")))
+                );
+                assert!(
+                    matches!(&exprs[2], FtsExpr::Near(n) if n.literals.iter().all(|l| l.field.as_deref() == Some("title")))
+                );
+            }
+            _ => panic!("{:?}", res),
+        }
     }
 }
@@ -140,16 +140,20 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         }
         Rule::vec_type => {
             let mut inner = pair.into_inner();
-            let eltype = match inner.next().unwrap().as_str() {
-                "F32" | "Float" => VecElementType::F32,
-                "F64" | "Double" => VecElementType::F64,
-                _ => unreachable!()
-            };
+            let eltype = inner.next().unwrap().as_str();
             let len = inner.next().unwrap();
             let len = len.as_str().replace('_', "").parse::<usize>().into_diagnostic()?;
-            ColType::Vec {
-                eltype,
-                len,
+            match eltype {
+                "F32" | "Float" => ColType::Vec {
+                    eltype: VecElementType::F32,
+                    len,
+                },
+                "F64" | "Double" => ColType::Vec {
+                    eltype: VecElementType::F64,
+                    len,
+                },
+                "Bit" => ColType::BitVec { len },
+                _ => unreachable!(),
             }
         }
         Rule::tuple_type => {
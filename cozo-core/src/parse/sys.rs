@@ -22,7 +22,7 @@ use crate::data::value::{DataValue, ValidityTs};
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pair, Pairs, Rule, SourceSpan};
 use crate::runtime::relation::AccessLevel;
 use crate::{Expr, FixedRule};
 
@@ -33,6 +33,7 @@ pub(crate) enum SysOp {
     ListIndices(Symbol),
     ListRelations,
     ListRunning,
+    ReplicationStatus,
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
@@ -46,7 +47,11 @@ pub(crate) enum SysOp {
     CreateFtsIndex(FtsIndexConfig),
     CreateMinHashLshIndex(MinHashLshConfig),
     RemoveIndex(Symbol, Symbol),
-    DescribeRelation(Symbol, SmartString<LazyCompact>)
+    DescribeRelation(Symbol, SmartString<LazyCompact>),
+    Backup(String, bool),
+    ListBackups(String),
+    PruneBackups(String, usize),
+    VerifyBackup(String, Option<u32>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -56,6 +61,8 @@ pub(crate) struct FtsIndexConfig {
     pub(crate) extractor: String,
     pub(crate) tokenizer: TokenizerConfig,
     pub(crate) filters: Vec<TokenizerConfig>,
+    /// Names and boosts of the fields of multi-field indices
+    pub(crate) fields: Vec<(SmartString<LazyCompact>, OrderedFloat<f64>)>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -85,6 +92,17 @@ pub(crate) struct HnswIndexConfig {
     pub(crate) index_filter: Option<String>,
     pub(crate) extend_candidates: bool,
     pub(crate) keep_pruned_connections: bool,
+    /// Quantized codes are stored in addition to the full vectors, increasing the disk use
+    pub(crate) quantization: Option<HnswQuantization>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum HnswQuantization {
+    Int8,
+    Product {
+        n_subspaces: usize,
+        n_centroids: usize,
+    },
 }
 
 #[derive(
@@ -94,6 +112,18 @@ pub(crate) enum HnswDistance {
     L2,
     InnerProduct,
     Cosine,
+    L1,
+    /// Number of differing bits between bit vectors
+    Hamming,
+    /// Jaccard distance between bit vectors, seen as sets of bits
+    Jaccard,
+}
+
+impl HnswDistance {
+    /// Whether the distance applies to bit vectors instead of float vectors
+    pub(crate) fn is_binary(&self) -> bool {
+        matches!(self, HnswDistance::Hamming | HnswDistance::Jaccard)
+    }
 }
 
 #[derive(Debug, Diagnostic, Error)]
@@ -101,6 +131,40 @@ pub(crate) enum HnswDistance {
 #[diagnostic(code(parser::not_proc_id))]
 struct ProcessIdError(String, #[label] SourceSpan);
 
+fn parse_backup_path(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<String> {
+    let path = build_expr(pair, param_pool)?.eval_to_const()?;
+    match path.get_str() {
+        Some(s) => Ok(s.to_string()),
+        None => bail!("Backup path must be a string, got {:?}", path),
+    }
+}
+
+/// Splits `{name: expr, ...}` into the field names and their expressions.
+fn parse_fts_field_map(ex: Expr) -> Result<Vec<(SmartString<LazyCompact>, Expr)>> {
+    match ex {
+        Expr::Apply { op, args, .. } if op.name == "OP_JSON_OBJECT" => args
+            .into_vec()
+            .into_iter()
+            .tuples()
+            .map(|(k, v)| {
+                let name = match k {
+                    Expr::Binding { var, .. } => var.name,
+                    Expr::Const {
+                        val: DataValue::Str(s),
+                        ..
+                    } => s,
+                    k => bail!("Invalid FTS field name: {}", k),
+                };
+                Ok((name, v))
+            })
+            .collect(),
+        ex => bail!("FTS fields must be given as a map, got {}", ex),
+    }
+}
+
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -110,7 +174,48 @@ pub(crate) fn parse_sys(
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
+        Rule::backup_op => {
+            let inner = inner.into_inner().next().unwrap();
+            match inner.as_rule() {
+                Rule::backup_create => {
+                    let mut inner = inner.into_inner();
+                    let to_p = inner.next().unwrap().into_inner().next().unwrap();
+                    let path = parse_backup_path(to_p, param_pool)?;
+                    SysOp::Backup(path, inner.next().is_some())
+                }
+                Rule::backup_list => {
+                    let path_p = inner.into_inner().next().unwrap();
+                    SysOp::ListBackups(parse_backup_path(path_p, param_pool)?)
+                }
+                Rule::backup_prune => {
+                    let mut inner = inner.into_inner();
+                    let path = parse_backup_path(inner.next().unwrap(), param_pool)?;
+                    let keep = build_expr(inner.next().unwrap(), param_pool)?
+                        .eval_to_const()?
+                        .get_non_neg_int()
+                        .ok_or_else(|| miette!("Number of backups to keep must be a non-negative integer"))?;
+                    SysOp::PruneBackups(path, keep as usize)
+                }
+                Rule::backup_verify => {
+                    let mut inner = inner.into_inner();
+                    let path = parse_backup_path(inner.next().unwrap(), param_pool)?;
+                    let id = match inner.next() {
+                        None => None,
+                        Some(id_p) => {
+                            let id = build_expr(id_p, param_pool)?
+                                .eval_to_const()?
+                                .get_non_neg_int()
+                                .ok_or_else(|| miette!("Backup ID must be a non-negative integer"))?;
+                            Some(id as u32)
+                        }
+                    };
+                    SysOp::VerifyBackup(path, id)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
         Rule::running_op => SysOp::ListRunning,
+        Rule::replication_op => SysOp::ReplicationStatus,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -415,6 +520,8 @@ pub(crate) fn parse_sys(
                     };
                     let mut extractor = "".to_string();
                     let mut extract_filter = "".to_string();
+                    let mut field_extractors = vec![];
+                    let mut boosts = BTreeMap::new();
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
                         let opt_name = opt_inner.next().unwrap();
@@ -430,6 +537,30 @@ pub(crate) fn parse_sys(
                                 ex.partial_eval()?;
                                 extract_filter = ex.to_string();
                             }
+                            "fields" => {
+                                for (name, mut ex) in
+                                    parse_fts_field_map(build_expr(opt_val, param_pool)?)?
+                                {
+                                    ex.partial_eval()?;
+                                    field_extractors.push((name, ex.to_string()));
+                                }
+                            }
+                            "boosts" => {
+                                for (name, ex) in
+                                    parse_fts_field_map(build_expr(opt_val, param_pool)?)?
+                                {
+                                    let boost =
+                                        ex.eval_to_const()?.get_float().ok_or_else(|| {
+                                            miette!("Boost of field `{}` must be a number", name)
+                                        })?;
+                                    ensure!(
+                                        boost > 0.,
+                                        "Boost of field `{}` must be positive",
+                                        name
+                                    );
+                                    boosts.insert(name, boost);
+                                }
+                            }
                             "tokenizer" => {
                                 let mut expr = build_expr(opt_val, param_pool)?;
                                 expr.partial_eval()?;
@@ -487,6 +618,25 @@ pub(crate) fn parse_sys(
                             _ => bail!("Unknown option {} for FTS index", opt_name.as_str()),
                         }
                     }
+                    let mut fields = vec![];
+                    if !field_extractors.is_empty() {
+                        ensure!(
+                            extractor.is_empty(),
+                            "FTS index cannot have both `extractor` and `fields`"
+                        );
+                        extractor =
+                            format!("[{}]", field_extractors.iter().map(|(_, ex)| ex).join(", "));
+                        for (name, _) in field_extractors {
+                            let boost = boosts.remove(&name).unwrap_or(1.);
+                            fields.push((name, OrderedFloat(boost)));
+                        }
+                    }
+                    if let Some(name) = boosts.keys().next() {
+                        bail!(
+                            "Boost given for `{}`, which is not a field of the index",
+                            name
+                        );
+                    }
                     if !extract_filter.is_empty() {
                         extractor = format!("if({}, {})", extract_filter, extractor);
                     }
@@ -496,6 +646,7 @@ pub(crate) fn parse_sys(
                         extractor,
                         tokenizer,
                         filters,
+                        fields,
                     };
                     SysOp::CreateFtsIndex(config)
                 }
@@ -528,6 +679,9 @@ pub(crate) fn parse_sys(
                     let mut index_filter = None;
                     let mut extend_candidates = false;
                     let mut keep_pruned_connections = false;
+                    let mut quantization = None;
+                    let mut pq_subspaces = None;
+                    let mut pq_centroids = 256;
 
                     for opt_pair in inner {
                         let mut opt_inner = opt_pair.into_inner();
@@ -581,6 +735,9 @@ pub(crate) fn parse_sys(
                                     "L2" => HnswDistance::L2,
                                     "IP" => HnswDistance::InnerProduct,
                                     "Cosine" => HnswDistance::Cosine,
+                                    "L1" | "Manhattan" => HnswDistance::L1,
+                                    "Hamming" => HnswDistance::Hamming,
+                                    "Jaccard" => HnswDistance::Jaccard,
                                     _ => {
                                         return Err(miette!(
                                             "Invalid distance: {}",
@@ -598,6 +755,46 @@ pub(crate) fn parse_sys(
                             "keep_pruned_connections" => {
                                 keep_pruned_connections = opt_val.as_str().trim() == "true";
                             }
+                            "quantization" => {
+                                quantization = match opt_val.as_str().trim() {
+                                    "None" => None,
+                                    "Int8" => Some(HnswQuantization::Int8),
+                                    "PQ" => Some(HnswQuantization::Product {
+                                        n_subspaces: 0,
+                                        n_centroids: 0,
+                                    }),
+                                    _ => {
+                                        return Err(miette!(
+                                            "Invalid quantization: {}",
+                                            opt_val.as_str()
+                                        ))
+                                    }
+                                }
+                            }
+                            "pq_subspaces" => {
+                                let v = build_expr(opt_val, param_pool)?
+                                    .eval_to_const()?
+                                    .get_int()
+                                    .ok_or_else(|| {
+                                        miette!("Invalid pq_subspaces: {}", opt_val_str)
+                                    })?;
+                                ensure!(v > 0, "Invalid pq_subspaces: {}", v);
+                                pq_subspaces = Some(v as usize);
+                            }
+                            "pq_centroids" => {
+                                let v = build_expr(opt_val, param_pool)?
+                                    .eval_to_const()?
+                                    .get_int()
+                                    .ok_or_else(|| {
+                                        miette!("Invalid pq_centroids: {}", opt_val_str)
+                                    })?;
+                                ensure!(
+                                    (2..=256).contains(&v),
+                                    "pq_centroids must be between 2 and 256, got {}",
+                                    v
+                                );
+                                pq_centroids = v as usize;
+                            }
                             _ => return Err(miette!("Invalid option: {}", opt_name.as_str())),
                         }
                     }
@@ -607,6 +804,29 @@ pub(crate) fn parse_sys(
                     if m_neighbours == 0 {
                         bail!("m_neighbours must be set");
                     }
+                    ensure!(
+                        quantization.is_none() || !distance.is_binary(),
+                        "Bit vectors cannot be quantized"
+                    );
+                    if let Some(HnswQuantization::Product { .. }) = quantization {
+                        // by default, as many subspaces as possible with at least 8 dimensions each
+                        let n_subspaces = pq_subspaces.unwrap_or_else(|| {
+                            (1..=(vec_dim / 8).max(1))
+                                .rev()
+                                .find(|n| vec_dim % n == 0)
+                                .unwrap()
+                        });
+                        ensure!(
+                            vec_dim % n_subspaces == 0,
+                            "pq_subspaces ({}) must divide the vector dimension ({})",
+                            n_subspaces,
+                            vec_dim
+                        );
+                        quantization = Some(HnswQuantization::Product {
+                            n_subspaces,
+                            n_centroids: pq_centroids,
+                        });
+                    }
                     SysOp::CreateVectorIndex(HnswIndexConfig {
                         base_relation: SmartString::from(rel.as_str()),
                         index_name: SmartString::from(name.as_str()),
@@ -619,6 +839,7 @@ pub(crate) fn parse_sys(
                         index_filter,
                         extend_candidates,
                         keep_pruned_connections,
+                        quantization,
                     })
                 }
                 Rule::index_drop => {
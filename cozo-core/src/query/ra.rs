@@ -1103,11 +1103,7 @@ impl HnswSearchRA {
             .parent
             .iter(tx, delta_rule, stores)?
             .map_ok(move |tuple| -> Result<_> {
-                let v = match tuple[bind_idx].clone() {
-                    DataValue::Vec(v) => v,
-                    d => bail!("Expected vector, got {:?}", d),
-                };
-
+                let v = tuple[bind_idx].clone();
                 let res = tx.hnsw_knn(v, &config, &filter_code, &mut stack)?;
                 Ok(res.into_iter().map(move |t| {
                     let mut r = tuple.clone();
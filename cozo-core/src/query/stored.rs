@@ -375,9 +375,11 @@ impl<'a> SessionTx<'a> {
         processors: &BTreeMap<SmartString<LazyCompact>, (Arc<TextAnalyzer>, Vec<Bytecode>)>,
         new_kv: &[DataValue],
     ) -> Result<()> {
-        for (k, (idx_handle, _)) in rel_handle.fts_indices.iter() {
+        for (k, (idx_handle, manifest)) in rel_handle.fts_indices.iter() {
             let (tokenizer, extractor) = processors.get(k).unwrap();
-            self.put_fts_index_item(new_kv, extractor, stack, tokenizer, rel_handle, idx_handle)?;
+            self.put_fts_index_item(
+                new_kv, extractor, stack, tokenizer, rel_handle, idx_handle, manifest,
+            )?;
         }
         Ok(())
     }
@@ -389,9 +391,11 @@ impl<'a> SessionTx<'a> {
         processors: &BTreeMap<SmartString<LazyCompact>, (Arc<TextAnalyzer>, Vec<Bytecode>)>,
         old_kv: &[DataValue],
     ) -> Result<()> {
-        for (k, (idx_handle, _)) in rel_handle.fts_indices.iter() {
+        for (k, (idx_handle, manifest)) in rel_handle.fts_indices.iter() {
             let (tokenizer, extractor) = processors.get(k).unwrap();
-            self.del_fts_index_item(old_kv, extractor, stack, tokenizer, rel_handle, idx_handle)?;
+            self.del_fts_index_item(
+                old_kv, extractor, stack, tokenizer, rel_handle, idx_handle, manifest,
+            )?;
         }
         Ok(())
     }
@@ -422,8 +426,8 @@ impl<'a> SessionTx<'a> {
     }
 
     fn del_in_lsh(&mut self, rel_handle: &RelationHandle, old_kv: &[DataValue]) -> Result<()> {
-        for (idx_handle, inv_idx_handle, _) in rel_handle.lsh_indices.values() {
-            self.del_lsh_index_item(old_kv, None, idx_handle, inv_idx_handle)?;
+        for (idx_handle, inv_idx_handle, manifest) in rel_handle.lsh_indices.values() {
+            self.del_lsh_index_item(old_kv, None, idx_handle, inv_idx_handle, manifest)?;
         }
         Ok(())
     }
@@ -0,0 +1,350 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The portable backup container, readable and writable by every storage engine.
+//!
+//! Layout of a backup file, all integers little-endian:
+//!
+//! * header: the magic bytes `COZOBKUP`, followed by the format version as `u32`
+//! * chunks: each chunk is a kind byte, the uncompressed length as `u32`,
+//!   the compressed length as `u32`, the XXH64 checksum of the compressed payload as `u64`,
+//!   then the LZ4-compressed payload
+//! * trailer: the offset of the manifest chunk as `u64`, followed by the magic bytes again
+//!
+//! The payload of a data chunk is a sequence of key-value pairs in ascending key order,
+//! each one being the key length as `u32`, the key, the value length as `u32` and the value.
+//! The last chunk is the manifest, a MessagePack-encoded [BackupManifest] recording the
+//! offsets of all data chunks and the chunks occupied by each stored relation.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+use twox_hash::XxHash64;
+
+const BACKUP_MAGIC: &[u8; 8] = b"COZOBKUP";
+const BACKUP_FORMAT_VERSION: u32 = 1;
+const CHUNK_KIND_DATA: u8 = 0;
+const CHUNK_KIND_MANIFEST: u8 = 1;
+const CHUNK_HEADER_LEN: usize = 17;
+const TRAILER_LEN: i64 = 16;
+/// A data chunk is sealed once its uncompressed payload exceeds this size
+const CHUNK_TARGET_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Corrupted backup file: {0}")]
+#[diagnostic(code(backup::corrupted))]
+pub(crate) struct CorruptedBackup(String);
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct BackupManifest {
+    pub(crate) format_version: u32,
+    pub(crate) source_engine: String,
+    pub(crate) n_pairs: u64,
+    pub(crate) chunk_offsets: Vec<u64>,
+    pub(crate) relations: Vec<BackupRelationManifest>,
+}
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct BackupRelationManifest {
+    pub(crate) name: SmartString<LazyCompact>,
+    pub(crate) id: u64,
+    pub(crate) n_rows: u64,
+    /// The range of data chunks holding the rows of the relation
+    pub(crate) chunks: Range<usize>,
+}
+
+impl BackupManifest {
+    pub(crate) fn get_relation(&self, name: &str) -> Option<&BackupRelationManifest> {
+        self.relations.iter().find(|r| r.name == name)
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(data);
+    hasher.finish()
+}
+
+fn relation_id_of_key(key: &[u8]) -> Option<u64> {
+    let prefix: [u8; 8] = key.get(..8)?.try_into().ok()?;
+    Some(u64::from_be_bytes(prefix))
+}
+
+/// Writes key-value pairs, which must come in ascending order, into a backup file.
+pub(crate) struct BackupWriter {
+    out: BufWriter<File>,
+    offset: u64,
+    buffer: Vec<u8>,
+    manifest: BackupManifest,
+    relation_idx: BTreeMap<u64, usize>,
+}
+
+impl BackupWriter {
+    /// Create a new backup file. `relations` contains the names and IDs of the stored
+    /// relations in the backup. Existing files are never overwritten.
+    pub(crate) fn create(
+        path: &Path,
+        source_engine: &str,
+        relations: Vec<(SmartString<LazyCompact>, u64)>,
+    ) -> Result<Self> {
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|err| {
+                miette::miette!(
+                    "Cannot create backup file {}: {}",
+                    path.to_string_lossy(),
+                    err
+                )
+            })?;
+        let mut out = BufWriter::new(file);
+        out.write_all(BACKUP_MAGIC).into_diagnostic()?;
+        out.write_u32::<LittleEndian>(BACKUP_FORMAT_VERSION)
+            .into_diagnostic()?;
+        let relation_idx = relations
+            .iter()
+            .enumerate()
+            .map(|(i, (_, id))| (*id, i))
+            .collect();
+        let relations = relations
+            .into_iter()
+            .map(|(name, id)| BackupRelationManifest {
+                name,
+                id,
+                n_rows: 0,
+                chunks: 0..0,
+            })
+            .collect();
+        Ok(Self {
+            out,
+            offset: (BACKUP_MAGIC.len() + 4) as u64,
+            buffer: vec![],
+            manifest: BackupManifest {
+                format_version: BACKUP_FORMAT_VERSION,
+                source_engine: source_engine.to_string(),
+                n_pairs: 0,
+                chunk_offsets: vec![],
+                relations,
+            },
+            relation_idx,
+        })
+    }
+
+    pub(crate) fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let chunk_idx = self.manifest.chunk_offsets.len();
+        if let Some(idx) = relation_id_of_key(key).and_then(|id| self.relation_idx.get(&id)) {
+            let rel = &mut self.manifest.relations[*idx];
+            if rel.n_rows == 0 {
+                rel.chunks.start = chunk_idx;
+            }
+            rel.n_rows += 1;
+            rel.chunks.end = chunk_idx + 1;
+        }
+        self.buffer
+            .write_u32::<LittleEndian>(key.len() as u32)
+            .into_diagnostic()?;
+        self.buffer.extend_from_slice(key);
+        self.buffer
+            .write_u32::<LittleEndian>(val.len() as u32)
+            .into_diagnostic()?;
+        self.buffer.extend_from_slice(val);
+        self.manifest.n_pairs += 1;
+        if self.buffer.len() >= CHUNK_TARGET_SIZE {
+            self.seal_data_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn seal_data_chunk(&mut self) -> Result<()> {
+        let raw = std::mem::take(&mut self.buffer);
+        let offset = self.write_chunk(CHUNK_KIND_DATA, &raw)?;
+        self.manifest.chunk_offsets.push(offset);
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, kind: u8, raw: &[u8]) -> Result<u64> {
+        let offset = self.offset;
+        let compressed = lz4_flex::compress(raw);
+        self.out.write_u8(kind).into_diagnostic()?;
+        self.out
+            .write_u32::<LittleEndian>(raw.len() as u32)
+            .into_diagnostic()?;
+        self.out
+            .write_u32::<LittleEndian>(compressed.len() as u32)
+            .into_diagnostic()?;
+        self.out
+            .write_u64::<LittleEndian>(checksum(&compressed))
+            .into_diagnostic()?;
+        self.out.write_all(&compressed).into_diagnostic()?;
+        self.offset += (CHUNK_HEADER_LEN + compressed.len()) as u64;
+        Ok(offset)
+    }
+
+    /// Write out the manifest. The backup file is incomplete until this is called.
+    pub(crate) fn finish(mut self) -> Result<BackupManifest> {
+        if !self.buffer.is_empty() {
+            self.seal_data_chunk()?;
+        }
+        let manifest_bytes = rmp_serde::to_vec_named(&self.manifest).into_diagnostic()?;
+        let manifest_offset = self.write_chunk(CHUNK_KIND_MANIFEST, &manifest_bytes)?;
+        self.out
+            .write_u64::<LittleEndian>(manifest_offset)
+            .into_diagnostic()?;
+        self.out.write_all(BACKUP_MAGIC).into_diagnostic()?;
+        let file = self.out.into_inner().into_diagnostic()?;
+        file.sync_all().into_diagnostic()?;
+        Ok(self.manifest)
+    }
+}
+
+/// Reads a backup file created by [BackupWriter].
+pub(crate) struct BackupReader {
+    file: BufReader<File>,
+    manifest: BackupManifest,
+}
+
+impl BackupReader {
+    /// Whether the file at `path` starts like a backup file of this format.
+    pub(crate) fn is_backup_file(path: &Path) -> Result<bool> {
+        let mut file = File::open(path).into_diagnostic()?;
+        let mut magic = [0u8; 8];
+        Ok(file.read_exact(&mut magic).is_ok() && &magic == BACKUP_MAGIC)
+    }
+
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path).into_diagnostic()?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic).into_diagnostic()?;
+        ensure!(
+            &magic == BACKUP_MAGIC,
+            CorruptedBackup("bad magic bytes in header".to_string())
+        );
+        let version = file.read_u32::<LittleEndian>().into_diagnostic()?;
+        if version > BACKUP_FORMAT_VERSION {
+            bail!(
+                "Backup format version {} is newer than the supported version {}",
+                version,
+                BACKUP_FORMAT_VERSION
+            );
+        }
+
+        file.seek(SeekFrom::End(-TRAILER_LEN))
+            .map_err(|_| CorruptedBackup("missing trailer".to_string()))?;
+        let manifest_offset = file.read_u64::<LittleEndian>().into_diagnostic()?;
+        file.read_exact(&mut magic).into_diagnostic()?;
+        ensure!(
+            &magic == BACKUP_MAGIC,
+            CorruptedBackup("bad magic bytes in trailer, the backup may be truncated".to_string())
+        );
+        let (kind, manifest_bytes) = read_chunk_at(&mut file, manifest_offset)?;
+        ensure!(
+            kind == CHUNK_KIND_MANIFEST,
+            CorruptedBackup("manifest not found".to_string())
+        );
+        let manifest: BackupManifest = rmp_serde::from_slice(&manifest_bytes)
+            .map_err(|err| CorruptedBackup(format!("cannot decode manifest: {err}")))?;
+        ensure!(
+            manifest.format_version == version,
+            CorruptedBackup("format versions in header and manifest differ".to_string())
+        );
+        Ok(Self { file, manifest })
+    }
+
+    pub(crate) fn manifest(&self) -> &BackupManifest {
+        &self.manifest
+    }
+
+    fn read_data_chunk(&mut self, idx: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let offset = *self
+            .manifest
+            .chunk_offsets
+            .get(idx)
+            .ok_or_else(|| CorruptedBackup(format!("data chunk {idx} not found")))?;
+        let (kind, raw) = read_chunk_at(&mut self.file, offset)?;
+        ensure!(
+            kind == CHUNK_KIND_DATA,
+            CorruptedBackup(format!("chunk {idx} is not a data chunk"))
+        );
+        let mut pairs = vec![];
+        let mut rdr = raw.as_slice();
+        while !rdr.is_empty() {
+            let key = read_len_prefixed(&mut rdr)?;
+            let val = read_len_prefixed(&mut rdr)?;
+            pairs.push((key, val));
+        }
+        Ok(pairs)
+    }
+
+    /// Check the checksums of all data chunks.
+    pub(crate) fn verify(&mut self) -> Result<()> {
+        let mut n_pairs = 0;
+        for idx in 0..self.manifest.chunk_offsets.len() {
+            n_pairs += self.read_data_chunk(idx)?.len() as u64;
+        }
+        ensure!(
+            n_pairs == self.manifest.n_pairs,
+            CorruptedBackup(format!(
+                "expect {} key-value pairs, found {}",
+                self.manifest.n_pairs, n_pairs
+            ))
+        );
+        Ok(())
+    }
+
+    /// Iterate over the key-value pairs in the given range of data chunks.
+    pub(crate) fn pairs(
+        &mut self,
+        chunks: Range<usize>,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        chunks.flat_map(move |idx| match self.read_data_chunk(idx) {
+            Ok(pairs) => pairs.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(err) => vec![Err(err)],
+        })
+    }
+}
+
+fn read_chunk_at(file: &mut BufReader<File>, offset: u64) -> Result<(u8, Vec<u8>)> {
+    file.seek(SeekFrom::Start(offset)).into_diagnostic()?;
+    let kind = file.read_u8().into_diagnostic()?;
+    let raw_len = file.read_u32::<LittleEndian>().into_diagnostic()? as usize;
+    let compressed_len = file.read_u32::<LittleEndian>().into_diagnostic()? as usize;
+    let expected_checksum = file.read_u64::<LittleEndian>().into_diagnostic()?;
+    let mut compressed = vec![0; compressed_len];
+    file.read_exact(&mut compressed)
+        .map_err(|_| CorruptedBackup(format!("chunk at offset {offset} is truncated")))?;
+    ensure!(
+        checksum(&compressed) == expected_checksum,
+        CorruptedBackup(format!("checksum mismatch for chunk at offset {offset}"))
+    );
+    let raw = lz4_flex::decompress(&compressed, raw_len)
+        .map_err(|err| CorruptedBackup(format!("cannot decompress chunk: {err}")))?;
+    Ok((kind, raw))
+}
+
+fn read_len_prefixed(rdr: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = rdr
+        .read_u32::<LittleEndian>()
+        .map_err(|_| CorruptedBackup("truncated key-value pair".to_string()))?
+        as usize;
+    ensure!(
+        rdr.len() >= len,
+        CorruptedBackup("truncated key-value pair".to_string())
+    );
+    let (data, rest) = rdr.split_at(len);
+    *rdr = rest;
+    Ok(data.to_vec())
+}
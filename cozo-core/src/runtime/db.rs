@@ -13,7 +13,7 @@ use std::fmt::{Debug, Formatter};
 use std::iter;
 use std::path::Path;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
@@ -39,6 +39,7 @@ use crate::data::relation::ColumnDef;
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
+use crate::fts::indexing::FTS_STATS_SHARDS;
 use crate::fts::TokenizerCache;
 use crate::parse::sys::SysOp;
 use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
@@ -47,21 +48,52 @@ use crate::query::ra::{
     FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
     StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
 };
+use crate::runtime::backup::{BackupReader, BackupWriter};
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::graph_cache::{GraphCache, VersionTrackingTx};
+use crate::runtime::quantization::Quantizer;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
 };
+use crate::runtime::replication::{Replication, ReplicatedTx};
 use crate::runtime::transact::SessionTx;
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
+use crate::storage::{BackupGeneration, Storage, StoreTx};
 use crate::{decode_tuple_from_kv, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
     pub(crate) poison: Poison,
+    pub(crate) progress: Progress,
+}
+
+/// Progress of a long-running operation such as an index build, shown by `::running`
+#[derive(Clone, Default)]
+pub(crate) struct Progress {
+    done: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl Progress {
+    pub(crate) fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+    pub(crate) fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+    /// `Null` for operations that do not report their progress
+    fn to_value(&self) -> DataValue {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            DataValue::Null
+        } else {
+            let done = self.done.load(Ordering::Relaxed);
+            DataValue::from(format!("{}/{}", done, total))
+        }
+    }
 }
 
 pub(crate) struct RunningQueryCleanup {
@@ -102,11 +134,13 @@ pub struct Db<S> {
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) graph_cache: Arc<GraphCache>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    pub(crate) replication: Arc<Replication>,
 }
 
 impl<S> Debug for Db<S> {
@@ -236,6 +270,28 @@ impl NamedRows {
 const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
+fn backup_generations_to_rows(generations: Vec<BackupGeneration>) -> NamedRows {
+    NamedRows::new(
+        vec![
+            "backup_id".to_string(),
+            "timestamp".to_string(),
+            "size".to_string(),
+            "num_files".to_string(),
+        ],
+        generations
+            .into_iter()
+            .map(|g| {
+                vec![
+                    DataValue::from(g.id as i64),
+                    DataValue::from(g.timestamp),
+                    DataValue::from(g.size as i64),
+                    DataValue::from(g.num_files as i64),
+                ]
+            })
+            .collect_vec(),
+    )
+}
+
 /// Commands to be sent to a multi-transaction
 #[derive(Eq, PartialEq, Debug)]
 pub enum TransactionPayload {
@@ -252,6 +308,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// You must call [`initialize`](Self::initialize) immediately after creation.
     /// Due to lifetime restrictions we are not able to call that for you automatically.
     pub fn new(storage: S) -> Result<Self> {
+        let graph_cache = GraphCache::new(storage.storage_kind());
         let ret = Self {
             db: storage,
             temp_db: Default::default(),
@@ -260,12 +317,14 @@ impl<'s, S: Storage<'s>> Db<S> {
             running_queries: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
             tokenizers: Arc::new(Default::default()),
+            graph_cache: Arc::new(graph_cache),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            replication: Default::default(),
         };
         Ok(ret)
     }
@@ -282,7 +341,7 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// the channels will fail.
     ///
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen
-    /// for the RocksDB backend.
+    /// for the RocksDB, SQLite and in-memory backends.
     pub fn run_multi_transaction(
         &'s self,
         is_write: bool,
@@ -390,6 +449,12 @@ impl<'s, S: Storage<'s>> Db<S> {
         mutability: ScriptMutability,
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
+        // replicas only run scripts as immutable
+        let mutability = if self.replication.is_replica() {
+            ScriptMutability::Immutable
+        } else {
+            mutability
+        };
         self.do_run_script(
             payload,
             &params,
@@ -604,50 +669,83 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx.commit_tx()?;
         Ok(())
     }
-    /// Backup the running database into an Sqlite file
-    #[allow(unused_variables)]
+    /// Backup the running database into a backup file.
+    /// The backup file can be restored into a database with any storage engine.
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
-        #[cfg(feature = "storage-sqlite")]
-        {
-            let sqlite_db = crate::new_cozo_sqlite(out_file)?;
-            if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
-                bail!("Cannot create backup: data exists in the target database.");
-            }
-            let mut tx = self.transact()?;
-            let iter = tx.store_tx.range_scan(&[], &[0xFF]);
-            sqlite_db.db.batch_put(iter)?;
-            tx.commit_tx()?;
-            Ok(())
+        let mut tx = self.transact()?;
+        self.backup_with_tx(&tx, out_file.as_ref())?;
+        tx.commit_tx()?;
+        Ok(())
+    }
+    fn backup_with_tx(&'s self, tx: &SessionTx<'_>, out_file: &Path) -> Result<()> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut relations = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (_, v_slice) = kv_res?;
+            let handle = RelationHandle::decode(&v_slice)?;
+            relations.push((handle.name, handle.id.0));
         }
-        #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+        let mut writer = BackupWriter::create(out_file, self.db.storage_kind(), relations)?;
+        for kv_res in tx.store_tx.range_scan(&[], &[0xFF]) {
+            let (k, v) = kv_res?;
+            writer.put(&k, &v)?;
+        }
+        writer.finish()?;
+        Ok(())
     }
-    /// Restore from an Sqlite backup
-    #[allow(unused_variables)]
+    /// Restore from a backup file. The current database must be empty.
+    ///
+    /// Backups made as Sqlite files by older versions can only be restored
+    /// if the `storage-sqlite` feature is enabled.
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        let in_file = in_file.as_ref();
+        if !BackupReader::is_backup_file(in_file)? {
+            return self.restore_sqlite_backup(in_file);
+        }
+        let mut reader = BackupReader::open(in_file)?;
+        reader.verify()?;
+        self.ensure_empty_for_restore()?;
+        log::info!(
+            "restoring backup created by the {} engine",
+            reader.manifest().source_engine
+        );
+        let n_chunks = reader.manifest().chunk_offsets.len();
+        self.db.batch_put(Box::new(reader.pairs(0..n_chunks)))?;
+        self.load_last_ids()
+    }
+    fn ensure_empty_for_restore(&'s self) -> Result<()> {
+        self.replication.ensure_writable()?;
+        if self.replication.has_coordinator() {
+            bail!("Cannot restore backup into a database with a commit coordinator");
+        }
+        let mut tx = self.transact()?;
+        let store_id = tx.relation_store_id.load(Ordering::SeqCst);
+        if store_id != 0 {
+            bail!(
+                "Cannot restore backup: data exists in the current database. \
+                You can only restore into a new database (store id: {}).",
+                store_id
+            );
+        }
+        tx.commit_tx()?;
+        Ok(())
+    }
+    #[allow(unused_variables)]
+    fn restore_sqlite_backup(&'s self, in_file: &Path) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
-            let sqlite_db = crate::new_cozo_sqlite(in_file)?;
+            let sqlite_db = crate::storage::sqlite::open_cozo_sqlite_read_only(in_file)?;
             let mut s_tx = sqlite_db.transact()?;
-            {
-                let mut tx = self.transact()?;
-                let store_id = tx.relation_store_id.load(Ordering::SeqCst);
-                if store_id != 0 {
-                    bail!(
-                        "Cannot restore backup: data exists in the current database. \
-                You can only restore into a new database (store id: {}).",
-                        store_id
-                    );
-                }
-                tx.commit_tx()?;
-            }
+            self.ensure_empty_for_restore()?;
             let iter = s_tx.store_tx.total_scan();
             self.db.batch_put(iter)?;
             s_tx.commit_tx()?;
-            Ok(())
+            self.load_last_ids()
         }
         #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+        bail!("restoring from Sqlite backups requires the 'storage-sqlite' feature to be enabled")
     }
     /// Import data from relations in a backup file.
     /// The target stored relations must already exist in the database, and it must not
@@ -656,14 +754,73 @@ impl<'s, S: Storage<'s>> Db<S> {
     ///
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
-    #[allow(unused_variables)]
     pub fn import_from_backup(
         &'s self,
         in_file: impl AsRef<Path>,
         relations: &[String],
     ) -> Result<()> {
+        let in_file = in_file.as_ref();
+        if !BackupReader::is_backup_file(in_file)? {
+            return self.import_from_sqlite_backup(in_file, relations);
+        }
+
+        let rel_names = relations.iter().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let mut reader = BackupReader::open(in_file)?;
+        let mut dst_tx = self.transact_write()?;
+
+        for relation in relations {
+            let dst_handle = Self::check_import_target(&dst_tx, relation)?;
+            let src_manifest = reader
+                .manifest()
+                .get_relation(relation)
+                .ok_or_else(|| miette!("Relation {} not found in the backup", relation))?
+                .clone();
+            let src_prefix = src_manifest.id.to_be_bytes();
+            for result in reader.pairs(src_manifest.chunks) {
+                let (mut key, mut val) = result?;
+                if !key.starts_with(&src_prefix) {
+                    continue;
+                }
+                dst_handle.amend_key_prefix(&mut key);
+                dst_handle.amend_key_prefix(&mut val);
+                dst_tx.store_tx.put(&key, &val)?;
+            }
+        }
+
+        dst_tx.commit_tx()
+    }
+    fn check_import_target(dst_tx: &SessionTx<'_>, relation: &str) -> Result<RelationHandle> {
+        if relation.contains(':') {
+            bail!(ImportIntoIndex(relation.to_string()))
+        }
+        let dst_handle = dst_tx.get_relation(relation, false)?;
+
+        if !dst_handle.indices.is_empty() {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Cannot import data into relation {0} from backup as the relation has indices")]
+            #[diagnostic(code(tx::bare_import_with_indices))]
+            #[diagnostic(help("Use `import_relations()` instead"))]
+            pub(crate) struct RestoreIntoRelWithIndices(pub(crate) String);
+
+            bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
+        }
+
+        if dst_handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                dst_handle.name.to_string(),
+                "data import".to_string(),
+                dst_handle.access_level
+            ));
+        }
+        Ok(dst_handle)
+    }
+    #[allow(unused_variables)]
+    fn import_from_sqlite_backup(&'s self, in_file: &Path, relations: &[String]) -> Result<()> {
         #[cfg(not(feature = "storage-sqlite"))]
-        bail!("backup requires the 'storage-sqlite' feature to be enabled");
+        bail!("importing from Sqlite backups requires the 'storage-sqlite' feature to be enabled");
 
         #[cfg(feature = "storage-sqlite")]
         {
@@ -671,34 +828,13 @@ impl<'s, S: Storage<'s>> Db<S> {
             let locks = self.obtain_relation_locks(rel_names.iter());
             let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
-            let source_db = crate::new_cozo_sqlite(in_file)?;
+            let source_db = crate::storage::sqlite::open_cozo_sqlite_read_only(in_file)?;
             let mut src_tx = source_db.transact()?;
             let mut dst_tx = self.transact_write()?;
 
             for relation in relations {
-                if relation.contains(':') {
-                    bail!(ImportIntoIndex(relation.to_string()))
-                }
+                let dst_handle = Self::check_import_target(&dst_tx, relation)?;
                 let src_handle = src_tx.get_relation(relation, false)?;
-                let dst_handle = dst_tx.get_relation(relation, false)?;
-
-                if !dst_handle.indices.is_empty() {
-                    #[derive(Debug, Error, Diagnostic)]
-                    #[error("Cannot import data into relation {0} from backup as the relation has indices")]
-                    #[diagnostic(code(tx::bare_import_with_indices))]
-                    #[diagnostic(help("Use `import_relations()` instead"))]
-                    pub(crate) struct RestoreIntoRelWithIndices(pub(crate) String);
-
-                    bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
-                }
-
-                if dst_handle.access_level < AccessLevel::Protected {
-                    bail!(InsufficientAccessLevel(
-                        dst_handle.name.to_string(),
-                        "data import".to_string(),
-                        dst_handle.access_level
-                    ));
-                }
 
                 let src_lower = Tuple::default().encode_as_key(src_handle.id);
                 let src_upper = Tuple::default().encode_as_key(src_handle.id.next());
@@ -827,7 +963,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
 
-    fn load_last_ids(&'s self) -> Result<()> {
+    pub(crate) fn load_last_ids(&'s self) -> Result<()> {
         let mut tx = self.transact_write()?;
         self.relation_store_id
             .store(tx.init_storage()?.0, Ordering::Release);
@@ -835,22 +971,38 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
     pub(crate) fn transact(&'s self) -> Result<SessionTx<'_>> {
+        let graph_generation = self.graph_cache.generation();
         let ret = SessionTx {
             store_tx: Box::new(self.db.transact(false)?),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            graph_cache: self.graph_cache.clone(),
+            graph_generation,
+            fts_stats_shard: 0,
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        self.replication.ensure_writable()?;
+        let graph_generation = self.graph_cache.generation();
+        let store_tx: Box<dyn StoreTx<'s> + 's> = match self.replication.begin_write()? {
+            Some(permit) => Box::new(ReplicatedTx::new(
+                Box::new(self.db.transact(true)?),
+                permit,
+            )),
+            None => Box::new(self.db.transact(true)?),
+        };
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx: Box::new(VersionTrackingTx::new(store_tx, self.graph_cache.clone())),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            graph_cache: self.graph_cache.clone(),
+            graph_generation,
+            fts_stats_shard: rand::random::<u8>() % FTS_STATS_SHARDS,
         };
         Ok(ret)
     }
@@ -1198,6 +1350,42 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Backup(path, incremental) => {
+                if read_only {
+                    bail!("Cannot create backups in read-only mode");
+                }
+                if *incremental {
+                    let generation = self.db.create_incremental_backup(Path::new(path))?;
+                    Ok(backup_generations_to_rows(vec![generation]))
+                } else {
+                    self.backup_with_tx(tx, Path::new(path))?;
+                    Ok(NamedRows::new(
+                        vec![STATUS_STR.to_string()],
+                        vec![vec![DataValue::from(OK_STR)]],
+                    ))
+                }
+            }
+            SysOp::ListBackups(path) => {
+                let generations = self.db.list_backup_generations(Path::new(path))?;
+                Ok(backup_generations_to_rows(generations))
+            }
+            SysOp::PruneBackups(path, keep) => {
+                if read_only {
+                    bail!("Cannot prune backups in read-only mode");
+                }
+                self.db.prune_backup_generations(Path::new(path), *keep)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::VerifyBackup(path, id) => {
+                self.db.verify_backup_generation(Path::new(path), *id)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::ListRelations => self.list_relations(tx),
             SysOp::ListFixedRules => {
                 let rules = self.fixed_rules.read().unwrap();
@@ -1265,15 +1453,30 @@ impl<'s, S: Storage<'s>> Db<S> {
                 if read_only {
                     bail!("Cannot create vector index in read-only mode");
                 }
+                // building the index over a large relation takes a while,
+                // so it is listed by `::running` and can be killed
+                let poison = Poison::default();
+                let progress = Progress::default();
+                let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
+                let handle = RunningQueryHandle {
+                    started_at: seconds_since_the_epoch()?,
+                    poison: poison.clone(),
+                    progress: progress.clone(),
+                };
+                self.running_queries.lock().unwrap().insert(id, handle);
+                let _running_guard = RunningQueryCleanup {
+                    id,
+                    running_queries: self.running_queries.clone(),
+                };
                 if skip_locking {
-                    tx.create_hnsw_index(config)?;
+                    tx.create_hnsw_index(config, &poison, &progress)?;
                 } else {
                     let lock = self
                         .obtain_relation_locks(iter::once(&config.base_relation))
                         .pop()
                         .unwrap();
                     let _guard = lock.write().unwrap();
-                    tx.create_hnsw_index(config)?;
+                    tx.create_hnsw_index(config, &poison, &progress)?;
                 }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1364,6 +1567,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListRunning => self.list_running(),
+            SysOp::ReplicationStatus => Ok(self.replication_status_rows()),
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
                 Ok(match queries.get(id) {
@@ -1501,6 +1705,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         let handle = RunningQueryHandle {
             started_at: since_the_epoch,
             poison: poison.clone(),
+            progress: Default::default(),
         };
         self.running_queries.lock().unwrap().insert(id, handle);
 
@@ -1667,6 +1872,9 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
+    /// The result of `::running`, with the columns `id`, `started_at` and `progress`.
+    /// `progress` is `"done/total"` for operations reporting it, such as building an
+    /// HNSW index over an existing relation, and `null` for every other query.
     pub(crate) fn list_running(&self) -> Result<NamedRows> {
         let rows = self
             .running_queries
@@ -1677,11 +1885,16 @@ impl<'s, S: Storage<'s>> Db<S> {
                 vec![
                     DataValue::from(*k as i64),
                     DataValue::from(format!("{:?}", v.started_at)),
+                    v.progress.to_value(),
                 ]
             })
             .collect_vec();
         Ok(NamedRows::new(
-            vec!["id".to_string(), "started_at".to_string()],
+            vec![
+                "id".to_string(),
+                "started_at".to_string(),
+                "progress".to_string(),
+            ],
             rows,
         ))
     }
@@ -1697,6 +1910,18 @@ impl<'s, S: Storage<'s>> Db<S> {
             ]);
         }
         for (name, (rel, manifest)) in &handle.hnsw_indices {
+            let quantization = match &manifest.quantizer {
+                None => json!(null),
+                Some(Quantizer::Int8) => json!("Int8"),
+                Some(Quantizer::Product {
+                    sub_dim,
+                    n_centroids,
+                    ..
+                }) => json!({
+                    "pq_subspaces": manifest.vec_dim / sub_dim,
+                    "pq_centroids": n_centroids,
+                }),
+            };
             rows.push(vec![
                 json!(name),
                 json!("hnsw"),
@@ -1713,6 +1938,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "level_multiplier": manifest.level_multiplier,
                     "extend_candidates": manifest.extend_candidates,
                     "keep_pruned_connections": manifest.keep_pruned_connections,
+                    "quantization": quantization,
                 }),
             ]);
         }
@@ -1725,6 +1951,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "extractor": manifest.extractor,
                     "tokenizer": manifest.tokenizer,
                     "tokenizer_filters": manifest.filters,
+                    "fields": manifest.fields,
                 }),
             ]);
         }
@@ -1741,6 +1968,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     "num_perm": manifest.num_perm,
                     "n_bands": manifest.n_bands,
                     "n_rows_in_band": manifest.n_rows_in_band,
+                    "forest_depth": manifest.forest_depth,
                     "threshold": manifest.threshold,
                 }),
             ]);
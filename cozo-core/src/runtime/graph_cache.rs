@@ -0,0 +1,436 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Graphs projected from stored relations, kept between queries for as long as the
+//! relations are unchanged.
+//!
+//! Every write transaction records the relations it writes to, found from the prefixes of the
+//! keys it writes, and when it ends, the write versions of those relations are set to a new
+//! generation. A transaction may use a graph cached for a relation only if no write to the
+//! relation was in progress or ended after the transaction started, as otherwise the
+//! transaction could see a different state of the relation than the cached one.
+//!
+//! Only writes made through the same [Db](crate::Db) are seen. Writes made by other processes
+//! to the same SQLite file go unnoticed, and graphs built before such writes keep being used
+//! until this process writes to the relation, so no other process may write to the relations
+//! that graph algorithms are run on. The cache is disabled for TiKV, where several clients
+//! routinely write to the same cluster.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+#[cfg(feature = "graph-algo")]
+use graph::prelude::DirectedCsrGraph;
+use miette::Result;
+
+use crate::data::tuple::Tuple;
+#[cfg(feature = "graph-algo")]
+use crate::data::value::DataValue;
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::RelationId;
+use crate::storage::StoreTx;
+
+/// Maximal number of graphs kept in the cache, the least recently used ones are evicted first
+#[cfg(feature = "graph-algo")]
+const MAX_CACHED_GRAPHS: usize = 8;
+
+#[derive(Default)]
+struct WriteState {
+    /// Incremented whenever a write transaction ends
+    generation: u64,
+    /// The generation in which the last write to each relation ended
+    versions: BTreeMap<RelationId, u64>,
+    /// The number of unfinished transactions that have written to each relation
+    writing: BTreeMap<RelationId, usize>,
+    /// The generation in which the last write to unknown relations ended
+    everything_version: u64,
+    /// The number of unfinished transactions that have written to unknown relations
+    everything_writing: usize,
+}
+
+/// Write versions of the stored relations, and the graphs built from them.
+#[derive(Default)]
+pub(crate) struct GraphCache {
+    /// Set for storages written to by other clients, whose writes cannot be tracked
+    disabled: bool,
+    state: Mutex<WriteState>,
+    #[cfg(feature = "graph-algo")]
+    graphs: Mutex<CachedGraphs>,
+}
+
+impl GraphCache {
+    pub(crate) fn new(storage_kind: &str) -> Self {
+        Self {
+            disabled: storage_kind == "tikv",
+            ..Default::default()
+        }
+    }
+
+    /// The current generation, to be taken before a transaction starts.
+    pub(crate) fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// The write version of a relation as seen by a transaction that started in
+    /// `read_generation`, or `None` if the transaction might not see exactly that version.
+    pub(crate) fn relation_version(&self, id: RelationId, read_generation: u64) -> Option<u64> {
+        if self.disabled {
+            return None;
+        }
+        let state = self.state.lock().unwrap();
+        if state.everything_writing > 0 || state.writing.contains_key(&id) {
+            return None;
+        }
+        let version = state
+            .versions
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+            .max(state.everything_version);
+        if version > read_generation {
+            None
+        } else {
+            Some(version)
+        }
+    }
+
+    fn begin_write(&self, id: Option<RelationId>) {
+        let mut state = self.state.lock().unwrap();
+        match id {
+            Some(id) => *state.writing.entry(id).or_default() += 1,
+            None => state.everything_writing += 1,
+        }
+    }
+
+    fn end_write(&self, written: &WrittenRelations) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let generation = state.generation;
+        for id in &written.relations {
+            state.versions.insert(*id, generation);
+            if let Some(count) = state.writing.get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    state.writing.remove(id);
+                }
+            }
+        }
+        if written.everything {
+            state.everything_version = generation;
+            state.everything_writing -= 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct WrittenRelations {
+    relations: BTreeSet<RelationId>,
+    everything: bool,
+}
+
+impl WrittenRelations {
+    fn contains(&self, id: Option<RelationId>) -> bool {
+        self.everything || matches!(id, Some(id) if self.relations.contains(&id))
+    }
+
+    fn record(&mut self, cache: &GraphCache, id: Option<RelationId>) {
+        if self.everything {
+            return;
+        }
+        match id {
+            Some(id) => {
+                if self.relations.insert(id) {
+                    cache.begin_write(Some(id));
+                }
+            }
+            None => {
+                self.everything = true;
+                cache.begin_write(None);
+            }
+        }
+    }
+}
+
+/// The relation that a key belongs to, from the prefix of the key.
+fn relation_of_key(key: &[u8]) -> Option<RelationId> {
+    let prefix: [u8; 8] = key.get(..8)?.try_into().ok()?;
+    Some(RelationId(u64::from_be_bytes(prefix)))
+}
+
+/// A write transaction recording the relations it writes to, so that graphs cached
+/// for those relations are no longer used once it ends.
+///
+/// The cache is told about a relation on the first write to it, not at commit, as the
+/// transaction itself must stop using the graphs cached for it from then on. Later writes
+/// to the relation only look it up in a set owned by the transaction.
+pub(crate) struct VersionTrackingTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    cache: Arc<GraphCache>,
+    written: WrittenRelations,
+    /// Relations written to only by parallel writes, which cannot borrow `written` mutably
+    par_written: RwLock<WrittenRelations>,
+}
+
+impl<'s> VersionTrackingTx<'s> {
+    pub(crate) fn new(inner: Box<dyn StoreTx<'s> + 's>, cache: Arc<GraphCache>) -> Self {
+        Self {
+            inner,
+            cache,
+            written: Default::default(),
+            par_written: Default::default(),
+        }
+    }
+
+    /// Must be called before the write is made, so that a transaction started in between
+    /// can tell that the relation is being written to.
+    fn record(&mut self, id: Option<RelationId>) {
+        self.written.record(&self.cache, id)
+    }
+
+    /// Same as `record`, for writes made through a shared reference.
+    fn record_par(&self, id: Option<RelationId>) {
+        if self.written.contains(id) || self.par_written.read().unwrap().contains(id) {
+            return;
+        }
+        self.par_written.write().unwrap().record(&self.cache, id)
+    }
+}
+
+impl Drop for VersionTrackingTx<'_> {
+    fn drop(&mut self) {
+        self.cache.end_write(&self.written);
+        let par_written = self.par_written.get_mut().unwrap();
+        if par_written.everything || !par_written.relations.is_empty() {
+            self.cache.end_write(par_written);
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for VersionTrackingTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(relation_of_key(key));
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record_par(relation_of_key(key));
+        self.inner.par_put(key, val)
+    }
+
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let written = &mut self.written;
+        let cache = &self.cache;
+        self.inner.batch_put(Box::new(data.inspect(|pair| {
+            if let Ok((key, _)) = pair {
+                written.record(cache, relation_of_key(key));
+            }
+        })))
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record(relation_of_key(key));
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.record_par(relation_of_key(key));
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        let lower_rel = relation_of_key(lower);
+        if lower_rel.is_some() && lower_rel == relation_of_key(upper) {
+            self.record(lower_rel);
+        } else {
+            self.record(None);
+        }
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
+
+/// A graph in compressed sparse row form, together with the mapping between the nodes
+/// in the graph and the values in the relation.
+#[cfg(feature = "graph-algo")]
+pub(crate) struct GraphProjection<G> {
+    pub(crate) graph: G,
+    pub(crate) indices: Vec<DataValue>,
+    pub(crate) inv_indices: BTreeMap<DataValue, u32>,
+}
+
+#[cfg(feature = "graph-algo")]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct GraphKey {
+    pub(crate) relation: RelationId,
+    pub(crate) version: u64,
+    pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) undirected: bool,
+    /// `None` for unweighted graphs, otherwise whether negative weights are allowed
+    pub(crate) weighted: Option<bool>,
+}
+
+#[cfg(feature = "graph-algo")]
+#[derive(Clone)]
+pub(crate) enum CachedGraph {
+    Unweighted(Arc<GraphProjection<DirectedCsrGraph<u32>>>),
+    Weighted(Arc<GraphProjection<DirectedCsrGraph<u32, (), f32>>>),
+}
+
+#[cfg(feature = "graph-algo")]
+#[derive(Default)]
+struct CachedGraphs {
+    clock: u64,
+    entries: BTreeMap<GraphKey, (u64, CachedGraph)>,
+}
+
+#[cfg(feature = "graph-algo")]
+impl GraphCache {
+    pub(crate) fn get_graph(&self, key: &GraphKey) -> Option<CachedGraph> {
+        let mut graphs = self.graphs.lock().unwrap();
+        graphs.clock += 1;
+        let clock = graphs.clock;
+        let (last_used, graph) = graphs.entries.get_mut(key)?;
+        *last_used = clock;
+        Some(graph.clone())
+    }
+
+    /// Cache a graph built by a transaction that started in `read_generation`, unless the
+    /// relation has been written to in the meantime. Graphs of older versions of the
+    /// relation are dropped.
+    pub(crate) fn put_graph(&self, key: GraphKey, graph: CachedGraph, read_generation: u64) {
+        if self.relation_version(key.relation, read_generation) != Some(key.version) {
+            return;
+        }
+        let mut graphs = self.graphs.lock().unwrap();
+        graphs
+            .entries
+            .retain(|k, _| k.relation != key.relation || k.version >= key.version);
+        while graphs.entries.len() >= MAX_CACHED_GRAPHS {
+            let oldest = graphs
+                .entries
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            graphs.entries.remove(&oldest);
+        }
+        graphs.clock += 1;
+        let clock = graphs.clock;
+        graphs.entries.insert(key, (clock, graph));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::graph_cache::{relation_of_key, GraphCache, WrittenRelations};
+    use crate::runtime::relation::RelationId;
+
+    #[test]
+    fn relation_versions() {
+        let cache = GraphCache::default();
+        let (r1, r2) = (RelationId(1), RelationId(2));
+        let started = cache.generation();
+        assert_eq!(cache.relation_version(r1, started), Some(0));
+
+        cache.begin_write(Some(r1));
+        assert_eq!(cache.relation_version(r1, started), None);
+        assert_eq!(cache.relation_version(r2, started), Some(0));
+        cache.end_write(&WrittenRelations {
+            relations: [r1].into(),
+            everything: false,
+        });
+        // the write ended after the reader started, so the reader may not have seen it
+        assert_eq!(cache.relation_version(r1, started), None);
+        assert_eq!(cache.relation_version(r1, cache.generation()), Some(1));
+        assert_eq!(cache.relation_version(r2, cache.generation()), Some(0));
+
+        cache.begin_write(None);
+        assert_eq!(cache.relation_version(r2, cache.generation()), None);
+        cache.end_write(&WrittenRelations {
+            relations: Default::default(),
+            everything: true,
+        });
+        assert_eq!(cache.relation_version(r2, cache.generation()), Some(2));
+
+        // writes by other clients cannot be seen
+        assert_eq!(GraphCache::new("tikv").relation_version(r1, 0), None);
+
+        assert_eq!(
+            relation_of_key(&[0, 0, 0, 0, 0, 0, 1, 0, 7]),
+            Some(RelationId(256))
+        );
+        assert_eq!(relation_of_key(&[0xFF]), None);
+    }
+}
@@ -7,22 +7,34 @@
  */
 
 use crate::data::expr::{eval_bytecode_pred, Bytecode};
+use crate::data::functions::TERMINAL_VALIDITY;
 use crate::data::program::HnswSearch;
-use crate::data::relation::VecElementType;
+use crate::data::relation::{ColType, NullableColType, VecElementType};
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::Vector;
+use crate::parse::expr::build_expr;
 use crate::parse::sys::HnswDistance;
+use crate::parse::{CozoScriptParser, Rule};
+use crate::runtime::db::{Poison, Progress};
+use crate::runtime::quantization::Quantizer;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, SourceSpan};
 use itertools::Itertools;
-use miette::{bail, miette, Result};
+use miette::{bail, miette, IntoDiagnostic, Result};
 use ordered_float::OrderedFloat;
+use pest::Parser;
 use priority_queue::PriorityQueue;
 use rand::Rng;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use rustc_hash::{FxHashMap, FxHashSet};
+use sha2::{Digest, Sha256};
 use smartstring::{LazyCompact, SmartString};
 use std::cmp::{max, Reverse};
+use std::collections::{btree_map, BTreeMap};
+use std::mem;
+use std::sync::RwLock;
 
 #[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub(crate) struct HnswIndexManifest {
@@ -40,6 +52,10 @@ pub(crate) struct HnswIndexManifest {
     pub(crate) index_filter: Option<String>,
     pub(crate) extend_candidates: bool,
     pub(crate) keep_pruned_connections: bool,
+    /// If set, the graph search uses the compressed vectors stored in the index,
+    /// and the results are re-ranked with the full vectors of the base relation.
+    #[serde(default)]
+    pub(crate) quantizer: Option<Quantizer>,
 }
 
 impl HnswIndexManifest {
@@ -54,16 +70,31 @@ impl HnswIndexManifest {
 
 type CompoundKey = (Tuple, usize, i32);
 
-struct VectorCache {
-    cache: FxHashMap<CompoundKey, Vector>,
+/// A filtered search falls back to scanning the whole relation once it has visited
+/// this many vectors per requested result, as the filter is then very selective.
+const FILTERED_SEARCH_MAX_EXPANSION: usize = 20;
+
+/// Cache of the indexed vectors, which are either float vectors (`DataValue::Vec`),
+/// or bit vectors (`DataValue::Bytes`) for the binary distances.
+struct VectorCache<'a> {
+    cache: FxHashMap<CompoundKey, DataValue>,
     distance: HnswDistance,
+    quantizer: Option<&'a Quantizer>,
+    dtype: VecElementType,
 }
 
-impl VectorCache {
-    fn insert(&mut self, k: CompoundKey, v: Vector) {
+impl VectorCache<'_> {
+    fn insert(&mut self, k: CompoundKey, v: DataValue) {
         self.cache.insert(k, v);
     }
-    fn dist(&self, v1: &Vector, v2: &Vector) -> f64 {
+    fn dist(&self, v1: &DataValue, v2: &DataValue) -> f64 {
+        match (v1, v2) {
+            (DataValue::Vec(a), DataValue::Vec(b)) => self.vec_dist(a, b),
+            (DataValue::Bytes(a), DataValue::Bytes(b)) => self.bits_dist(a, b),
+            _ => panic!("Cannot compute distance between {:?} and {:?}", v1, v2),
+        }
+    }
+    fn vec_dist(&self, v1: &Vector, v2: &Vector) -> f64 {
         match self.distance {
             HnswDistance::L2 => match (v1, v2) {
                 (Vector::F32(a), Vector::F32(b)) => {
@@ -105,9 +136,49 @@ impl VectorCache {
                 }
                 _ => panic!("Cannot compute inner product between {:?} and {:?}", v1, v2),
             },
+            HnswDistance::L1 => match (v1, v2) {
+                (Vector::F32(a), Vector::F32(b)) => {
+                    let diff = a - b;
+                    diff.mapv(f32::abs).sum() as f64
+                }
+                (Vector::F64(a), Vector::F64(b)) => {
+                    let diff = a - b;
+                    diff.mapv(f64::abs).sum()
+                }
+                _ => panic!("Cannot compute L1 distance between {:?} and {:?}", v1, v2),
+            },
+            HnswDistance::Hamming | HnswDistance::Jaccard => {
+                panic!(
+                    "Cannot compute {:?} distance between float vectors",
+                    self.distance
+                )
+            }
+        }
+    }
+    fn bits_dist(&self, v1: &[u8], v2: &[u8]) -> f64 {
+        match self.distance {
+            HnswDistance::Hamming => v1
+                .iter()
+                .zip(v2)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum::<u32>() as f64,
+            HnswDistance::Jaccard => {
+                let (intersection, union) = v1.iter().zip(v2).fold((0, 0), |(i, u), (a, b)| {
+                    (i + (a & b).count_ones(), u + (a | b).count_ones())
+                });
+                if union == 0 {
+                    0.
+                } else {
+                    1. - intersection as f64 / union as f64
+                }
+            }
+            _ => panic!(
+                "Cannot compute {:?} distance between bit vectors",
+                self.distance
+            ),
         }
     }
-    fn v_dist(&self, v: &Vector, key: &CompoundKey) -> f64 {
+    fn v_dist(&self, v: &DataValue, key: &CompoundKey) -> f64 {
         let v2 = self.cache.get(key).unwrap();
         self.dist(v, v2)
     }
@@ -116,36 +187,58 @@ impl VectorCache {
         let v2 = self.cache.get(k2).unwrap();
         self.dist(v1, v2)
     }
-    fn get_key(&self, key: &CompoundKey) -> &Vector {
+    fn get_key(&self, key: &CompoundKey) -> &DataValue {
         self.cache.get(key).unwrap()
     }
     fn ensure_key(
         &mut self,
         key: &CompoundKey,
-        handle: &RelationHandle,
+        orig_table: &RelationHandle,
+        idx_table: &RelationHandle,
         tx: &SessionTx<'_>,
     ) -> Result<()> {
-        if !self.cache.contains_key(key) {
-            match handle.get(tx, &key.0)? {
-                Some(tuple) => {
-                    let mut field = &tuple[key.1];
-                    if key.2 >= 0 {
-                        match field {
-                            DataValue::List(l) => {
-                                field = &l[key.2 as usize];
-                            }
-                            _ => bail!("Cannot interpret {} as list", field),
-                        }
+        if self.cache.contains_key(key) {
+            return Ok(());
+        }
+        if let Some(quantizer) = self.quantizer {
+            // the code is kept in the self-link at the bottom level
+            let mut self_key = vec![DataValue::from(0)];
+            for _ in 0..2 {
+                self_key.extend_from_slice(&key.0);
+                self_key.push(DataValue::from(key.1 as i64));
+                self_key.push(DataValue::from(key.2 as i64));
+            }
+            match idx_table.get(tx, &self_key)? {
+                Some(tuple) => match tuple.get(self_key.len() + 3) {
+                    Some(DataValue::Bytes(code)) => {
+                        let v = quantizer.decode(code, self.dtype);
+                        self.cache.insert(key.clone(), DataValue::Vec(v));
                     }
+                    _ => bail!("Cannot find quantized vector for HNSW: {:?}", key),
+                },
+                None => bail!("Cannot find compound key for HNSW: {:?}", key),
+            }
+            return Ok(());
+        }
+        match orig_table.get(tx, &key.0)? {
+            Some(tuple) => {
+                let mut field = &tuple[key.1];
+                if key.2 >= 0 {
                     match field {
-                        DataValue::Vec(v) => {
-                            self.cache.insert(key.clone(), v.clone());
+                        DataValue::List(l) => {
+                            field = &l[key.2 as usize];
                         }
-                        _ => bail!("Cannot interpret {} as vector", field),
+                        _ => bail!("Cannot interpret {} as list", field),
                     }
                 }
-                None => bail!("Cannot find compound key for HNSW: {:?}", key),
+                match field {
+                    DataValue::Vec(_) | DataValue::Bytes(_) => {
+                        self.cache.insert(key.clone(), field.clone());
+                    }
+                    _ => bail!("Cannot interpret {} as vector", field),
+                }
             }
+            None => bail!("Cannot find compound key for HNSW: {:?}", key),
         }
         Ok(())
     }
@@ -155,17 +248,26 @@ impl<'a> SessionTx<'a> {
     fn hnsw_put_vector(
         &mut self,
         tuple: &[DataValue],
-        q: &Vector,
+        q: &DataValue,
         idx: usize,
         subidx: i32,
         manifest: &HnswIndexManifest,
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<()> {
         let tuple_key = &tuple[..orig_table.metadata.keys.len()];
-        vec_cache.insert((tuple_key.to_vec(), idx, subidx), q.clone());
-        let hash = q.get_hash();
+        let code = match (&manifest.quantizer, q) {
+            (Some(quantizer), DataValue::Vec(v)) => Some(quantizer.encode(v)),
+            _ => None,
+        };
+        // neighbours see the vector as it will be read back from the index
+        let cached = match (&manifest.quantizer, &code) {
+            (Some(quantizer), Some(code)) => DataValue::Vec(quantizer.decode(code, manifest.dtype)),
+            _ => q.clone(),
+        };
+        vec_cache.insert((tuple_key.to_vec(), idx, subidx), cached);
+        let hash = vector_hash(q);
         let mut canary_tuple = vec![DataValue::from(0)];
         for _ in 0..2 {
             canary_tuple.extend_from_slice(tuple_key);
@@ -174,7 +276,7 @@ impl<'a> SessionTx<'a> {
         }
         if let Some(v) = idx_table.get(self, &canary_tuple)? {
             if let DataValue::Bytes(b) = &v[tuple_key.len() * 2 + 6] {
-                if b == hash.as_ref() {
+                if *b == hash {
                     return Ok(());
                 }
             }
@@ -197,7 +299,7 @@ impl<'a> SessionTx<'a> {
             let ep_idx = ep[orig_table.metadata.keys.len() + 1].get_int().unwrap() as usize;
             let ep_subidx = ep[orig_table.metadata.keys.len() + 2].get_int().unwrap() as i32;
             let ep_key = (ep_t_key, ep_idx, ep_subidx);
-            vec_cache.ensure_key(&ep_key, orig_table, self)?;
+            vec_cache.ensure_key(&ep_key, orig_table, idx_table, self)?;
             let ep_distance = vec_cache.v_dist(q, &ep_key);
             // max queue
             let mut found_nn = PriorityQueue::new();
@@ -206,7 +308,8 @@ impl<'a> SessionTx<'a> {
             if target_level < bottom_level {
                 // this becomes the entry point
                 self.hnsw_put_fresh_at_levels(
-                    hash.as_ref(),
+                    &hash,
+                    code.as_deref(),
                     tuple_key,
                     idx,
                     subidx,
@@ -236,9 +339,12 @@ impl<'a> SessionTx<'a> {
             }
             let mut self_tuple_val = vec![
                 DataValue::from(0.0),
-                DataValue::Bytes(hash.as_ref().to_vec()),
+                DataValue::Bytes(hash.clone()),
                 DataValue::from(false),
             ];
+            if let Some(code) = &code {
+                self_tuple_val.push(DataValue::Bytes(code.clone()));
+            }
             for current_level in max(target_level, bottom_level)..=0 {
                 let m_max = if current_level == 0 {
                     manifest.m_max0
@@ -361,7 +467,8 @@ impl<'a> SessionTx<'a> {
             // This is the first vector in the index.
             let level = manifest.get_random_level();
             self.hnsw_put_fresh_at_levels(
-                hash.as_ref(),
+                &hash,
+                code.as_deref(),
                 tuple_key,
                 idx,
                 subidx,
@@ -381,9 +488,9 @@ impl<'a> SessionTx<'a> {
         manifest: &HnswIndexManifest,
         idx_table: &RelationHandle,
         orig_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<usize> {
-        vec_cache.ensure_key(target_key, orig_table, self)?;
+        vec_cache.ensure_key(target_key, orig_table, idx_table, self)?;
         let vec = vec_cache.get_key(target_key).clone();
         let mut candidates = PriorityQueue::new();
         for (neighbour_key, neighbour_dist) in
@@ -469,14 +576,14 @@ impl<'a> SessionTx<'a> {
     }
     fn hnsw_select_neighbours_heuristic(
         &self,
-        q: &Vector,
+        q: &DataValue,
         found: &PriorityQueue<CompoundKey, OrderedFloat<f64>>,
         m: usize,
         level: i64,
         manifest: &HnswIndexManifest,
         idx_table: &RelationHandle,
         orig_table: &RelationHandle,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<PriorityQueue<CompoundKey, Reverse<OrderedFloat<f64>>>> {
         let mut candidates = PriorityQueue::new();
         // Simple non-heuristic selection
@@ -500,7 +607,7 @@ impl<'a> SessionTx<'a> {
             for (item, _) in found.iter() {
                 // Extend by neighbours
                 for (neighbour_key, _) in self.hnsw_get_neighbours(item, level, idx_table, false)? {
-                    vec_cache.ensure_key(&neighbour_key, orig_table, self)?;
+                    vec_cache.ensure_key(&neighbour_key, orig_table, idx_table, self)?;
                     let dist = vec_cache.v_dist(q, &neighbour_key);
                     candidates.push(
                         (neighbour_key.0, neighbour_key.1, neighbour_key.2),
@@ -513,8 +620,8 @@ impl<'a> SessionTx<'a> {
             let (cand_key, Reverse(OrderedFloat(cand_dist_to_q))) = candidates.pop().unwrap();
             let mut should_add = true;
             for (existing, _) in ret.iter() {
-                vec_cache.ensure_key(&cand_key, orig_table, self)?;
-                vec_cache.ensure_key(existing, orig_table, self)?;
+                vec_cache.ensure_key(&cand_key, orig_table, idx_table, self)?;
+                vec_cache.ensure_key(existing, orig_table, idx_table, self)?;
                 let dist_to_existing = vec_cache.k_dist(existing, &cand_key);
                 if dist_to_existing < cand_dist_to_q {
                     should_add = false;
@@ -538,13 +645,13 @@ impl<'a> SessionTx<'a> {
     }
     fn hnsw_search_level(
         &self,
-        q: &Vector,
+        q: &DataValue,
         ef: usize,
         cur_level: i64,
         orig_table: &RelationHandle,
         idx_table: &RelationHandle,
         found_nn: &mut PriorityQueue<CompoundKey, OrderedFloat<f64>>,
-        vec_cache: &mut VectorCache,
+        vec_cache: &mut VectorCache<'_>,
     ) -> Result<()> {
         let mut visited: FxHashSet<CompoundKey> = FxHashSet::default();
         // min queue
@@ -569,7 +676,7 @@ impl<'a> SessionTx<'a> {
                 if visited.contains(&neighbour_key) {
                     continue;
                 }
-                vec_cache.ensure_key(&neighbour_key, orig_table, self)?;
+                vec_cache.ensure_key(&neighbour_key, orig_table, idx_table, self)?;
                 let neighbour_dist = vec_cache.v_dist(q, &neighbour_key);
                 let (_, OrderedFloat(cand_furtherest_dist)) = found_nn.peek().unwrap();
                 if found_nn.len() < ef || neighbour_dist < *cand_furtherest_dist {
@@ -585,6 +692,153 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Searches the bottom level for the `ef` nearest vectors satisfying `pred`, which returns
+    /// the tuple of the base relation for the vectors it accepts. Rejected vectors are still
+    /// traversed to keep the graph connected, and the search is only bounded by the accepted
+    /// ones, so that it widens as the predicate becomes more selective. Gives up and
+    /// returns `None` after visiting `max_visits` vectors. With quantization, the distances
+    /// given to `pred` and returned are those between the quantized vectors.
+    fn hnsw_search_level_filtered(
+        &self,
+        q: &DataValue,
+        ef: usize,
+        max_visits: usize,
+        entry: (CompoundKey, f64),
+        orig_table: &RelationHandle,
+        idx_table: &RelationHandle,
+        pred: &mut impl FnMut(&CompoundKey, f64) -> Result<Option<Tuple>>,
+        vec_cache: &mut VectorCache<'_>,
+    ) -> Result<Option<Vec<(CompoundKey, f64, Tuple)>>> {
+        let mut visited: FxHashSet<CompoundKey> = FxHashSet::default();
+        // min queue
+        let mut candidates: PriorityQueue<CompoundKey, Reverse<OrderedFloat<f64>>> =
+            PriorityQueue::new();
+        // max queue of the accepted vectors
+        let mut found: PriorityQueue<CompoundKey, OrderedFloat<f64>> = PriorityQueue::new();
+        let mut found_tuples: FxHashMap<CompoundKey, Tuple> = FxHashMap::default();
+
+        let (entry_key, entry_dist) = entry;
+        if let Some(tuple) = pred(&entry_key, entry_dist)? {
+            found.push(entry_key.clone(), OrderedFloat(entry_dist));
+            found_tuples.insert(entry_key.clone(), tuple);
+        }
+        visited.insert(entry_key.clone());
+        candidates.push(entry_key, Reverse(OrderedFloat(entry_dist)));
+
+        while let Some((candidate, Reverse(OrderedFloat(candidate_dist)))) = candidates.pop() {
+            if found.len() >= ef {
+                let (_, OrderedFloat(furtherest_dist)) = found.peek().unwrap();
+                if candidate_dist > *furtherest_dist {
+                    break;
+                }
+            }
+            for (neighbour_key, _) in self.hnsw_get_neighbours(&candidate, 0, idx_table, false)? {
+                if visited.contains(&neighbour_key) {
+                    continue;
+                }
+                if visited.len() >= max_visits {
+                    return Ok(None);
+                }
+                visited.insert(neighbour_key.clone());
+                vec_cache.ensure_key(&neighbour_key, orig_table, idx_table, self)?;
+                let neighbour_dist = vec_cache.v_dist(q, &neighbour_key);
+                if found.len() >= ef {
+                    let (_, OrderedFloat(furtherest_dist)) = found.peek().unwrap();
+                    if neighbour_dist >= *furtherest_dist {
+                        continue;
+                    }
+                }
+                candidates.push(neighbour_key.clone(), Reverse(OrderedFloat(neighbour_dist)));
+                if let Some(tuple) = pred(&neighbour_key, neighbour_dist)? {
+                    found.push(neighbour_key.clone(), OrderedFloat(neighbour_dist));
+                    found_tuples.insert(neighbour_key, tuple);
+                    if found.len() > ef {
+                        let (evicted, _) = found.pop().unwrap();
+                        found_tuples.remove(&evicted);
+                    }
+                }
+            }
+        }
+
+        let mut ret = vec![];
+        while let Some((key, OrderedFloat(distance))) = found.pop() {
+            let tuple = found_tuples.remove(&key).unwrap();
+            ret.push((key, distance, tuple));
+        }
+        // nearest first
+        ret.reverse();
+        Ok(Some(ret))
+    }
+    /// Exact search over all the vectors of the index satisfying the filter,
+    /// by scanning the base relation.
+    fn hnsw_brute_force_knn(
+        &self,
+        q: &DataValue,
+        config: &HnswSearch,
+        filter: &[Bytecode],
+        filter_span: SourceSpan,
+        stack: &mut Vec<DataValue>,
+        vec_cache: &VectorCache<'_>,
+    ) -> Result<Vec<(CompoundKey, f64, Tuple)>> {
+        let index_filter = match &config.manifest.index_filter {
+            None => None,
+            Some(f_code) => {
+                let parsed = CozoScriptParser::parse(Rule::expr, f_code)
+                    .into_diagnostic()?
+                    .next()
+                    .unwrap();
+                let mut code_expr = build_expr(parsed, &Default::default())?;
+                let binding_map = config.base_handle.raw_binding_map();
+                code_expr.fill_binding_indices(&binding_map)?;
+                Some(code_expr.compile()?)
+            }
+        };
+        let n_keys = config.base_handle.metadata.keys.len();
+        // max queue
+        let mut found: PriorityQueue<CompoundKey, OrderedFloat<f64>> = PriorityQueue::new();
+        let mut found_tuples: FxHashMap<CompoundKey, Tuple> = FxHashMap::default();
+        for tuple in config.base_handle.scan_all(self) {
+            let tuple = tuple?;
+            if let Some(code) = &index_filter {
+                if !eval_bytecode_pred(code, &tuple, stack, Default::default())? {
+                    continue;
+                }
+            }
+            for (v, idx, subidx) in extract_indexed_vectors(&config.manifest, &tuple) {
+                let distance = vec_cache.dist(q, v);
+                if let Some(r) = config.radius {
+                    if distance > r {
+                        continue;
+                    }
+                }
+                if found.len() >= config.k {
+                    let (_, OrderedFloat(furtherest_dist)) = found.peek().unwrap();
+                    if distance >= *furtherest_dist {
+                        continue;
+                    }
+                }
+                let key = (tuple[..n_keys].to_vec(), idx, subidx);
+                let bound = bind_search_outputs(config, &key, distance, tuple.clone())?;
+                if !eval_bytecode_pred(filter, &bound, stack, filter_span)? {
+                    continue;
+                }
+                found.push(key.clone(), OrderedFloat(distance));
+                found_tuples.insert(key, tuple.clone());
+                if found.len() > config.k {
+                    let (evicted, _) = found.pop().unwrap();
+                    found_tuples.remove(&evicted);
+                }
+            }
+        }
+        let mut ret = vec![];
+        while let Some((key, OrderedFloat(distance))) = found.pop() {
+            let tuple = found_tuples.remove(&key).unwrap();
+            ret.push((key, distance, tuple));
+        }
+        // nearest first
+        ret.reverse();
+        Ok(ret)
+    }
     fn hnsw_get_neighbours<'b>(
         &'b self,
         cand_key: &'b CompoundKey,
@@ -630,6 +884,7 @@ impl<'a> SessionTx<'a> {
     fn hnsw_put_fresh_at_levels(
         &mut self,
         hash: &[u8],
+        code: Option<&[u8]>,
         tuple: &[DataValue],
         idx: usize,
         subidx: i32,
@@ -650,11 +905,14 @@ impl<'a> SessionTx<'a> {
             canary_key.push(DataValue::Null);
             canary_key.push(DataValue::Null);
         }
-        let target_value = [
+        let mut target_value = vec![
             DataValue::from(0.0),
             DataValue::Bytes(hash.to_vec()),
             DataValue::from(false),
         ];
+        if let Some(code) = code {
+            target_value.push(DataValue::Bytes(code.to_vec()));
+        }
         let target_key_bytes = idx_table.encode_key_for_store(&target_key, Default::default())?;
 
         // canary value is for conflict detection: prevent the scenario of disconnected graphs at all levels
@@ -691,25 +949,15 @@ impl<'a> SessionTx<'a> {
                 return Ok(false);
             }
         }
-        let mut extracted_vectors = vec![];
-        for idx in &manifest.vec_fields {
-            let val = tuple.get(*idx).unwrap();
-            if let DataValue::Vec(v) = val {
-                extracted_vectors.push((v, *idx, -1));
-            } else if let DataValue::List(l) = val {
-                for (sidx, v) in l.iter().enumerate() {
-                    if let DataValue::Vec(v) = v {
-                        extracted_vectors.push((v, *idx, sidx as i32));
-                    }
-                }
-            }
-        }
+        let extracted_vectors = extract_indexed_vectors(manifest, tuple);
         if extracted_vectors.is_empty() {
             return Ok(false);
         }
         let mut vec_cache = VectorCache {
             cache: FxHashMap::default(),
             distance: manifest.distance,
+            quantizer: manifest.quantizer.as_ref(),
+            dtype: manifest.dtype,
         };
         for (vec, idx, sub) in extracted_vectors {
             self.hnsw_put_vector(
@@ -725,6 +973,85 @@ impl<'a> SessionTx<'a> {
         }
         Ok(true)
     }
+    /// Builds the index over all of `tuples` at once, for a newly created index.
+    /// The graph is constructed in memory, in parallel if possible, and the index
+    /// relation is then written in one batch, which is much faster than `hnsw_put`
+    /// for each tuple.
+    pub(crate) fn hnsw_bulk_build(
+        &mut self,
+        manifest: &HnswIndexManifest,
+        orig_table: &RelationHandle,
+        idx_table: &RelationHandle,
+        filter: Option<&Vec<Bytecode>>,
+        tuples: impl Iterator<Item = Tuple>,
+        poison: &Poison,
+        progress: &Progress,
+    ) -> Result<()> {
+        let mut stack = vec![];
+        let mut nodes = vec![];
+        for tuple in tuples {
+            if let Some(code) = filter {
+                if !eval_bytecode_pred(code, &tuple, &mut stack, Default::default())? {
+                    continue;
+                }
+            }
+            let tuple_key = &tuple[..orig_table.metadata.keys.len()];
+            for (vec, idx, subidx) in extract_indexed_vectors(manifest, &tuple) {
+                let code = match (&manifest.quantizer, vec) {
+                    (Some(quantizer), DataValue::Vec(v)) => Some(quantizer.encode(v)),
+                    _ => None,
+                };
+                let cached = match (&manifest.quantizer, &code) {
+                    (Some(quantizer), Some(code)) => {
+                        DataValue::Vec(quantizer.decode(code, manifest.dtype))
+                    }
+                    _ => vec.clone(),
+                };
+                let level = manifest.get_random_level();
+                nodes.push(BulkNode {
+                    key: (tuple_key.to_vec(), idx, subidx),
+                    vec: cached,
+                    hash: vector_hash(vec),
+                    code,
+                    level,
+                    links: (level..=0).map(|_| RwLock::new(vec![])).collect(),
+                });
+            }
+        }
+        if nodes.is_empty() {
+            return Ok(());
+        }
+        progress.set_total(nodes.len());
+        let builder = BulkBuilder {
+            manifest,
+            entry: RwLock::new((0, nodes[0].level)),
+            nodes,
+            metric: VectorCache {
+                cache: FxHashMap::default(),
+                distance: manifest.distance,
+                quantizer: None,
+                dtype: manifest.dtype,
+            },
+        };
+        progress.inc();
+        let insert = |i| -> Result<()> {
+            poison.check()?;
+            builder.insert(i);
+            progress.inc();
+            Ok(())
+        };
+        #[cfg(feature = "rayon")]
+        (1..builder.nodes.len())
+            .into_par_iter()
+            .try_for_each(insert)?;
+        #[cfg(not(feature = "rayon"))]
+        (1..builder.nodes.len()).try_for_each(insert)?;
+
+        let rows = builder.into_rows(idx_table)?;
+        self.store_tx
+            .batch_put(Box::new(rows.into_iter().map(Ok)))?;
+        Ok(())
+    }
     pub(crate) fn hnsw_remove(
         &mut self,
         orig_table: &RelationHandle,
@@ -868,24 +1195,40 @@ impl<'a> SessionTx<'a> {
     }
     pub(crate) fn hnsw_knn(
         &self,
-        q: Vector,
+        q: DataValue,
         config: &HnswSearch,
         filter_bytecode: &Option<(Vec<Bytecode>, SourceSpan)>,
         stack: &mut Vec<DataValue>,
     ) -> Result<Vec<Tuple>> {
-        if q.len() != config.manifest.vec_dim {
-            bail!("query vector dimension mismatch");
-        }
-        let q = match (q, config.manifest.dtype) {
-            (v @ Vector::F32(_), VecElementType::F32) => v,
-            (v @ Vector::F64(_), VecElementType::F64) => v,
-            (Vector::F32(v), VecElementType::F64) => Vector::F64(v.mapv(|x| x as f64)),
-            (Vector::F64(v), VecElementType::F32) => Vector::F32(v.mapv(|x| x as f32)),
+        let q = if config.manifest.distance.is_binary() {
+            let typing = NullableColType {
+                coltype: ColType::BitVec {
+                    len: config.manifest.vec_dim,
+                },
+                nullable: false,
+            };
+            typing.coerce(q, TERMINAL_VALIDITY.timestamp)?
+        } else {
+            let q = match q {
+                DataValue::Vec(v) => v,
+                d => bail!("Expected vector, got {:?}", d),
+            };
+            if q.len() != config.manifest.vec_dim {
+                bail!("query vector dimension mismatch");
+            }
+            DataValue::Vec(match (q, config.manifest.dtype) {
+                (v @ Vector::F32(_), VecElementType::F32) => v,
+                (v @ Vector::F64(_), VecElementType::F64) => v,
+                (Vector::F32(v), VecElementType::F64) => Vector::F64(v.mapv(|x| x as f64)),
+                (Vector::F64(v), VecElementType::F32) => Vector::F32(v.mapv(|x| x as f32)),
+            })
         };
 
         let mut vec_cache = VectorCache {
             cache: Default::default(),
             distance: config.manifest.distance,
+            quantizer: config.manifest.quantizer.as_ref(),
+            dtype: config.manifest.dtype,
         };
 
         let ep_res = config
@@ -912,7 +1255,7 @@ impl<'a> SessionTx<'a> {
                 .get_int()
                 .unwrap() as i32;
             let ep_key = (ep_t_key, ep_idx, ep_subidx);
-            vec_cache.ensure_key(&ep_key, &config.base_handle, self)?;
+            vec_cache.ensure_key(&ep_key, &config.base_handle, &config.idx_handle, self)?;
             let ep_distance = vec_cache.v_dist(&q, &ep_key);
             let mut found_nn = PriorityQueue::new();
             found_nn.push(ep_key, OrderedFloat(ep_distance));
@@ -927,72 +1270,111 @@ impl<'a> SessionTx<'a> {
                     &mut vec_cache,
                 )?;
             }
-            self.hnsw_search_level(
-                &q,
-                config.ef,
-                0,
-                &config.base_handle,
-                &config.idx_handle,
-                &mut found_nn,
-                &mut vec_cache,
-            )?;
-            if found_nn.is_empty() {
-                return Ok(vec![]);
-            }
+            let mut candidates = match filter_bytecode {
+                None => {
+                    self.hnsw_search_level(
+                        &q,
+                        config.ef,
+                        0,
+                        &config.base_handle,
+                        &config.idx_handle,
+                        &mut found_nn,
+                        &mut vec_cache,
+                    )?;
+                    // with quantization, all candidates are kept for re-ranking
+                    if config.manifest.quantizer.is_none() {
+                        while found_nn.len() > config.k {
+                            found_nn.pop();
+                        }
+                    }
+                    let mut candidates = vec![];
+                    while let Some((cand_key, OrderedFloat(distance))) = found_nn.pop() {
+                        if config.manifest.quantizer.is_none() {
+                            if let Some(r) = config.radius {
+                                if distance > r {
+                                    continue;
+                                }
+                            }
+                        }
+                        let cand_tuple = config
+                            .base_handle
+                            .get(self, &cand_key.0)?
+                            .ok_or_else(|| miette!("corrupted index"))?;
+                        candidates.push((cand_key, distance, cand_tuple));
+                    }
+                    // nearest first
+                    candidates.reverse();
+                    candidates
+                }
+                Some((code, span)) => {
+                    let ef = max(config.ef, config.k);
+                    let (ep_key, OrderedFloat(ep_distance)) = found_nn.pop().unwrap();
+                    let exact = VectorCache {
+                        cache: Default::default(),
+                        distance: config.manifest.distance,
+                        quantizer: None,
+                        dtype: config.manifest.dtype,
+                    };
+                    let mut pred = |cand_key: &CompoundKey, distance: f64| -> Result<_> {
+                        let cand_tuple = config
+                            .base_handle
+                            .get(self, &cand_key.0)?
+                            .ok_or_else(|| miette!("corrupted index"))?;
+                        // the filter sees the same distances as the results
+                        let distance = if config.manifest.quantizer.is_some() {
+                            exact.dist(&q, extract_vector(&cand_tuple, cand_key)?)
+                        } else {
+                            distance
+                        };
+                        let bound =
+                            bind_search_outputs(config, cand_key, distance, cand_tuple.clone())?;
+                        Ok(if eval_bytecode_pred(code, &bound, stack, *span)? {
+                            Some(cand_tuple)
+                        } else {
+                            None
+                        })
+                    };
+                    let found = self.hnsw_search_level_filtered(
+                        &q,
+                        ef,
+                        ef * FILTERED_SEARCH_MAX_EXPANSION,
+                        (ep_key, ep_distance),
+                        &config.base_handle,
+                        &config.idx_handle,
+                        &mut pred,
+                        &mut vec_cache,
+                    )?;
+                    match found {
+                        Some(found) => found,
+                        None => {
+                            self.hnsw_brute_force_knn(&q, config, code, *span, stack, &vec_cache)?
+                        }
+                    }
+                }
+            };
 
-            if config.filter.is_none() {
-                while found_nn.len() > config.k {
-                    found_nn.pop();
+            if config.manifest.quantizer.is_some() {
+                for (cand_key, distance, cand_tuple) in candidates.iter_mut() {
+                    let field = match extract_vector(cand_tuple, cand_key)? {
+                        v @ DataValue::Vec(_) => v,
+                        v => bail!("corrupted index value {:?}", v),
+                    };
+                    *distance = vec_cache.dist(&q, field);
                 }
+                candidates.sort_by(|(_, a, _), (_, b, _)| a.total_cmp(b));
+            }
+            if let Some(r) = config.radius {
+                candidates.retain(|(_, distance, _)| *distance <= r);
             }
 
             let mut ret = vec![];
 
-            while let Some((cand_key, OrderedFloat(distance))) = found_nn.pop() {
-                if let Some(r) = config.radius {
-                    if distance > r {
-                        continue;
-                    }
+            for (cand_key, distance, cand_tuple) in candidates {
+                if ret.len() >= config.k {
+                    break;
                 }
 
-                let mut cand_tuple = config
-                    .base_handle
-                    .get(self, &cand_key.0)?
-                    .ok_or_else(|| miette!("corrupted index"))?;
-
-                // make sure the order is the same as in all_bindings()!!!
-                if config.bind_field.is_some() {
-                    let field = if cand_key.1 < config.base_handle.metadata.keys.len() {
-                        config.base_handle.metadata.keys[cand_key.1].name.clone()
-                    } else {
-                        config.base_handle.metadata.non_keys
-                            [cand_key.1 - config.base_handle.metadata.keys.len()]
-                        .name
-                        .clone()
-                    };
-                    cand_tuple.push(DataValue::Str(field));
-                }
-                if config.bind_field_idx.is_some() {
-                    cand_tuple.push(if cand_key.2 < 0 {
-                        DataValue::Null
-                    } else {
-                        DataValue::from(cand_key.2 as i64)
-                    });
-                }
-                if config.bind_distance.is_some() {
-                    cand_tuple.push(DataValue::from(distance));
-                }
-                if config.bind_vector.is_some() {
-                    let vec = if cand_key.2 < 0 {
-                        cand_tuple[cand_key.1].clone()
-                    } else {
-                        match &cand_tuple[cand_key.1] {
-                            DataValue::List(v) => v[cand_key.2 as usize].clone(),
-                            v => bail!("corrupted index value {:?}", v),
-                        }
-                    };
-                    cand_tuple.push(vec);
-                }
+                let cand_tuple = bind_search_outputs(config, &cand_key, distance, cand_tuple)?;
 
                 if let Some((code, span)) = filter_bytecode {
                     if !eval_bytecode_pred(code, &cand_tuple, stack, *span)? {
@@ -1002,8 +1384,6 @@ impl<'a> SessionTx<'a> {
 
                 ret.push(cand_tuple);
             }
-            ret.reverse();
-            ret.truncate(config.k);
 
             Ok(ret)
         } else {
@@ -1012,6 +1392,365 @@ impl<'a> SessionTx<'a> {
     }
 }
 
+/// A vector in the in-memory graph of a bulk build
+struct BulkNode {
+    key: CompoundKey,
+    /// The vector as seen by the distances, i.e. decoded from `code` if quantized
+    vec: DataValue,
+    hash: Vec<u8>,
+    code: Option<Vec<u8>>,
+    level: i64,
+    /// The neighbours with their distances at each level, indexed by the negated level
+    links: Vec<RwLock<Vec<(usize, f64)>>>,
+}
+
+/// In-memory HNSW graph for building an index over existing data. The insertion of each
+/// vector follows `hnsw_put_vector`, but vectors may be inserted from several threads at once.
+struct BulkBuilder<'a> {
+    manifest: &'a HnswIndexManifest,
+    nodes: Vec<BulkNode>,
+    metric: VectorCache<'a>,
+    /// The entry point and its level
+    entry: RwLock<(usize, i64)>,
+}
+
+impl BulkBuilder<'_> {
+    fn dist(&self, a: usize, b: usize) -> f64 {
+        self.metric.dist(&self.nodes[a].vec, &self.nodes[b].vec)
+    }
+    fn neighbours(&self, node: usize, level: i64) -> Vec<(usize, f64)> {
+        self.nodes[node].links[(-level) as usize]
+            .read()
+            .unwrap()
+            .clone()
+    }
+    fn insert(&self, target: usize) {
+        let level = self.nodes[target].level;
+        let (ep, ep_level) = *self.entry.read().unwrap();
+        // max queue
+        let mut found_nn = PriorityQueue::new();
+        found_nn.push(ep, OrderedFloat(self.dist(target, ep)));
+        for current_level in ep_level..level {
+            self.search_level(target, 1, current_level, &mut found_nn);
+        }
+        for current_level in max(level, ep_level)..=0 {
+            let m_max = if current_level == 0 {
+                self.manifest.m_max0
+            } else {
+                self.manifest.m_max
+            };
+            self.search_level(
+                target,
+                self.manifest.ef_construction,
+                current_level,
+                &mut found_nn,
+            );
+            let candidates = found_nn.iter().map(|(n, d)| (*n, d.0)).collect_vec();
+            let neighbours = self.select_neighbours_heuristic(
+                target,
+                candidates,
+                m_max,
+                current_level,
+                self.manifest.extend_candidates,
+            );
+            *self.nodes[target].links[(-current_level) as usize]
+                .write()
+                .unwrap() = neighbours.clone();
+            for (neighbour, dist) in neighbours {
+                let mut links = self.nodes[neighbour].links[(-current_level) as usize]
+                    .write()
+                    .unwrap();
+                links.push((target, dist));
+                if links.len() > m_max {
+                    // shrink links, without extending the candidates so that no other
+                    // lock is taken while holding this one
+                    let candidates = mem::take(&mut *links);
+                    *links = self.select_neighbours_heuristic(
+                        neighbour,
+                        candidates,
+                        m_max,
+                        current_level,
+                        false,
+                    );
+                }
+            }
+        }
+        if level < ep_level {
+            let mut entry = self.entry.write().unwrap();
+            if level < entry.1 {
+                *entry = (target, level);
+            }
+        }
+    }
+    fn search_level(
+        &self,
+        q: usize,
+        ef: usize,
+        cur_level: i64,
+        found_nn: &mut PriorityQueue<usize, OrderedFloat<f64>>,
+    ) {
+        let mut visited: FxHashSet<usize> = FxHashSet::default();
+        visited.insert(q);
+        // min queue
+        let mut candidates: PriorityQueue<usize, Reverse<OrderedFloat<f64>>> = PriorityQueue::new();
+
+        for (item, dist) in found_nn.iter() {
+            visited.insert(*item);
+            candidates.push(*item, Reverse(*dist));
+        }
+
+        while let Some((candidate, Reverse(OrderedFloat(candidate_dist)))) = candidates.pop() {
+            let (_, OrderedFloat(furtherest_dist)) = found_nn.peek().unwrap();
+            if candidate_dist > *furtherest_dist {
+                break;
+            }
+            for (neighbour, _) in self.neighbours(candidate, cur_level) {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let neighbour_dist = self.dist(q, neighbour);
+                let (_, OrderedFloat(cand_furtherest_dist)) = found_nn.peek().unwrap();
+                if found_nn.len() < ef || neighbour_dist < *cand_furtherest_dist {
+                    candidates.push(neighbour, Reverse(OrderedFloat(neighbour_dist)));
+                    found_nn.push(neighbour, OrderedFloat(neighbour_dist));
+                    if found_nn.len() > ef {
+                        found_nn.pop();
+                    }
+                }
+            }
+        }
+    }
+    fn select_neighbours_heuristic(
+        &self,
+        q: usize,
+        found: Vec<(usize, f64)>,
+        m: usize,
+        level: i64,
+        extend_candidates: bool,
+    ) -> Vec<(usize, f64)> {
+        let mut candidates: PriorityQueue<usize, Reverse<OrderedFloat<f64>>> = PriorityQueue::new();
+        for (item, dist) in &found {
+            candidates.push(*item, Reverse(OrderedFloat(*dist)));
+        }
+        if extend_candidates {
+            for (item, _) in &found {
+                for (neighbour, _) in self.neighbours(*item, level) {
+                    if neighbour != q {
+                        let dist = self.dist(q, neighbour);
+                        candidates.push(neighbour, Reverse(OrderedFloat(dist)));
+                    }
+                }
+            }
+        }
+        let mut ret: Vec<(usize, f64)> = vec![];
+        let mut discarded = vec![];
+        while ret.len() < m {
+            let Some((cand, Reverse(OrderedFloat(cand_dist_to_q)))) = candidates.pop() else {
+                break;
+            };
+            if ret
+                .iter()
+                .all(|(existing, _)| self.dist(*existing, cand) >= cand_dist_to_q)
+            {
+                ret.push((cand, cand_dist_to_q));
+            } else if self.manifest.keep_pruned_connections {
+                discarded.push((cand, cand_dist_to_q));
+            }
+        }
+        // `discarded` is already sorted by the distance to `q`
+        for pruned in discarded {
+            if ret.len() >= m {
+                break;
+            }
+            ret.push(pruned);
+        }
+        ret
+    }
+    /// The rows of the index relation, encoded and sorted. The rows are the same as those
+    /// written by `hnsw_put_vector`: a link that is only kept in one direction is stored as
+    /// ignored in the other direction, so that removals can find it.
+    fn into_rows(self, idx_table: &RelationHandle) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+        let (ep, ep_level) = self.entry.into_inner().unwrap();
+        let nodes = self
+            .nodes
+            .into_iter()
+            .map(|node| {
+                let links = node
+                    .links
+                    .into_iter()
+                    .map(|l| l.into_inner().unwrap())
+                    .collect_vec();
+                (node.key, node.hash, node.code, links)
+            })
+            .collect_vec();
+        let mut rows = BTreeMap::new();
+        let mut reverse_links = vec![];
+        for (i, (key, hash, code, links)) in nodes.iter().enumerate() {
+            for (neg_level, neighbours) in links.iter().enumerate() {
+                let level = -(neg_level as i64);
+                let mut self_val = vec![
+                    DataValue::from(neighbours.len() as f64),
+                    DataValue::Bytes(hash.clone()),
+                    DataValue::from(false),
+                ];
+                if let Some(code) = code {
+                    self_val.push(DataValue::Bytes(code.clone()));
+                }
+                rows.insert(
+                    idx_table
+                        .encode_key_for_store(&link_key(level, key, key), Default::default())?,
+                    idx_table.encode_val_only_for_store(&self_val, Default::default())?,
+                );
+                for (neighbour, dist) in neighbours {
+                    let val = vec![
+                        DataValue::from(*dist),
+                        DataValue::Null,
+                        DataValue::from(false),
+                    ];
+                    rows.insert(
+                        idx_table.encode_key_for_store(
+                            &link_key(level, key, &nodes[*neighbour].0),
+                            Default::default(),
+                        )?,
+                        idx_table.encode_val_only_for_store(&val, Default::default())?,
+                    );
+                    reverse_links.push((level, *neighbour, i, *dist));
+                }
+            }
+        }
+        for (level, from, to, dist) in reverse_links {
+            let key = idx_table.encode_key_for_store(
+                &link_key(level, &nodes[from].0, &nodes[to].0),
+                Default::default(),
+            )?;
+            if let btree_map::Entry::Vacant(entry) = rows.entry(key) {
+                let val = vec![
+                    DataValue::from(dist),
+                    DataValue::Null,
+                    DataValue::from(true),
+                ];
+                entry.insert(idx_table.encode_val_only_for_store(&val, Default::default())?);
+            }
+        }
+
+        // canary value is for conflict detection: prevent the scenario of disconnected graphs at all levels
+        let ep_key = &nodes[ep].0;
+        let mut canary_key = vec![DataValue::from(1)];
+        for _ in 0..2 {
+            for _ in 0..ep_key.0.len() {
+                canary_key.push(DataValue::Null);
+            }
+            canary_key.push(DataValue::Null);
+            canary_key.push(DataValue::Null);
+        }
+        let ep_key_bytes = idx_table
+            .encode_key_for_store(&link_key(ep_level, ep_key, ep_key), Default::default())?;
+        let canary_value = [
+            DataValue::from(ep_level),
+            DataValue::Bytes(ep_key_bytes),
+            DataValue::from(false),
+        ];
+        rows.insert(
+            idx_table.encode_key_for_store(&canary_key, Default::default())?,
+            idx_table.encode_val_only_for_store(&canary_value, Default::default())?,
+        );
+        Ok(rows)
+    }
+}
+
+/// Key of the link from `from` to `to` in the index relation, which is a self-link if they are equal
+fn link_key(level: i64, from: &CompoundKey, to: &CompoundKey) -> Vec<DataValue> {
+    let mut key = Vec::with_capacity(from.0.len() * 2 + 5);
+    key.push(DataValue::from(level));
+    for k in [from, to] {
+        key.extend_from_slice(&k.0);
+        key.push(DataValue::from(k.1 as i64));
+        key.push(DataValue::from(k.2 as i64));
+    }
+    key
+}
+
+/// The vectors of `tuple` that are indexed, with their field and their position in the field
+fn extract_indexed_vectors<'a>(
+    manifest: &HnswIndexManifest,
+    tuple: &'a [DataValue],
+) -> Vec<(&'a DataValue, usize, i32)> {
+    let mut extracted_vectors = vec![];
+    for idx in &manifest.vec_fields {
+        let val = tuple.get(*idx).unwrap();
+        match val {
+            DataValue::Vec(_) | DataValue::Bytes(_) => {
+                extracted_vectors.push((val, *idx, -1));
+            }
+            DataValue::List(l) => {
+                for (sidx, v) in l.iter().enumerate() {
+                    if let DataValue::Vec(_) | DataValue::Bytes(_) = v {
+                        extracted_vectors.push((v, *idx, sidx as i32));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    extracted_vectors
+}
+
+/// Appends the values bound by the search to a tuple of the base relation.
+/// The order must be the same as in `all_bindings()`!
+fn bind_search_outputs(
+    config: &HnswSearch,
+    cand_key: &CompoundKey,
+    distance: f64,
+    mut cand_tuple: Tuple,
+) -> Result<Tuple> {
+    if config.bind_field.is_some() {
+        let field = if cand_key.1 < config.base_handle.metadata.keys.len() {
+            config.base_handle.metadata.keys[cand_key.1].name.clone()
+        } else {
+            config.base_handle.metadata.non_keys
+                [cand_key.1 - config.base_handle.metadata.keys.len()]
+            .name
+            .clone()
+        };
+        cand_tuple.push(DataValue::Str(field));
+    }
+    if config.bind_field_idx.is_some() {
+        cand_tuple.push(if cand_key.2 < 0 {
+            DataValue::Null
+        } else {
+            DataValue::from(cand_key.2 as i64)
+        });
+    }
+    if config.bind_distance.is_some() {
+        cand_tuple.push(DataValue::from(distance));
+    }
+    if config.bind_vector.is_some() {
+        let vec = extract_vector(&cand_tuple, cand_key)?.clone();
+        cand_tuple.push(vec);
+    }
+    Ok(cand_tuple)
+}
+
+/// Hash of an indexed vector, used to skip the re-indexing of unchanged vectors
+fn vector_hash(v: &DataValue) -> Vec<u8> {
+    match v {
+        DataValue::Vec(v) => v.get_hash().as_ref().to_vec(),
+        DataValue::Bytes(b) => Sha256::digest(b).to_vec(),
+        v => panic!("Cannot hash {:?} as vector", v),
+    }
+}
+
+fn extract_vector<'a>(tuple: &'a [DataValue], key: &CompoundKey) -> Result<&'a DataValue> {
+    Ok(if key.2 < 0 {
+        &tuple[key.1]
+    } else {
+        match &tuple[key.1] {
+            DataValue::List(v) => &v[key.2 as usize],
+            v => bail!("corrupted index value {:?}", v),
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use rand::Rng;
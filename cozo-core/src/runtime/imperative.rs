@@ -291,6 +291,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             let q_handle = RunningQueryHandle {
                 started_at: since_the_epoch,
                 poison: poison.clone(),
+                progress: Default::default(),
             };
             self.running_queries.lock().unwrap().insert(qid, q_handle);
             let _guard = RunningQueryCleanup {
@@ -29,37 +29,38 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn del_lsh_index_item(
         &mut self,
         tuple: &[DataValue],
-        bytes: Option<Vec<Vec<u8>>>,
+        stored: Option<DataValue>,
         idx_handle: &RelationHandle,
         inv_idx_handle: &RelationHandle,
+        manifest: &MinHashLshIndexManifest,
     ) -> Result<()> {
-        let bytes = match bytes {
+        let key_part = &tuple[..inv_idx_handle.metadata.keys.len()];
+        let stored = match stored {
             None => {
-                if let Some(mut found) = inv_idx_handle.get_val_only(self, &tuple[..inv_idx_handle.metadata.keys.len()])? {
+                if let Some(mut found) = inv_idx_handle.get_val_only(self, key_part)? {
                     let inv_key = inv_idx_handle.encode_key_for_store(tuple, Default::default())?;
                     self.store_tx.del(&inv_key)?;
-                    match found.pop() {
-                        Some(DataValue::List(l)) => l
-                            .into_iter()
-                            .map(|chunk| match chunk {
-                                DataValue::Bytes(b) => b,
-                                _ => unreachable!(),
-                            })
-                            .collect_vec(),
-                        _ => unreachable!(),
-                    }
+                    found.pop().unwrap()
                 } else {
                     return Ok(());
                 }
             }
-            Some(b) => b,
+            Some(v) => v,
         };
 
-        let mut key = Vec::with_capacity(idx_handle.metadata.keys.len());
-        key.push(DataValue::Bot);
-        key.extend_from_slice(tuple);
-        for chunk in bytes {
-            key[0] = DataValue::Bytes(chunk);
+        let band_keys = match stored {
+            // written by older versions, which store the bands themselves
+            DataValue::List(l) => l.into_iter().map(|chunk| vec![chunk]).collect_vec(),
+            DataValue::Bytes(b) => {
+                let min_hash = HashValues::from_bytes(&b);
+                (0..manifest.n_bands)
+                    .map(|i| manifest.band_key(&min_hash, i, manifest.forest_depth))
+                    .collect_vec()
+            }
+            _ => unreachable!(),
+        };
+        for mut key in band_keys {
+            key.extend_from_slice(key_part);
             let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
             self.store_tx.del(&key_bytes)?;
         }
@@ -77,20 +78,10 @@ impl<'a> SessionTx<'a> {
         manifest: &MinHashLshIndexManifest,
         hash_perms: &HashPermutations,
     ) -> Result<()> {
-        if let Some(mut found) =
-            inv_idx_handle.get_val_only(self, &tuple[..rel_handle.metadata.keys.len()])?
-        {
-            let bytes = match found.pop() {
-                Some(DataValue::List(l)) => l
-                    .into_iter()
-                    .map(|chunk| match chunk {
-                        DataValue::Bytes(b) => b,
-                        _ => unreachable!(),
-                    })
-                    .collect_vec(),
-                _ => unreachable!(),
-            };
-            self.del_lsh_index_item(tuple, Some(bytes), idx_handle, inv_idx_handle)?;
+        let inv_key_part = &tuple[..rel_handle.metadata.keys.len()];
+        if let Some(mut found) = inv_idx_handle.get_val_only(self, inv_key_part)? {
+            let stored = found.pop().unwrap();
+            self.del_lsh_index_item(tuple, Some(stored), idx_handle, inv_idx_handle, manifest)?;
         }
         let to_index = eval_bytecode(extractor, tuple, stack)?;
         let min_hash = match to_index {
@@ -102,32 +93,16 @@ impl<'a> SessionTx<'a> {
             }
             _ => bail!("Cannot put value {:?} into a LSH index", to_index),
         };
-        let bytes = min_hash.get_bytes();
-
-        let chunk_size = manifest.n_rows_in_band * std::mem::size_of::<u32>();
-        let chunks = (0..manifest.n_bands)
-            .map(|i| {
-                let mut byte_range = bytes[i * chunk_size..(i + 1) * chunk_size].to_vec();
-                byte_range.extend_from_slice(&(i as u16).to_le_bytes());
-                byte_range
-            })
-            .collect_vec();
-
-        let inv_key_part = &tuple[..rel_handle.metadata.keys.len()];
-
-        let mut key = Vec::with_capacity(bytes.len() + 1);
-        key.push(DataValue::Bot);
-        key.extend_from_slice(inv_key_part);
 
-        for chunk in chunks.iter() {
-            key[0] = DataValue::Bytes(chunk.clone());
+        for i in 0..manifest.n_bands {
+            let mut key = manifest.band_key(&min_hash, i, manifest.forest_depth);
+            key.extend_from_slice(inv_key_part);
             let key_bytes = idx_handle.encode_key_for_store(&key, Default::default())?;
             self.store_tx.put(&key_bytes, &[])?;
         }
 
-        let inv_val_part = vec![DataValue::List(
-            chunks.into_iter().map(DataValue::Bytes).collect_vec(),
-        )];
+        // the whole minhash is kept for deletions and for computing similarities
+        let inv_val_part = vec![DataValue::Bytes(min_hash.get_bytes().to_vec())];
         let inv_key = inv_idx_handle.encode_key_for_store(inv_key_part, Default::default())?;
         let inv_val =
             inv_idx_handle.encode_val_only_for_store(&inv_val_part, Default::default())?;
@@ -144,47 +119,61 @@ impl<'a> SessionTx<'a> {
         perms: &HashPermutations,
         tokenizer: &TextAnalyzer,
     ) -> Result<Vec<Tuple>> {
-        let bytes = match q {
+        let min_hash = match q {
             DataValue::Null => {
                 return Ok(vec![]);
             }
-            DataValue::List(l) => HashValues::new(l.iter(), perms).get_bytes().to_vec(),
+            DataValue::List(l) => HashValues::new(l.iter(), perms),
             DataValue::Str(s) => {
                 let n_grams = tokenizer.unique_ngrams(s, config.manifest.n_gram);
-                HashValues::new(n_grams.iter(), perms).get_bytes().to_vec()
+                HashValues::new(n_grams.iter(), perms)
             }
             _ => bail!("Cannot search for value {:?} in a LSH index", q),
         };
-        let chunk_size = config.manifest.n_rows_in_band * std::mem::size_of::<u32>();
-        let mut key_prefix = Vec::with_capacity(1);
+        let n_keys = config.base_handle.metadata.keys.len();
+        let key_offset = config.idx_handle.metadata.keys.len() - n_keys;
         let mut found_tuples: FxHashSet<_> = FxHashSet::default();
-        let early_stopper = if filter_code.is_some() {
-            None
-        } else {
-            config.k
-        };
-        for (i, chunk) in bytes.chunks_exact(chunk_size).enumerate() {
-            key_prefix.clear();
-            let mut chunk = chunk.to_vec();
-            chunk.extend_from_slice(&(i as u16).to_le_bytes());
-            key_prefix.push(DataValue::Bytes(chunk));
+        for i in 0..config.params.b {
+            let key_prefix = config.manifest.band_key(&min_hash, i, config.params.r);
             for ks in config.idx_handle.scan_prefix(self, &key_prefix) {
                 let ks = ks?;
-                let key_part = &ks[1..];
-                found_tuples.insert(key_part.to_vec());
-                if let Some(k) = early_stopper {
-                    if found_tuples.len() >= k {
-                        break;
-                    }
-                }
+                found_tuples.insert(ks[key_offset..key_offset + n_keys].to_vec());
             }
         }
-        let mut ret = vec![];
+
+        // candidates are ranked by the similarity of their minhashes to that of the query
+        let rank = config.k.is_some() || config.rerank || config.bind_similarity.is_some();
+        let mut candidates = Vec::with_capacity(found_tuples.len());
         for key in found_tuples {
-            let orig_tuple = config
+            let similarity = if rank {
+                let stored = config
+                    .inv_idx_handle
+                    .get_val_only(self, &key)?
+                    .and_then(|mut v| v.pop())
+                    .ok_or_else(|| miette!("Tuple not found in inverse LSH index"))?;
+                let similarity = min_hash.jaccard(&HashValues::from_stored(&stored));
+                if config.rerank && similarity < config.threshold {
+                    continue;
+                }
+                similarity
+            } else {
+                0.
+            };
+            candidates.push((key, similarity));
+        }
+        if rank {
+            candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        }
+
+        let mut ret = vec![];
+        for (key, similarity) in candidates {
+            let mut orig_tuple = config
                 .base_handle
                 .get(self, &key)?
                 .ok_or_else(|| miette!("Tuple not found in base LSH relation"))?;
+            if config.bind_similarity.is_some() {
+                orig_tuple.push(DataValue::from(similarity));
+            }
             if let Some((filter_code, span)) = filter_code {
                 if !eval_bytecode_pred(filter_code, &orig_tuple, stack, *span)? {
                     continue;
@@ -205,17 +194,25 @@ impl<'a> SessionTx<'a> {
 pub(crate) struct LshSearch {
     pub(crate) base_handle: RelationHandle,
     pub(crate) idx_handle: RelationHandle,
+    pub(crate) inv_idx_handle: RelationHandle,
     pub(crate) manifest: MinHashLshIndexManifest,
     pub(crate) bindings: Vec<Symbol>,
+    /// Returns the `k` candidates most similar to the query
     pub(crate) k: Option<usize>,
     pub(crate) query: Symbol,
+    /// The bands and the rows in each band that are matched against the query
+    pub(crate) params: LshParams,
+    pub(crate) threshold: f64,
+    /// Drops the candidates whose estimated similarity is below `threshold`
+    pub(crate) rerank: bool,
+    pub(crate) bind_similarity: Option<Symbol>,
     pub(crate) filter: Option<Expr>,
     pub(crate) span: SourceSpan,
 }
 
 impl LshSearch {
     pub(crate) fn all_bindings(&self) -> impl Iterator<Item = &Symbol> {
-        self.bindings.iter()
+        self.bindings.iter().chain(self.bind_similarity.iter())
     }
 }
 
@@ -236,12 +233,66 @@ pub(crate) struct MinHashLshIndexManifest {
     pub(crate) n_rows_in_band: usize,
     pub(crate) threshold: f64,
     pub(crate) perms: Vec<u8>,
+    /// In the LSH Forest layout, each of the `n_bands` bands is a prefix tree of this
+    /// many hash values, and queries at any threshold can match a prefix of every band.
+    /// Zero for indices created by older versions, which can only match whole bands of
+    /// `n_rows_in_band` values.
+    #[serde(default)]
+    pub(crate) forest_depth: usize,
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_positive_weight: f64,
+    #[serde(default = "default_lsh_weight")]
+    pub(crate) false_negative_weight: f64,
+}
+
+fn default_lsh_weight() -> f64 {
+    0.5
 }
 
 impl MinHashLshIndexManifest {
     pub(crate) fn get_hash_perms(&self) -> HashPermutations {
         HashPermutations::from_bytes(&self.perms)
     }
+    /// The bands and rows to match for a query at `threshold`
+    pub(crate) fn query_params(&self, threshold: Option<f64>) -> Result<LshParams> {
+        Ok(match threshold {
+            None => LshParams {
+                b: self.n_bands,
+                r: self.n_rows_in_band,
+            },
+            Some(_) if self.forest_depth == 0 => bail!(
+                "LSH index {} was created by an older version and cannot be searched with \
+                 a `threshold`; drop and recreate the index to use query-time thresholds",
+                self.index_name
+            ),
+            Some(threshold) => LshParams::find_optimal_params_within(
+                threshold,
+                self.num_perm,
+                self.n_bands,
+                self.forest_depth,
+                &Weights(self.false_positive_weight, self.false_negative_weight),
+            ),
+        })
+    }
+    /// The leading key columns in the index relation for the first `n_rows` hash values of
+    /// the `band`-th band. Without the LSH Forest layout, only whole bands can be keyed.
+    fn band_key(&self, min_hash: &HashValues, band: usize, n_rows: usize) -> Vec<DataValue> {
+        if self.forest_depth == 0 {
+            let chunk_size = self.n_rows_in_band * std::mem::size_of::<u32>();
+            let mut chunk =
+                min_hash.get_bytes()[band * chunk_size..(band + 1) * chunk_size].to_vec();
+            chunk.extend_from_slice(&(band as u16).to_le_bytes());
+            vec![DataValue::Bytes(chunk)]
+        } else {
+            let start = band * self.forest_depth;
+            let mut key = Vec::with_capacity(n_rows + 1);
+            key.push(DataValue::from(band as i64));
+            for h in &min_hash.0[start..start + n_rows] {
+                key.push(DataValue::from(*h as i64));
+            }
+            key
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -258,11 +309,22 @@ const _ALLOWED_INTEGRATE_ERR: f64 = 0.001;
 // code is mostly from https://github.com/schelterlabs/rust-minhash/blob/81ea3fec24fd888a330a71b6932623643346b591/src/minhash_lsh.rs
 impl LshParams {
     pub fn find_optimal_params(threshold: f64, num_perm: usize, weights: &Weights) -> LshParams {
+        Self::find_optimal_params_within(threshold, num_perm, num_perm, num_perm, weights)
+    }
+
+    /// Same as `find_optimal_params`, but with at most `max_b` bands of at most `max_r` rows
+    pub fn find_optimal_params_within(
+        threshold: f64,
+        num_perm: usize,
+        max_b: usize,
+        max_r: usize,
+        weights: &Weights,
+    ) -> LshParams {
         let Weights(false_positive_weight, false_negative_weight) = weights;
         let mut min_error = f64::INFINITY;
         let mut opt = LshParams { b: 0, r: 0 };
-        for b in 1..num_perm + 1 {
-            let max_r = num_perm / b;
+        for b in 1..max_b + 1 {
+            let max_r = min(max_r, num_perm / b);
             for r in 1..max_r + 1 {
                 let false_pos = LshParams::false_positive_probability(threshold, b, r);
                 let false_neg = LshParams::false_negative_probability(threshold, b, r);
@@ -339,16 +401,41 @@ impl HashValues {
             }
         }
     }
-    #[cfg(test)]
-    pub(crate) fn jaccard(&self, other_minhash: &Self) -> f32 {
+    /// Estimate of the Jaccard similarity of the hashed sets
+    pub(crate) fn jaccard(&self, other_minhash: &Self) -> f64 {
         let matches = self
             .0
             .iter()
             .zip_eq(&other_minhash.0)
             .filter(|(left, right)| left == right)
             .count();
-        let result = matches as f32 / self.0.len() as f32;
-        result
+        matches as f64 / self.0.len() as f64
+    }
+    // this is the inverse of `get_bytes`
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Self(
+            bytes
+                .chunks_exact(std::mem::size_of::<u32>())
+                .map(|c| u32::from_ne_bytes(c.try_into().unwrap()))
+                .collect(),
+        )
+    }
+    /// The minhash stored in the inverse index. Older versions store the bands,
+    /// each followed by two bytes for the number of the band.
+    pub(crate) fn from_stored(stored: &DataValue) -> Self {
+        match stored {
+            DataValue::Bytes(b) => Self::from_bytes(b),
+            DataValue::List(chunks) => Self(
+                chunks
+                    .iter()
+                    .flat_map(|chunk| match chunk {
+                        DataValue::Bytes(b) => Self::from_bytes(&b[..b.len() - 2]).0,
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            ),
+            _ => unreachable!(),
+        }
     }
     pub(crate) fn get_bytes(&self) -> &[u8] {
         unsafe {
@@ -386,4 +473,49 @@ mod test {
         // println!("{:?}", m2.get_byte_chunks(2).collect_vec());
         assert_eq!(perms.0, HashPermutations::from_bytes(perms.as_bytes()).0);
     }
+
+    #[test]
+    fn test_params_within() {
+        let weights = Weights(0.5, 0.5);
+        let LshParams { b, r } = LshParams::find_optimal_params(0.8, 200, &weights);
+        let depth = 200 / b;
+        let low = LshParams::find_optimal_params_within(0.3, b * depth, b, depth, &weights);
+        let high = LshParams::find_optimal_params_within(0.95, b * depth, b, depth, &weights);
+        assert!(low.b <= b && high.b <= b);
+        assert!(low.r <= depth && high.r <= depth);
+        assert!(low.r < r && r <= high.r);
+        assert!(low.b >= high.b);
+        let m1 = HashValues::new([1, 2, 3].iter(), &HashPermutations::new(10));
+        assert_eq!(HashValues::from_bytes(m1.get_bytes()).0, m1.0);
+    }
+
+    #[test]
+    fn test_query_params() {
+        let mut manifest = MinHashLshIndexManifest {
+            base_relation: "a".into(),
+            index_name: "lsh".into(),
+            extractor: "v".to_string(),
+            n_gram: 1,
+            tokenizer: TokenizerConfig {
+                name: "Simple".into(),
+                args: vec![],
+            },
+            filters: vec![],
+            num_perm: 200,
+            n_bands: 20,
+            n_rows_in_band: 10,
+            threshold: 0.8,
+            perms: vec![],
+            forest_depth: 10,
+            false_positive_weight: 0.5,
+            false_negative_weight: 0.5,
+        };
+        let params = manifest.query_params(Some(0.3)).unwrap();
+        assert!(params.r < 10);
+        // indices without the forest layout can only be searched at their own threshold
+        manifest.forest_depth = 0;
+        let params = manifest.query_params(None).unwrap();
+        assert_eq!((params.b, params.r), (20, 10));
+        assert!(manifest.query_params(Some(0.3)).is_err());
+    }
 }
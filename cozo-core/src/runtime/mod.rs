@@ -6,13 +6,17 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+pub(crate) mod backup;
 pub(crate) mod callback;
 pub(crate) mod db;
+pub(crate) mod graph_cache;
 pub(crate) mod imperative;
 pub(crate) mod relation;
+pub(crate) mod replication;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
+pub(crate) mod quantization;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,227 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::data::relation::VecElementType;
+use crate::data::value::Vector;
+use miette::{ensure, Result};
+use ndarray::Array1;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Number of vectors sampled from the relation for training product quantization codebooks
+const PQ_TRAINING_SAMPLES: usize = 10000;
+const PQ_TRAINING_ITERATIONS: usize = 16;
+
+/// Compression of the vectors kept in an HNSW index. The compressed codes are
+/// stored in the index relation and are used to compute approximate distances
+/// during the graph search.
+///
+/// The full vectors stay in the base relation, as results are ranked by their exact
+/// distances, so the codes add to the disk use of the index instead of reducing it.
+/// What quantization saves is the memory and time spent on distances while searching.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum Quantizer {
+    /// Each component is mapped to one byte, linearly between the minimum
+    /// and the maximum component of the vector.
+    Int8,
+    /// The vector is cut into subspaces of `sub_dim` components, and each subspace
+    /// is represented by the index of its nearest centroid. The centroids of all subspaces
+    /// are stored one after the other in `codebooks`.
+    Product {
+        sub_dim: usize,
+        n_centroids: usize,
+        codebooks: Vec<f32>,
+    },
+}
+
+impl Quantizer {
+    /// Train product quantization codebooks with k-means on a random sample of `vectors`.
+    /// At most `PQ_TRAINING_SAMPLES` vectors are kept in memory at any time.
+    pub(crate) fn train_product(
+        vectors: impl Iterator<Item = Result<Vec<f32>>>,
+        dim: usize,
+        n_subspaces: usize,
+        n_centroids: usize,
+    ) -> Result<Self> {
+        let mut rng = rand::thread_rng();
+        // reservoir sampling
+        let mut samples = Vec::with_capacity(PQ_TRAINING_SAMPLES);
+        for (i, v) in vectors.enumerate() {
+            let v = v?;
+            if i < PQ_TRAINING_SAMPLES {
+                samples.push(v);
+            } else {
+                let j = rng.gen_range(0..=i);
+                if j < PQ_TRAINING_SAMPLES {
+                    samples[j] = v;
+                }
+            }
+        }
+        ensure!(
+            !samples.is_empty(),
+            "Product quantization requires existing vectors in the relation passing the index filter to train its codebooks"
+        );
+        let n_centroids = n_centroids.min(samples.len());
+        let sub_dim = dim / n_subspaces;
+        let mut codebooks = Vec::with_capacity(n_subspaces * n_centroids * sub_dim);
+        for s in 0..n_subspaces {
+            let range = s * sub_dim..(s + 1) * sub_dim;
+            let points = samples
+                .iter()
+                .map(|v| &v[range.clone()])
+                .collect::<Vec<_>>();
+            codebooks.extend(k_means(&points, n_centroids, sub_dim, &mut rng));
+        }
+        Ok(Quantizer::Product {
+            sub_dim,
+            n_centroids,
+            codebooks,
+        })
+    }
+
+    pub(crate) fn encode(&self, v: &Vector) -> Vec<u8> {
+        let v = to_f32(v);
+        match self {
+            Quantizer::Int8 => {
+                let min = v.iter().copied().fold(f32::INFINITY, f32::min);
+                let max = v.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let scale = if max > min { (max - min) / 255. } else { 1. };
+                let mut code = Vec::with_capacity(v.len() + 8);
+                code.extend_from_slice(&min.to_le_bytes());
+                code.extend_from_slice(&scale.to_le_bytes());
+                code.extend(v.iter().map(|x| ((x - min) / scale).round() as u8));
+                code
+            }
+            Quantizer::Product {
+                sub_dim,
+                n_centroids,
+                codebooks,
+            } => v
+                .chunks(*sub_dim)
+                .enumerate()
+                .map(|(s, sub)| {
+                    let codebook = &codebooks[s * n_centroids * sub_dim..];
+                    nearest_centroid(sub, codebook, *n_centroids) as u8
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn decode(&self, code: &[u8], dtype: VecElementType) -> Vector {
+        let v: Vec<f32> = match self {
+            Quantizer::Int8 => {
+                let min = f32::from_le_bytes(code[0..4].try_into().unwrap());
+                let scale = f32::from_le_bytes(code[4..8].try_into().unwrap());
+                code[8..].iter().map(|c| min + *c as f32 * scale).collect()
+            }
+            Quantizer::Product {
+                sub_dim,
+                n_centroids,
+                codebooks,
+            } => {
+                let mut v = Vec::with_capacity(code.len() * sub_dim);
+                for (s, c) in code.iter().enumerate() {
+                    let start = (s * n_centroids + *c as usize) * sub_dim;
+                    v.extend_from_slice(&codebooks[start..start + sub_dim]);
+                }
+                v
+            }
+        };
+        match dtype {
+            VecElementType::F32 => Vector::F32(Array1::from(v)),
+            VecElementType::F64 => Vector::F64(v.into_iter().map(|x| x as f64).collect()),
+        }
+    }
+}
+
+pub(crate) fn to_f32(v: &Vector) -> Vec<f32> {
+    match v {
+        Vector::F32(a) => a.to_vec(),
+        Vector::F64(a) => a.iter().map(|x| *x as f32).collect(),
+    }
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn nearest_centroid(point: &[f32], centroids: &[f32], n_centroids: usize) -> usize {
+    let dim = point.len();
+    (0..n_centroids)
+        .map(|i| squared_distance(point, &centroids[i * dim..(i + 1) * dim]))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// Lloyd's algorithm, started from distinct random points
+fn k_means(points: &[&[f32]], k: usize, dim: usize, rng: &mut impl Rng) -> Vec<f32> {
+    let mut centroids = points
+        .choose_multiple(rng, k)
+        .flat_map(|p| p.iter().copied())
+        .collect::<Vec<_>>();
+    let mut assignment = vec![0; points.len()];
+    for _ in 0..PQ_TRAINING_ITERATIONS {
+        let mut changed = false;
+        for (p, a) in points.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_centroid(p, &centroids, k);
+            changed |= nearest != *a;
+            *a = nearest;
+        }
+        let mut sums = vec![0f32; k * dim];
+        let mut counts = vec![0usize; k];
+        for (p, a) in points.iter().zip(&assignment) {
+            counts[*a] += 1;
+            for (s, x) in sums[a * dim..(a + 1) * dim].iter_mut().zip(p.iter()) {
+                *s += x;
+            }
+        }
+        for (i, count) in counts.into_iter().enumerate() {
+            // empty clusters keep their old centroid
+            if count > 0 {
+                for j in i * dim..(i + 1) * dim {
+                    centroids[j] = sums[j] / count as f32;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::relation::VecElementType;
+    use crate::data::value::Vector;
+    use crate::runtime::quantization::{to_f32, Quantizer};
+    use ndarray::Array1;
+
+    #[test]
+    fn test_quantization() {
+        let v = Vector::F32(Array1::from(vec![-1.0, 0.5, 2.0, 0.0]));
+        let q = Quantizer::Int8;
+        let code = q.encode(&v);
+        assert_eq!(code.len(), 12);
+        let decoded = to_f32(&q.decode(&code, VecElementType::F32));
+        for (a, b) in decoded.iter().zip(to_f32(&v)) {
+            assert!((a - b).abs() < 0.01);
+        }
+
+        let vectors = (0..100)
+            .map(|i| vec![(i % 2) as f32, (i % 2) as f32, (i % 5) as f32, 1.])
+            .collect::<Vec<_>>();
+        let q = Quantizer::train_product(vectors.into_iter().map(Ok), 4, 2, 64).unwrap();
+        let v = Vector::F64(Array1::from(vec![1.0, 1.0, 3.0, 1.0]));
+        let code = q.encode(&v);
+        assert_eq!(code.len(), 2);
+        assert_eq!(to_f32(&q.decode(&code, VecElementType::F64)), to_f32(&v));
+    }
+}
@@ -19,18 +19,21 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::eval_bytecode_pred;
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
-use crate::fts::FtsIndexManifest;
+use crate::fts::{FtsField, FtsIndexManifest};
 use crate::parse::expr::build_expr;
-use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
+use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, HnswQuantization, MinHashLshConfig};
 use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
+use crate::runtime::db::{Poison, Progress};
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
+use crate::runtime::quantization::{to_f32, Quantizer};
 use crate::runtime::transact::SessionTx;
 use crate::utils::TempCollector;
 use crate::{NamedRows, StoreTx};
@@ -416,15 +419,6 @@ impl RelationHandle {
         }
     }
 
-    pub(crate) fn exists(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<bool> {
-        let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx.exists(&key_data, false)
-        } else {
-            tx.store_tx.exists(&key_data, false)
-        }
-    }
-
     pub(crate) fn scan_prefix<'a>(
         &self,
         tx: &'a SessionTx<'_>,
@@ -758,14 +752,35 @@ impl<'a> SessionTx<'a> {
             default_gen: None,
         }];
 
+        // LSH Forest layout: the band, followed by the hash values of the band
+        // as separate columns so that any prefix of them can be scanned
+        let params = LshParams::find_optimal_params(
+            config.target_threshold.0,
+            config.n_perm,
+            &Weights(
+                config.false_positive_weight.0,
+                config.false_negative_weight.0,
+            ),
+        );
+        let forest_depth = config.n_perm / params.b;
         let mut idx_keys = vec![ColumnDef {
-            name: SmartString::from("hash"),
+            name: SmartString::from("band"),
             typing: NullableColType {
-                coltype: ColType::Bytes,
+                coltype: ColType::Int,
                 nullable: false,
             },
             default_gen: None,
         }];
+        for i in 0..forest_depth {
+            idx_keys.push(ColumnDef {
+                name: format!("hash_{}", i).into(),
+                typing: NullableColType {
+                    coltype: ColType::Int,
+                    nullable: false,
+                },
+                default_gen: None,
+            });
+        }
         for k in rel_handle.metadata.keys.iter() {
             idx_keys.push(ColumnDef {
                 name: format!("src_{}", k.name).into(),
@@ -790,15 +805,7 @@ impl<'a> SessionTx<'a> {
         )?;
 
         // add index to relation
-        let params = LshParams::find_optimal_params(
-            config.target_threshold.0,
-            config.n_perm,
-            &Weights(
-                config.false_positive_weight.0,
-                config.false_negative_weight.0,
-            ),
-        );
-        let num_perm = params.b * params.r;
+        let num_perm = params.b * forest_depth;
         let perms = HashPermutations::new(num_perm);
         let manifest = MinHashLshIndexManifest {
             base_relation: config.base_relation.clone(),
@@ -812,6 +819,9 @@ impl<'a> SessionTx<'a> {
             n_rows_in_band: params.r,
             threshold: config.target_threshold.0,
             perms: perms.as_bytes().to_vec(),
+            forest_depth,
+            false_positive_weight: config.false_positive_weight.0,
+            false_negative_weight: config.false_negative_weight.0,
         };
 
         // populate index
@@ -920,7 +930,7 @@ impl<'a> SessionTx<'a> {
             },
             ColumnDef {
                 name: SmartString::from("position"),
-                typing: col_type,
+                typing: col_type.clone(),
                 default_gen: None,
             },
             ColumnDef {
@@ -931,6 +941,16 @@ impl<'a> SessionTx<'a> {
                 },
                 default_gen: None,
             },
+            ColumnDef {
+                name: SmartString::from("field"),
+                typing: col_type.clone(),
+                default_gen: None,
+            },
+            ColumnDef {
+                name: SmartString::from("field_lengths"),
+                typing: col_type,
+                default_gen: None,
+            },
         ];
 
         let idx_handle = self.write_idx_relation(
@@ -947,6 +967,15 @@ impl<'a> SessionTx<'a> {
             extractor: config.extractor.clone(),
             tokenizer: config.tokenizer.clone(),
             filters: config.filters.clone(),
+            fields: config
+                .fields
+                .iter()
+                .map(|(name, boost)| FtsField {
+                    name: name.clone(),
+                    boost: boost.0,
+                })
+                .collect(),
+            doc_stats: true,
         };
 
         // populate index
@@ -970,17 +999,6 @@ impl<'a> SessionTx<'a> {
             existing.push(tuple?);
         }
         for tuple in existing.into_iter() {
-            let key_part = &tuple[..rel_handle.metadata.keys.len()];
-            if rel_handle.exists(self, key_part)? {
-                self.del_fts_index_item(
-                    &tuple,
-                    &extractor,
-                    &mut stack,
-                    &tokenizer,
-                    &rel_handle,
-                    &idx_handle,
-                )?;
-            }
             self.put_fts_index_item(
                 &tuple,
                 &extractor,
@@ -988,6 +1006,7 @@ impl<'a> SessionTx<'a> {
                 &tokenizer,
                 &rel_handle,
                 &idx_handle,
+                &manifest,
             )?;
         }
 
@@ -1007,7 +1026,12 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
-    pub(crate) fn create_hnsw_index(&mut self, config: &HnswIndexConfig) -> Result<()> {
+    pub(crate) fn create_hnsw_index(
+        &mut self,
+        config: &HnswIndexConfig,
+        poison: &Poison,
+        progress: &Progress,
+    ) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
 
@@ -1039,15 +1063,28 @@ impl<'a> SessionTx<'a> {
                         col_type = eltype.coltype.clone();
                     }
 
-                    if let ColType::Vec { eltype, len } = col_type {
-                        if eltype != config.dtype {
-                            bail!("Cannot create HNSW index with field {} of type {:?} (expected {:?})", field, eltype, config.dtype);
+                    match col_type {
+                        ColType::Vec { eltype, len } if !config.distance.is_binary() => {
+                            if eltype != config.dtype {
+                                bail!("Cannot create HNSW index with field {} of type {:?} (expected {:?})", field, eltype, config.dtype);
+                            }
+                            if len != config.vec_dim {
+                                bail!("Cannot create HNSW index with field {} of dimension {} (expected {})", field, len, config.vec_dim);
+                            }
+                        }
+                        ColType::BitVec { len } if config.distance.is_binary() => {
+                            if len != config.vec_dim {
+                                bail!("Cannot create HNSW index with field {} of dimension {} (expected {})", field, len, config.vec_dim);
+                            }
                         }
-                        if len != config.vec_dim {
-                            bail!("Cannot create HNSW index with field {} of dimension {} (expected {})", field, len, config.vec_dim);
+                        ColType::Vec { .. } | ColType::BitVec { .. } => {
+                            bail!(
+                                "Cannot create HNSW index with field {} for distance {:?}",
+                                field,
+                                config.distance
+                            )
                         }
-                    } else {
-                        bail!("Cannot create HNSW index with non-vector field {}", field)
+                        _ => bail!("Cannot create HNSW index with non-vector field {}", field),
                     }
 
                     found = true;
@@ -1096,7 +1133,7 @@ impl<'a> SessionTx<'a> {
         }
 
         // Build non-key columns definitions
-        let non_idx_keys = vec![
+        let mut non_idx_keys = vec![
             // For self-loops, stores the number of neighbours
             ColumnDef {
                 name: SmartString::from("dist"),
@@ -1124,6 +1161,17 @@ impl<'a> SessionTx<'a> {
                 default_gen: None,
             },
         ];
+        if config.quantization.is_some() {
+            // For self-loops, stores the quantized vector
+            non_idx_keys.push(ColumnDef {
+                name: SmartString::from("code"),
+                typing: NullableColType {
+                    coltype: ColType::Bytes,
+                    nullable: true,
+                },
+                default_gen: None,
+            });
+        }
         // create index relation
         let idx_handle = self.write_idx_relation(
             &config.base_relation,
@@ -1132,6 +1180,69 @@ impl<'a> SessionTx<'a> {
             non_idx_keys,
         )?;
 
+        let filter = if let Some(f_code) = &config.index_filter {
+            let parsed = CozoScriptParser::parse(Rule::expr, f_code)
+                .into_diagnostic()?
+                .next()
+                .unwrap();
+            let mut code_expr = build_expr(parsed, &Default::default())?;
+            let binding_map = rel_handle.raw_binding_map();
+            code_expr.fill_binding_indices(&binding_map)?;
+            code_expr.compile()?
+        } else {
+            vec![]
+        };
+        let filter = if filter.is_empty() {
+            None
+        } else {
+            Some(&filter)
+        };
+
+        // the codebooks of product quantization are trained on the vectors already in the relation
+        // that pass the filter, streamed into a bounded sample
+        let quantizer = match config.quantization {
+            None => None,
+            Some(HnswQuantization::Int8) => Some(Quantizer::Int8),
+            Some(HnswQuantization::Product {
+                n_subspaces,
+                n_centroids,
+            }) => {
+                let mut stack = vec![];
+                let training = rel_handle
+                    .scan_all(self)
+                    .map(|tuple| -> Result<Vec<Vec<f32>>> {
+                        let tuple = tuple?;
+                        if let Some(code) = filter {
+                            if !eval_bytecode_pred(code, &tuple, &mut stack, Default::default())? {
+                                return Ok(vec![]);
+                            }
+                        }
+                        let mut vectors = vec![];
+                        for idx in &vec_field_indices {
+                            match &tuple[*idx] {
+                                DataValue::Vec(v) => vectors.push(to_f32(v)),
+                                DataValue::List(l) => {
+                                    for v in l {
+                                        if let DataValue::Vec(v) = v {
+                                            vectors.push(to_f32(v));
+                                        }
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                        Ok(vectors)
+                    })
+                    .flatten_ok();
+                Some(Quantizer::train_product(
+                    training,
+                    config.vec_dim,
+                    n_subspaces,
+                    n_centroids,
+                )?)
+            }
+        };
+
         // add index to relation
         let manifest = HnswIndexManifest {
             base_relation: config.base_relation.clone(),
@@ -1148,6 +1259,7 @@ impl<'a> SessionTx<'a> {
             index_filter: config.index_filter.clone(),
             extend_candidates: config.extend_candidates,
             keep_pruned_connections: config.keep_pruned_connections,
+            quantizer,
         };
 
         // populate index
@@ -1155,34 +1267,15 @@ impl<'a> SessionTx<'a> {
         for tuple in rel_handle.scan_all(self) {
             all_tuples.push(tuple?);
         }
-        let filter = if let Some(f_code) = &manifest.index_filter {
-            let parsed = CozoScriptParser::parse(Rule::expr, f_code)
-                .into_diagnostic()?
-                .next()
-                .unwrap();
-            let mut code_expr = build_expr(parsed, &Default::default())?;
-            let binding_map = rel_handle.raw_binding_map();
-            code_expr.fill_binding_indices(&binding_map)?;
-            code_expr.compile()?
-        } else {
-            vec![]
-        };
-        let filter = if filter.is_empty() {
-            None
-        } else {
-            Some(&filter)
-        };
-        let mut stack = vec![];
-        for tuple in all_tuples.into_iter() {
-            self.hnsw_put(
-                &manifest,
-                &rel_handle,
-                &idx_handle,
-                filter,
-                &mut stack,
-                &tuple,
-            )?;
-        }
+        self.hnsw_bulk_build(
+            &manifest,
+            &rel_handle,
+            &idx_handle,
+            filter,
+            all_tuples.into_iter(),
+            poison,
+            progress,
+        )?;
 
         rel_handle
             .hnsw_indices
@@ -1379,6 +1472,9 @@ impl<'a> SessionTx<'a> {
             self.tokenizers.named_cache.write().unwrap().clear();
             self.tokenizers.hashed_cache.write().unwrap().clear();
         }
+        if let Some((idx_handle, _)) = rel.fts_indices.get(&idx_name.name) {
+            self.del_fts_stats(idx_handle)?;
+        }
         if rel.indices.remove(&idx_name.name).is_none()
             && rel.hnsw_indices.remove(&idx_name.name).is_none()
             && rel.lsh_indices.remove(&idx_name.name).is_none()
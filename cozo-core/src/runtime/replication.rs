@@ -0,0 +1,714 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Logical replication by shipping the write sets of committed transactions.
+//!
+//! A primary records the key-value mutations of every write transaction and, once the
+//! transaction commits, ships them as a [ReplicationMessage::WriteSet] to every attached replica.
+//! Write sets are numbered consecutively, and the numbers are assigned while holding the
+//! commit lock, so replicas applying them in order reproduce the state of the primary.
+//! Under the lock, write sets are only put on a queue for each replica, and a thread for each
+//! replica ships them from there, so a slow replica does not hold up commits. A replica
+//! falling so far behind that its queue is full is detached.
+//!
+//! A newly attached replica first receives a snapshot of the whole store, read from
+//! a transaction started at a known sequence number. The write sets committed while
+//! the snapshot is sent are queued, and shipped after it.
+//!
+//! Alternatively, a [CommitCoordinator] can take part in every commit, which is how
+//! consensus protocols replicating write sets among several nodes plug in.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use crossbeam::sync::ShardedLock;
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::runtime::graph_cache::VersionTrackingTx;
+use crate::storage::{Storage, StoreTx};
+use crate::{DataValue, Db, NamedRows};
+
+/// Number of key-value pairs in each snapshot message
+const SNAPSHOT_CHUNK_SIZE: usize = 1024;
+/// Number of write sets waiting to be shipped to a replica before the replica is detached
+const REPLICA_QUEUE_SIZE: usize = 4096;
+
+/// A single mutation of the key-value store.
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum WriteOp {
+    /// Put a key-value pair
+    Put(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+    /// Delete a key
+    Del(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// Delete the persisted keys in a range, lower bound inclusive and upper bound exclusive
+    DelRange(
+        #[serde(with = "serde_bytes")] Vec<u8>,
+        #[serde(with = "serde_bytes")] Vec<u8>,
+    ),
+}
+
+impl WriteOp {
+    /// Delete every key of the store, used before putting the content of a snapshot.
+    pub fn delete_all() -> Self {
+        WriteOp::DelRange(vec![], vec![0xFF])
+    }
+}
+
+/// A message sent from a primary to its replicas.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum ReplicationMessage {
+    /// Part of the snapshot sent to a newly attached replica
+    SnapshotChunk(Vec<WriteOp>),
+    /// End of the snapshot, which contains all write sets up to and including `seq`
+    SnapshotEnd {
+        /// The sequence number of the last write set contained in the snapshot
+        seq: u64,
+    },
+    /// The mutations of a committed transaction
+    WriteSet {
+        /// The sequence number, increasing by one for each write set
+        seq: u64,
+        /// Commit time on the primary, as seconds since the UNIX epoch
+        committed_at: f64,
+        /// The mutations, in the order they were made
+        ops: Vec<WriteOp>,
+    },
+}
+
+impl ReplicationMessage {
+    /// Encode the message as bytes, for transports that send bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap()
+    }
+    /// Decode a message from bytes produced by [to_bytes](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(bytes).into_diagnostic()
+    }
+}
+
+/// The sending half of a replication transport, held by the primary.
+pub trait ReplicationSender: Send {
+    /// Send a message to the replica. Returning an error detaches the replica.
+    ///
+    /// The snapshot is sent from the thread attaching the replica, and write sets from
+    /// a thread of the replica's own, so blocking here does not hold up commits on the primary.
+    fn send(&mut self, msg: &ReplicationMessage) -> Result<()>;
+}
+
+/// The receiving half of a replication transport, used by a replica.
+pub trait ReplicationReceiver {
+    /// Wait for the next message. `Ok(None)` means that the primary has closed the stream.
+    fn recv(&mut self) -> Result<Option<ReplicationMessage>>;
+}
+
+/// Sending half of [local_replication_channel].
+pub struct LocalReplicationSender(Sender<ReplicationMessage>);
+
+/// Receiving half of [local_replication_channel].
+pub struct LocalReplicationReceiver(Receiver<ReplicationMessage>);
+
+/// Create a transport for replicating between databases within the same process.
+pub fn local_replication_channel() -> (LocalReplicationSender, LocalReplicationReceiver) {
+    let (sender, receiver) = unbounded();
+    (
+        LocalReplicationSender(sender),
+        LocalReplicationReceiver(receiver),
+    )
+}
+
+impl ReplicationSender for LocalReplicationSender {
+    fn send(&mut self, msg: &ReplicationMessage) -> Result<()> {
+        self.0.send(msg.clone()).into_diagnostic()
+    }
+}
+
+impl ReplicationReceiver for LocalReplicationReceiver {
+    fn recv(&mut self) -> Result<Option<ReplicationMessage>> {
+        Ok(self.0.recv().ok())
+    }
+}
+
+/// Takes part in committing the write sets of transactions, as done by consensus protocols.
+/// See [Db::set_commit_coordinator].
+pub trait CommitCoordinator: Send + Sync {
+    /// Called before a write transaction starts. Returning an error rejects the write.
+    fn before_write(&self) -> Result<()>;
+    /// Commit a transaction with the write set `ops`. Implementations must call `local_commit`
+    /// to commit the transaction locally once the write set is safe to commit,
+    /// and return its result.
+    fn commit(&self, ops: &[WriteOp], local_commit: &mut dyn FnMut() -> Result<()>) -> Result<()>;
+}
+
+/// The role of a database in replication.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplicationRole {
+    /// Replication is not in use
+    Standalone,
+    /// The database ships its write sets to replicas
+    Primary,
+    /// The database applies write sets from a primary and rejects writes
+    Replica,
+}
+
+/// The replication status of a database, see [Db::replication_status].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationStatus {
+    /// The role of the database
+    pub role: ReplicationRole,
+    /// For a primary, the sequence number of the last shipped write set.
+    /// For a replica, the sequence number of the last applied write set.
+    pub seq: u64,
+    /// Number of attached replicas, always zero unless the database is a primary
+    pub n_replicas: usize,
+    /// For a replica, the number of seconds between the commit of the last applied write set
+    /// on the primary and its application on the replica
+    pub lag: Option<f64>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is a read replica and cannot be written to")]
+#[diagnostic(code(replication::read_replica))]
+#[diagnostic(help("Send writes to the primary, or promote the replica first"))]
+pub(crate) struct WriteToReplica;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Replication stream out of order: expected write set {0}, got {1}")]
+#[diagnostic(code(replication::gap))]
+#[diagnostic(help("Reattach the replica to the primary to start from a fresh snapshot"))]
+pub(crate) struct ReplicationGap(u64, u64);
+
+#[derive(Default)]
+struct PrimaryLog {
+    last_seq: u64,
+    next_replica_id: u32,
+    /// The queues of write sets waiting to be shipped to each replica
+    replicas: BTreeMap<u32, Sender<Arc<ReplicationMessage>>>,
+}
+
+#[derive(Default)]
+struct ReplicaProgress {
+    applied_seq: u64,
+    lag: Option<f64>,
+}
+
+/// Replication state shared by all clones of a [Db].
+#[derive(Default)]
+pub(crate) struct Replication {
+    is_primary: AtomicBool,
+    is_replica: AtomicBool,
+    log: Mutex<PrimaryLog>,
+    progress: Mutex<ReplicaProgress>,
+    coordinator: ShardedLock<Option<Arc<dyn CommitCoordinator>>>,
+    /// Write transactions are serialized when a coordinator is set
+    serial_writes: Mutex<()>,
+}
+
+/// Permission to run a write transaction whose write set needs to be replicated.
+pub(crate) struct WritePermit<'s> {
+    replication: &'s Replication,
+    coordinator: Option<Arc<dyn CommitCoordinator>>,
+    _serial: Option<MutexGuard<'s, ()>>,
+}
+
+fn seconds_now() -> f64 {
+    let ValidityTs(ts) = current_validity();
+    ts.0 as f64 / 1_000_000.
+}
+
+impl Replication {
+    pub(crate) fn is_primary(&self) -> bool {
+        self.is_primary.load(Ordering::Acquire)
+    }
+    pub(crate) fn is_replica(&self) -> bool {
+        self.is_replica.load(Ordering::Acquire)
+    }
+    pub(crate) fn ensure_writable(&self) -> Result<()> {
+        ensure!(!self.is_replica(), WriteToReplica);
+        Ok(())
+    }
+    pub(crate) fn has_coordinator(&self) -> bool {
+        self.coordinator.read().unwrap().is_some()
+    }
+    /// Must be called before opening the write transaction in the storage engine.
+    /// Returns `None` if the write set of the transaction need not be recorded.
+    pub(crate) fn begin_write(&self) -> Result<Option<WritePermit<'_>>> {
+        let coordinator = self.coordinator.read().unwrap().clone();
+        let serial = match &coordinator {
+            None => {
+                if !self.is_primary() {
+                    return Ok(None);
+                }
+                None
+            }
+            Some(coordinator) => {
+                let serial = self.serial_writes.lock().unwrap();
+                coordinator.before_write()?;
+                Some(serial)
+            }
+        };
+        Ok(Some(WritePermit {
+            replication: self,
+            coordinator,
+            _serial: serial,
+        }))
+    }
+    /// Commit with `commit` and queue `ops` for shipping to the replicas if the commit succeeds.
+    /// The commit happens under the lock, as the sequence numbers must follow the commit order.
+    fn commit_and_ship(
+        &self,
+        ops: Vec<WriteOp>,
+        commit: impl FnOnce(&[WriteOp]) -> Result<()>,
+    ) -> Result<()> {
+        let mut log = self.log.lock().unwrap();
+        commit(&ops)?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+        log.last_seq += 1;
+        let msg = Arc::new(ReplicationMessage::WriteSet {
+            seq: log.last_seq,
+            committed_at: seconds_now(),
+            ops,
+        });
+        log.replicas
+            .retain(|id, queue| match queue.try_send(msg.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("detaching replica {id}: too many write sets waiting to be shipped");
+                    false
+                }
+                // shipping failed, which has been logged already
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        Ok(())
+    }
+}
+
+/// A write transaction of a primary, recording its mutations for shipping.
+pub(crate) struct ReplicatedTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    ops: Mutex<Vec<WriteOp>>,
+    permit: WritePermit<'s>,
+}
+
+impl<'s> ReplicatedTx<'s> {
+    pub(crate) fn new(inner: Box<dyn StoreTx<'s> + 's>, permit: WritePermit<'s>) -> Self {
+        Self {
+            inner,
+            ops: Default::default(),
+            permit,
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for ReplicatedTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)?;
+        self.ops
+            .get_mut()
+            .unwrap()
+            .push(WriteOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        let mut ops = self.ops.lock().unwrap();
+        self.inner.par_put(key, val)?;
+        ops.push(WriteOp::Put(key.to_vec(), val.to_vec()));
+        Ok(())
+    }
+
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let ops = self.ops.get_mut().unwrap();
+        self.inner.batch_put(Box::new(data.inspect(|pair| {
+            if let Ok((key, val)) = pair {
+                ops.push(WriteOp::Put(key.clone(), val.clone()));
+            }
+        })))
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)?;
+        self.ops.get_mut().unwrap().push(WriteOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        let mut ops = self.ops.lock().unwrap();
+        self.inner.par_del(key)?;
+        ops.push(WriteOp::Del(key.to_vec()));
+        Ok(())
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)?;
+        self.ops
+            .get_mut()
+            .unwrap()
+            .push(WriteOp::DelRange(lower.to_vec(), upper.to_vec()));
+        Ok(())
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let ops = mem::take(self.ops.get_mut().unwrap());
+        let inner = &mut self.inner;
+        let permit = &self.permit;
+        permit
+            .replication
+            .commit_and_ship(ops, |ops| match &permit.coordinator {
+                Some(coordinator) if !ops.is_empty() => {
+                    coordinator.commit(ops, &mut || inner.commit())
+                }
+                _ => inner.commit(),
+            })
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
+
+/// Pass the whole content of the store to `chunk` as put operations, a bounded number at a time.
+fn scan_chunks<'s>(
+    tx: &impl StoreTx<'s>,
+    chunk: &mut dyn FnMut(Vec<WriteOp>) -> Result<()>,
+) -> Result<()> {
+    for ops in &tx.total_scan().chunks(SNAPSHOT_CHUNK_SIZE) {
+        chunk(ops.map_ok(|(k, v)| WriteOp::Put(k, v)).try_collect()?)?;
+    }
+    Ok(())
+}
+
+fn apply_op<'s>(tx: &mut impl StoreTx<'s>, op: &WriteOp) -> Result<()> {
+    match op {
+        WriteOp::Put(key, val) => tx.put(key, val),
+        WriteOp::Del(key) => tx.del(key),
+        WriteOp::DelRange(lower, upper) => tx.del_range_from_persisted(lower, upper),
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Make this database a primary. Must be called before any write that should reach
+    /// the replicas, usually right after [initialize](Self::initialize).
+    ///
+    /// Writes bypassing transactions, such as [restore_backup](Self::restore_backup),
+    /// are not replicated.
+    pub fn enable_replication(&'s self) -> Result<()> {
+        self.replication.ensure_writable()?;
+        self.replication.is_primary.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Attach a replica to this primary. The replica first receives a snapshot of the
+    /// current content, then every write set committed afterwards.
+    /// Returns an ID for [detach_replica](Self::detach_replica).
+    ///
+    /// The snapshot is sent before returning, without blocking writes on the primary.
+    /// If more write sets are committed in the meantime than can be queued for the replica,
+    /// the replica is detached and an error is returned.
+    pub fn attach_replica(&'s self, mut sender: Box<dyn ReplicationSender>) -> Result<u32> {
+        if !self.replication.is_primary() {
+            bail!("Cannot attach replicas before replication is enabled on the primary");
+        }
+        if cfg!(target_arch = "wasm32") {
+            bail!("Cannot attach replicas when threading is disallowed");
+        }
+        let (id, seq, tx, pending) = loop {
+            let seq_before = self.replication.log.lock().unwrap().last_seq;
+            // For some engines, opening a read transaction waits for ongoing writes,
+            // which in turn may need the commit lock: we must not hold it now.
+            let tx = self.db.transact(false)?;
+            let mut log = self.replication.log.lock().unwrap();
+            if log.last_seq != seq_before {
+                // something committed in the meantime, we cannot tell if the snapshot has it
+                continue;
+            }
+            // write sets committed from now on are queued until the snapshot is sent
+            let (queue, pending) = bounded(REPLICA_QUEUE_SIZE);
+            let id = log.next_replica_id;
+            log.next_replica_id += 1;
+            log.replicas.insert(id, queue);
+            break (id, log.last_seq, tx, pending);
+        };
+        let mut send_snapshot = || -> Result<()> {
+            scan_chunks(&tx, &mut |ops| {
+                sender.send(&ReplicationMessage::SnapshotChunk(ops))
+            })?;
+            sender.send(&ReplicationMessage::SnapshotEnd { seq })
+        };
+        if let Err(err) = send_snapshot() {
+            self.detach_replica(id);
+            return Err(err);
+        }
+        drop(tx);
+        let attached = self
+            .replication
+            .log
+            .lock()
+            .unwrap()
+            .replicas
+            .contains_key(&id);
+        ensure!(
+            attached,
+            "The replica fell too far behind while receiving the snapshot and was detached"
+        );
+        thread::spawn(move || {
+            // ends once the queue is dropped by detaching the replica
+            for msg in pending {
+                if let Err(err) = sender.send(&msg) {
+                    log::warn!("detaching replica {id}: {err}");
+                    break;
+                }
+            }
+        });
+        Ok(id)
+    }
+
+    /// Stop shipping write sets to a replica. Returns `false` if no such replica exists.
+    pub fn detach_replica(&'s self, id: u32) -> bool {
+        self.replication
+            .log
+            .lock()
+            .unwrap()
+            .replicas
+            .remove(&id)
+            .is_some()
+    }
+
+    /// Turn this database into a replica and apply the messages from `receiver` in order,
+    /// blocking until the primary closes the stream or the replica is promoted.
+    /// The existing content of the database is replaced by the snapshot from the primary.
+    ///
+    /// While the database is a replica, every script runs as [ScriptMutability::Immutable](crate::ScriptMutability::Immutable)
+    /// and other writes are rejected. Callbacks are not triggered by replicated writes.
+    pub fn run_replica(&'s self, receiver: &mut dyn ReplicationReceiver) -> Result<()> {
+        if self.replication.is_primary() {
+            bail!("A primary cannot become a replica");
+        }
+        self.replication.is_replica.store(true, Ordering::Release);
+        *self.replication.progress.lock().unwrap() = Default::default();
+        let mut snapshot_tx = None;
+        let mut snapshot_done = false;
+        while self.replication.is_replica() {
+            let msg = match receiver.recv()? {
+                None => break,
+                Some(msg) => msg,
+            };
+            match msg {
+                ReplicationMessage::SnapshotChunk(ops) => {
+                    let tx = match &mut snapshot_tx {
+                        Some(tx) => tx,
+                        None => {
+                            let mut tx = self.transact_replicated()?;
+                            tx.del_range_from_persisted(&[], &[0xFF])?;
+                            snapshot_tx.insert(tx)
+                        }
+                    };
+                    for op in &ops {
+                        apply_op(tx, op)?;
+                    }
+                }
+                ReplicationMessage::SnapshotEnd { seq } => {
+                    let mut tx = match snapshot_tx.take() {
+                        Some(tx) => tx,
+                        None => {
+                            let mut tx = self.transact_replicated()?;
+                            tx.del_range_from_persisted(&[], &[0xFF])?;
+                            tx
+                        }
+                    };
+                    tx.commit()?;
+                    snapshot_done = true;
+                    self.replication.progress.lock().unwrap().applied_seq = seq;
+                }
+                ReplicationMessage::WriteSet {
+                    seq,
+                    committed_at,
+                    ops,
+                } => {
+                    ensure!(
+                        snapshot_done,
+                        "Replication stream does not start with a snapshot"
+                    );
+                    let expected = self.replication.progress.lock().unwrap().applied_seq + 1;
+                    ensure!(seq == expected, ReplicationGap(expected, seq));
+                    self.apply_write_set(&ops)?;
+                    let mut progress = self.replication.progress.lock().unwrap();
+                    progress.applied_seq = seq;
+                    progress.lag = Some((seconds_now() - committed_at).max(0.));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn a replica into a standalone database accepting writes.
+    /// A running [run_replica](Self::run_replica) returns after the next message.
+    pub fn promote_replica(&'s self) -> Result<()> {
+        if !self.replication.is_replica.swap(false, Ordering::AcqRel) {
+            bail!("The database is not a replica");
+        }
+        self.load_last_ids()
+    }
+
+    /// Let `coordinator` take part in every commit of a write transaction, or remove the
+    /// coordinator with `None`. While a coordinator is set, write transactions are serialized.
+    pub fn set_commit_coordinator(&'s self, coordinator: Option<Arc<dyn CommitCoordinator>>) {
+        *self.replication.coordinator.write().unwrap() = coordinator;
+    }
+
+    /// A write transaction applying replicated mutations, which are neither recorded
+    /// for shipping nor passed to the commit coordinator.
+    fn transact_replicated(&'s self) -> Result<VersionTrackingTx<'s>> {
+        Ok(VersionTrackingTx::new(
+            Box::new(self.db.transact(true)?),
+            self.graph_cache.clone(),
+        ))
+    }
+
+    /// Apply a write set replicated from another node in a transaction of its own.
+    /// The write set is neither passed to the commit coordinator nor shipped to replicas.
+    pub fn apply_write_set(&'s self, ops: &[WriteOp]) -> Result<()> {
+        let mut tx = self.transact_replicated()?;
+        for op in ops {
+            apply_op(&mut tx, op)?;
+        }
+        tx.commit()
+    }
+
+    /// Pass the whole content of the store to `chunk` as put operations, a bounded number at
+    /// a time. The content is read in a single transaction, so it is consistent. Applied after
+    /// [WriteOp::delete_all], the operations reproduce the store.
+    pub fn export_store(&'s self, chunk: &mut dyn FnMut(Vec<WriteOp>) -> Result<()>) -> Result<()> {
+        let tx = self.db.transact(false)?;
+        scan_chunks(&tx, chunk)
+    }
+
+    /// Report the replication status of the database.
+    pub fn replication_status(&'s self) -> ReplicationStatus {
+        if self.replication.is_replica() {
+            let progress = self.replication.progress.lock().unwrap();
+            ReplicationStatus {
+                role: ReplicationRole::Replica,
+                seq: progress.applied_seq,
+                n_replicas: 0,
+                lag: progress.lag,
+            }
+        } else if self.replication.is_primary() {
+            let log = self.replication.log.lock().unwrap();
+            ReplicationStatus {
+                role: ReplicationRole::Primary,
+                seq: log.last_seq,
+                n_replicas: log.replicas.len(),
+                lag: None,
+            }
+        } else {
+            ReplicationStatus {
+                role: ReplicationRole::Standalone,
+                seq: 0,
+                n_replicas: 0,
+                lag: None,
+            }
+        }
+    }
+
+    pub(crate) fn replication_status_rows(&'s self) -> NamedRows {
+        let status = self.replication_status();
+        let role = match status.role {
+            ReplicationRole::Standalone => "standalone",
+            ReplicationRole::Primary => "primary",
+            ReplicationRole::Replica => "replica",
+        };
+        NamedRows::new(
+            vec![
+                "role".to_string(),
+                "seq".to_string(),
+                "n_replicas".to_string(),
+                "lag".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(role),
+                DataValue::from(status.seq as i64),
+                DataValue::from(status.n_replicas as i64),
+                status.lag.map(DataValue::from).unwrap_or(DataValue::Null),
+            ]],
+        )
+    }
+}
@@ -12,18 +12,22 @@ use std::time::Duration;
 
 use itertools::Itertools;
 use log::debug;
+use rand::Rng;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, Vector};
 use crate::fixed_rule::FixedRulePayload;
 use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::{
+    local_replication_channel, DbInstance, FixedRule, RegularTempStore, ReplicationMessage,
+    ReplicationSender, ScriptMutability,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -853,6 +857,683 @@ fn test_fts_indexing() {
     }
 }
 
+#[test]
+fn test_fts_bm25() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => title: String, body: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, title, body] <- [
+            ['a', 'about cats', 'the cat sat on the mat'],
+            ['b', 'a very long story', 'once upon a time a cat went out to see the wide world and all its many wonders'],
+            ['c', 'cat', 'dogs are nice too']
+        ] :put a {k => title, body}",
+    )
+    .unwrap();
+    db.run_default(r"::fts create a:body {extractor: body, tokenizer: Simple}")
+        .unwrap();
+    db.run_default(
+        r"::fts create a:both {
+            fields: {title: title, body: body},
+            boosts: {title: 5},
+            tokenizer: Simple
+        }",
+    )
+    .unwrap();
+
+    // shorter documents rank higher under BM25
+    let res = db
+        .run_default(r"?[k] := ~a:body{k | query: 'cat', k: 10, score_kind: 'bm25'}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a"], ["b"]]));
+
+    // matches in the boosted title field dominate
+    let res = db
+        .run_default(
+            r"?[k, s] := ~a:both{k | query: 'cat', k: 10, score_kind: 'bm25', bind_score: s}
+              :order -s",
+        )
+        .unwrap();
+    let rows = res.into_json()["rows"].clone();
+    assert_eq!(rows[0][0], json!("c"));
+    assert_eq!(rows[1][0], json!("a"));
+    assert_eq!(rows[2][0], json!("b"));
+
+    // deleting documents updates the statistics
+    let before = db
+        .run_default(
+            r"?[s] := ~a:body{k | query: 'cat', k: 10, score_kind: 'bm25', bind_score: s}, k = 'a'",
+        )
+        .unwrap()
+        .into_json()["rows"][0][0]
+        .as_f64()
+        .unwrap();
+    db.run_default(r"?[k] <- [['c']] :rm a {k}").unwrap();
+    let after = db
+        .run_default(
+            r"?[s] := ~a:body{k | query: 'cat', k: 10, score_kind: 'bm25', bind_score: s}, k = 'a'",
+        )
+        .unwrap()
+        .into_json()["rows"][0][0]
+        .as_f64()
+        .unwrap();
+    assert!(after < before);
+
+    // statistics written by many transactions add up to those of a new index
+    for i in 0..20 {
+        db.run_script(
+            r"?[k, title, body] <- [[$k, 'more', 'a cat and a dog and a bird']] :put a {k => title, body}",
+            BTreeMap::from([("k".to_string(), DataValue::from(format!("x{i}")))]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    }
+    for i in 0..10 {
+        db.run_script(
+            r"?[k] <- [[$k]] :rm a {k}",
+            BTreeMap::from([("k".to_string(), DataValue::from(format!("x{}", i * 2)))]),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    }
+    db.run_default(r"::fts create a:fresh {extractor: body, tokenizer: Simple}")
+        .unwrap();
+    let score = |idx: &str| {
+        db.run_default(&format!(
+            r"?[s] := ~a:{idx}{{k | query: 'cat', k: 100, score_kind: 'bm25', bind_score: s}}, k = 'a'"
+        ))
+        .unwrap()
+        .into_json()["rows"][0][0]
+            .as_f64()
+            .unwrap()
+    };
+    assert!((score("body") - score("fresh")).abs() < 1e-9);
+
+    assert!(db
+        .run_default(r"?[k] := ~a:body{k | query: 'cat', k: 10, score_kind: 'bm25', b: 2}")
+        .is_err());
+    assert!(db
+        .run_default(r"::fts create a:bad {extractor: body, fields: {body: body}}")
+        .is_err());
+}
+
+#[test]
+fn test_fts_positional() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => title: String, body: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, title, body] <- [
+            ['a', 'the fox', 'the quick brown fox jumps'],
+            ['b', 'brown', 'the brown quick fox sleeps'],
+            ['c', 'quick fox', 'a fox that is quick and brown']
+        ] :put a {k => title, body}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::fts create a:fts {
+            fields: {title: title, body: body},
+            tokenizer: Simple,
+            filters: [Lowercase]
+        }",
+    )
+    .unwrap();
+    let search = |q: &str| {
+        db.run_script(
+            r"?[k] := ~a:fts{k | query: $q, k: 10}",
+            BTreeMap::from([("q".to_string(), DataValue::from(q))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+
+    assert_eq!(search("quick brown"), json!([["a"], ["b"], ["c"]]));
+    assert_eq!(search("\"quick brown fox\""), json!([["a"]]));
+    assert_eq!(search("NEAR/1(quick brown)"), json!([["a"], ["b"]]));
+    assert_eq!(search("ONEAR/1(quick brown)"), json!([["a"]]));
+    assert_eq!(search("ONEAR/2(brown fox)"), json!([["a"], ["b"]]));
+    assert_eq!(search("title:fox"), json!([["a"], ["c"]]));
+    assert_eq!(
+        search("title:brown OR body:\"fox that\""),
+        json!([["b"], ["c"]])
+    );
+    // 'fox' ends the title of `a` and 'brown' is the third word of its body
+    assert_eq!(search("\"fox brown\""), json!([]));
+    assert!(db
+        .run_default(r"?[k] := ~a:fts{k | query: 'nofield:fox', k: 10}")
+        .is_err());
+}
+
+#[test]
+fn test_fts_highlights() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => title: String, body: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, title, body] <- [
+            ['a', 'Foxes', 'The quick brown fox jumps over the lazy dog, and the fox runs away']
+        ] :put a {k => title, body}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::fts create a:body {extractor: body, tokenizer: Simple, filters: [Lowercase]}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::fts create a:both {
+            fields: {title: title, body: body},
+            tokenizer: Simple,
+            filters: [Lowercase]
+        }",
+    )
+    .unwrap();
+
+    let res = db
+        .run_default(
+            r"?[h, s] := ~a:body{k | query: 'fox NOT cat', k: 1, bind_highlights: h, bind_snippet: s}",
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[
+            [[16, 19], [53, 56]],
+            "The quick brown <b>fox</b> jumps over the lazy dog, and the <b>fox</b> runs away"
+        ]])
+    );
+
+    let res = db
+        .run_default(
+            r"?[s] := ~a:body{k | query: 'lazy', k: 1, bind_snippet: s,
+                              snippet_size: 20, highlight_start: '[', highlight_end: ']'}",
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["…the [lazy] dog,…"]]));
+
+    let res = db
+        .run_default(r"?[h] := ~a:both{k | query: 'title:fox* OR quick', k: 1, bind_highlights: h}")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[[["title", 0, 5], ["body", 4, 9]]]])
+    );
+}
+
+#[test]
+fn test_fts_fuzzy() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => v: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, v] <- [
+            ['a', 'John Smith'],
+            ['b', 'Jon Smythe'],
+            ['c', 'Joan Smithers'],
+            ['d', 'Mary Jones']
+        ] :put a {k => v}",
+    )
+    .unwrap();
+    db.run_default(r"::fts create a:fts {extractor: v, tokenizer: Simple, filters: [Lowercase]}")
+        .unwrap();
+    let search = |q: &str| {
+        db.run_script(
+            r"?[k, s] := ~a:fts{k | query: $q, k: 10, bind_score: s} :order -s, k",
+            BTreeMap::from([("q".to_string(), DataValue::from(q))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r[0].as_str().unwrap().to_string())
+            .collect_vec()
+    };
+
+    assert_eq!(search("jonh"), Vec::<String>::new());
+    assert_eq!(search("jonh~1"), vec!["b"]);
+    // exact matches score higher than approximate ones
+    assert_eq!(search("jon~1"), vec!["b", "a", "c"]);
+    assert_eq!(search("jonh~2"), vec!["b", "a", "c", "d"]);
+    assert_eq!(search("smit*~1"), vec!["a", "c", "b"]);
+
+    let res = db
+        .run_default(r"?[s] := ~a:fts{k | query: 'smythee~1', k: 10, bind_snippet: s}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["Jon <b>Smythe</b>"]]));
+
+    // terms with the largest character are skipped past instead of seeked to again
+    db.run_default(r":create b {k: String => v: String}")
+        .unwrap();
+    db.run_script(
+        r"?[k, v] <- [['a', $a], ['b', $b]] :put b {k => v}",
+        BTreeMap::from([
+            ("a".to_string(), DataValue::from("x\u{10FFFF}\u{10FFFF}")),
+            ("b".to_string(), DataValue::from("y\u{10FFFF}")),
+        ]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    db.run_default(r"::fts create b:fts {extractor: v, tokenizer: Raw}")
+        .unwrap();
+    let res = db
+        .run_default(r"?[k] := ~b:fts{k | query: 'ab~1', k: 10}")
+        .unwrap();
+    assert!(res.rows.is_empty());
+    let res = db
+        .run_default(r"?[k] := ~b:fts{k | query: 'y~1', k: 10}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["b"]]));
+}
+
+#[test]
+fn test_fts_analyzers() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => v: String}")
+        .unwrap();
+    db.run_default(
+        r"?[k, v] <- [
+            ['a', '東京タワーは高い'],
+            ['b', '京都の寺'],
+            ['c', 'L’automobile de Marie'],
+            ['d', 'une voiture rouge']
+        ] :put a {k => v}",
+    )
+    .unwrap();
+    db.run_default(r"::fts create a:ja {extractor: v, tokenizer: Japanese}")
+        .unwrap();
+    db.run_default(
+        r"::fts create a:fr {
+            extractor: v,
+            tokenizer: Unicode,
+            filters: [Lowercase, Elision, Synonyms([['voiture', 'automobile', 'auto']])]
+        }",
+    )
+    .unwrap();
+    let search = |idx: &str, q: &str| {
+        db.run_script(
+            &format!("?[k] := ~a:{idx}{{k | query: $q, k: 10}}"),
+            BTreeMap::from([("q".to_string(), DataValue::from(q))]),
+            ScriptMutability::Immutable,
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r[0].as_str().unwrap().to_string())
+            .sorted()
+            .collect_vec()
+    };
+
+    assert_eq!(search("ja", "東京"), vec!["a"]);
+    assert_eq!(search("ja", "京"), Vec::<String>::new());
+    assert_eq!(search("ja", "京都"), vec!["b"]);
+    assert_eq!(search("ja", "\"タワー\""), vec!["a"]);
+    assert_eq!(search("fr", "voiture"), vec!["c", "d"]);
+    assert_eq!(search("fr", "\"l'auto\""), vec!["c", "d"]);
+    assert!(db
+        .run_default(
+            r"::fts create a:bad {extractor: v, tokenizer: Simple, filters: [Synonyms(['a'])]}"
+        )
+        .is_err());
+}
+
+#[test]
+fn test_hnsw_quantization() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: Int => v: <F32; 16>}")
+        .unwrap();
+    db.run_default(r":create q {k: Int => v: <F32; 16>}")
+        .unwrap();
+    assert!(db
+        .run_default(r"::hnsw create a:pq {dim: 16, fields: [v], m: 16, ef: 50, quantization: PQ}")
+        .is_err());
+    db.run_default(r"?[k, v] := k in int_range(200), v = rand_vec(16) :put a {k => v}")
+        .unwrap();
+    db.run_default(r"?[k, v] := k = 0, v = rand_vec(16) :put q {k => v}")
+        .unwrap();
+    for (name, options) in [
+        ("int8", "quantization: Int8"),
+        ("pq", "quantization: PQ, pq_subspaces: 4, pq_centroids: 64"),
+    ] {
+        db.run_default(&format!(
+            r"::hnsw create a:{name} {{dim: 16, fields: [v], m: 16, ef: 50, {options}}}"
+        ))
+        .unwrap();
+    }
+    // the codebooks are only trained on the vectors passing the filter
+    assert!(db
+        .run_default(
+            r"::hnsw create a:none {dim: 16, fields: [v], m: 16, ef: 50, quantization: PQ, filter: k < 0}"
+        )
+        .is_err());
+    // rows written after the creation of the indices
+    db.run_default(r"?[k, v] := k in int_range(200, 260), v = rand_vec(16) :put a {k => v}")
+        .unwrap();
+    db.run_default(r"?[k] := k in int_range(0, 260, 7) :rm a {k}")
+        .unwrap();
+
+    let exact_rows = db
+        .run_default(r"?[k, d] := *q{v: qv}, *a{k, v}, d = l2_dist(v, qv) :order d :limit 5")
+        .unwrap()
+        .rows;
+    let exact = exact_rows
+        .iter()
+        .map(|r| r[0].get_int().unwrap())
+        .collect_vec();
+    for name in ["int8", "pq"] {
+        let res = db
+            .run_default(&format!(
+                r"?[k, d, e] := *q{{v: qv}}, ~a:{name}{{k, v | query: qv, k: 5, ef: 100, bind_distance: d}},
+                                e = l2_dist(v, qv)
+                  :order d"
+            ))
+            .unwrap()
+            .rows;
+        assert_eq!(res.len(), 5);
+        // distances are re-ranked with the full vectors
+        for r in &res {
+            assert_eq!(r[1], r[2]);
+        }
+        let found = res
+            .iter()
+            .filter(|r| exact.contains(&r[0].get_int().unwrap()));
+        assert!(found.count() >= 4, "{name}: {res:?} vs {exact:?}");
+
+        // filters on the distance see the full vectors as well
+        let cutoff = exact_rows[2][1].get_float().unwrap();
+        let res = db
+            .run_script(
+                &format!(
+                    r"?[k] := *q{{v: qv}}, ~a:{name}{{k | query: qv, k: 5, ef: 100, bind_distance: d,
+                                                          filter: d <= $cutoff}}"
+                ),
+                BTreeMap::from([("cutoff".to_string(), DataValue::from(cutoff))]),
+                ScriptMutability::Immutable,
+            )
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|r| r[0].get_int().unwrap())
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            res,
+            exact[..3].iter().copied().sorted().collect_vec(),
+            "{name}"
+        );
+    }
+
+    let res = db.run_default(r"::indices a").unwrap().into_json();
+    let quantizations = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r[3]["quantization"].clone())
+        .collect_vec();
+    assert_eq!(
+        quantizations,
+        vec![
+            json!("Int8"),
+            json!({"pq_subspaces": 4, "pq_centroids": 64})
+        ]
+    );
+}
+
+#[test]
+fn test_hnsw_filtered_search() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: Int => t: Int, v: <F32; 8>}")
+        .unwrap();
+    db.run_default(
+        r"?[k, t, v] := k in int_range(500), t = k % 50, v = rand_vec(8) :put a {k => t, v}",
+    )
+    .unwrap();
+    db.run_default(r"::hnsw create a:idx {dim: 8, fields: [v], m: 16, ef: 50}")
+        .unwrap();
+    db.run_default(r":create q {k: Int => v: <F32; 8>}")
+        .unwrap();
+    db.run_default(r"?[k, v] := k = 0, v = rand_vec(8) :put q {k => v}")
+        .unwrap();
+
+    for (filter, k, ef, min_found) in [
+        // 2% of the rows, found by scanning the relation
+        ("t == 7", 5, 20, 5),
+        ("t < 25", 10, 50, 8),
+    ] {
+        let exact = db
+            .run_default(&format!(
+                r"?[k, d] := *q{{v: qv}}, *a{{k, t, v}}, {filter}, d = l2_dist(v, qv) :order d :limit {k}"
+            ))
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|r| r[0].get_int().unwrap())
+            .collect_vec();
+        let res = db
+            .run_default(&format!(
+                r"?[k, t] := *q{{v: qv}}, ~a:idx{{k, t | query: qv, k: {k}, ef: {ef}, filter: {filter}}}"
+            ))
+            .unwrap()
+            .rows;
+        assert_eq!(res.len(), k, "{filter}: {res:?}");
+        let found = res
+            .iter()
+            .filter(|r| exact.contains(&r[0].get_int().unwrap()));
+        assert!(found.count() >= min_found, "{filter}: {res:?} vs {exact:?}");
+    }
+}
+
+#[test]
+fn test_hnsw_binary_distances() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: Int => b: <Bit; 12>, v: <F32; 4>}")
+        .unwrap();
+    let res = db.run_default(r"::columns a").unwrap().into_json();
+    assert_eq!(res["rows"][1][3], json!("<Bit;12>"));
+    // bits beyond the length of the bit vector must be zero
+    assert!(db
+        .run_default(r"?[k, b, v] <- [[0, pack_bits([true, true, true, true, true, true, true, true, true, true, true, true, true, true, true, true]), [0, 0, 0, 0]]] :put a {k => b, v}")
+        .is_err());
+    assert!(db
+        .run_default(r"?[k, b, v] <- [[0, [true, false], [0, 0, 0, 0]]] :put a {k => b, v}")
+        .is_err());
+    assert!(db
+        .run_default(r"::hnsw create a:bad {dim: 12, fields: [b], m: 8, ef: 20, distance: L2}")
+        .is_err());
+    assert!(db
+        .run_default(r"::hnsw create a:bad {dim: 4, fields: [v], m: 8, ef: 20, distance: Hamming}")
+        .is_err());
+
+    let mut rng = rand::thread_rng();
+    let rows = (0..100)
+        .map(|k| {
+            let bits = (0..12).map(|_| DataValue::Bool(rng.gen())).collect_vec();
+            let floats = (0..4)
+                .map(|_| DataValue::from(rng.gen::<f64>()))
+                .collect_vec();
+            DataValue::List(vec![
+                DataValue::from(k),
+                DataValue::List(bits),
+                DataValue::List(floats),
+            ])
+        })
+        .collect_vec();
+    db.run_script(
+        r"?[k, b, v] <- $rows :put a {k => b, v}",
+        BTreeMap::from([("rows".to_string(), DataValue::List(rows))]),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    for (name, field, distance) in [
+        ("hamming", "b", "Hamming"),
+        ("jaccard", "b", "Jaccard"),
+        ("l1", "v", "L1"),
+    ] {
+        let dim = if field == "b" { 12 } else { 4 };
+        db.run_default(&format!(
+            r"::hnsw create a:{name} {{dim: {dim}, fields: [{field}], m: 16, ef: 100, distance: {distance}}}"
+        ))
+        .unwrap();
+    }
+
+    for (name, field, function) in [
+        ("hamming", "b", "hamming_dist"),
+        ("jaccard", "b", "jaccard_dist"),
+        ("l1", "v", "l1_dist"),
+    ] {
+        let res = db
+            .run_default(&format!(
+                r"?[k, d, e] := *a{{k: 0, {field}: q}}, ~a:{name}{{k, {field} | query: q, k: 10, ef: 100, bind_distance: d}},
+                                e = {function}({field}, q)
+                  :order d"
+            ))
+            .unwrap()
+            .rows;
+        assert_eq!(res.len(), 10);
+        assert_eq!(res[0][1], DataValue::from(0.));
+        for r in &res {
+            assert_eq!(r[1].get_float(), r[2].get_float(), "{name}: {res:?}");
+        }
+    }
+
+    // the query can also be a list of booleans
+    let res = db
+        .run_default(
+            r"?[d] := ~a:hamming{| query: [true, true, true, true, true, true, true, true, true, true, true, true], k: 1, ef: 20, bind_distance: d}",
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res.len(), 1);
+}
+
+#[test]
+fn test_hnsw_bulk_build() {
+    let check_db = |db: DbInstance| {
+        db.run_default(r":create a {k: Int => v: <F32; 8>}")
+            .unwrap();
+        db.run_default(r"?[k, v] := k in int_range(1000), v = rand_vec(8) :put a {k => v}")
+            .unwrap();
+        db.run_default(
+            r"::hnsw create a:idx {dim: 8, fields: [v], m: 16, ef: 50, filter: k % 10 != 9}",
+        )
+        .unwrap();
+        let res = db.run_default(r"::running").unwrap();
+        assert_eq!(res.headers, ["id", "started_at", "progress"]);
+        db.run_default(r":create q {k: Int => v: <F32; 8>}")
+            .unwrap();
+        db.run_default(r"?[k, v] := k in int_range(10), v = rand_vec(8) :put q {k => v}")
+            .unwrap();
+
+        let check = |n_indexed: i64| {
+            let res = db
+                .run_default(r"?[count(k)] := *a:idx{layer: 0, fr_k: k, to_k: k}")
+                .unwrap();
+            assert_eq!(res.rows[0][0].get_int().unwrap(), n_indexed);
+            let mut found = 0;
+            for qk in 0..10 {
+                let exact = db
+                    .run_default(&format!(
+                        r"?[k, d] := *q{{k: {qk}, v: qv}}, *a{{k, v}}, k % 10 != 9, d = l2_dist(v, qv) :order d :limit 10"
+                    ))
+                    .unwrap()
+                    .rows
+                    .into_iter()
+                    .map(|r| r[0].get_int().unwrap())
+                    .collect_vec();
+                let res = db
+                    .run_default(&format!(
+                        r"?[k] := *q{{k: {qk}, v: qv}}, ~a:idx{{k | query: qv, k: 10, ef: 50}}"
+                    ))
+                    .unwrap()
+                    .rows;
+                assert_eq!(res.len(), 10);
+                found += res
+                    .iter()
+                    .filter(|r| exact.contains(&r[0].get_int().unwrap()))
+                    .count();
+            }
+            assert!(found >= 90, "recall too low: {found}");
+        };
+        check(900);
+
+        // the bulk-built graph is maintained by later mutations
+        db.run_default(r"?[k, v] := k in int_range(1000, 1200), v = rand_vec(8) :put a {k => v}")
+            .unwrap();
+        db.run_default(r"?[k] := k in int_range(0, 300) :rm a {k}")
+            .unwrap();
+        check(900 + 180 - 270);
+    };
+
+    check_db(DbInstance::new("mem", "", "").unwrap());
+    #[cfg(feature = "storage-sqlite")]
+    {
+        let path = "_test_hnsw_bulk_build.db";
+        let _ = std::fs::remove_file(path);
+        check_db(DbInstance::new("sqlite", path, "").unwrap());
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(format!("{path}-wal"));
+        let _ = std::fs::remove_file(format!("{path}-shm"));
+    }
+}
+
+#[test]
+fn test_rank_fusion() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: String => t: String, v: <F32; 2>}")
+        .unwrap();
+    db.run_default(
+        r"?[k, t, v] <- [
+            ['a', 'red apple', [0, 0]],
+            ['b', 'green apple', [1, 0]],
+            ['c', 'red car', [5, 5]],
+            ['d', 'blue sky', [0.2, 0]]
+        ] :put a {k => t, v}",
+    )
+    .unwrap();
+    db.run_default(r"::hnsw create a:vec {dim: 2, dtype: F32, fields: [v], distance: L2, m: 10, ef_construction: 20}")
+        .unwrap();
+    db.run_default(r"::fts create a:fts {extractor: t, tokenizer: Simple}")
+        .unwrap();
+    let fuse = |options: &str| {
+        db.run_default(&format!(
+            r"
+            vec[k, d] := ~a:vec{{k | query: vec([0, 0]), k: 3, ef: 20, bind_distance: d}}
+            txt[k, s] := ~a:fts{{k | query: 'red', k: 10, bind_score: s}}
+            ?[k, s] <~ RankFusion(vec[], txt[], ascending: [true, false]{options})
+            :order -s
+            "
+        ))
+        .unwrap()
+        .into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r[0].as_str().unwrap().to_string())
+            .collect_vec()
+    };
+
+    assert_eq!(fuse(""), vec!["a", "c", "d", "b"]);
+    assert_eq!(fuse(", k: 0, weights: [4, 1]"), vec!["a", "d", "b", "c"]);
+    assert_eq!(
+        fuse(", method: 'weighted', weights: [2, 1], take: 3"),
+        vec!["a", "d", "c"]
+    );
+
+    let res = db
+        .run_default(r"?[k, s] <~ RankFusion(x[]) x[k, s] <- [['a', 1], ['b', 2], ['c', 2]]")
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 1. / 63.], ["b", 1. / 61.], ["c", 1. / 61.]])
+    );
+    assert!(db
+        .run_default(r"?[k, s] <~ RankFusion(x[], weights: [1, 2]) x[k, s] <- [['a', 1]]")
+        .is_err());
+}
+
 #[test]
 fn test_lsh_indexing2() {
     for i in 1..10 {
@@ -995,8 +1676,8 @@ fn test_lsh_indexing() {
     let _res = db
         .run_default(
             r"
-        ?[src_k, hash] :=
-            *a:lsh{src_k, hash}
+        ?[src_k, band] :=
+            *a:lsh{src_k, band}
         ",
         )
         .unwrap();
@@ -1023,14 +1704,74 @@ fn test_lsh_indexing() {
             ",
         )
         .unwrap();
-    for row in res.into_json()["rows"].as_array().unwrap() {
-        println!("{}", row);
-    }
-    let res = db.run_default("::indices a").unwrap();
-    for row in res.into_json()["rows"].as_array().unwrap() {
-        println!("{}", row);
-    }
-    db.run_default(r"::lsh drop a:lsh").unwrap();
+    for row in res.into_json()["rows"].as_array().unwrap() {
+        println!("{}", row);
+    }
+    let res = db.run_default("::indices a").unwrap();
+    for row in res.into_json()["rows"].as_array().unwrap() {
+        println!("{}", row);
+    }
+    db.run_default(r"::lsh drop a:lsh").unwrap();
+}
+
+#[test]
+fn test_lsh_thresholds() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_default(r":create a {k: Int => v: [Int]}").unwrap();
+    // the Jaccard similarity of row `k` to the query is (100 - k) / (100 + k)
+    db.run_default(
+        r"?[k, v] := k in int_range(0, 55, 5), v = int_range(k, k + 100) :put a {k => v}",
+    )
+    .unwrap();
+    db.run_default(
+        r"::lsh create a:lsh {extractor: v, tokenizer: Simple, n_perm: 200, target_threshold: 0.8}",
+    )
+    .unwrap();
+
+    let count = |threshold: f64| {
+        db.run_default(&format!(
+            r"?[k] := ~a:lsh{{k | query: int_range(0, 100), threshold: {threshold}}}"
+        ))
+        .unwrap()
+        .rows
+        .len()
+    };
+    // lower thresholds match shorter prefixes of at least as many bands
+    let (low, high) = (count(0.3), count(0.95));
+    assert!(high >= 1 && low >= high, "{low} {high}");
+
+    let res = db
+        .run_default(
+            r"?[k, s] := ~a:lsh{k | query: int_range(0, 100), threshold: 0.3, rerank: true, bind_similarity: s}",
+        )
+        .unwrap();
+    assert!(res.rows.iter().all(|r| r[1].get_float().unwrap() >= 0.3));
+    assert!(res
+        .rows
+        .contains(&vec![DataValue::from(0), DataValue::from(1.0)]));
+
+    let res = db
+        .run_default(
+            r"top[k, s] := ~a:lsh{k | query: int_range(0, 100), threshold: 0.3, k: 3, bind_similarity: s}
+              ?[k, s] := top[k, s] :order -s",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+    assert_eq!(res.rows[0], vec![DataValue::from(0), DataValue::from(1.0)]);
+    assert!(res.rows[2][0].get_int().unwrap() <= 15, "{:?}", res.rows);
+
+    assert!(db
+        .run_default(r"?[k] := ~a:lsh{k | query: int_range(0, 100), threshold: 1.5}")
+        .is_err());
+
+    db.run_default(r"?[k] <- [[0]] :rm a {k}").unwrap();
+    let res = db
+        .run_default(
+            r"?[k, s] := ~a:lsh{k | query: int_range(0, 100), threshold: 0.5, k: 1, bind_similarity: s}",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+    assert!(res.rows[0][1].get_float().unwrap() < 1.0);
 }
 
 #[test]
@@ -1553,4 +2294,737 @@ fn fts_drop() {
     db.run_default(r#"
         ::fts drop entity:fts_index
     "#).unwrap();
-}
\ No newline at end of file
+}
+
+#[test]
+fn incremental_backup_not_supported() {
+    let db = DbInstance::default();
+    db.run_default(r":create a {k => v}").unwrap();
+    assert!(db
+        .run_default(r"::backup to '_test_mem_backup' incremental")
+        .is_err());
+    assert!(db.run_default(r"::backup list '_test_mem_backup'").is_err());
+    assert!(db
+        .run_script(
+            r"::backup prune '_test_mem_backup' keep 1",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+}
+
+#[cfg(feature = "storage-rocksdb")]
+#[test]
+fn rocksdb_incremental_backup() {
+    let db_path = "_test_rocksdb_backup_src";
+    let backup_path = "_test_rocksdb_backup";
+    let restored_path = "_test_rocksdb_backup_restored";
+    for path in [db_path, backup_path, restored_path] {
+        let _ = std::fs::remove_dir_all(path);
+    }
+    {
+        let db = DbInstance::new("rocksdb", db_path, "").unwrap();
+        db.run_default(r":create a {k => v}").unwrap();
+        db.run_default(r"?[k, v] <- [[1, 'a']] :put a {k, v}")
+            .unwrap();
+        db.run_default(&format!("::backup to '{backup_path}' incremental"))
+            .unwrap();
+        db.run_default(r"?[k, v] <- [[2, 'b']] :put a {k, v}")
+            .unwrap();
+        let res = db
+            .run_default(&format!("::backup to '{backup_path}' incremental"))
+            .unwrap();
+        assert_eq!(res.rows[0][0], DataValue::from(2));
+        let res = db
+            .run_default(&format!("::backup list '{backup_path}'"))
+            .unwrap();
+        assert_eq!(res.rows.len(), 2);
+        db.run_default(&format!("::backup verify '{backup_path}' id 1"))
+            .unwrap();
+        db.run_default(&format!("::backup prune '{backup_path}' keep 1"))
+            .unwrap();
+        let res = db
+            .run_default(&format!("::backup list '{backup_path}'"))
+            .unwrap();
+        assert_eq!(res.rows.len(), 1);
+        assert!(db
+            .run_default(&format!("::backup verify '{backup_path}' id 1"))
+            .is_err());
+    }
+    crate::restore_cozo_rocksdb_backup(backup_path, restored_path, None).unwrap();
+    assert!(crate::restore_cozo_rocksdb_backup(backup_path, restored_path, None).is_err());
+    {
+        let db = DbInstance::new("rocksdb", restored_path, "").unwrap();
+        let res = db.run_default(r"?[k, v] := *a{k, v}").unwrap();
+        assert_eq!(res.rows.len(), 2);
+    }
+    for path in [db_path, backup_path, restored_path] {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+#[test]
+fn portable_backup() {
+    let backup_path = "_test_portable_backup";
+    let _ = std::fs::remove_file(backup_path);
+
+    let db = DbInstance::default();
+    db.run_default(r":create a {k => v}").unwrap();
+    db.run_default(r":create b {k => v}").unwrap();
+    db.run_default(r"?[k, v] <- [[1, 'x'], [2, 'y']] :put a {k => v}")
+        .unwrap();
+    db.run_default(r"?[k, v] <- [[3, 'z']] :put b {k => v}")
+        .unwrap();
+    db.backup_db(backup_path).unwrap();
+    assert!(db.backup_db(backup_path).is_err());
+
+    let restored = DbInstance::default();
+    restored.restore_backup(backup_path).unwrap();
+    let res = restored.run_default(r"?[k, v] := *a[k, v]").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert!(restored.restore_backup(backup_path).is_err());
+    restored.run_default(r":create c {k => v}").unwrap();
+    let res = restored.run_default(r"::relations").unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    let imported = DbInstance::default();
+    imported.run_default(r":create b {k => v}").unwrap();
+    imported
+        .import_from_backup(backup_path, &["b".to_string()])
+        .unwrap();
+    let res = imported.run_default(r"?[k, v] := *b[k, v]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, "z"]]));
+    assert!(imported
+        .import_from_backup(backup_path, &["c".to_string()])
+        .is_err());
+
+    let mut data = std::fs::read(backup_path).unwrap();
+    data[30] ^= 0xFF;
+    std::fs::write(backup_path, data).unwrap();
+    assert!(DbInstance::default().restore_backup(backup_path).is_err());
+
+    std::fs::remove_file(backup_path).unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn legacy_sqlite_backup() {
+    let backup_path = "_test_legacy_sqlite_backup.db";
+    let _ = std::fs::remove_file(backup_path);
+    {
+        let db = DbInstance::new("sqlite", backup_path, "").unwrap();
+        db.run_default(r":create a {k => v}").unwrap();
+        db.run_default(r"?[k, v] <- [[1, 'x'], [2, 'y']] :put a {k => v}")
+            .unwrap();
+    }
+    // backups made by older versions are sqlite files with a rollback journal
+    let journal_mode = |flags: ::sqlite::OpenFlags, mode: &str| {
+        let conn = ::sqlite::Connection::open_with_flags(backup_path, flags).unwrap();
+        let mut stmt = conn.prepare(format!("pragma journal_mode{mode};")).unwrap();
+        stmt.next().unwrap();
+        stmt.read::<String, _>(0).unwrap()
+    };
+    let read_write = ::sqlite::OpenFlags::new().with_read_write();
+    assert_eq!(journal_mode(read_write, "=delete"), "delete");
+    let original = std::fs::metadata(backup_path).unwrap().permissions();
+    let mut read_only = original.clone();
+    read_only.set_readonly(true);
+    std::fs::set_permissions(backup_path, read_only).unwrap();
+
+    let restored = DbInstance::default();
+    restored.restore_backup(backup_path).unwrap();
+    let res = restored.run_default(r"?[k, v] := *a[k, v]").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "x"], [2, "y"]]));
+
+    let imported = DbInstance::default();
+    imported.run_default(r":create a {k => v}").unwrap();
+    imported
+        .import_from_backup(backup_path, &["a".to_string()])
+        .unwrap();
+    let res = imported.run_default(r"?[k, v] := *a[k, v]").unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    // the backup file is left as it was
+    assert!(!std::path::Path::new(&format!("{backup_path}-wal")).exists());
+    assert!(!std::path::Path::new(&format!("{backup_path}-shm")).exists());
+    let read_only = ::sqlite::OpenFlags::new().with_read_only();
+    assert_eq!(journal_mode(read_only, ""), "delete");
+
+    std::fs::set_permissions(backup_path, original).unwrap();
+    std::fs::remove_file(backup_path).unwrap();
+}
+
+#[test]
+fn replication() {
+    let primary = DbInstance::default();
+    primary.enable_replication().unwrap();
+    primary.run_default(r":create a {k => v}").unwrap();
+    primary
+        .run_default(r"?[k, v] <- [[1, 'x']] :put a {k => v}")
+        .unwrap();
+
+    let (sender, mut receiver) = local_replication_channel();
+    let replica_id = primary.attach_replica(Box::new(sender)).unwrap();
+    primary
+        .run_default(r"?[k, v] <- [[2, 'y']] :put a {k => v}")
+        .unwrap();
+    assert_eq!(primary.replication_status().seq, 3);
+    assert_eq!(primary.replication_status().n_replicas, 1);
+
+    let replica = DbInstance::default();
+    let runner = replica.clone();
+    let handle = std::thread::spawn(move || runner.run_replica(&mut receiver));
+    while replica.replication_status().seq < 3 {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let res = replica.run_default(r"?[k, v] := *a[k, v]").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert!(replica
+        .run_default(r"?[k, v] <- [[3, 'z']] :put a {k => v}")
+        .is_err());
+    assert!(replica.run_default(r":create b {k => v}").is_err());
+    let res = replica.run_default(r"::replication").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("replica"));
+    assert_ne!(res.rows[0][3], DataValue::Null);
+
+    assert!(primary.detach_replica(replica_id));
+    handle.join().unwrap().unwrap();
+    replica.promote_replica().unwrap();
+    replica
+        .run_default(r"?[k, v] <- [[3, 'z']] :put a {k => v}")
+        .unwrap();
+    replica.run_default(r":create b {k => v}").unwrap();
+    let res = replica.run_default(r"::relations").unwrap();
+    assert_eq!(res.rows.len(), 2);
+}
+
+#[test]
+fn stalled_replica_does_not_block_commits() {
+    /// Blocks on every write set until released
+    struct StalledSender(crossbeam::channel::Receiver<()>);
+
+    impl ReplicationSender for StalledSender {
+        fn send(&mut self, msg: &ReplicationMessage) -> miette::Result<()> {
+            if let ReplicationMessage::WriteSet { .. } = msg {
+                self.0.recv().map_err(|_| miette::miette!("released"))?;
+            }
+            Ok(())
+        }
+    }
+
+    let primary = DbInstance::default();
+    primary.enable_replication().unwrap();
+    primary.run_default(r":create a {k => v}").unwrap();
+    let (release, stalled) = crossbeam::channel::unbounded();
+    let (sender, _receiver) = local_replication_channel();
+    primary
+        .attach_replica(Box::new(StalledSender(stalled)))
+        .unwrap();
+    primary.attach_replica(Box::new(sender)).unwrap();
+    for i in 0..10 {
+        primary
+            .run_default(&format!("?[k, v] <- [[{i}, 'x']] :put a {{k => v}}"))
+            .unwrap();
+    }
+    assert_eq!(primary.replication_status().seq, 11);
+    assert_eq!(primary.replication_status().n_replicas, 2);
+    drop(release);
+}
+
+#[test]
+fn snapshot_reads() {
+    fn check(db: DbInstance) {
+        db.run_default(":create a {a}").unwrap();
+        db.run_default("?[a] <- [[1]] :put a {a}").unwrap();
+        let read = |db: &DbInstance| {
+            db.run_script("?[a] := *a[a]", Default::default(), ScriptMutability::Immutable)
+                .unwrap()
+                .into_json()["rows"]
+                .clone()
+        };
+
+        let writer = db.multi_transaction(true);
+        writer
+            .run_script("?[a] <- [[2]] :put a {a}", Default::default())
+            .unwrap();
+        // not blocked by the writer, and does not see its uncommitted changes
+        assert_eq!(read(&db), json!([[1]]));
+
+        let reader = db.multi_transaction(false);
+        let snapshot_read = || {
+            reader
+                .run_script("?[a] := *a[a]", Default::default())
+                .unwrap()
+                .into_json()["rows"]
+                .clone()
+        };
+        assert_eq!(snapshot_read(), json!([[1]]));
+        writer.commit().unwrap();
+        assert_eq!(snapshot_read(), json!([[1]]));
+        reader.commit().unwrap();
+        assert_eq!(read(&db), json!([[1], [2]]));
+    }
+
+    check(DbInstance::default());
+    #[cfg(feature = "storage-sqlite")]
+    {
+        let path = "_test_snapshot_reads.db";
+        let _ = std::fs::remove_file(path);
+        check(DbInstance::new("sqlite", path, "").unwrap());
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(format!("{path}-wal"));
+        let _ = std::fs::remove_file(format!("{path}-shm"));
+    }
+}
+
+#[test]
+fn leiden_and_infomap() {
+    let db = DbInstance::default();
+    let script = r#"
+        clique[a, b] := a in [1, 2, 3, 4], b in [1, 2, 3, 4], a < b
+        e[a, b] := clique[a, b]
+        e[a, b] := clique[x, y], a = x + 10, b = y + 10
+        e[a, b] := a = 4, b = 11
+    "#;
+    for rule in [
+        "CommunityDetectionLeiden(e[], undirected: true)",
+        "CommunityDetectionLeiden(e[], resolution: 0.5)",
+        "CommunityDetectionInfomap(e[], undirected: true)",
+        "Infomap(e[], undirected: true, max_iter: 3)",
+    ] {
+        let res = db
+            .run_default(&format!("{script} ?[c, n] <~ {rule} :order n"))
+            .unwrap()
+            .into_json();
+        let labels = res["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row[0].as_i64().unwrap())
+            .collect_vec();
+        assert_eq!(labels, vec![0, 0, 0, 0, 1, 1, 1, 1], "{rule}");
+    }
+    assert!(db
+        .run_default(&format!(
+            "{script} ?[c, n] <~ CommunityDetectionLeiden(e[], resolution: 0)"
+        ))
+        .is_err());
+}
+
+#[test]
+fn spectral_centralities() {
+    let db = DbInstance::default();
+    let edges = "e[a, b] <- [[1, 2], [2, 3], [3, 1], [4, 1]]";
+    let scores = |query: &str| -> BTreeMap<i64, Vec<f64>> {
+        db.run_default(&format!("{edges} {query}"))
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row[0].get_int().unwrap(),
+                    row[1..].iter().map(|v| v.get_float().unwrap()).collect(),
+                )
+            })
+            .collect()
+    };
+
+    let ppr = scores("s[n] <- [[4]] ?[n, s] <~ PersonalizedPageRank(e[], s[])");
+    let total: f64 = ppr.values().map(|v| v[0]).sum();
+    assert!((total - 1.).abs() < 1e-6);
+    assert!(ppr[&1][0] > ppr[&2][0] && ppr[&2][0] > ppr[&3][0]);
+    let ppr = scores("s[n] <- [[2]] ?[n, s] <~ PersonalizedPageRank(e[], s[])");
+    assert!(!ppr.contains_key(&4));
+
+    let hits = scores("?[n, h, a] <~ HITS(e[])");
+    assert!(hits[&4][0] > hits[&2][0]);
+    assert!(hits[&1][1] > hits[&2][1]);
+    assert_eq!(hits[&4][1], 0.);
+
+    let eigen = scores("?[n, s] <~ EigenvectorCentrality(e[])");
+    assert!(eigen[&1][0] > eigen[&4][0]);
+
+    let katz = scores("?[n, s] <~ KatzCentrality(e[], alpha: 0.1, normalized: false)");
+    assert!((katz[&4][0] - 1.).abs() < 1e-6);
+    assert!(katz[&1][0] > katz[&2][0]);
+    assert!(db
+        .run_default(
+            "e[a, b] <- [[1, 2], [2, 1]] ?[n, s] <~ KatzCentrality(e[], alpha: 2, iterations: 20)"
+        )
+        .is_err());
+}
+
+#[test]
+fn flows_and_matchings() {
+    let db = DbInstance::default();
+    let network = r#"
+        e[] <- [['s', 'a', 3], ['s', 'b', 2], ['a', 'b', 1], ['a', 't', 2], ['b', 't', 3]]
+        src[] <- [['s']]
+        dst[] <- [['t']]
+    "#;
+    let res = db
+        .run_default(&format!(
+            "{network} f[a, b, c] <~ MaxFlow(e[], src[], dst[]) ?[sum(c)] := f['s', _, c]"
+        ))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5.0]]));
+    let res = db
+        .run_default(&format!(
+            "{network} ?[n, side] <~ MinCut(e[], src[], dst[]) :order n"
+        ))
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", false], ["b", false], ["s", true], ["t", false]])
+    );
+    assert!(db
+        .run_default(&format!(
+            "{network} ?[a, b, c] <~ MaxFlow(e[], src[], src[])"
+        ))
+        .is_err());
+
+    let workers = r#"
+        e[] <- [['w1', 'j1', 4], ['w1', 'j2', 1], ['w2', 'j1', 2], ['w2', 'j2', 5], ['w3', 'j1', 10]]
+    "#;
+    let res = db
+        .run_default(&format!("{workers} ?[w, j] <~ BipartiteMatching(e[])"))
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db
+        .run_default(&format!(
+            "{workers} ?[w, j] <~ BipartiteMatching(e[], weighted: true)"
+        ))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["w2", "j2"], ["w3", "j1"]]));
+    let res = db
+        .run_default(&format!(
+            "{workers} ?[w, j] <~ BipartiteMatching(e[], weighted: true, minimize: true)"
+        ))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["w1", "j2"], ["w2", "j1"]]));
+}
+
+#[test]
+fn subgraph_matching() {
+    let db = DbInstance::default();
+    let count = |data: &str, pattern: &str, options: &str| -> usize {
+        let res = db
+            .run_default(&format!(
+                "d[] <- {data} p[] <- {pattern} m[i, p, d] <~ SubgraphMatch(d[], p[]{options}) \
+                 ?[count_unique(i)] := m[i, _, _]"
+            ))
+            .unwrap();
+        res.rows[0][0].get_int().unwrap() as usize
+    };
+    let diamond = "[[1, 2], [2, 3], [3, 4], [4, 1], [1, 3]]";
+    let square = "[['a', 'b'], ['b', 'c'], ['c', 'd'], ['d', 'a']]";
+    let triangle = "[['a', 'b'], ['b', 'c'], ['c', 'a']]";
+    assert_eq!(count(diamond, square, ", undirected: true"), 8);
+    assert_eq!(
+        count(diamond, square, ", undirected: true, distinct: true"),
+        1
+    );
+    assert_eq!(
+        count(diamond, square, ", undirected: true, induced: true"),
+        0
+    );
+    assert_eq!(count(diamond, triangle, ", undirected: true"), 12);
+    assert_eq!(
+        count(diamond, triangle, ", undirected: true, distinct: true"),
+        2
+    );
+    assert_eq!(count(diamond, triangle, ""), 3);
+
+    let payments = "[['a', 'b', 'pays'], ['a', 'c', 'pays'], ['a', 'd', 'owns']]";
+    let fan_out = "[['x', 'y', 'pays'], ['x', 'z', 'pays']]";
+    assert_eq!(count(payments, fan_out, ", labeled: true"), 2);
+    assert_eq!(
+        count(payments, fan_out, ", labeled: true, distinct: true"),
+        1
+    );
+    assert_eq!(count(payments, fan_out, ""), 6);
+    assert_eq!(count(payments, fan_out, ", limit: 4"), 4);
+    assert!(db
+        .run_default(&format!(
+            "d[] <- {payments} p[] <- {fan_out} ?[i, p, d] <~ SubgraphMatch(d[], p[], limit: 0)"
+        ))
+        .is_err());
+
+    let res = db
+        .run_default(&format!(
+            "d[] <- {payments} p[] <- [['x', 'y', 'owns']] \
+             m[i, p, d] <~ SubgraphMatch(d[], p[], labeled: true) ?[p, d] := m[_, p, d] :order p"
+        ))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["x", "a"], ["y", "d"]]));
+}
+
+#[test]
+fn node_embeddings_into_hnsw() {
+    let db = DbInstance::default();
+    db.run_default(":create emb {node: Int => v: <F32; 8>}")
+        .unwrap();
+    db.run_default(
+        r"
+        ::hnsw create emb:vec {
+            dim: 8, dtype: F32, fields: [v], distance: Cosine, m: 16, ef_construction: 20
+        }
+        ",
+    )
+    .unwrap();
+    db.run_default(
+        r"
+        e[a, b] := a in int_range(5), b in int_range(5), a != b
+        e[a, b] := a in int_range(5, 10), b in int_range(5, 10), a != b
+        e[a, b] := a = 4, b = 5
+        ?[node, v] <~ Node2Vec(e[], undirected: true, dimensions: 8, q: 0.5, epochs: 3, seed: 7)
+        :put emb {node => v}
+        ",
+    )
+    .unwrap();
+    let res = db
+        .run_default(
+            r"
+            ?[n] := *emb{node: 0, v: q}, ~emb:vec{node: n | query: q, k: 3, ef: 20}
+            ",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    let res = db
+        .run_default(
+            r"
+            e[] <- [[1, 2], [2, 3]]
+            ?[n, v] <~ DeepWalk(e[], dimensions: 4, walks_per_node: 2, seed: 1)
+            ",
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+    assert!(matches!(&res.rows[0][1], DataValue::Vec(Vector::F32(v)) if v.len() == 4));
+    assert!(db
+        .run_default("e[] <- [[1, 2]] ?[n, v] <~ DeepWalk(e[], seed: -1)")
+        .is_err());
+}
+
+#[test]
+fn biconnectivity_and_cores() {
+    let db = DbInstance::default();
+    let network = r"
+        e[] <- [['r1', 'r2'], ['r2', 'r3'], ['r3', 'r1'], ['r3', 'r4'], ['r4', 'r5'],
+                ['r5', 'r6'], ['r6', 'r4'], ['r6', 'r7']]
+    ";
+    let run = |rule: &str| {
+        db.run_default(&format!("{network} {rule}"))
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("?[n] <~ ArticulationPoints(e[])"),
+        json!([["r3"], ["r4"], ["r6"]])
+    );
+    assert_eq!(
+        run("b[a, b] <~ Bridges(e[]) ?[x, y] := b[x, y], x < y ?[x, y] := b[y, x], x < y"),
+        json!([["r3", "r4"], ["r6", "r7"]])
+    );
+    assert_eq!(
+        run("c[n, c] <~ BiconnectedComponents(e[]) ?[count_unique(c)] := c[_, c]"),
+        json!([[4]])
+    );
+    assert_eq!(
+        run("c[n, c] <~ BiconnectedComponents(e[]) ?[n, count(c)] := c[n, c], n = 'r4'"),
+        json!([["r4", 2]])
+    );
+    assert_eq!(
+        run("?[n, k] <~ KCore(e[]) :order n"),
+        json!([
+            ["r1", 2],
+            ["r2", 2],
+            ["r3", 2],
+            ["r4", 2],
+            ["r5", 2],
+            ["r6", 2],
+            ["r7", 1]
+        ])
+    );
+}
+
+#[test]
+fn temporal_paths() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[from, to, dep, arr] <- [['a', 'b', 1, 2], ['b', 'c', 3, 5], ['a', 'c', 0, 10],
+                                  ['b', 'c', 1, 2], ['a', 'b', 6, 7], ['b', 'c', 7, 8]]
+        :create schedule {from, to, dep, arr}
+    ",
+    )
+    .unwrap();
+    let earliest = db
+        .run_default(
+            r"
+        s[] <- [['a']]
+        ?[s, n, t, p] <~ TemporalEarliestArrival(*schedule[], s[])
+    ",
+        )
+        .unwrap()
+        .into_json()["rows"]
+        .clone();
+    assert_eq!(
+        earliest,
+        json!([["a", "b", 2, ["a", "b"]], ["a", "c", 5, ["a", "b", "c"]]])
+    );
+    let latest = db
+        .run_default(
+            r"
+        s[] <- [['c']]
+        ?[s, n, t, p] <~ TemporalLatestDeparture(*schedule[], s[], end_time: 6)
+    ",
+        )
+        .unwrap()
+        .into_json()["rows"]
+        .clone();
+    assert_eq!(
+        latest,
+        json!([["c", "a", 1, ["a", "b", "c"]], ["c", "b", 3, ["b", "c"]]])
+    );
+    let fastest = db
+        .run_default(
+            r"
+        s[] <- [['a']]
+        ?[s, n, dep, arr, p] <~ TemporalFastestPath(*schedule[], s[])
+    ",
+        )
+        .unwrap()
+        .into_json()["rows"]
+        .clone();
+    assert_eq!(
+        fastest,
+        json!([
+            ["a", "b", 1, 2, ["a", "b"]],
+            ["a", "c", 6, 8, ["a", "b", "c"]]
+        ])
+    );
+
+    // schedules kept with validity can be travelled as of any time
+    db.run_default(
+        r"
+        ?[from, to, dep, arr, at] <- [['a', 'c', 0, 1, [10, true]], ['a', 'c', 0, 1, [20, false]]]
+        :create timetable {from, to, dep, arr, at: Validity}
+    ",
+    )
+    .unwrap();
+    let as_of = |at: i64| {
+        db.run_default(&format!(
+            "s[] <- [['a']] ?[s, n, t, p] <~ TemporalEarliestArrival(*timetable[@ {at}], s[])"
+        ))
+        .unwrap()
+        .rows
+        .len()
+    };
+    assert_eq!(as_of(15), 1);
+    assert_eq!(as_of(25), 0);
+}
+
+#[test]
+fn approximate_betweenness() {
+    let db = DbInstance::default();
+    let graph = r"
+        e[] <- [['a', 'b'], ['b', 'c'], ['c', 'd'], ['a', 'e'], ['e', 'd'], ['d', 'f'], ['b', 'e']]
+    ";
+    let centralities = |rule: &str| {
+        let res = db.run_default(&format!("{graph} {rule}")).unwrap();
+        res.rows
+            .into_iter()
+            .map(|row| (row[0].clone(), row[1].get_float().unwrap()))
+            .collect::<Vec<_>>()
+    };
+    let exact = centralities("?[n, c] <~ BetweennessCentrality(e[], undirected: true)");
+    // with as many pivots as nodes the estimate is exact
+    let full = centralities(
+        "?[n, c] <~ ApproxBetweennessCentrality(e[], undirected: true, samples: 100, seed: 1)",
+    );
+    assert_eq!(exact.len(), full.len());
+    for ((n1, c1), (n2, c2)) in exact.iter().zip(&full) {
+        assert_eq!(n1, n2);
+        assert!((c1 - c2).abs() < 1e-6, "{n1:?}: {c1} != {c2}");
+    }
+    let sampled = centralities(
+        "?[n, c] <~ ApproxBetweennessCentrality(e[], undirected: true, samples: 3, seed: 1)",
+    );
+    assert_eq!(sampled.len(), 6);
+    assert!(sampled.iter().all(|(_, c)| c.is_finite() && *c >= 0.));
+    // `f` is a leaf, so no shortest path passes through it
+    assert_eq!(sampled[5], (DataValue::from("f"), 0.));
+    for rule in ["samples: 0", "seed: -1", "samples: 'many'"] {
+        assert!(db
+            .run_default(&format!(
+                "{graph} ?[n, c] <~ ApproxBetweennessCentrality(e[], {rule})"
+            ))
+            .is_err());
+    }
+
+    let paths = db
+        .run_default(&format!(
+            "{graph} s[] <- [['a'], ['f']]
+             ?[s, t, c, p] <~ ShortestPathDijkstra(e[], s[], undirected: true)
+             :order s, t"
+        ))
+        .unwrap()
+        .rows;
+    assert_eq!(paths.len(), 12);
+    assert_eq!(paths[3][2], DataValue::from(2.));
+    assert_eq!(paths[6][2], DataValue::from(3.));
+}
+
+#[test]
+fn cached_graph_projections() {
+    let db = DbInstance::default();
+    db.run_default(
+        r"
+        ?[fr, to, at] <- [['a', 'b', [0, true]], ['b', 'c', [0, true]], ['c', 'a', [10, true]]]
+        :create edge {fr, to, at: Validity}
+    ",
+    )
+    .unwrap();
+    let run = |query: &str| db.run_default(query).unwrap().into_json()["rows"].clone();
+    let cores = || run("?[n, k] <~ KCore(*edge[]) :order n");
+    let first = cores();
+    assert_eq!(first, json!([["a", 2], ["b", 2], ["c", 2]]));
+    // served from the cache, and must not differ
+    assert_eq!(cores(), first);
+
+    run("?[fr, to, at] <- [['a', 'd', [20, true]]] :put edge {fr, to, at}");
+    assert_eq!(cores(), json!([["a", 2], ["b", 2], ["c", 2], ["d", 1]]));
+    // graphs at different points in time are kept apart
+    assert_eq!(
+        run("?[n, c] <~ StronglyConnectedComponents(*edge[@ 5]) :order n"),
+        json!([["a", 0], ["b", 1], ["c", 2]])
+    );
+    assert_eq!(
+        run("?[n, c] <~ StronglyConnectedComponents(*edge[@ 15]) :order n"),
+        json!([["a", 0], ["b", 0], ["c", 0]])
+    );
+
+    // a relation created under the same name is a different relation
+    run("::remove edge");
+    run(r"
+        ?[fr, to, at] <- [['x', 'y', [0, true]]]
+        :create edge {fr, to, at: Validity}
+    ");
+    assert_eq!(cores(), json!([["x", 1], ["y", 1]]));
+
+    // writes within the same transaction are seen, and aborted writes are not
+    let tx = db.multi_transaction(true);
+    tx.run_script(
+        "?[fr, to, at] <- [['y', 'z', [0, true]]] :put edge {fr, to, at}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = tx
+        .run_script("?[n, k] <~ KCore(*edge[])", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+    tx.abort().unwrap();
+    assert_eq!(cores(), json!([["x", 1], ["y", 1]]));
+}
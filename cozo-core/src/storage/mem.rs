@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
+use crossbeam::sync::ShardedLock;
 use std::cmp::Ordering;
 use std::collections::btree_map::Range;
 use std::collections::BTreeMap;
@@ -14,8 +14,9 @@ use std::default::Default;
 use std::iter::Fuse;
 use std::mem;
 use std::ops::Bound;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 
+use im::ordmap::{Iter as OrdMapIter, OrdMap};
 use itertools::Itertools;
 use miette::{bail, Result};
 
@@ -28,6 +29,7 @@ use crate::utils::swap_option_result;
 /// Create a database backed by memory.
 /// This is the fastest storage, but non-persistent.
 /// Supports concurrent readers but only a single writer.
+/// Readers work on snapshots and are never blocked by the writer.
 pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     let ret = crate::Db::new(MemStorage::default())?;
 
@@ -35,10 +37,21 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
     Ok(ret)
 }
 
+type MemMap = OrdMap<Vec<u8>, Vec<u8>>;
+
 /// The non-persistent storage
 #[derive(Default, Clone)]
 pub struct MemStorage {
-    store: Arc<ShardedLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    /// The latest committed version of the data. The map shares structure between versions,
+    /// so taking a snapshot of it is cheap.
+    store: Arc<ShardedLock<MemMap>>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl MemStorage {
+    fn snapshot(&self) -> MemMap {
+        self.store.read().unwrap().clone()
+    }
 }
 
 impl<'s> Storage<'s> for MemStorage {
@@ -50,11 +63,15 @@ impl<'s> Storage<'s> for MemStorage {
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         Ok(if write {
-            let wtr = self.store.write().unwrap();
-            MemTx::Writer(wtr, Default::default())
+            let guard = self.write_lock.lock().unwrap();
+            MemTx::Writer {
+                storage: self,
+                _guard: guard,
+                stored: self.snapshot(),
+                delta: Default::default(),
+            }
         } else {
-            let rdr = self.store.read().unwrap();
-            MemTx::Reader(rdr)
+            MemTx::Reader(self.snapshot())
         })
     }
 
@@ -66,28 +83,39 @@ impl<'s> Storage<'s> for MemStorage {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()> {
-        let mut store = self.store.write().unwrap();
+        let _guard = self.write_lock.lock().unwrap();
+        let mut store = self.snapshot();
         for pair in data {
             let (k, v) = pair?;
             store.insert(k, v);
         }
+        *self.store.write().unwrap() = store;
         Ok(())
     }
 }
 
 pub enum MemTx<'s> {
-    Reader(ShardedLockReadGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>),
-    Writer(
-        ShardedLockWriteGuard<'s, BTreeMap<Vec<u8>, Vec<u8>>>,
-        BTreeMap<Vec<u8>, Option<Vec<u8>>>,
-    ),
+    /// A snapshot of the data at the start of the transaction
+    Reader(MemMap),
+    /// Holds the write lock. Changes are made to a private version of the data,
+    /// which replaces the shared one on commit.
+    Writer {
+        storage: &'s MemStorage,
+        _guard: MutexGuard<'s, ()>,
+        stored: MemMap,
+        delta: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    },
 }
 
 impl<'s> StoreTx<'s> for MemTx<'s> {
     fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.get(key).cloned(),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => match cache.get(key) {
                 Some(r) => r.clone(),
                 None => wtr.get(key).cloned(),
             },
@@ -99,13 +127,31 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer { delta: cache, .. } => {
                 cache.insert(key.to_vec(), Some(val.to_vec()));
                 Ok(())
             }
         }
     }
 
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        match self {
+            MemTx::Reader(_) => {
+                bail!("write in read transaction")
+            }
+            MemTx::Writer { delta: cache, .. } => {
+                for pair in data {
+                    let (key, val) = pair?;
+                    cache.insert(key, Some(val));
+                }
+                Ok(())
+            }
+        }
+    }
+
     fn supports_par_put(&self) -> bool {
         false
     }
@@ -119,7 +165,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(_, cache) => {
+            MemTx::Writer { delta: cache, .. } => {
                 cache.insert(key.to_vec(), None);
                 Ok(())
             }
@@ -131,7 +177,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
             MemTx::Reader(_) => {
                 bail!("write in read transaction")
             }
-            MemTx::Writer(ref mut wtr, _) => {
+            MemTx::Writer { stored: wtr, .. } => {
                 let keys = wtr
                     .range(lower.to_vec()..upper.to_vec())
                     .map(|kv| kv.0.clone())
@@ -148,7 +194,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.contains_key(key),
-            MemTx::Writer(wtr, cache) => match cache.get(key) {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => match cache.get(key) {
                 Some(r) => r.is_some(),
                 None => wtr.contains_key(key),
             },
@@ -158,7 +208,12 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     fn commit(&mut self) -> Result<()> {
         match self {
             MemTx::Reader(_) => Ok(()),
-            MemTx::Writer(wtr, cached) => {
+            MemTx::Writer {
+                storage,
+                stored: wtr,
+                delta: cached,
+                ..
+            } => {
                 let mut cache = BTreeMap::default();
                 mem::swap(&mut cache, cached);
                 for (k, mv) in cache {
@@ -171,6 +226,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                         }
                     }
                 }
+                *storage.store.write().unwrap() = wtr.clone();
                 Ok(())
             }
         }
@@ -189,7 +245,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok(decode_tuple_from_kv(k, v, None))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIter {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => Box::new(CacheIter {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -215,7 +275,7 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 }
                 .map(Ok),
             ),
-            MemTx::Writer(stored, delta) => Box::new(
+            MemTx::Writer { stored, delta, .. } => Box::new(
                 SkipDualIterator {
                     stored,
                     delta,
@@ -241,7 +301,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
                 rdr.range(lower.to_vec()..upper.to_vec())
                     .map(|(k, v)| Ok((k.clone(), v.clone()))),
             ),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => Box::new(CacheIterRaw {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -256,7 +320,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     {
         Ok(match self {
             MemTx::Reader(rdr) => rdr.range(lower.to_vec()..upper.to_vec()).count(),
-            MemTx::Writer(wtr, cache) => (CacheIterRaw {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => (CacheIterRaw {
                 change_iter: cache.range(lower.to_vec()..upper.to_vec()).fuse(),
                 db_iter: wtr.range(lower.to_vec()..upper.to_vec()).fuse(),
                 change_cache: None,
@@ -272,7 +340,11 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
     {
         match self {
             MemTx::Reader(rdr) => Box::new(rdr.iter().map(|(k, v)| Ok((k.clone(), v.clone())))),
-            MemTx::Writer(wtr, cache) => Box::new(CacheIterRaw {
+            MemTx::Writer {
+                stored: wtr,
+                delta: cache,
+                ..
+            } => Box::new(CacheIterRaw {
                 change_iter: cache.iter().fuse(),
                 db_iter: wtr.iter().fuse(),
                 change_cache: None,
@@ -369,7 +441,7 @@ where
 
 struct CacheIter<'a> {
     change_iter: Fuse<Range<'a, Vec<u8>, Option<Vec<u8>>>>,
-    db_iter: Fuse<Range<'a, Vec<u8>, Vec<u8>>>,
+    db_iter: Fuse<OrdMapIter<'a, Vec<u8>, Vec<u8>>>,
     change_cache: Option<(&'a Vec<u8>, &'a Option<Vec<u8>>)>,
     db_cache: Option<(&'a Vec<u8>, &'a Vec<u8>)>,
 }
@@ -440,27 +512,47 @@ impl Iterator for CacheIter<'_> {
     }
 }
 
+/// Sorted maps that [`SkipIterator`] can seek in.
+pub(crate) trait SeekableMap {
+    /// The first pair with key in the range `[lower, upper)`.
+    fn seek(&self, lower: &[u8], upper: &[u8]) -> Option<(&Vec<u8>, &Vec<u8>)>;
+}
+
+impl SeekableMap for BTreeMap<Vec<u8>, Vec<u8>> {
+    fn seek(&self, lower: &[u8], upper: &[u8]) -> Option<(&Vec<u8>, &Vec<u8>)> {
+        self.range::<[u8], (Bound<&[u8]>, Bound<&[u8]>)>((
+            Bound::Included(lower),
+            Bound::Excluded(upper),
+        ))
+        .next()
+    }
+}
+
+impl SeekableMap for MemMap {
+    fn seek(&self, lower: &[u8], upper: &[u8]) -> Option<(&Vec<u8>, &Vec<u8>)> {
+        self.range::<(Bound<&[u8]>, Bound<&[u8]>), [u8]>((
+            Bound::Included(lower),
+            Bound::Excluded(upper),
+        ))
+        .next()
+    }
+}
+
 /// Keep an eye on https://github.com/rust-lang/rust/issues/49638
-pub(crate) struct SkipIterator<'a> {
-    pub(crate) inner: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+pub(crate) struct SkipIterator<'a, M: SeekableMap> {
+    pub(crate) inner: &'a M,
     pub(crate) upper: Vec<u8>,
     pub(crate) valid_at: ValidityTs,
     pub(crate) next_bound: Vec<u8>,
     pub(crate) size_hint: Option<usize>,
 }
 
-impl<'a> Iterator for SkipIterator<'a> {
+impl<'a, M: SeekableMap> Iterator for SkipIterator<'a, M> {
     type Item = Tuple;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let nxt = self
-                .inner
-                .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
-                    Bound::Included(&self.next_bound),
-                    Bound::Excluded(&self.upper),
-                ))
-                .next();
+            let nxt = self.inner.seek(&self.next_bound, &self.upper);
             match nxt {
                 None => return None,
                 Some((candidate_key, candidate_val)) => {
@@ -478,7 +570,7 @@ impl<'a> Iterator for SkipIterator<'a> {
 }
 
 struct SkipDualIterator<'a> {
-    stored: &'a BTreeMap<Vec<u8>, Vec<u8>>,
+    stored: &'a MemMap,
     delta: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
     upper: Vec<u8>,
     valid_at: ValidityTs,
@@ -490,13 +582,7 @@ impl<'a> Iterator for SkipDualIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let stored_nxt = self
-                .stored
-                .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
-                    Bound::Included(&self.next_bound),
-                    Bound::Excluded(&self.upper),
-                ))
-                .next();
+            let stored_nxt = self.stored.seek(&self.next_bound, &self.upper);
             let delta_nxt = self
                 .delta
                 .range::<Vec<u8>, (Bound<&Vec<u8>>, Bound<&Vec<u8>>)>((
@@ -6,8 +6,11 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::path::Path;
+
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
@@ -47,8 +50,51 @@ pub trait Storage<'s>: Send + Sync + Clone {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()>;
+
+    /// Create a new generation of an online, incremental backup in the directory `path`.
+    /// Data already present in the directory from previous generations should be reused.
+    /// The default implementation returns an error, meaning that the engine only supports
+    /// full backups.
+    fn create_incremental_backup(&'s self, _path: &Path) -> Result<BackupGeneration> {
+        bail!(IncrementalBackupNotSupported(self.storage_kind()))
+    }
+
+    /// List the generations of incremental backups in the directory `path`, oldest first.
+    fn list_backup_generations(&'s self, _path: &Path) -> Result<Vec<BackupGeneration>> {
+        bail!(IncrementalBackupNotSupported(self.storage_kind()))
+    }
+
+    /// Remove all but the latest `keep` generations of incremental backups in the directory `path`.
+    fn prune_backup_generations(&'s self, _path: &Path, _keep: usize) -> Result<()> {
+        bail!(IncrementalBackupNotSupported(self.storage_kind()))
+    }
+
+    /// Verify the integrity of a generation of incremental backups in the directory `path`.
+    /// `None` refers to the latest generation.
+    fn verify_backup_generation(&'s self, _path: &Path, _id: Option<u32>) -> Result<()> {
+        bail!(IncrementalBackupNotSupported(self.storage_kind()))
+    }
+}
+
+/// A generation of an incremental backup created by [`Storage::create_incremental_backup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupGeneration {
+    /// The ID of the generation, increasing for newer generations
+    pub id: u32,
+    /// Creation time as seconds since the UNIX epoch
+    pub timestamp: i64,
+    /// Total size of the files in the generation, including files shared with other generations
+    pub size: u64,
+    /// Number of files in the generation
+    pub num_files: u32,
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The {0} storage engine does not support incremental backups")]
+#[diagnostic(code(storage::incremental_backup_not_supported))]
+#[diagnostic(help("Use a full backup instead"))]
+pub(crate) struct IncrementalBackupNotSupported(pub(crate) &'static str);
+
 /// Trait for the associated transaction type of a storage engine.
 /// A transaction needs to guarantee MVCC semantics for all operations.
 pub trait StoreTx<'s>: Sync {
@@ -79,6 +125,22 @@ pub trait StoreTx<'s>: Sync {
         panic!("par_put is not supported")
     }
 
+    /// Put multiple key-value pairs into the storage, in strictly ascending order of keys.
+    /// Unlike [`Storage::batch_put`], the data is written inside the transaction.
+    /// Implementations may skip locking the keys, so no other transaction may write to them,
+    /// as is the case for the relation of an index being created.
+    /// The default implementation calls `put` for each pair.
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        for pair in data {
+            let (key, val) = pair?;
+            self.put(&key, &val)?;
+        }
+        Ok(())
+    }
+
     /// Delete a key-value pair from the storage.
     fn del(&mut self, key: &[u8]) -> Result<()>;
 
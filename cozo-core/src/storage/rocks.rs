@@ -10,15 +10,15 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, IntoDiagnostic, Result, WrapErr};
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{BackupGenerationInfo, DbBuilder, DbIter, RocksDb, Tx};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{BackupGeneration, Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
 
@@ -110,6 +110,71 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
     Ok(ret)
 }
 
+/// Restore a RocksDB database at `path` from a generation of incremental backups
+/// created by `::backup to ... incremental`, verifying the checksums of the backup first.
+/// `backup_id` of `None` refers to the latest generation.
+/// The target path must not contain an existing database.
+pub fn restore_cozo_rocksdb_backup(
+    backup_path: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+    backup_id: Option<u32>,
+) -> Result<()> {
+    let path_buf = PathBuf::from(path.as_ref());
+    let mut manifest_path = path_buf.clone();
+    manifest_path.push("manifest");
+    if manifest_path.exists() {
+        bail!(
+            "Cannot restore backup: a database already exists at {}",
+            path_buf.to_string_lossy()
+        );
+    }
+    fs::create_dir_all(&path_buf).map_err(|err| {
+        BadDbInit(format!(
+            "cannot create directory {}: {}",
+            path_buf.to_string_lossy(),
+            err
+        ))
+    })?;
+
+    let mut store_path = path_buf;
+    store_path.push("data");
+    let store_path = store_path
+        .to_str()
+        .ok_or_else(|| miette!("bad path name"))?;
+    let backup_path = backup_path
+        .as_ref()
+        .to_str()
+        .ok_or_else(|| miette!("bad path name"))?;
+    cozorocks::restore_from_backup(backup_path, store_path, backup_id)?;
+
+    fs::write(
+        manifest_path,
+        rmp_serde::to_vec_named(&DbManifest {
+            storage_version: CURRENT_STORAGE_VERSION,
+        })
+        .into_diagnostic()
+        .wrap_err_with(|| "when serializing manifest")?,
+    )
+    .into_diagnostic()
+    .wrap_err_with(|| "when serializing manifest")?;
+    Ok(())
+}
+
+fn path_to_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| miette!("bad path name"))
+}
+
+impl From<BackupGenerationInfo> for BackupGeneration {
+    fn from(info: BackupGenerationInfo) -> Self {
+        Self {
+            id: info.id,
+            timestamp: info.timestamp,
+            size: info.size,
+            num_files: info.num_files,
+        }
+    }
+}
+
 /// RocksDB storage engine
 #[derive(Clone)]
 pub struct RocksDbStorage {
@@ -148,6 +213,28 @@ impl Storage<'_> for RocksDbStorage {
         }
         Ok(())
     }
+
+    fn create_incremental_backup(&self, path: &Path) -> Result<BackupGeneration> {
+        fs::create_dir_all(path).into_diagnostic()?;
+        let info = self.db.create_backup(path_to_str(path)?)?;
+        Ok(info.into())
+    }
+
+    fn list_backup_generations(&self, path: &Path) -> Result<Vec<BackupGeneration>> {
+        let infos = cozorocks::list_backups(path_to_str(path)?)?;
+        Ok(infos.into_iter().map(BackupGeneration::from).collect())
+    }
+
+    fn prune_backup_generations(&self, path: &Path, keep: usize) -> Result<()> {
+        let keep = u32::try_from(keep).into_diagnostic()?;
+        cozorocks::purge_old_backups(path_to_str(path)?, keep)?;
+        Ok(())
+    }
+
+    fn verify_backup_generation(&self, path: &Path, id: Option<u32>) -> Result<()> {
+        cozorocks::verify_backup(path_to_str(path)?, id)?;
+        Ok(())
+    }
 }
 
 pub struct RocksDbTx {
@@ -176,6 +263,17 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         Ok(self.db_tx.put(key, val)?)
     }
 
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        for pair in data {
+            let (key, val) = pair?;
+            self.db_tx.put_untracked(&key, &val)?;
+        }
+        Ok(())
+    }
+
     #[inline]
     fn del(&mut self, key: &[u8]) -> Result<()> {
         Ok(self.db_tx.del(key)?)
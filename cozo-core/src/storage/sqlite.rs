@@ -7,13 +7,11 @@
  */
 
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use ::sqlite::Connection;
-use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
-use either::{Either, Left, Right};
-use miette::{bail, miette, IntoDiagnostic, Result};
-use sqlite::{ConnectionThreadSafe, State, Statement};
+use miette::{bail, ensure, miette, IntoDiagnostic, Result};
+use sqlite::{ConnectionThreadSafe, OpenFlags, State, Statement};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
@@ -24,13 +22,16 @@ use crate::utils::swap_option_result;
 /// The Sqlite storage engine
 #[derive(Clone)]
 pub struct SqliteStorage {
-    lock: Arc<ShardedLock<()>>,
+    write_lock: Arc<Mutex<()>>,
     name: PathBuf,
     pool: Arc<Mutex<Vec<ConnectionThreadSafe>>>,
+    read_only: bool,
 }
 
 /// Create a sqlite backed database.
 /// Supports concurrent readers but only a single writer.
+/// The database is put in WAL mode, so that readers work on snapshots
+/// and neither block nor are blocked by the writer.
 ///
 /// You must provide a disk-based path: `:memory:` is not OK.
 /// If you want a pure memory storage, use [`new_cozo_mem`](crate::new_cozo_mem).
@@ -38,7 +39,14 @@ pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage
     if path.as_ref().to_str() == Some("") {
         bail!("empty path for sqlite storage")
     }
-    let conn = Connection::open_thread_safe(&path).into_diagnostic()?;
+    let conn = open_connection(path.as_ref(), false)?;
+    let mut statement = conn.prepare("pragma journal_mode=wal;").into_diagnostic()?;
+    ensure!(
+        statement.next().into_diagnostic()? == State::Row
+            && statement.read::<String, _>(0).into_diagnostic()? == "wal",
+        "cannot put the sqlite database in WAL mode"
+    );
+    drop(statement);
     let query = r#"
         create table if not exists cozo
         (
@@ -50,36 +58,74 @@ pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage
     while statement.next().into_diagnostic()? != State::Done {}
 
     let ret = crate::Db::new(SqliteStorage {
-        lock: Default::default(),
+        write_lock: Default::default(),
         name: PathBuf::from(path.as_ref()),
         pool: Default::default(),
+        read_only: false,
     })?;
 
     ret.initialize()?;
     Ok(ret)
 }
 
+/// Open an existing sqlite database for reading only, such as a backup made by
+/// older versions. Unlike [`new_cozo_sqlite`], the journal mode of the file is left
+/// alone, so that files on read-only media can be opened and are never modified.
+pub(crate) fn open_cozo_sqlite_read_only(path: &Path) -> Result<crate::Db<SqliteStorage>> {
+    if !path.is_file() {
+        bail!("sqlite database {} does not exist", path.display())
+    }
+    crate::Db::new(SqliteStorage {
+        write_lock: Default::default(),
+        name: PathBuf::from(path),
+        pool: Default::default(),
+        read_only: true,
+    })
+}
+
+/// How long to wait for locks held by other connections,
+/// which in WAL mode only happens briefly during checkpoints
+const BUSY_TIMEOUT_MS: usize = 5000;
+
+fn open_connection(path: &Path, read_only: bool) -> Result<ConnectionThreadSafe> {
+    let mut conn = if read_only {
+        Connection::open_thread_safe_with_flags(path, OpenFlags::new().with_read_only())
+    } else {
+        Connection::open_thread_safe(path)
+    }
+    .into_diagnostic()?;
+    conn.set_busy_timeout(BUSY_TIMEOUT_MS).into_diagnostic()?;
+    Ok(conn)
+}
+
 impl<'s> Storage<'s> for SqliteStorage {
     type Tx = SqliteTx<'s>;
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         let conn = {
             match self.pool.lock().unwrap().pop() {
-                None => Connection::open_thread_safe(&self.name).into_diagnostic()?,
+                None => open_connection(&self.name, self.read_only)?,
                 Some(conn) => conn,
             }
         };
-        let lock = if write {
-            Right(self.lock.write().unwrap())
+        // readers do not take the lock: they run in their own transactions and see a snapshot
+        let write_lock = if write {
+            Some(self.write_lock.lock().unwrap())
         } else {
-            Left(self.lock.read().unwrap())
+            None
         };
-        if write {
-            let mut stmt = conn.prepare("begin;").into_diagnostic()?;
-            while stmt.next().into_diagnostic()? != State::Done {}
+        let mut stmt = conn.prepare("begin;").into_diagnostic()?;
+        while stmt.next().into_diagnostic()? != State::Done {}
+        drop(stmt);
+        if !write {
+            // the snapshot of a transaction in WAL mode starts with its first read
+            let mut stmt = conn
+                .prepare("select 1 from cozo limit 1;")
+                .into_diagnostic()?;
+            stmt.next().into_diagnostic()?;
         }
         Ok(SqliteTx {
-            lock,
+            write_lock,
             storage: self,
             conn: Some(conn),
             stmts: [
@@ -117,7 +163,7 @@ impl<'s> Storage<'s> for SqliteStorage {
 }
 
 pub struct SqliteTx<'a> {
-    lock: Either<ShardedLockReadGuard<'a, ()>, ShardedLockWriteGuard<'a, ()>>,
+    write_lock: Option<MutexGuard<'a, ()>>,
     storage: &'a SqliteStorage,
     conn: Option<ConnectionThreadSafe>,
     stmts: [Mutex<Option<Statement<'a>>>; N_CACHED_QUERIES],
@@ -148,11 +194,10 @@ const COUNT_RANGE_QUERY: usize = 6;
 
 impl Drop for SqliteTx<'_> {
     fn drop(&mut self) {
-        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
-            if !self.committed {
-                let query = r#"rollback;"#;
-                let _ = self.conn.as_ref().unwrap().execute(query);
-            }
+        // also ends the transactions of readers, releasing their snapshots
+        if !self.committed {
+            let query = r#"rollback;"#;
+            let _ = self.conn.as_ref().unwrap().execute(query);
         }
         let mut pool = self.storage.pool.lock().unwrap();
         let conn = self.conn.take().unwrap();
@@ -214,6 +259,23 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
         Ok(())
     }
 
+    fn batch_put<'a>(
+        &mut self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        self.ensure_stmt(PUT_QUERY);
+        let mut statement = self.stmts[PUT_QUERY].lock().unwrap();
+        let statement = statement.as_mut().unwrap();
+        for pair in data {
+            let (key, val) = pair?;
+            statement.reset().unwrap();
+            statement.bind((1, key.as_slice())).unwrap();
+            statement.bind((2, val.as_slice())).unwrap();
+            while statement.next().into_diagnostic()? != State::Done {}
+        }
+        Ok(())
+    }
+
     fn del(&mut self, key: &[u8]) -> Result<()> {
         self.par_del(key)
     }
@@ -256,7 +318,7 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
     }
 
     fn commit(&mut self) -> Result<()> {
-        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
+        if self.write_lock.is_some() {
             if !self.committed {
                 let query = r#"commit;"#;
                 let mut statement = self.conn.as_ref().unwrap().prepare(query).unwrap();
@@ -17,12 +17,14 @@
 #include "rocksdb/table.h"
 #include "rocksdb/filter_policy.h"
 #include "rocksdb/slice_transform.h"
+#include "rocksdb/utilities/backup_engine.h"
 
 using namespace rocksdb;
 using namespace std;
 
 struct RocksDbStatus;
 struct DbOpts;
+struct BackupGenerationInfo;
 
 typedef Status::Code StatusCode;
 typedef Status::SubCode StatusSubCode;
@@ -150,3 +150,97 @@ RocksDbBridge::~RocksDbBridge() {
         }
     }
 }
+
+// A `backup_id` of zero refers to the latest backup in the directory.
+static BackupID resolve_backup_id(BackupEngine *engine, uint32_t backup_id) {
+    if (backup_id != 0) {
+        return backup_id;
+    }
+    std::vector<BackupInfo> infos;
+    engine->GetBackupInfo(&infos);
+    BackupID latest = 0;
+    for (auto &info: infos) {
+        if (info.backup_id > latest) {
+            latest = info.backup_id;
+        }
+    }
+    return latest;
+}
+
+static unique_ptr<BackupEngine> open_backup_engine(rust::Str path, RocksDbStatus &status) {
+    BackupEngine *engine = nullptr;
+    BackupEngineOptions options{string(path)};
+    write_status(BackupEngine::Open(Env::Default(), options, &engine), status);
+    return unique_ptr<BackupEngine>(engine);
+}
+
+void RocksDbBridge::create_backup(rust::Str path, BackupGenerationInfo &info, RocksDbStatus &status) const {
+    auto engine = open_backup_engine(path, status);
+    if (engine == nullptr) {
+        return;
+    }
+    BackupID new_id = 0;
+    CreateBackupOptions options;
+    options.flush_before_backup = true;
+    auto s = engine->CreateNewBackup(options, get_base_db(), &new_id);
+    if (!s.ok()) {
+        write_status(s, status);
+        return;
+    }
+    BackupInfo created;
+    write_status(engine->GetBackupInfo(new_id, &created), status);
+    info.id = created.backup_id;
+    info.timestamp = created.timestamp;
+    info.size = created.size;
+    info.num_files = created.number_files;
+}
+
+rust::Vec<BackupGenerationInfo> list_backups(rust::Str path, RocksDbStatus &status) {
+    rust::Vec<BackupGenerationInfo> ret;
+    auto engine = open_backup_engine(path, status);
+    if (engine == nullptr) {
+        return ret;
+    }
+    std::vector<BackupInfo> infos;
+    engine->GetBackupInfo(&infos);
+    for (auto &info: infos) {
+        ret.push_back(BackupGenerationInfo{
+                info.backup_id,
+                info.timestamp,
+                info.size,
+                info.number_files
+        });
+    }
+    return ret;
+}
+
+void purge_old_backups(rust::Str path, uint32_t keep, RocksDbStatus &status) {
+    auto engine = open_backup_engine(path, status);
+    if (engine == nullptr) {
+        return;
+    }
+    write_status(engine->PurgeOldBackups(keep), status);
+}
+
+void verify_backup(rust::Str path, uint32_t backup_id, RocksDbStatus &status) {
+    auto engine = open_backup_engine(path, status);
+    if (engine == nullptr) {
+        return;
+    }
+    write_status(engine->VerifyBackup(resolve_backup_id(&*engine, backup_id), true), status);
+}
+
+void restore_from_backup(rust::Str path, rust::Str db_path, uint32_t backup_id, RocksDbStatus &status) {
+    auto engine = open_backup_engine(path, status);
+    if (engine == nullptr) {
+        return;
+    }
+    auto id = resolve_backup_id(&*engine, backup_id);
+    auto s = engine->VerifyBackup(id, true);
+    if (!s.ok()) {
+        write_status(s, status);
+        return;
+    }
+    string db_path_(db_path);
+    write_status(engine->RestoreDBFromBackup(RestoreOptions(), id, db_path_, db_path_), status);
+}
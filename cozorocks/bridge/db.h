@@ -114,10 +114,20 @@ struct RocksDbBridge {
         return db->GetBaseDB();
     }
 
+    void create_backup(rust::Str path, BackupGenerationInfo &info, RocksDbStatus &status) const;
+
     ~RocksDbBridge();
 };
 
 shared_ptr<RocksDbBridge>
 open_db(const DbOpts &opts, RocksDbStatus &status);
 
+rust::Vec<BackupGenerationInfo> list_backups(rust::Str path, RocksDbStatus &status);
+
+void purge_old_backups(rust::Str path, uint32_t keep, RocksDbStatus &status);
+
+void verify_backup(rust::Str path, uint32_t backup_id, RocksDbStatus &status);
+
+void restore_from_backup(rust::Str path, rust::Str db_path, uint32_t backup_id, RocksDbStatus &status);
+
 #endif //COZOROCKS_DB_H
@@ -109,6 +109,10 @@ struct TxBridge {
         write_status(tx->Put(convert_slice(key), convert_slice(val)), status);
     }
 
+    inline void put_untracked(RustBytes key, RustBytes val, RocksDbStatus &status) const {
+        write_status(tx->PutUntracked(convert_slice(key), convert_slice(val)), status);
+    }
+
     inline void del(RustBytes key, RocksDbStatus &status) const {
         write_status(tx->Delete(convert_slice(key)), status);
     }
@@ -195,6 +195,67 @@ impl RocksDb {
             Err(status)
         }
     }
+    /// Create a new backup in the backup directory `path`. Table files already present
+    /// in the directory from previous backups are shared, so only new data is copied.
+    pub fn create_backup(&self, path: &str) -> Result<BackupGenerationInfo, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let mut info = BackupGenerationInfo::default();
+        self.inner.create_backup(path, &mut info, &mut status);
+        if status.is_ok() {
+            Ok(info)
+        } else {
+            Err(status)
+        }
+    }
+}
+
+/// List the backups in the backup directory `path`, oldest first.
+pub fn list_backups(path: &str) -> Result<Vec<BackupGenerationInfo>, RocksDbStatus> {
+    let mut status = RocksDbStatus::default();
+    let ret = crate::bridge::ffi::list_backups(path, &mut status);
+    if status.is_ok() {
+        Ok(ret)
+    } else {
+        Err(status)
+    }
+}
+
+/// Delete all but the latest `keep` backups in the backup directory `path`.
+pub fn purge_old_backups(path: &str, keep: u32) -> Result<(), RocksDbStatus> {
+    let mut status = RocksDbStatus::default();
+    crate::bridge::ffi::purge_old_backups(path, keep, &mut status);
+    if status.is_ok() {
+        Ok(())
+    } else {
+        Err(status)
+    }
+}
+
+/// Verify the checksums of the files of a backup. `None` refers to the latest backup.
+pub fn verify_backup(path: &str, backup_id: Option<u32>) -> Result<(), RocksDbStatus> {
+    let mut status = RocksDbStatus::default();
+    crate::bridge::ffi::verify_backup(path, backup_id.unwrap_or(0), &mut status);
+    if status.is_ok() {
+        Ok(())
+    } else {
+        Err(status)
+    }
+}
+
+/// Verify a backup and restore it into `db_path`, which must not be opened by anyone.
+/// `None` refers to the latest backup.
+pub fn restore_from_backup(
+    path: &str,
+    db_path: &str,
+    backup_id: Option<u32>,
+) -> Result<(), RocksDbStatus> {
+    let mut status = RocksDbStatus::default();
+    crate::bridge::ffi::restore_from_backup(path, db_path, backup_id.unwrap_or(0), &mut status);
+    if status.is_ok() {
+        Ok(())
+    } else {
+        Err(status)
+    }
 }
 
 pub struct SstWriter {
@@ -43,6 +43,14 @@ pub(crate) mod ffi {
         pub block_cache_size: usize,
     }
 
+    #[derive(Clone, Debug, Default, Eq, PartialEq)]
+    pub struct BackupGenerationInfo {
+        pub id: u32,
+        pub timestamp: i64,
+        pub size: u64,
+        pub num_files: u32,
+    }
+
     #[derive(Clone, Debug, Eq, PartialEq)]
     pub struct RocksDbStatus {
         pub code: StatusCode,
@@ -140,6 +148,21 @@ pub(crate) mod ffi {
             status: &mut RocksDbStatus,
         ) -> UniquePtr<SstFileWriterBridge>;
         fn ingest_sst(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
+        fn create_backup(
+            self: &RocksDbBridge,
+            path: &str,
+            info: &mut BackupGenerationInfo,
+            status: &mut RocksDbStatus,
+        );
+        fn list_backups(path: &str, status: &mut RocksDbStatus) -> Vec<BackupGenerationInfo>;
+        fn purge_old_backups(path: &str, keep: u32, status: &mut RocksDbStatus);
+        fn verify_backup(path: &str, backup_id: u32, status: &mut RocksDbStatus);
+        fn restore_from_backup(
+            path: &str,
+            db_path: &str,
+            backup_id: u32,
+            status: &mut RocksDbStatus,
+        );
 
         type SstFileWriterBridge;
         fn put(
@@ -166,6 +189,7 @@ pub(crate) mod ffi {
         ) -> UniquePtr<PinnableSlice>;
         fn exists(self: &TxBridge, key: &[u8], for_update: bool, status: &mut RocksDbStatus);
         fn put(self: &TxBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
+        fn put_untracked(self: &TxBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
         fn del(self: &TxBridge, key: &[u8], status: &mut RocksDbStatus);
         fn commit(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
         fn rollback(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
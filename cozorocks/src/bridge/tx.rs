@@ -107,6 +107,18 @@ impl Tx {
             Err(status)
         }
     }
+    /// Put without locking the key or checking for conflicts, for keys that
+    /// no other transaction writes to.
+    #[inline]
+    pub fn put_untracked(&self, key: &[u8], val: &[u8]) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.put_untracked(key, val, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
     #[inline]
     pub fn del(&self, key: &[u8]) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();
@@ -9,8 +9,13 @@
 #![warn(rust_2018_idioms, future_incompatible)]
 #![allow(clippy::type_complexity)]
 
+pub use bridge::db::list_backups;
+pub use bridge::db::purge_old_backups;
+pub use bridge::db::restore_from_backup;
+pub use bridge::db::verify_backup;
 pub use bridge::db::DbBuilder;
 pub use bridge::db::RocksDb;
+pub use bridge::ffi::BackupGenerationInfo;
 pub use bridge::ffi::RocksDbStatus;
 pub use bridge::ffi::SnapshotBridge;
 pub use bridge::ffi::StatusCode;